@@ -1,10 +1,18 @@
 #include "ShaderHeader.hlsli"
 
+// permutation features: USE_TEXTURE, ALPHA_TEST (needs USE_TEXTURE).
+// without a texture the uv is shown, for debugging
+[RootSignature(BasicRS)]
 float4 BasicPS(Output input): SV_TARGET {
-	return float4(tex.Sample(smp, input.uv));
-}
+#if USE_TEXTURE
+	float4 color = tex.Sample(smp, input.uv);
+#else
+	float4 color = float4(input.uv, input.uv);
+#endif
+
+#if ALPHA_TEST
+	clip(color.a - 0.5);
+#endif
 
-/// debug uv
-// float4 BasicPS(Output input): SV_TARGET {
-// 	return float4(input.uv, input.uv);
-// }
+	return color;
+}
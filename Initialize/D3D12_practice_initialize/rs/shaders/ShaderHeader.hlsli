@@ -1,6 +1,16 @@
 Texture2D<float4> tex: register(t0);
 SamplerState smp: register(s0);
 
+cbuffer SceneConstants: register(b0) {
+	row_major float4x4 transform;
+};
+
+#define BasicRS \
+	"RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), " \
+	"DescriptorTable(SRV(t0), visibility = SHADER_VISIBILITY_PIXEL), " \
+	"CBV(b0, visibility = SHADER_VISIBILITY_VERTEX), " \
+	"StaticSampler(s0, filter = FILTER_MIN_MAG_MIP_POINT, visibility = SHADER_VISIBILITY_PIXEL)"
+
 struct Output {
 	float4 svpos: SV_POSITION;
     float2 uv: TEXCOORD;
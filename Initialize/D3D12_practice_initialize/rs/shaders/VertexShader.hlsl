@@ -1,8 +1,9 @@
 #include "ShaderHeader.hlsli"
 
+[RootSignature(BasicRS)]
 Output BasicVS(float4 position : POSITION, float2 uv: TEXCOORD) {
 	Output output;
-	output.svpos = position;
+	output.svpos = mul(transform, position);
 	output.uv = uv;
 	return output;
 }
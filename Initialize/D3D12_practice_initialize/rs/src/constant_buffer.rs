@@ -0,0 +1,349 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+};
+
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+
+use crate::lib;
+use crate::memory_tracker;
+
+// every constant buffer view starts on 256 bytes
+pub const CONSTANT_BUFFER_ALIGNMENT: u64 = d3d12::D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT as u64;
+
+// cbuffer members are packed into 16 byte registers
+const REGISTER_SIZE: usize = 16;
+const COMPONENT_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HlslType {
+    // float, float2 .. float4, also int and uint vectors
+    Vector(usize),
+    // floatRxC, column_major is the HLSL default
+    Matrix { rows: usize, columns: usize, row_major: bool },
+}
+
+impl HlslType {
+    pub const FLOAT: HlslType = HlslType::Vector(1);
+    pub const FLOAT2: HlslType = HlslType::Vector(2);
+    pub const FLOAT3: HlslType = HlslType::Vector(3);
+    pub const FLOAT4: HlslType = HlslType::Vector(4);
+    pub const ROW_MAJOR_FLOAT4X4: HlslType = HlslType::Matrix { rows: 4, columns: 4, row_major: true };
+    pub const FLOAT4X4: HlslType = HlslType::Matrix { rows: 4, columns: 4, row_major: false };
+
+    // bytes used by one value, without padding after the last register
+    fn packed_size(&self) -> usize {
+        match *self {
+            HlslType::Vector(components) => components * COMPONENT_SIZE,
+            HlslType::Matrix { rows, columns, row_major } => {
+                let (registers, components) = if row_major { (rows, columns) } else { (columns, rows) };
+                (registers - 1) * REGISTER_SIZE + components * COMPONENT_SIZE
+            },
+        }
+    }
+}
+
+// one member of a cbuffer next to the matching Rust field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CbufferField {
+    pub name: &'static str,
+    pub hlsl_type: HlslType,
+    // 0 when the member is not an array
+    pub array_length: usize,
+    pub rust_offset: usize,
+    pub rust_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CbufferLayoutError {
+    FieldOffset { field: &'static str, rust_offset: usize, hlsl_offset: usize },
+    FieldSize { field: &'static str, rust_size: usize, hlsl_size: usize },
+    // the Rust struct needs explicit padding up to the last register
+    StructSize { rust_size: usize, hlsl_size: usize },
+}
+
+// Rust structs which mirror an HLSL cbuffer
+pub trait ConstantBufferLayout: Copy {
+    // members in declaration order
+    fn hlsl_fields() -> Vec<CbufferField>;
+}
+
+fn align_to_register(offset: usize) -> usize {
+    (offset + REGISTER_SIZE - 1) / REGISTER_SIZE * REGISTER_SIZE
+}
+
+// offset and size of each member after HLSL packing, and the size of the whole cbuffer
+pub fn hlsl_offsets(fields: &[CbufferField]) -> (Vec<(usize, usize)>, usize) {
+
+    let mut offset = 0;
+    let mut offsets = Vec::new();
+
+    for field in fields {
+        let element_size = field.hlsl_type.packed_size();
+
+        let (start, size) = match (field.array_length, field.hlsl_type) {
+            // array elements and matrices always start on a new register
+            (0, HlslType::Vector(_)) => {
+                let straddles = offset % REGISTER_SIZE + element_size > REGISTER_SIZE;
+                (if straddles { align_to_register(offset) } else { offset }, element_size)
+            },
+            (0, HlslType::Matrix { .. }) => (align_to_register(offset), element_size),
+            (length, _) => (align_to_register(offset), align_to_register(element_size) * (length - 1) + element_size),
+        };
+
+        offsets.push((start, size));
+        offset = start + size;
+    }
+
+    (offsets, align_to_register(offset))
+}
+
+pub fn validate_layout<T: ConstantBufferLayout>() -> Result<(), CbufferLayoutError> {
+
+    let fields = T::hlsl_fields();
+    let (offsets, hlsl_size) = hlsl_offsets(&fields);
+
+    for (field, &(hlsl_offset, hlsl_size)) in fields.iter().zip(offsets.iter()) {
+        if field.rust_offset != hlsl_offset {
+            return Err(CbufferLayoutError::FieldOffset { field: field.name, rust_offset: field.rust_offset, hlsl_offset: hlsl_offset });
+        }
+
+        if field.rust_size != hlsl_size {
+            return Err(CbufferLayoutError::FieldSize { field: field.name, rust_size: field.rust_size, hlsl_size: hlsl_size });
+        }
+    }
+
+    if mem::size_of::<T>() != hlsl_size {
+        return Err(CbufferLayoutError::StructSize { rust_size: mem::size_of::<T>(), hlsl_size: hlsl_size });
+    }
+
+    Ok(())
+}
+
+// `count` copies of T in a persistently mapped upload buffer, one per frame in flight
+pub struct ConstantBuffer<T: ConstantBufferLayout> {
+    pub buffer_object: *mut d3d12::ID3D12Resource,
+    mapped: *mut u8,
+    gpu_address: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    element_size: u64,
+    count: u32,
+    kind: PhantomData<T>,
+}
+
+impl<T: ConstantBufferLayout> ConstantBuffer<T> {
+    pub fn new(device: *mut d3d12::ID3D12Device, count: u32) -> Result<ConstantBuffer<T>, winerror::HRESULT> {
+
+        debug_assert_eq!(validate_layout::<T>(), Ok(()), "Rust struct does not match the cbuffer packing");
+
+        let element_size = (mem::size_of::<T>() as u64 + CONSTANT_BUFFER_ALIGNMENT - 1) / CONSTANT_BUFFER_ALIGNMENT * CONSTANT_BUFFER_ALIGNMENT;
+
+        let heap_prop = d3d12::D3D12_HEAP_PROPERTIES {
+            Type : d3d12::D3D12_HEAP_TYPE_UPLOAD,
+            CPUPageProperty : d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference : d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 0,
+            VisibleNodeMask: 0,
+        };
+
+        let resource_desc = lib::create_buffer_resource_desc(element_size * count as u64);
+
+        let buffer = lib::create_committed_resource(device, lib::CommittedResource {
+            pHeapProperties: &heap_prop,
+            HeapFlags: d3d12::D3D12_HEAP_FLAG_NONE,
+            pResourceDesc: &resource_desc,
+            InitialResourceState: d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+            pOptimizedClearValue: ptr::null_mut(),
+        })?;
+
+        let mut mapped = ptr::null_mut::<u8>();
+
+        let result = unsafe {
+            buffer.as_ref().unwrap().
+            Map(0, &d3d12::D3D12_RANGE { Begin: 0, End: 0 }, lib::get_pointer_of_interface(&mut mapped))
+        };
+
+        if result != winerror::S_OK {
+            memory_tracker::untrack(buffer);
+            unsafe { buffer.as_ref().unwrap().Release() };
+            return Err(result);
+        }
+
+        Ok(ConstantBuffer {
+            buffer_object: buffer,
+            mapped: mapped,
+            gpu_address: unsafe { buffer.as_ref().unwrap().GetGPUVirtualAddress() },
+            element_size: element_size,
+            count: count,
+            kind: PhantomData,
+        })
+    }
+
+    // the GPU must not read copy `index` while it is written
+    pub fn write(&mut self, index: u32, value: &T) {
+        assert!(index < self.count, "constant buffer index {} out of range {}", index, self.count);
+
+        unsafe {
+            self.mapped.offset((self.element_size * index as u64) as isize).cast::<T>().write_unaligned(*value)
+        };
+    }
+
+    pub fn gpu_address(&self, index: u32) -> d3d12::D3D12_GPU_VIRTUAL_ADDRESS {
+        assert!(index < self.count, "constant buffer index {} out of range {}", index, self.count);
+
+        self.gpu_address + self.element_size * index as u64
+    }
+
+    pub fn view_desc(&self, index: u32) -> d3d12::D3D12_CONSTANT_BUFFER_VIEW_DESC {
+        d3d12::D3D12_CONSTANT_BUFFER_VIEW_DESC {
+            BufferLocation: self.gpu_address(index),
+            SizeInBytes: self.element_size as u32,
+        }
+    }
+
+    // CBV descriptor for descriptor tables
+    pub fn create_view(&self, device: *mut d3d12::ID3D12Device, index: u32, handle: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE) {
+        unsafe { device.as_ref().unwrap().CreateConstantBufferView(&self.view_desc(index), handle) };
+    }
+
+    // bind copy `index` to a D3D12_ROOT_PARAMETER_TYPE_CBV parameter
+    pub fn bind_graphics_root(&self, cmd_list: *mut d3d12::ID3D12GraphicsCommandList, root_parameter_index: u32, index: u32) {
+        unsafe { cmd_list.as_ref().unwrap().SetGraphicsRootConstantBufferView(root_parameter_index, self.gpu_address(index)) };
+    }
+}
+
+impl<T: ConstantBufferLayout> Drop for ConstantBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.buffer_object.as_ref().unwrap().Unmap(0, ptr::null());
+            memory_tracker::untrack(self.buffer_object);
+            self.buffer_object.as_ref().unwrap().Release();
+        }
+    }
+}
+
+// cbuffer SceneConstants : register(b0) in ShaderHeader.hlsli
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SceneConstants {
+    pub transform: lib::XMFLOAT4X4,
+}
+
+impl ConstantBufferLayout for SceneConstants {
+    fn hlsl_fields() -> Vec<CbufferField> {
+        vec![
+            CbufferField {
+                name: "transform",
+                hlsl_type: HlslType::ROW_MAJOR_FLOAT4X4,
+                array_length: 0,
+                rust_offset: mem::offset_of!(SceneConstants, transform),
+                rust_size: mem::size_of::<lib::XMFLOAT4X4>(),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &'static str, hlsl_type: HlslType, array_length: usize, rust_offset: usize, rust_size: usize) -> CbufferField {
+        CbufferField { name: name, hlsl_type: hlsl_type, array_length: array_length, rust_offset: rust_offset, rust_size: rust_size }
+    }
+
+    #[test]
+    fn scene_constants_match_hlsl() {
+        assert_eq!(validate_layout::<SceneConstants>(), Ok(()));
+    }
+
+    #[test]
+    fn vectors_do_not_cross_registers() {
+        // float3 a; float b; float2 c; float3 d; float e;
+        let fields = [
+            field("a", HlslType::FLOAT3, 0, 0, 12),
+            field("b", HlslType::FLOAT, 0, 0, 4),
+            field("c", HlslType::FLOAT2, 0, 0, 8),
+            field("d", HlslType::FLOAT3, 0, 0, 12),
+            field("e", HlslType::FLOAT, 0, 0, 4),
+        ];
+
+        let (offsets, size) = hlsl_offsets(&fields);
+
+        assert_eq!(offsets, vec![(0, 12), (12, 4), (16, 8), (32, 12), (44, 4)]);
+        assert_eq!(size, 48);
+    }
+
+    #[test]
+    fn arrays_and_matrices_start_on_registers() {
+        // float a; float b[3]; float c; float3x3 d; float e;
+        let fields = [
+            field("a", HlslType::FLOAT, 0, 0, 4),
+            field("b", HlslType::FLOAT, 3, 0, 0),
+            field("c", HlslType::FLOAT, 0, 0, 4),
+            field("d", HlslType::Matrix { rows: 3, columns: 3, row_major: false }, 0, 0, 0),
+            field("e", HlslType::FLOAT, 0, 0, 4),
+        ];
+
+        let (offsets, size) = hlsl_offsets(&fields);
+
+        assert_eq!(offsets, vec![(0, 4), (16, 36), (52, 4), (64, 44), (108, 4)]);
+        assert_eq!(size, 112);
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Unpadded {
+        light_direction: lib::XMFLOAT3,
+        color: lib::XMFLOAT3,
+    }
+
+    impl ConstantBufferLayout for Unpadded {
+        fn hlsl_fields() -> Vec<CbufferField> {
+            vec![
+                field("light_direction", HlslType::FLOAT3, 0, mem::offset_of!(Unpadded, light_direction), 12),
+                field("color", HlslType::FLOAT3, 0, mem::offset_of!(Unpadded, color), 12),
+            ]
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Padded {
+        light_direction: lib::XMFLOAT3,
+        padding: f32,
+        color: lib::XMFLOAT3,
+        intensity: f32,
+    }
+
+    impl ConstantBufferLayout for Padded {
+        fn hlsl_fields() -> Vec<CbufferField> {
+            vec![
+                field("light_direction", HlslType::FLOAT3, 0, mem::offset_of!(Padded, light_direction), 12),
+                field("color", HlslType::FLOAT3, 0, mem::offset_of!(Padded, color), 12),
+                field("intensity", HlslType::FLOAT, 0, mem::offset_of!(Padded, intensity), 4),
+            ]
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct TightArray {
+        weights: [f32; 4],
+    }
+
+    impl ConstantBufferLayout for TightArray {
+        fn hlsl_fields() -> Vec<CbufferField> {
+            vec![field("weights", HlslType::FLOAT, 4, mem::offset_of!(TightArray, weights), mem::size_of::<[f32; 4]>())]
+        }
+    }
+
+    #[test]
+    fn mismatched_structs_are_reported() {
+        assert_eq!(validate_layout::<Unpadded>(), Err(CbufferLayoutError::FieldOffset { field: "color", rust_offset: 12, hlsl_offset: 16 }));
+        assert_eq!(validate_layout::<Padded>(), Ok(()));
+
+        // float weights[4] uses one register per element
+        assert_eq!(validate_layout::<TightArray>(), Err(CbufferLayoutError::FieldSize { field: "weights", rust_size: 16, hlsl_size: 52 }));
+    }
+}
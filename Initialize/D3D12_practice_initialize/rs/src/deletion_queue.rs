@@ -0,0 +1,124 @@
+use winapi::{
+    um::{
+        d3d12,
+        unknwnbase,
+    },
+    Interface,
+};
+
+use crate::lib;
+use crate::memory_tracker;
+
+// objects waiting for the GPU, in retire order
+#[derive(Debug)]
+pub struct DeletionQueue<T> {
+    entries: Vec<(u64, T)>,
+}
+
+impl<T> Default for DeletionQueue<T> {
+    fn default() -> DeletionQueue<T> {
+        DeletionQueue { entries: Vec::new() }
+    }
+}
+
+impl<T> DeletionQueue<T> {
+    pub fn new() -> DeletionQueue<T> {
+        DeletionQueue::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // `fence_value` is the value signaled after the last submission which used `object`
+    pub fn retire(&mut self, object: T, fence_value: u64) {
+        self.entries.push((fence_value, object));
+    }
+
+    // objects the GPU is done with
+    pub fn collect(&mut self, completed_fence_value: u64) -> Vec<T> {
+        let (completed, pending) = self.entries.drain(..).partition::<Vec<_>, _>(|&(fence_value, _)| fence_value <= completed_fence_value);
+
+        self.entries = pending;
+
+        completed.into_iter().map(|(_, object)| object).collect()
+    }
+
+    // highest fence value any queued object waits for
+    pub fn last_fence_value(&self) -> Option<u64> {
+        self.entries.iter().map(|&(fence_value, _)| fence_value).max()
+    }
+}
+
+// COM objects released once the fence passes their retire value
+#[derive(Default)]
+pub struct ResourceDeletionQueue {
+    queue: DeletionQueue<*mut unknwnbase::IUnknown>,
+}
+
+impl ResourceDeletionQueue {
+    pub fn new() -> ResourceDeletionQueue {
+        ResourceDeletionQueue::default()
+    }
+
+    // takes over the reference of `object`
+    pub fn retire<I: Interface>(&mut self, object: *mut I, fence_value: u64) {
+        self.queue.retire(object.cast::<unknwnbase::IUnknown>(), fence_value);
+    }
+
+    // release what the GPU no longer uses, called once per frame
+    pub fn release_completed(&mut self, fence: *mut d3d12::ID3D12Fence) {
+
+        let completed_fence_value = unsafe { fence.as_ref().unwrap().GetCompletedValue() };
+
+        for object in self.queue.collect(completed_fence_value) {
+            memory_tracker::untrack(object);
+            unsafe { object.as_ref().unwrap().Release() };
+        }
+    }
+
+    // wait for every queued object and release it, called at shutdown
+    pub fn flush(&mut self, fence: *mut d3d12::ID3D12Fence) {
+
+        if let Some(fence_value) = self.queue.last_fence_value() {
+            lib::wait_for_fence(fence, fence_value);
+        }
+
+        self.release_completed(fence);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_are_released_after_their_fence() {
+        let mut queue = DeletionQueue::new();
+
+        queue.retire("upload buffer", 1);
+        queue.retire("old texture", 3);
+        queue.retire("vertex buffer", 2);
+
+        assert!(queue.collect(0).is_empty());
+        assert_eq!(queue.collect(2), vec!["upload buffer", "vertex buffer"]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.last_fence_value(), Some(3));
+
+        assert_eq!(queue.collect(5), vec!["old texture"]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.last_fence_value(), None);
+    }
+}
@@ -0,0 +1,438 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+};
+
+use std::marker::PhantomData;
+
+use crate::lib;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DescriptorError {
+    // no free block is large enough for the request
+    OutOfDescriptors { requested: u32, largest_free: u32 },
+    // the range was not allocated from this heap or is already free
+    InvalidFree { start: u32, count: u32 },
+}
+
+// first fit free list of descriptor indices, no GPU objects involved
+#[derive(Debug, Clone)]
+pub struct RangeAllocator {
+    capacity: u32,
+    // (start, count) sorted by start, neighbours are always merged
+    free_list: Vec<(u32, u32)>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> RangeAllocator {
+        RangeAllocator {
+            capacity: capacity,
+            free_list: if capacity > 0 { vec![(0, capacity)] } else { Vec::new() },
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.free_list.iter().map(|&(_, count)| count).sum()
+    }
+
+    pub fn largest_free(&self) -> u32 {
+        self.free_list.iter().map(|&(_, count)| count).max().unwrap_or(0)
+    }
+
+    // returns the first index of `count` contiguous descriptors
+    pub fn allocate(&mut self, count: u32) -> Result<u32, DescriptorError> {
+
+        let position = self.free_list.iter().position(|&(_, free)| free >= count && count > 0);
+
+        match position {
+            Some(i) => {
+                let (start, free) = self.free_list[i];
+
+                if free == count {
+                    self.free_list.remove(i);
+                } else {
+                    self.free_list[i] = (start + count, free - count);
+                }
+
+                Ok(start)
+            },
+            None => Err(DescriptorError::OutOfDescriptors { requested: count, largest_free: self.largest_free() })
+        }
+    }
+
+    pub fn free(&mut self, start: u32, count: u32) -> Result<(), DescriptorError> {
+
+        let invalid = DescriptorError::InvalidFree { start: start, count: count };
+
+        let end = start.checked_add(count).ok_or(invalid)?;
+
+        if count == 0 || end > self.capacity {
+            return Err(invalid);
+        }
+
+        // index of the first free block after the range
+        let i = self.free_list.iter().position(|&(free_start, _)| free_start >= start).unwrap_or(self.free_list.len());
+
+        let overlaps_next = i < self.free_list.len() && self.free_list[i].0 < end;
+        let overlaps_prev = i > 0 && { let (s, c) = self.free_list[i - 1]; s + c > start };
+
+        if overlaps_next || overlaps_prev {
+            return Err(invalid);
+        }
+
+        let merge_next = i < self.free_list.len() && self.free_list[i].0 == end;
+        let merge_prev = i > 0 && { let (s, c) = self.free_list[i - 1]; s + c == start };
+
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                let (_, next_count) = self.free_list.remove(i);
+                self.free_list[i - 1].1 += count + next_count;
+            },
+            (true, false) => self.free_list[i - 1].1 += count,
+            (false, true) => self.free_list[i] = (start, count + self.free_list[i].1),
+            (false, false) => self.free_list.insert(i, (start, count)),
+        }
+
+        Ok(())
+    }
+}
+
+// shader visible heap split into one linear region per frame in flight
+#[derive(Debug, Clone)]
+pub struct FrameRegions {
+    frame_capacity: u32,
+    frame_count: u32,
+    frame_index: u32,
+    used: u32,
+}
+
+impl FrameRegions {
+    pub fn new(frame_capacity: u32, frame_count: u32) -> FrameRegions {
+        assert!(frame_count > 0, "at least one frame region is required");
+
+        FrameRegions {
+            frame_capacity: frame_capacity,
+            frame_count: frame_count,
+            frame_index: 0,
+            used: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.frame_capacity * self.frame_count
+    }
+
+    pub fn used(&self) -> u32 {
+        self.used
+    }
+
+    // the caller has to make sure the GPU is done with the region of `frame_index`
+    pub fn begin_frame(&mut self, frame_index: u32) {
+        self.frame_index = frame_index % self.frame_count;
+        self.used = 0;
+    }
+
+    pub fn allocate(&mut self, count: u32) -> Result<u32, DescriptorError> {
+
+        if count == 0 || self.used + count > self.frame_capacity {
+            return Err(DescriptorError::OutOfDescriptors { requested: count, largest_free: self.frame_capacity - self.used });
+        }
+
+        let start = self.frame_index * self.frame_capacity + self.used;
+
+        self.used += count;
+
+        Ok(start)
+    }
+}
+
+// descriptor heap types as marker types so handles of different heaps can not be mixed
+pub trait DescriptorHeapType {
+    const TYPE: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE;
+}
+
+// heap types which can be bound with SetDescriptorHeaps
+pub trait ShaderVisibleHeapType: DescriptorHeapType {}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rtv {}
+#[derive(Debug, Clone, Copy)]
+pub enum Dsv {}
+#[derive(Debug, Clone, Copy)]
+pub enum CbvSrvUav {}
+#[derive(Debug, Clone, Copy)]
+pub enum Sampler {}
+
+impl DescriptorHeapType for Rtv {
+    const TYPE: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE = d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV;
+}
+
+impl DescriptorHeapType for Dsv {
+    const TYPE: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE = d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_DSV;
+}
+
+impl DescriptorHeapType for CbvSrvUav {
+    const TYPE: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE = d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV;
+}
+
+impl DescriptorHeapType for Sampler {
+    const TYPE: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE = d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_SAMPLER;
+}
+
+impl ShaderVisibleHeapType for CbvSrvUav {}
+impl ShaderVisibleHeapType for Sampler {}
+
+// contiguous descriptors in a CPU only heap
+#[derive(Clone, Copy)]
+pub struct DescriptorRange<K: DescriptorHeapType> {
+    pub start: u32,
+    pub count: u32,
+    cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE,
+    increment: u32,
+    kind: PhantomData<K>,
+}
+
+impl<K: DescriptorHeapType> DescriptorRange<K> {
+    pub fn cpu_handle(&self, index: u32) -> d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(index < self.count, "descriptor index {} out of range {}", index, self.count);
+
+        d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: self.cpu_start.ptr + (index * self.increment) as usize
+        }
+    }
+
+    pub fn cpu_handles(&self) -> Vec<d3d12::D3D12_CPU_DESCRIPTOR_HANDLE> {
+        (0..self.count).map(|i| self.cpu_handle(i)).collect()
+    }
+}
+
+// contiguous descriptors in the shader visible heap, valid for the current frame only
+#[derive(Clone, Copy)]
+pub struct GpuDescriptorRange<K: ShaderVisibleHeapType> {
+    pub count: u32,
+    cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE,
+    gpu_start: d3d12::D3D12_GPU_DESCRIPTOR_HANDLE,
+    increment: u32,
+    kind: PhantomData<K>,
+}
+
+impl<K: ShaderVisibleHeapType> GpuDescriptorRange<K> {
+    pub fn cpu_handle(&self, index: u32) -> d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(index < self.count, "descriptor index {} out of range {}", index, self.count);
+
+        d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: self.cpu_start.ptr + (index * self.increment) as usize
+        }
+    }
+
+    // base handle for SetGraphicsRootDescriptorTable
+    pub fn gpu_handle(&self) -> d3d12::D3D12_GPU_DESCRIPTOR_HANDLE {
+        self.gpu_start
+    }
+}
+
+fn create_heap<K: DescriptorHeapType>(device: *mut d3d12::ID3D12Device, capacity: u32, flags: d3d12::D3D12_DESCRIPTOR_HEAP_FLAGS) -> Result<(*mut d3d12::ID3D12DescriptorHeap, u32), winerror::HRESULT> {
+
+    let heap_desc = d3d12::D3D12_DESCRIPTOR_HEAP_DESC {
+        Type: K::TYPE,
+        NodeMask: 0,
+        NumDescriptors: capacity,
+        Flags: flags,
+    };
+
+    let heap = lib::create_descriptor_heap(device, &heap_desc)?;
+
+    let increment = unsafe { device.as_ref().unwrap().GetDescriptorHandleIncrementSize(K::TYPE) };
+
+    Ok((heap, increment))
+}
+
+// non shader visible heap to create views into
+pub struct DescriptorAllocator<K: DescriptorHeapType> {
+    pub heap: *mut d3d12::ID3D12DescriptorHeap,
+    cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE,
+    increment: u32,
+    allocator: RangeAllocator,
+    kind: PhantomData<K>,
+}
+
+impl<K: DescriptorHeapType> DescriptorAllocator<K> {
+    pub fn new(device: *mut d3d12::ID3D12Device, capacity: u32) -> Result<DescriptorAllocator<K>, winerror::HRESULT> {
+
+        let (heap, increment) = create_heap::<K>(device, capacity, d3d12::D3D12_DESCRIPTOR_HEAP_FLAG_NONE)?;
+
+        let cpu_start = unsafe { heap.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart() };
+
+        Ok(DescriptorAllocator {
+            heap: heap,
+            cpu_start: cpu_start,
+            increment: increment,
+            allocator: RangeAllocator::new(capacity),
+            kind: PhantomData,
+        })
+    }
+
+    pub fn allocate(&mut self, count: u32) -> Result<DescriptorRange<K>, DescriptorError> {
+
+        let start = self.allocator.allocate(count)?;
+
+        Ok(DescriptorRange {
+            start: start,
+            count: count,
+            cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+                ptr: self.cpu_start.ptr + (start * self.increment) as usize
+            },
+            increment: self.increment,
+            kind: PhantomData,
+        })
+    }
+
+    pub fn free(&mut self, range: DescriptorRange<K>) -> Result<(), DescriptorError> {
+        self.allocator.free(range.start, range.count)
+    }
+
+    pub fn allocator(&self) -> &RangeAllocator {
+        &self.allocator
+    }
+}
+
+impl<K: DescriptorHeapType> Drop for DescriptorAllocator<K> {
+    fn drop(&mut self) {
+        unsafe { self.heap.as_ref().unwrap().Release() };
+    }
+}
+
+// shader visible heap, staging descriptors are copied into the region of the current frame
+pub struct ShaderVisibleDescriptorRing<K: ShaderVisibleHeapType> {
+    pub heap: *mut d3d12::ID3D12DescriptorHeap,
+    cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE,
+    gpu_start: d3d12::D3D12_GPU_DESCRIPTOR_HANDLE,
+    increment: u32,
+    regions: FrameRegions,
+    kind: PhantomData<K>,
+}
+
+impl<K: ShaderVisibleHeapType> ShaderVisibleDescriptorRing<K> {
+    pub fn new(device: *mut d3d12::ID3D12Device, frame_capacity: u32, frame_count: u32) -> Result<ShaderVisibleDescriptorRing<K>, winerror::HRESULT> {
+
+        let regions = FrameRegions::new(frame_capacity, frame_count);
+
+        let (heap, increment) = create_heap::<K>(device, regions.capacity(), d3d12::D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE)?;
+
+        let (cpu_start, gpu_start) = unsafe {
+            (heap.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart(), heap.as_ref().unwrap().GetGPUDescriptorHandleForHeapStart())
+        };
+
+        Ok(ShaderVisibleDescriptorRing {
+            heap: heap,
+            cpu_start: cpu_start,
+            gpu_start: gpu_start,
+            increment: increment,
+            regions: regions,
+            kind: PhantomData,
+        })
+    }
+
+    pub fn begin_frame(&mut self, frame_index: u32) {
+        self.regions.begin_frame(frame_index);
+    }
+
+    pub fn allocate(&mut self, count: u32) -> Result<GpuDescriptorRange<K>, DescriptorError> {
+
+        let start = self.regions.allocate(count)?;
+
+        Ok(GpuDescriptorRange {
+            count: count,
+            cpu_start: d3d12::D3D12_CPU_DESCRIPTOR_HANDLE {
+                ptr: self.cpu_start.ptr + (start * self.increment) as usize
+            },
+            gpu_start: d3d12::D3D12_GPU_DESCRIPTOR_HANDLE {
+                ptr: self.gpu_start.ptr + (start * self.increment) as u64
+            },
+            increment: self.increment,
+            kind: PhantomData,
+        })
+    }
+
+    // copy staging descriptors into this frame's region
+    pub fn copy_from(&mut self, device: *mut d3d12::ID3D12Device, source: &DescriptorRange<K>) -> Result<GpuDescriptorRange<K>, DescriptorError> {
+
+        let destination = self.allocate(source.count)?;
+
+        unsafe {
+            device.as_ref().unwrap().
+            CopyDescriptorsSimple(source.count, destination.cpu_start, source.cpu_start, K::TYPE)
+        };
+
+        Ok(destination)
+    }
+}
+
+impl<K: ShaderVisibleHeapType> Drop for ShaderVisibleDescriptorRing<K> {
+    fn drop(&mut self) {
+        unsafe { self.heap.as_ref().unwrap().Release() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_allocator_reuses_and_merges_free_blocks() {
+        let mut allocator = RangeAllocator::new(16);
+
+        let a = allocator.allocate(4).unwrap();
+        let b = allocator.allocate(4).unwrap();
+        let c = allocator.allocate(4).unwrap();
+
+        assert_eq!((a, b, c), (0, 4, 8));
+
+        allocator.free(a, 4).unwrap();
+        allocator.free(c, 4).unwrap();
+
+        // 0..4 and 8..16 are free but not contiguous
+        assert_eq!(allocator.allocate(10), Err(DescriptorError::OutOfDescriptors { requested: 10, largest_free: 8 }));
+
+        allocator.free(b, 4).unwrap();
+        assert_eq!(allocator.largest_free(), 16);
+        assert_eq!(allocator.allocate(16), Ok(0));
+    }
+
+    #[test]
+    fn range_allocator_rejects_invalid_frees() {
+        let mut allocator = RangeAllocator::new(8);
+
+        let a = allocator.allocate(4).unwrap();
+
+        assert_eq!(allocator.free(a, 0), Err(DescriptorError::InvalidFree { start: 0, count: 0 }));
+        assert_eq!(allocator.free(6, 4), Err(DescriptorError::InvalidFree { start: 6, count: 4 }));
+        // overlaps the free block 4..8
+        assert_eq!(allocator.free(2, 4), Err(DescriptorError::InvalidFree { start: 2, count: 4 }));
+
+        allocator.free(a, 4).unwrap();
+        assert_eq!(allocator.free(a, 4), Err(DescriptorError::InvalidFree { start: 0, count: 4 }));
+        assert_eq!(allocator.free_count(), 8);
+    }
+
+    #[test]
+    fn frame_regions_do_not_overlap() {
+        let mut regions = FrameRegions::new(4, 2);
+
+        regions.begin_frame(0);
+        assert_eq!(regions.allocate(3), Ok(0));
+        assert_eq!(regions.allocate(2), Err(DescriptorError::OutOfDescriptors { requested: 2, largest_free: 1 }));
+
+        regions.begin_frame(1);
+        assert_eq!(regions.allocate(2), Ok(4));
+        assert_eq!(regions.allocate(2), Ok(6));
+
+        // the region of frame 0 is reused from its start
+        regions.begin_frame(2);
+        assert_eq!(regions.allocate(1), Ok(0));
+    }
+}
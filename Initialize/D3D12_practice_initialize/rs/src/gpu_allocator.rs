@@ -0,0 +1,568 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+    Interface,
+};
+
+use std::collections::{BTreeSet, HashMap};
+use std::ptr;
+
+use crate::lib;
+use crate::memory_tracker;
+
+// 64KB, alignment of buffers and regular textures
+pub const DEFAULT_PLACEMENT_ALIGNMENT: u64 = d3d12::D3D12_DEFAULT_RESOURCE_PLACEMENT_ALIGNMENT as u64;
+// 4MB, alignment of MSAA textures
+pub const MSAA_PLACEMENT_ALIGNMENT: u64 = d3d12::D3D12_DEFAULT_MSAA_RESOURCE_PLACEMENT_ALIGNMENT as u64;
+
+pub const DEFAULT_HEAP_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocationError {
+    ZeroSize,
+    InvalidAlignment(u64),
+    // larger than the whole heap
+    TooLarge { size: u64, capacity: u64 },
+    // no free block of the needed size
+    OutOfMemory { size: u64 },
+    // the offset is not the start of a live allocation
+    InvalidFree(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuddyAllocation {
+    pub offset: u64,
+    // size of the block, the requested size rounded up to a power of two
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AllocatorStatistics {
+    pub capacity: u64,
+    // bytes in allocated blocks
+    pub allocated: u64,
+    // bytes callers asked for, the difference to `allocated` is padding
+    pub requested: u64,
+    pub allocation_count: usize,
+    pub free_block_count: usize,
+    pub largest_free_block: u64,
+}
+
+impl AllocatorStatistics {
+    pub fn free(&self) -> u64 {
+        self.capacity - self.allocated
+    }
+
+    // 0 when all free memory is one block, close to 1 when it is split into small pieces
+    pub fn fragmentation(&self) -> f32 {
+        match self.free() {
+            0 => 0.0,
+            free => 1.0 - self.largest_free_block as f32 / free as f32,
+        }
+    }
+
+    fn merge(&mut self, other: &AllocatorStatistics) {
+        self.capacity += other.capacity;
+        self.allocated += other.allocated;
+        self.requested += other.requested;
+        self.allocation_count += other.allocation_count;
+        self.free_block_count += other.free_block_count;
+        self.largest_free_block = self.largest_free_block.max(other.largest_free_block);
+    }
+}
+
+// power of two buddy allocator over a range of offsets, no GPU objects involved
+#[derive(Debug, Clone)]
+pub struct BuddyAllocator {
+    capacity: u64,
+    min_block_size: u64,
+    // free block offsets per order, block size is `min_block_size << order`
+    free_lists: Vec<BTreeSet<u64>>,
+    // offset -> (order, requested size)
+    allocations: HashMap<u64, (usize, u64)>,
+}
+
+impl BuddyAllocator {
+    pub fn new(capacity: u64, min_block_size: u64) -> BuddyAllocator {
+        assert!(capacity.is_power_of_two() && min_block_size.is_power_of_two() && capacity >= min_block_size,
+            "capacity and block size must be powers of two");
+
+        let max_order = (capacity / min_block_size).trailing_zeros() as usize;
+
+        let mut free_lists = vec![BTreeSet::new(); max_order + 1];
+        free_lists[max_order].insert(0);
+
+        BuddyAllocator {
+            capacity: capacity,
+            min_block_size: min_block_size,
+            free_lists: free_lists,
+            allocations: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    fn block_size(&self, order: usize) -> u64 {
+        self.min_block_size << order
+    }
+
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Result<BuddyAllocation, AllocationError> {
+
+        if size == 0 {
+            return Err(AllocationError::ZeroSize);
+        }
+
+        if !alignment.is_power_of_two() {
+            return Err(AllocationError::InvalidAlignment(alignment));
+        }
+
+        // blocks are aligned to their own size
+        let block_size = size.max(alignment).max(self.min_block_size).checked_next_power_of_two().unwrap_or(u64::MAX);
+
+        if block_size > self.capacity {
+            return Err(AllocationError::TooLarge { size: size, capacity: self.capacity });
+        }
+
+        let order = (block_size / self.min_block_size).trailing_zeros() as usize;
+
+        let found = (order..self.free_lists.len()).find(|&o| !self.free_lists[o].is_empty());
+
+        let mut current = match found {
+            Some(o) => o,
+            None => return Err(AllocationError::OutOfMemory { size: size }),
+        };
+
+        let offset = *self.free_lists[current].iter().next().unwrap();
+        self.free_lists[current].remove(&offset);
+
+        // split until the block has the needed size, the upper halves become free
+        while current > order {
+            current -= 1;
+            let buddy = offset + self.block_size(current);
+            self.free_lists[current].insert(buddy);
+        }
+
+        self.allocations.insert(offset, (order, size));
+
+        Ok(BuddyAllocation {
+            offset: offset,
+            size: block_size,
+        })
+    }
+
+    pub fn free(&mut self, offset: u64) -> Result<(), AllocationError> {
+
+        let (mut order, _) = self.allocations.remove(&offset).ok_or(AllocationError::InvalidFree(offset))?;
+
+        let mut offset = offset;
+
+        // merge with the buddy as long as it is free
+        while order + 1 < self.free_lists.len() {
+            let buddy = offset ^ self.block_size(order);
+
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+
+            offset = offset.min(buddy);
+            order += 1;
+        }
+
+        self.free_lists[order].insert(offset);
+
+        Ok(())
+    }
+
+    pub fn statistics(&self) -> AllocatorStatistics {
+
+        let allocated = self.allocations.values().map(|&(order, _)| self.block_size(order)).sum();
+
+        let largest_free_block = (0..self.free_lists.len()).rev()
+            .find(|&order| !self.free_lists[order].is_empty())
+            .map_or(0, |order| self.block_size(order));
+
+        AllocatorStatistics {
+            capacity: self.capacity,
+            allocated: allocated,
+            requested: self.allocations.values().map(|&(_, size)| size).sum(),
+            allocation_count: self.allocations.len(),
+            free_block_count: self.free_lists.iter().map(|list| list.len()).sum(),
+            largest_free_block: largest_free_block,
+        }
+    }
+}
+
+// resource heap tier 1 can not mix these in one heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceCategory {
+    Buffer,
+    Texture,
+    RenderTargetDepthStencil,
+}
+
+impl ResourceCategory {
+    pub fn from_desc(desc: &d3d12::D3D12_RESOURCE_DESC) -> ResourceCategory {
+        let render_target_or_depth = d3d12::D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET | d3d12::D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL;
+
+        if desc.Dimension == d3d12::D3D12_RESOURCE_DIMENSION_BUFFER {
+            ResourceCategory::Buffer
+        } else if desc.Flags & render_target_or_depth != 0 {
+            ResourceCategory::RenderTargetDepthStencil
+        } else {
+            ResourceCategory::Texture
+        }
+    }
+
+    fn heap_flags(&self) -> d3d12::D3D12_HEAP_FLAGS {
+        match self {
+            ResourceCategory::Buffer => d3d12::D3D12_HEAP_FLAG_ALLOW_ONLY_BUFFERS,
+            ResourceCategory::Texture => d3d12::D3D12_HEAP_FLAG_ALLOW_ONLY_NON_RT_DS_TEXTURES,
+            ResourceCategory::RenderTargetDepthStencil => d3d12::D3D12_HEAP_FLAG_ALLOW_ONLY_RT_DS_TEXTURES,
+        }
+    }
+
+    // render targets may be multisampled and need the 4MB heap alignment
+    fn heap_alignment(&self) -> u64 {
+        match self {
+            ResourceCategory::RenderTargetDepthStencil => MSAA_PLACEMENT_ALIGNMENT,
+            _ => DEFAULT_PLACEMENT_ALIGNMENT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeapAllocatorError {
+    Allocation(AllocationError),
+    Device(winerror::HRESULT),
+}
+
+impl From<AllocationError> for HeapAllocatorError {
+    fn from(error: AllocationError) -> HeapAllocatorError {
+        HeapAllocatorError::Allocation(error)
+    }
+}
+
+type PoolKey = (d3d12::D3D12_HEAP_TYPE, ResourceCategory);
+
+struct HeapBlock {
+    heap: *mut d3d12::ID3D12Heap,
+    allocator: BuddyAllocator,
+}
+
+// resource placed in one of the heaps of HeapAllocator
+pub struct PlacedResource {
+    pub resource: *mut d3d12::ID3D12Resource,
+    pub offset: u64,
+    pub size: u64,
+    pool: PoolKey,
+    block: usize,
+}
+
+// reserves large ID3D12Heaps per heap type and places resources in them
+pub struct HeapAllocator {
+    device: *mut d3d12::ID3D12Device,
+    heap_size: u64,
+    pools: HashMap<PoolKey, Vec<HeapBlock>>,
+}
+
+pub fn create_heap(device: *mut d3d12::ID3D12Device, heap_desc: *const d3d12::D3D12_HEAP_DESC) -> Result<*mut d3d12::ID3D12Heap, winerror::HRESULT> {
+
+    let mut obj = ptr::null_mut::<d3d12::ID3D12Heap>();
+
+    let result = unsafe {
+        device.as_ref().unwrap().
+        CreateHeap(
+            heap_desc,
+            &d3d12::ID3D12Heap::uuidof(),
+            lib::get_pointer_of_interface(&mut obj)
+        )
+    };
+
+    match result {
+        winerror::S_OK => {
+            memory_tracker::track_heap(obj, unsafe { &*heap_desc });
+
+            Ok(obj)
+        },
+        _ => Err(result)
+    }
+}
+
+impl HeapAllocator {
+    pub fn new(device: *mut d3d12::ID3D12Device, heap_size: u64) -> HeapAllocator {
+        assert!(heap_size.is_power_of_two() && heap_size % MSAA_PLACEMENT_ALIGNMENT == 0, "heap size must be a power of two multiple of 4MB");
+
+        HeapAllocator {
+            device: device,
+            heap_size: heap_size,
+            pools: HashMap::new(),
+        }
+    }
+
+    fn create_block(&self, key: PoolKey, size: u64) -> Result<HeapBlock, winerror::HRESULT> {
+
+        let (heap_type, category) = key;
+
+        let heap_desc = d3d12::D3D12_HEAP_DESC {
+            SizeInBytes: size,
+            Properties: d3d12::D3D12_HEAP_PROPERTIES {
+                Type : heap_type,
+                CPUPageProperty : d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                MemoryPoolPreference : d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+                CreationNodeMask: 0,
+                VisibleNodeMask: 0,
+            },
+            Alignment: category.heap_alignment(),
+            Flags: category.heap_flags(),
+        };
+
+        let heap = create_heap(self.device, &heap_desc)?;
+
+        Ok(HeapBlock {
+            heap: heap,
+            allocator: BuddyAllocator::new(size, DEFAULT_PLACEMENT_ALIGNMENT),
+        })
+    }
+
+    pub fn create_placed_resource(&mut self, heap_type: d3d12::D3D12_HEAP_TYPE, desc: &d3d12::D3D12_RESOURCE_DESC, initial_state: d3d12::D3D12_RESOURCE_STATES, optimized_clear_value: *const d3d12::D3D12_CLEAR_VALUE) -> Result<PlacedResource, HeapAllocatorError> {
+
+        let info = unsafe { self.device.as_ref().unwrap().GetResourceAllocationInfo(0, 1, desc) };
+
+        let key = (heap_type, ResourceCategory::from_desc(desc));
+
+        // search the existing heaps first, then add one, large resources get a heap of their own
+        let existing = self.pools.get_mut(&key).and_then(|blocks| {
+            blocks.iter_mut().enumerate().find_map(|(i, block)| {
+                block.allocator.allocate(info.SizeInBytes, info.Alignment).ok().map(|allocation| (i, allocation))
+            })
+        });
+
+        let (block_index, allocation) = match existing {
+            Some(found) => found,
+            None => {
+                let size = info.SizeInBytes.max(info.Alignment).next_power_of_two().max(self.heap_size);
+
+                let mut block = self.create_block(key, size).map_err(HeapAllocatorError::Device)?;
+                let allocation = block.allocator.allocate(info.SizeInBytes, info.Alignment)?;
+
+                let blocks = self.pools.entry(key).or_insert_with(Vec::new);
+                blocks.push(block);
+
+                (blocks.len() - 1, allocation)
+            }
+        };
+
+        let block = &mut self.pools.get_mut(&key).unwrap()[block_index];
+
+        let mut resource = ptr::null_mut::<d3d12::ID3D12Resource>();
+
+        let result = unsafe {
+            self.device.as_ref().unwrap().
+            CreatePlacedResource(
+                block.heap,
+                allocation.offset,
+                desc,
+                initial_state,
+                optimized_clear_value,
+                &d3d12::ID3D12Resource::uuidof(),
+                lib::get_pointer_of_interface(&mut resource)
+            )
+        };
+
+        if result != winerror::S_OK {
+            block.allocator.free(allocation.offset)?;
+            return Err(HeapAllocatorError::Device(result));
+        }
+
+        Ok(PlacedResource {
+            resource: resource,
+            offset: allocation.offset,
+            size: info.SizeInBytes,
+            pool: key,
+            block: block_index,
+        })
+    }
+
+    // release the resource and return its range to the heap
+    pub fn free(&mut self, placed: PlacedResource) -> Result<(), AllocationError> {
+
+        let block = self.pools.get_mut(&placed.pool)
+            .and_then(|blocks| blocks.get_mut(placed.block))
+            .ok_or(AllocationError::InvalidFree(placed.offset))?;
+
+        block.allocator.free(placed.offset)?;
+
+        unsafe { placed.resource.as_ref().unwrap().Release() };
+
+        Ok(())
+    }
+
+    pub fn statistics(&self, heap_type: d3d12::D3D12_HEAP_TYPE) -> AllocatorStatistics {
+
+        let mut statistics = AllocatorStatistics::default();
+
+        for ((pool_heap_type, _), blocks) in self.pools.iter() {
+            if *pool_heap_type == heap_type {
+                for block in blocks {
+                    statistics.merge(&block.allocator.statistics());
+                }
+            }
+        }
+
+        statistics
+    }
+}
+
+impl Drop for HeapAllocator {
+    fn drop(&mut self) {
+        for block in self.pools.values().flatten() {
+            memory_tracker::untrack(block.heap);
+            unsafe { block.heap.as_ref().unwrap().Release() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    const KB: u64 = 1024;
+
+    #[test]
+    fn blocks_are_split_and_merged() {
+        let mut allocator = BuddyAllocator::new(256 * KB, 64 * KB);
+
+        let a = allocator.allocate(10 * KB, 64 * KB).unwrap();
+        let b = allocator.allocate(100 * KB, 64 * KB).unwrap();
+        let c = allocator.allocate(64 * KB, 64 * KB).unwrap();
+
+        assert_eq!(a, BuddyAllocation { offset: 0, size: 64 * KB });
+        assert_eq!(b, BuddyAllocation { offset: 128 * KB, size: 128 * KB });
+        assert_eq!(c, BuddyAllocation { offset: 64 * KB, size: 64 * KB });
+
+        assert_eq!(allocator.allocate(1, 64 * KB), Err(AllocationError::OutOfMemory { size: 1 }));
+
+        let statistics = allocator.statistics();
+        assert_eq!(statistics.allocated, 256 * KB);
+        assert_eq!(statistics.requested, 174 * KB);
+
+        allocator.free(a.offset).unwrap();
+        allocator.free(c.offset).unwrap();
+        allocator.free(b.offset).unwrap();
+
+        assert_eq!(allocator.statistics().largest_free_block, 256 * KB);
+        assert_eq!(allocator.statistics().free_block_count, 1);
+    }
+
+    #[test]
+    fn large_alignment_uses_a_larger_block() {
+        let mut allocator = BuddyAllocator::new(16 * MSAA_PLACEMENT_ALIGNMENT, DEFAULT_PLACEMENT_ALIGNMENT);
+
+        allocator.allocate(DEFAULT_PLACEMENT_ALIGNMENT, DEFAULT_PLACEMENT_ALIGNMENT).unwrap();
+        let msaa = allocator.allocate(DEFAULT_PLACEMENT_ALIGNMENT, MSAA_PLACEMENT_ALIGNMENT).unwrap();
+
+        assert_eq!(msaa.offset % MSAA_PLACEMENT_ALIGNMENT, 0);
+        assert_eq!(msaa.size, MSAA_PLACEMENT_ALIGNMENT);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let mut allocator = BuddyAllocator::new(256 * KB, 64 * KB);
+
+        assert_eq!(allocator.allocate(0, 64 * KB), Err(AllocationError::ZeroSize));
+        assert_eq!(allocator.allocate(1, 3), Err(AllocationError::InvalidAlignment(3)));
+        assert_eq!(allocator.allocate(512 * KB, 64 * KB), Err(AllocationError::TooLarge { size: 512 * KB, capacity: 256 * KB }));
+
+        let a = allocator.allocate(1, 64 * KB).unwrap();
+        allocator.free(a.offset).unwrap();
+
+        assert_eq!(allocator.free(a.offset), Err(AllocationError::InvalidFree(a.offset)));
+    }
+
+    #[test]
+    fn fragmentation_is_reported() {
+        let mut allocator = BuddyAllocator::new(256 * KB, 64 * KB);
+
+        let blocks = (0..4).map(|_| allocator.allocate(64 * KB, 64 * KB).unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(allocator.statistics().fragmentation(), 0.0);
+
+        // two free blocks which are not buddies
+        allocator.free(blocks[1].offset).unwrap();
+        allocator.free(blocks[2].offset).unwrap();
+
+        let statistics = allocator.statistics();
+        assert_eq!(statistics.free(), 128 * KB);
+        assert_eq!(statistics.largest_free_block, 64 * KB);
+        assert_eq!(statistics.fragmentation(), 0.5);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Allocate { size: u64, alignment: u64 },
+        Free(usize),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            (1..300 * KB, 0..4u32).prop_map(|(size, shift)| Operation::Allocate { size: size, alignment: (64 * KB) << shift }),
+            any::<usize>().prop_map(Operation::Free),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn allocations_never_overlap(operations in proptest::collection::vec(operation(), 1..200)) {
+            let capacity = 4 * 1024 * KB;
+            let mut allocator = BuddyAllocator::new(capacity, 64 * KB);
+            let mut live: Vec<(BuddyAllocation, u64)> = Vec::new();
+
+            for operation in operations {
+                match operation {
+                    Operation::Allocate { size, alignment } => {
+                        if let Ok(allocation) = allocator.allocate(size, alignment) {
+                            prop_assert_eq!(allocation.offset % alignment, 0);
+                            prop_assert!(allocation.size >= size);
+                            prop_assert!(allocation.offset + allocation.size <= capacity);
+
+                            for (other, _) in live.iter() {
+                                let disjoint = allocation.offset + allocation.size <= other.offset || other.offset + other.size <= allocation.offset;
+                                prop_assert!(disjoint);
+                            }
+
+                            live.push((allocation, size));
+                        }
+                    },
+                    Operation::Free(index) => {
+                        if !live.is_empty() {
+                            let (allocation, _) = live.swap_remove(index % live.len());
+                            prop_assert_eq!(allocator.free(allocation.offset), Ok(()));
+                        }
+                    },
+                }
+
+                let statistics = allocator.statistics();
+                prop_assert_eq!(statistics.allocated, live.iter().map(|(allocation, _)| allocation.size).sum::<u64>());
+                prop_assert_eq!(statistics.requested, live.iter().map(|(_, size)| size).sum::<u64>());
+                prop_assert!(statistics.largest_free_block <= statistics.free());
+            }
+
+            for (allocation, _) in live {
+                prop_assert_eq!(allocator.free(allocation.offset), Ok(()));
+            }
+
+            // everything merges back into one block
+            prop_assert!(allocator.is_empty());
+            prop_assert_eq!(allocator.statistics().largest_free_block, capacity);
+            prop_assert_eq!(allocator.statistics().free_block_count, 1);
+        }
+    }
+}
@@ -5,6 +5,10 @@ use winapi::{
         d3dcommon,
         d3dcompiler,
         unknwnbase,
+        synchapi,
+        handleapi,
+        winbase,
+        winver,
     },
     shared::{
         windef,
@@ -30,22 +34,82 @@ use std::str;
 use std::path;
 use std::ffi::CString;
 use std::env;
+use std::ops;
+use std::slice;
+use std::sync::OnceLock;
 use image::{ GenericImageView };
+use vertex_layout_derive::VertexLayout;
+
+pub const D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING: u32 = 0x1688;
 
 #[derive(Debug, Clone, Copy)]
+#[repr(C)]
 pub struct XMFLOAT3 {
     pub x: f32,
     pub y: f32,
     pub z: f32
 }
 #[derive(Debug, Clone, Copy)]
+#[repr(C)]
 pub struct XMFLOAT2 {
     pub x: f32,
     pub y: f32,
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, VertexLayout)]
+#[repr(C)]
 pub struct Vertex {
+    #[vertex(semantic = "POSITION")]
+    pub position: XMFLOAT3,
+    #[vertex(semantic = "TEXCOORD")]
+    pub uv: XMFLOAT2,
+}
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct XMFLOAT4 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+// 4x4 matrix, rows are stored contiguously
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct XMFLOAT4X4 {
+    pub m: [[f32; 4]; 4],
+}
+impl XMFLOAT4X4 {
+    pub fn identity() -> XMFLOAT4X4 {
+        XMFLOAT4X4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, VertexLayout)]
+#[repr(C)]
+pub struct NormalVertex {
+    #[vertex(semantic = "POSITION")]
     pub position: XMFLOAT3,
+    #[vertex(semantic = "NORMAL")]
+    pub normal: XMFLOAT3,
+    #[vertex(semantic = "TEXCOORD")]
+    pub uv: XMFLOAT2,
+}
+// tangent.w is the handedness, bitangent = tangent.w * cross(normal, tangent.xyz)
+#[derive(Debug, Clone, Copy, VertexLayout)]
+#[repr(C)]
+pub struct TangentVertex {
+    #[vertex(semantic = "POSITION")]
+    pub position: XMFLOAT3,
+    #[vertex(semantic = "NORMAL")]
+    pub normal: XMFLOAT3,
+    #[vertex(semantic = "TANGENT")]
+    pub tangent: XMFLOAT4,
+    #[vertex(semantic = "TEXCOORD")]
     pub uv: XMFLOAT2,
 }
 #[derive(Debug, Clone)]
@@ -82,6 +146,40 @@ pub struct CommittedResource {
     pub pOptimizedClearValue: *const d3d12::D3D12_CLEAR_VALUE
 }
 
+// index types which can be bound to the input assembler
+pub trait IndexFormat: PlainData {
+    const FORMAT: dxgiformat::DXGI_FORMAT;
+    // index values are 0..MAX_VERTEX_COUNT, the strip cut value is excluded
+    const MAX_VERTEX_COUNT: usize;
+
+    fn to_usize(self) -> usize;
+}
+
+impl IndexFormat for u16 {
+    const FORMAT: dxgiformat::DXGI_FORMAT = dxgiformat::DXGI_FORMAT_R16_UINT;
+    const MAX_VERTEX_COUNT: usize = 0xffff;
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl IndexFormat for u32 {
+    const FORMAT: dxgiformat::DXGI_FORMAT = dxgiformat::DXGI_FORMAT_R32_UINT;
+    const MAX_VERTEX_COUNT: usize = 0xffff_ffff;
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexBufferError {
+    Empty,
+    TooManyVertices { vertex_count: usize, max_vertex_count: usize },
+    IndexOutOfRange { position: usize, index: usize, vertex_count: usize },
+}
+
 // #[derive(Debug, Clone, Copy, Default)]
 pub struct BufferResources<T> {
     pub buffer_view: T,
@@ -181,6 +279,24 @@ pub fn get_adapter(dxgi_factory: *mut dxgi::IDXGIFactory) -> Result<*mut dxgi::I
     }
 }
 
+// adapter the device was created on, IDXGIAdapter3 reports the video memory budget
+pub fn get_device_adapter(dxgi_factory: *mut IDXGIFactory4, device: *mut d3d12::ID3D12Device) -> Result<*mut IDXGIAdapter3, winerror::HRESULT> {
+
+    let mut adapter = ptr::null_mut::<IDXGIAdapter3>();
+
+    let luid = unsafe { device.as_ref().unwrap().GetAdapterLuid() };
+
+    let result = unsafe {
+        dxgi_factory.as_ref().unwrap().
+        EnumAdapterByLuid(luid, &IDXGIAdapter3::uuidof(), get_pointer_of_interface(&mut adapter))
+    };
+
+    match result {
+        winerror::S_OK => Ok(adapter),
+        _ => Err(result)
+    }
+}
+
 pub fn create_command_allocator(device: *mut d3d12::ID3D12Device, type_: d3d12::D3D12_COMMAND_LIST_TYPE) -> Result<*mut d3d12::ID3D12CommandAllocator, winerror::HRESULT> {
 
     let mut obj = ptr::null_mut::<d3d12::ID3D12CommandAllocator>();
@@ -277,13 +393,28 @@ pub fn create_descriptor_heap(device: *mut d3d12::ID3D12Device, pDescriptorHeapD
     }
 }
 
-pub fn create_back_buffer(device: *mut d3d12::ID3D12Device, swapchain: *mut dxgi1_2::IDXGISwapChain1, swapchain_desc: dxgi1_2::DXGI_SWAP_CHAIN_DESC1, descriotor_heap: *mut d3d12::ID3D12DescriptorHeap, pDesc: *const d3d12::D3D12_RENDER_TARGET_VIEW_DESC) -> Vec<*mut d3d12::ID3D12Resource> {
+// block until the fence reaches `fence_value`
+pub fn wait_for_fence(fence: *mut d3d12::ID3D12Fence, fence_value: u64) {
+
+    if unsafe { fence.as_ref().unwrap().GetCompletedValue() } >= fence_value {
+        return;
+    }
+
+    let event = unsafe { synchapi::CreateEventW(ptr::null_mut(), 0, 0, ptr::null_mut()) };
+
+    unsafe { fence.as_ref().unwrap().SetEventOnCompletion(fence_value, event); };
+
+    unsafe { synchapi::WaitForSingleObject(event, winbase::INFINITE); };
+
+    unsafe { handleapi::CloseHandle(event); };
+}
+
+pub fn create_back_buffer(device: *mut d3d12::ID3D12Device, swapchain: *mut dxgi1_2::IDXGISwapChain1, swapchain_desc: dxgi1_2::DXGI_SWAP_CHAIN_DESC1, rtv_handles: &[d3d12::D3D12_CPU_DESCRIPTOR_HANDLE], pDesc: *const d3d12::D3D12_RENDER_TARGET_VIEW_DESC) -> Vec<*mut d3d12::ID3D12Resource> {
 
     // bind render target view heap to swap chain buffer
     let mut back_buffers = vec![std::ptr::null_mut::<d3d12::ID3D12Resource>(); swapchain_desc.BufferCount as usize];
 
-    let mut handle = unsafe { descriotor_heap.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart() };
-
+    assert_eq!(rtv_handles.len(), back_buffers.len(), "one render target view per back buffer is required");
 
     for i in 0..swapchain_desc.BufferCount {
         unsafe {
@@ -291,11 +422,7 @@ pub fn create_back_buffer(device: *mut d3d12::ID3D12Device, swapchain: *mut dxgi
         }
 
         unsafe {
-            device.as_ref().unwrap().CreateRenderTargetView(back_buffers[i as usize], pDesc, handle)
-        }
-
-        handle.ptr += unsafe {
-            device.as_ref().unwrap().GetDescriptorHandleIncrementSize(d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV) as usize
+            device.as_ref().unwrap().CreateRenderTargetView(back_buffers[i as usize], pDesc, rtv_handles[i as usize])
         }
     }
 
@@ -322,38 +449,180 @@ pub fn create_fence(device: *mut d3d12::ID3D12Device, InitialValue: i32, Flags:
     }
 }
 
-fn create_buffer_map<T>(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource, resource: Vec<T>) -> *mut d3d12::ID3D12Resource {
+// called with every resource create_committed_resource creates, lib.rs can't reach the binary's
+// memory_tracker so main.rs installs memory_tracker::track_resource here
+pub type ResourceHook = fn(&d3d12::ID3D12Resource, d3d12::D3D12_HEAP_TYPE);
 
-    let mut buffer = std::ptr::null_mut::<d3d12::ID3D12Resource>();
+static RESOURCE_HOOK: OnceLock<ResourceHook> = OnceLock::new();
 
-    let mut result = unsafe {
-                device.as_ref().unwrap().
-                CreateCommittedResource(
-                    comitted_resource.pHeapProperties,
-                    comitted_resource.HeapFlags,
-                    comitted_resource.pResourceDesc,
-                    comitted_resource.InitialResourceState,
-                    comitted_resource.pOptimizedClearValue,
-                    &d3d12::ID3D12Resource::uuidof(),
-                    get_pointer_of_interface(&mut buffer)
-            )
+// only the first hook is kept
+pub fn set_resource_hook(hook: ResourceHook) {
+    let _ = RESOURCE_HOOK.set(hook);
+}
+
+pub fn create_committed_resource(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource) -> Result<*mut d3d12::ID3D12Resource, winerror::HRESULT> {
+
+    let mut obj = ptr::null_mut::<d3d12::ID3D12Resource>();
+
+    let result = unsafe {
+        device.as_ref().unwrap().
+        CreateCommittedResource(
+            comitted_resource.pHeapProperties,
+            comitted_resource.HeapFlags,
+            comitted_resource.pResourceDesc,
+            comitted_resource.InitialResourceState,
+            comitted_resource.pOptimizedClearValue,
+            &d3d12::ID3D12Resource::uuidof(),
+            get_pointer_of_interface(&mut obj)
+        )
     };
 
+    match result {
+        winerror::S_OK => {
+            if let Some(hook) = RESOURCE_HOOK.get() {
+                hook(unsafe { obj.as_ref().unwrap() }, unsafe { (*comitted_resource.pHeapProperties).Type });
+            }
+
+            Ok(obj)
+        },
+        _ => Err(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapError {
+    // only buffers have a linear layout which can be viewed as a slice
+    NotABuffer,
+    // the requested elements do not fit in the resource width
+    OutOfBounds { offset: u64, size: u64, width: u64 },
+    Device(winerror::HRESULT),
+}
+
+// byte range of `count` elements from element `first` in a buffer of `width` bytes
+pub fn mapped_range<T>(first: usize, count: usize, width: u64) -> Result<d3d12::D3D12_RANGE, MapError> {
+
+    let offset = (first * mem::size_of::<T>()) as u64;
+    let size = (count * mem::size_of::<T>()) as u64;
+
+    if offset + size > width {
+        return Err(MapError::OutOfBounds { offset: offset, size: size, width: width });
+    }
+
+    Ok(d3d12::D3D12_RANGE { Begin: offset as usize, End: (offset + size) as usize })
+}
+
+// types every bit pattern is a valid value of, so they can be viewed in mapped GPU memory.
+// sealed, the types are listed in plain_data
+pub trait PlainData: Copy + plain_data::Sealed {}
+
+impl<T: Copy + plain_data::Sealed> PlainData for T {}
+
+mod plain_data {
+    use super::{ XMFLOAT2, XMFLOAT3, XMFLOAT4, XMFLOAT4X4, Vertex, NormalVertex, TangentVertex };
+
+    // no bool, enum, reference or pointer fields
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+    impl Sealed for i32 {}
+    impl Sealed for f32 {}
+    impl<T: Sealed, const N: usize> Sealed for [T; N] {}
+    impl Sealed for XMFLOAT2 {}
+    impl Sealed for XMFLOAT3 {}
+    impl Sealed for XMFLOAT4 {}
+    impl Sealed for XMFLOAT4X4 {}
+    impl Sealed for Vertex {}
+    impl Sealed for NormalVertex {}
+    impl Sealed for TangentVertex {}
+}
+
+// mapped elements of a buffer, unmapped on drop
+//
+// the resource is borrowed mutably so only one view of its memory exists at a time.
+// the range passed to Unmap is the whole view for writes and empty for reads
+pub struct Mapped<'a, T> {
+    resource: &'a d3d12::ID3D12Resource,
+    data: &'a mut [T],
+    written_range: d3d12::D3D12_RANGE,
+}
+
+impl<'a, T: PlainData> Mapped<'a, T> {
+    fn map(resource: &'a mut d3d12::ID3D12Resource, first: usize, count: usize, read: bool) -> Result<Mapped<'a, T>, MapError> {
+
+        let desc = unsafe { resource.GetDesc() };
+
+        if desc.Dimension != d3d12::D3D12_RESOURCE_DIMENSION_BUFFER {
+            return Err(MapError::NotABuffer);
+        }
+
+        let range = mapped_range::<T>(first, count, desc.Width)?;
+        let offset = range.Begin;
+        let empty_range = d3d12::D3D12_RANGE { Begin: 0, End: 0 };
+
+        // Map always returns the start of the subresource
+        let mut buffer_map = ptr::null_mut::<u8>();
+
+        let result = unsafe {
+            resource.Map(0, if read { &range } else { &empty_range }, get_pointer_of_interface(&mut buffer_map))
+        };
+
+        if result != winerror::S_OK {
+            return Err(MapError::Device(result));
+        }
+
+        let data = unsafe { slice::from_raw_parts_mut(buffer_map.offset(offset as isize).cast::<T>(), count) };
+
+        Ok(Mapped {
+            resource: resource,
+            data: data,
+            written_range: if read { empty_range } else { range },
+        })
+    }
+
+    // map `count` elements from element `first` for CPU writes
+    pub fn write(resource: &'a mut d3d12::ID3D12Resource, first: usize, count: usize) -> Result<Mapped<'a, T>, MapError> {
+        Mapped::map(resource, first, count, false)
+    }
+
+    // map `count` elements from element `first` of a readback buffer
+    pub fn read(resource: &'a mut d3d12::ID3D12Resource, first: usize, count: usize) -> Result<Mapped<'a, T>, MapError> {
+        Mapped::map(resource, first, count, true)
+    }
+}
+
+impl<'a, T> ops::Deref for Mapped<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.data
+    }
+}
+
+impl<'a, T> ops::DerefMut for Mapped<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.data
+    }
+}
+
+impl<'a, T> Drop for Mapped<'a, T> {
+    fn drop(&mut self) {
+        unsafe { self.resource.Unmap(0, &self.written_range) };
+    }
+}
+
+fn create_buffer_map<T: PlainData>(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource, resource: Vec<T>) -> *mut d3d12::ID3D12Resource {
+
+    let buffer = create_committed_resource(device, comitted_resource).unwrap();
+
     // buffer map
-    let mut buffer_map = std::ptr::null_mut::<T>();
+    let mut buffer_map = Mapped::<T>::write(unsafe { buffer.as_mut().unwrap() }, 0, resource.len()).unwrap();
 
-    // map buffer to GPU
-    result = unsafe {
-        buffer.as_ref().unwrap().
-        Map(0, std::ptr::null_mut(), get_pointer_of_interface(&mut buffer_map))
-    };
-    unsafe {
-        buffer_map.copy_from_nonoverlapping(resource.as_ptr().cast::<T>(), std::mem::size_of_val(&resource) * &resource.len() )
-    };
-    unsafe {
-        buffer.as_ref().unwrap().
-        Unmap(0, std::ptr::null_mut() )
-    };
+    buffer_map.copy_from_slice(&resource);
+
+    drop(buffer_map);
 
     buffer
 }
@@ -376,22 +645,59 @@ pub fn create_vertex_buffer_resources(device: *mut d3d12::ID3D12Device, comitted
     }
 }
 
-pub fn create_index_buffer_resources(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource, resource: Vec<u16>) -> BufferResources<d3d12::D3D12_INDEX_BUFFER_VIEW> {
+pub fn create_index_buffer_resources<T: IndexFormat>(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource, resource: Vec<T>, vertex_count: usize) -> Result<BufferResources<d3d12::D3D12_INDEX_BUFFER_VIEW>, IndexBufferError> {
 
-    let tmp_resource = resource.clone();
+    validate_indices(&resource, vertex_count)?;
+
+    let size_in_bytes = resource.len() * mem::size_of::<T>();
+
+    // index buffer has its own desc, caller's desc is used only for heap settings
+    let index_buffer_resource_desc = create_buffer_resource_desc(size_in_bytes as u64);
 
-    // reuse vertex buffer desc
-    let pResourceDesc = comitted_resource.pResourceDesc as *mut d3d12::D3D12_RESOURCE_DESC;
-    unsafe {
-        (*pResourceDesc).Width = (std::mem::size_of_val(&resource) * &resource.len()) as u64
+    let index_comitted_resource = CommittedResource {
+        pResourceDesc: &index_buffer_resource_desc,
+        ..comitted_resource
     };
 
-    let buffer = create_buffer_map(device, comitted_resource, resource);
+    let buffer = create_buffer_map(device, index_comitted_resource, resource);
 
     let buffer_view = d3d12::D3D12_INDEX_BUFFER_VIEW {
         BufferLocation : unsafe { buffer.as_ref().unwrap().GetGPUVirtualAddress() },
-        Format : dxgiformat::DXGI_FORMAT_R16_UINT,
-        SizeInBytes : (tmp_resource.len() * mem::size_of::<u16>()) as u32,
+        Format : T::FORMAT,
+        SizeInBytes : size_in_bytes as u32,
+    };
+
+    Ok(BufferResources {
+        buffer_view: buffer_view,
+        buffer_object: buffer
+    })
+}
+
+// upload any flat array (e.g. meshlet buffers) and describe it as a structured buffer
+pub fn create_structured_buffer_resources<T: PlainData>(device: *mut d3d12::ID3D12Device, comitted_resource: CommittedResource, resource: Vec<T>) -> BufferResources<d3d12::D3D12_SHADER_RESOURCE_VIEW_DESC> {
+
+    let element_count = resource.len();
+
+    let buffer_resource_desc = create_buffer_resource_desc((element_count * mem::size_of::<T>()) as u64);
+
+    let buffer_comitted_resource = CommittedResource {
+        pResourceDesc: &buffer_resource_desc,
+        ..comitted_resource
+    };
+
+    let buffer = create_buffer_map(device, buffer_comitted_resource, resource);
+
+    let mut buffer_view = d3d12::D3D12_SHADER_RESOURCE_VIEW_DESC {
+        Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+        ViewDimension: d3d12::D3D12_SRV_DIMENSION_BUFFER,
+        Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+        u: unsafe { mem::zeroed() },
+    };
+    * unsafe { buffer_view.u.Buffer_mut() } = d3d12::D3D12_BUFFER_SRV {
+        FirstElement: 0,
+        NumElements: element_count as u32,
+        StructureByteStride: mem::size_of::<T>() as u32,
+        Flags: d3d12::D3D12_BUFFER_SRV_FLAG_NONE,
     };
 
     BufferResources {
@@ -400,6 +706,45 @@ pub fn create_index_buffer_resources(device: *mut d3d12::ID3D12Device, comitted_
     }
 }
 
+// check every index refers to an existing vertex
+pub fn validate_indices<T: IndexFormat>(indices: &[T], vertex_count: usize) -> Result<(), IndexBufferError> {
+
+    if indices.is_empty() {
+        return Err(IndexBufferError::Empty);
+    }
+
+    if vertex_count > T::MAX_VERTEX_COUNT {
+        return Err(IndexBufferError::TooManyVertices { vertex_count: vertex_count, max_vertex_count: T::MAX_VERTEX_COUNT });
+    }
+
+    match indices.iter().position(|index| index.to_usize() >= vertex_count) {
+        Some(position) => Err(IndexBufferError::IndexOutOfRange {
+            position: position,
+            index: indices[position].to_usize(),
+            vertex_count: vertex_count,
+        }),
+        None => Ok(())
+    }
+}
+
+pub fn create_buffer_resource_desc(width: u64) -> d3d12::D3D12_RESOURCE_DESC {
+    d3d12::D3D12_RESOURCE_DESC {
+        Dimension : d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
+        Alignment: 0,
+        Width : width,
+        Height : 1,
+        DepthOrArraySize : 1,
+        MipLevels : 1,
+        Format : dxgiformat::DXGI_FORMAT_UNKNOWN,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count : 1,
+            Quality: 0,
+        },
+        Flags : d3d12::D3D12_RESOURCE_FLAG_NONE,
+        Layout : d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+    }
+}
+
 pub fn get_texture_data_from_file(path: &str) -> Image {
 
     let img = image::open(get_relative_file_path(path)).unwrap();
@@ -459,14 +804,24 @@ pub fn get_texture_data_from_file(path: &str) -> Image {
 }
 
 
-pub fn create_shader_resource(path: &str, pEntrypoint: &str, pTarget: &str, error_blob: *mut d3dcommon::ID3DBlob) -> Result<*mut d3dcommon::ID3DBlob, winerror::HRESULT> {
+// `defines` are NAME=value pairs like /D, e.g. the features of a shader permutation
+pub fn create_shader_resource(path: &str, pEntrypoint: &str, pTarget: &str, defines: &[(&str, &str)], error_blob: *mut d3dcommon::ID3DBlob) -> Result<*mut d3dcommon::ID3DBlob, winerror::HRESULT> {
 
     let mut shader_blob = std::ptr::null_mut::<d3dcommon::ID3DBlob>();
 
+    // the strings must outlive the call, the macro array ends with a null entry
+    let define_strings: Vec<(CString, CString)> = defines.iter()
+        .map(|(name, value)| (CString::new(*name).unwrap(), CString::new(*value).unwrap()))
+        .collect();
+    let mut shader_macros: Vec<d3dcommon::D3D_SHADER_MACRO> = define_strings.iter()
+        .map(|(name, value)| d3dcommon::D3D_SHADER_MACRO { Name: name.as_ptr(), Definition: value.as_ptr() })
+        .collect();
+    shader_macros.push(d3dcommon::D3D_SHADER_MACRO { Name: ptr::null(), Definition: ptr::null() });
+
     let result = unsafe {
         d3dcompiler::D3DCompileFromFile(
             path_to_wide_str(path).as_ptr() as *const u16,
-            std::ptr::null_mut(),
+            shader_macros.as_ptr(),
             d3dcompiler::D3D_COMPILE_STANDARD_FILE_INCLUDE,
             CString::new(pEntrypoint).unwrap().as_ptr(),
             CString::new(pTarget).unwrap().as_ptr(),
@@ -501,87 +856,128 @@ pub fn create_shader_resource(path: &str, pEntrypoint: &str, pTarget: &str, erro
     }
 }
 
-pub fn create_root_signature(device: *mut d3d12::ID3D12Device, error_blob: *mut d3dcommon::ID3DBlob) -> *mut d3d12::ID3D12RootSignature {
+// the fixed part of a version resource, VS_FIXEDFILEINFO up to the file version
+#[repr(C)]
+struct FixedFileInfo {
+    signature: u32,
+    struct_version: u32,
+    file_version_ms: u32,
+    file_version_ls: u32,
+}
 
-    let mut root_signature = std::ptr::null_mut::<d3d12::ID3D12RootSignature>();
+const FIXED_FILE_INFO_SIGNATURE: u32 = 0xFEEF04BD;
 
-    let mut root_signature_desc: d3d12::D3D12_ROOT_SIGNATURE_DESC = unsafe { mem::zeroed() };
-    root_signature_desc.Flags = d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT;
+// file version of a module such as "d3dcompiler_47.dll", found the way LoadLibrary would.
+// None if it has no version resource
+pub fn get_file_version(file_name: &str) -> Option<[u16; 4]> {
 
-    // texture settings
-    let descriptor_range = d3d12::D3D12_DESCRIPTOR_RANGE {
-        NumDescriptors: 1,
-        RangeType: d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
-        BaseShaderRegister: 0,
-        RegisterSpace: 0,
-        OffsetInDescriptorsFromTableStart: d3d12::D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
-    };
+    let wide_name = utf16_to_vec(file_name);
+    let mut handle = 0;
 
-    let mut root_param = d3d12::D3D12_ROOT_PARAMETER {
-        ParameterType: d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
-        ShaderVisibility: d3d12::D3D12_SHADER_VISIBILITY_PIXEL,
-        u: unsafe { mem::zeroed() },
-    };
-    * unsafe { root_param.u.DescriptorTable_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR_TABLE {
-        NumDescriptorRanges: 1,
-        pDescriptorRanges: &descriptor_range,
-    };
+    let size = unsafe { winver::GetFileVersionInfoSizeW(wide_name.as_ptr(), &mut handle) };
 
-    let sampler_desc = d3d12::D3D12_STATIC_SAMPLER_DESC {
-        AddressU: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-        AddressV: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-        AddressW: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-        BorderColor: d3d12::D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
-        Filter: d3d12::D3D12_FILTER_MIN_MAG_MIP_POINT,
-        MaxLOD: d3d12::D3D12_FLOAT32_MAX,
-        MinLOD: 0.0,
-        ComparisonFunc: d3d12::D3D12_COMPARISON_FUNC_NEVER,
-        ShaderVisibility: d3d12::D3D12_SHADER_VISIBILITY_PIXEL,
-        MipLODBias: 0.0,
-        MaxAnisotropy: 0,
-        ShaderRegister: 0,
-        RegisterSpace: 0
-    };
+    if size == 0 {
+        return None;
+    }
 
-    root_signature_desc.pParameters = &root_param;
-    root_signature_desc.NumParameters = 1;
+    let mut data = vec![0u8; size as usize];
 
-    root_signature_desc.pStaticSamplers = &sampler_desc;
-    root_signature_desc.NumStaticSamplers = 1;
-    // texture settings
+    if unsafe { winver::GetFileVersionInfoW(wide_name.as_ptr(), 0, size, data.as_mut_ptr().cast()) } == minwindef::FALSE {
+        return None;
+    }
 
+    let root = utf16_to_vec("\\");
+    let mut info = ptr::null_mut();
+    let mut length = 0;
 
-    // create root signature binary
-    let mut root_signature_blob = std::ptr::null_mut::<d3dcommon::ID3DBlob>();
+    if unsafe { winver::VerQueryValueW(data.as_ptr().cast(), root.as_ptr(), &mut info, &mut length) } == minwindef::FALSE {
+        return None;
+    }
 
-    let mut result = unsafe {
-            d3d12::D3D12SerializeRootSignature(
-                &root_signature_desc,
-                d3d12::D3D_ROOT_SIGNATURE_VERSION_1_0,
-                &mut root_signature_blob,
-                error_blob as *mut *mut d3dcommon::ID3D10Blob
-            )
-    };
+    if (length as usize) < mem::size_of::<FixedFileInfo>() {
+        return None;
+    }
 
-    result = unsafe {
-        device.as_ref().unwrap().
-        CreateRootSignature(
+    let info = unsafe { &*(info as *const FixedFileInfo) };
+
+    match info.signature {
+        FIXED_FILE_INFO_SIGNATURE => Some([
+            (info.file_version_ms >> 16) as u16,
+            info.file_version_ms as u16,
+            (info.file_version_ls >> 16) as u16,
+            info.file_version_ls as u16,
+        ]),
+        _ => None,
+    }
+}
+
+// compiles source text such as preprocessor output, no include handler is set.
+// returns the bytecode, or the compiler's messages on failure
+pub fn compile_shader(source: &str, source_name: &str, entry_point: &str, target: &str, flags: u32) -> Result<Vec<u8>, String> {
+
+    let mut shader_blob = ptr::null_mut::<d3dcommon::ID3DBlob>();
+    let mut error_blob = ptr::null_mut::<d3dcommon::ID3DBlob>();
+
+    let source_name = CString::new(source_name).unwrap();
+    let entry_point = CString::new(entry_point).unwrap();
+    let target = CString::new(target).unwrap();
+
+    let result = unsafe {
+        d3dcompiler::D3DCompile(
+            source.as_ptr() as minwindef::LPCVOID,
+            source.len(),
+            source_name.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            entry_point.as_ptr(),
+            target.as_ptr(),
+            flags,
             0,
-            root_signature_blob.as_ref().unwrap().GetBufferPointer(),
-            root_signature_blob.as_ref().unwrap().GetBufferSize(),
-            &d3d12::ID3D12RootSignature::uuidof(),
-            get_pointer_of_interface(&mut root_signature)
+            &mut shader_blob,
+            &mut error_blob
         )
     };
 
-    unsafe {
-        root_signature_blob.as_ref().unwrap().Release()
+    let messages = match error_blob.is_null() {
+        true => String::new(),
+        false => {
+            let messages = blob_to_string(error_blob);
+            unsafe { error_blob.as_ref().unwrap().Release(); }
+            messages
+        },
+    };
+
+    match result {
+        winerror::S_OK => {
+            let bytecode = unsafe {
+                slice::from_raw_parts(
+                    shader_blob.as_ref().unwrap().GetBufferPointer().cast::<u8>(),
+                    shader_blob.as_ref().unwrap().GetBufferSize()
+                ).to_vec()
+            };
+            unsafe { shader_blob.as_ref().unwrap().Release(); }
+
+            Ok(bytecode)
+        },
+        _ if messages.is_empty() => Err(format!("D3DCompile failed with {:#x}", result)),
+        _ => Err(messages),
+    }
+}
+
+// text of an error blob from the compiler or the root signature serializer
+pub fn blob_to_string(blob: *mut d3dcommon::ID3DBlob) -> String {
+
+    let bytes = unsafe {
+        slice::from_raw_parts(
+            blob.as_ref().unwrap().GetBufferPointer().cast::<u8>(),
+            blob.as_ref().unwrap().GetBufferSize()
+        )
     };
 
-    root_signature
+    String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
 }
 
-pub fn create_pipeline_state(device: *mut d3d12::ID3D12Device, gr_pipeline: d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC) -> *mut d3d12::ID3D12PipelineState {
+pub fn create_pipeline_state(device: *mut d3d12::ID3D12Device, gr_pipeline: d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC) -> Result<*mut d3d12::ID3D12PipelineState, winerror::HRESULT> {
 
     let mut pipeline_state = std::ptr::null_mut::<d3d12::ID3D12PipelineState>();
 
@@ -594,7 +990,10 @@ pub fn create_pipeline_state(device: *mut d3d12::ID3D12Device, gr_pipeline: d3d1
         )
     };
 
-    pipeline_state
+    match result {
+        winerror::S_OK => Ok(pipeline_state),
+        _ => Err(result)
+    }
 }
 
 pub fn set_viewport(width: i32, height: i32) -> d3d12::D3D12_VIEWPORT {
@@ -705,4 +1104,54 @@ mod tests {
     #[test]
     fn some_test() {
     }
+
+    #[test]
+    fn vertex_input_layout_matches_struct() {
+        let input_element = Vertex::input_layout();
+
+        assert_eq!(input_element.len(), 2);
+
+        let semantic = unsafe { std::ffi::CStr::from_ptr(input_element[0].SemanticName) };
+        assert_eq!(semantic.to_str().unwrap(), "POSITION");
+        assert_eq!(input_element[0].Format, dxgiformat::DXGI_FORMAT_R32G32B32_FLOAT);
+        assert_eq!(input_element[0].AlignedByteOffset, 0);
+
+        let semantic = unsafe { std::ffi::CStr::from_ptr(input_element[1].SemanticName) };
+        assert_eq!(semantic.to_str().unwrap(), "TEXCOORD");
+        assert_eq!(input_element[1].Format, dxgiformat::DXGI_FORMAT_R32G32_FLOAT);
+        assert_eq!(input_element[1].AlignedByteOffset, mem::size_of::<XMFLOAT3>() as u32);
+        assert_eq!(input_element[1].InputSlot, 0);
+    }
+
+    #[test]
+    fn validate_indices_checks_range_and_format() {
+        assert_eq!(validate_indices(&[0u16, 1, 2, 2, 1, 3], 4), Ok(()));
+        assert_eq!(validate_indices(&[0u32, 1, 70000], 70001), Ok(()));
+
+        assert_eq!(
+            validate_indices(&[0u16, 1, 4], 4),
+            Err(IndexBufferError::IndexOutOfRange { position: 2, index: 4, vertex_count: 4 })
+        );
+        assert_eq!(
+            validate_indices(&[0u16, 1, 2], 70000),
+            Err(IndexBufferError::TooManyVertices { vertex_count: 70000, max_vertex_count: 0xffff })
+        );
+        assert_eq!(validate_indices::<u32>(&[], 3), Err(IndexBufferError::Empty));
+
+        assert_eq!(<u16 as IndexFormat>::FORMAT, dxgiformat::DXGI_FORMAT_R16_UINT);
+        assert_eq!(<u32 as IndexFormat>::FORMAT, dxgiformat::DXGI_FORMAT_R32_UINT);
+    }
+
+    #[test]
+    fn mapped_range_is_bounded_by_buffer_width() {
+        let width = (mem::size_of::<Vertex>() * 4) as u64;
+
+        let range = mapped_range::<Vertex>(1, 3, width).unwrap();
+        assert_eq!((range.Begin, range.End), (mem::size_of::<Vertex>(), mem::size_of::<Vertex>() * 4));
+
+        assert_eq!(
+            mapped_range::<Vertex>(2, 3, width).map(|range| range.End),
+            Err(MapError::OutOfBounds { offset: 40, size: 60, width: 80 })
+        );
+    }
 }
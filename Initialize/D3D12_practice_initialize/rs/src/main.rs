@@ -1,41 +1,50 @@
 use winapi::{
     um::{
-        winuser::{ GetMessageW, TranslateMessage, DispatchMessageW },
+        winuser::{ GetMessageW, TranslateMessage, DispatchMessageW, PostMessageW, WM_NULL },
         d3d12::*,
         d3dcommon::*,
+        d3dcompiler::{ D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION },
         winbase::{ INFINITE },
         synchapi::{ CreateEventW, WaitForSingleObject },
         handleapi::{ CloseHandle },
     },
     shared::{
         minwindef::{ FLOAT },
+        windef::{ HWND },
         dxgi::*,
         dxgi1_2::*,
         dxgi1_3::*,
+        dxgi1_4::*,
         dxgi1_5::*,
         dxgi1_6::*,
         dxgiformat::*,
         dxgitype::*,
     },
-    ctypes,
 };
 
 use std::ptr;
 use std::mem;
-use std::ffi::CString;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub mod lib;
 pub mod win;
+pub mod mesh;
+pub mod upload_ring;
+pub mod descriptor;
+pub mod resource_state;
+pub mod gpu_allocator;
+pub mod constant_buffer;
+pub mod deletion_queue;
+pub mod readback;
+pub mod pipeline;
+pub mod root_signature;
+pub mod shader;
+pub mod memory_tracker;
 
 const WINDOW_WIDTH: i32 = 1280;
 const WINDOW_HEIGHT: i32 = 720;
 const DEBUG: bool = true;
-const D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING: u32 = 0x1688;
-
-enum BOOL {
-    FALSE = 0,
-    TRUE = 1,
-}
 
 fn main() {
     let class_name = lib::utf16_to_vec("DX12Sample");
@@ -48,9 +57,6 @@ fn main() {
         return;
     }
 
-    // return value 0(HRESULT->S_OK) is ok
-    let mut result = -1;
-
 
     let mut dxgi_factory = ptr::null_mut();
     let mut swapchain = ptr::null_mut(); // IDXGISwapChain4
@@ -68,6 +74,24 @@ fn main() {
     // device
     let d3d12_device = lib::create_d3d12_device().unwrap();
 
+    // every committed resource the lib helpers create shows up in the memory report
+    lib::set_resource_hook(memory_tracker::track_resource);
+
+    // video memory budget, polled every frame
+    let video_memory_adapter = match dxgi_factory.is_null() {
+        true => None,
+        false => lib::get_device_adapter(dxgi_factory.cast::<IDXGIFactory4>(), d3d12_device).ok(),
+    };
+
+    for &threshold in [0.8, 0.95].iter() {
+        memory_tracker::memory_tracker().add_threshold(threshold, Arc::new(|event| {
+            println!(
+                "video memory usage went {:?} {:.0}% of the budget ({} / {} bytes)",
+                event.crossing, event.threshold * 100.0, event.budget.usage, event.budget.budget
+            );
+        }));
+    }
+
     // create command list, allocator
     let cmd_allocator = lib::create_command_allocator(d3d12_device, D3D12_COMMAND_LIST_TYPE_DIRECT).unwrap();
     let cmd_list = lib::create_command_list(d3d12_device, 0, D3D12_COMMAND_LIST_TYPE_DIRECT, cmd_allocator, ptr::null_mut()).unwrap();
@@ -113,14 +137,9 @@ fn main() {
     // create Render Target View //
 
     // create discriptor heap
-    let heap_desc = D3D12_DESCRIPTOR_HEAP_DESC {
-        Type : D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
-        NodeMask : 0,
-        NumDescriptors : 2,
-        Flags : D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
-    };
+    let mut rtv_descriptors = descriptor::DescriptorAllocator::<descriptor::Rtv>::new(d3d12_device, 16).unwrap();
 
-    let rtv_heaps = lib::create_descriptor_heap(d3d12_device, &heap_desc).unwrap();
+    let rtv_range = rtv_descriptors.allocate(swapchain_desc1.BufferCount).unwrap();
 
     // SRGB render target view
 	let rtv_desc = D3D12_RENDER_TARGET_VIEW_DESC {
@@ -130,7 +149,16 @@ fn main() {
     };
 
     // bind render target view heap to swap chain buffer
-    let back_buffers = lib::create_back_buffer(d3d12_device, swapchain, swapchain_desc1, rtv_heaps, &rtv_desc);
+    let back_buffers = lib::create_back_buffer(d3d12_device, swapchain, swapchain_desc1, &rtv_range.cpu_handles(), &rtv_desc);
+
+    // resource states are tracked across command lists, barriers are generated from the required states
+    let mut resource_states = resource_state::ResourceStateTracker::new();
+
+    for back_buffer in back_buffers.iter() {
+        resource_states.register(*back_buffer, 1, D3D12_RESOURCE_STATE_PRESENT).unwrap();
+    }
+
+    let fixup_cmd_list = resource_state::FixupCommandList::new(d3d12_device).unwrap();
 
     // create vertices
     let vertices  = vec![
@@ -152,6 +180,17 @@ fn main() {
         }
     ];
 
+    // create indices
+    let indices: Vec<u32> = vec![
+        0, 1, 2,
+        2, 1, 3
+    ];
+
+    // optimize mesh before creating buffers
+    let (optimized_mesh, _) = mesh::optimizer::optimize_mesh(&mesh::Mesh::new(vertices, indices), &mesh::optimizer::OptimizeOptions::default());
+    let vertices = optimized_mesh.vertices;
+    let indices = optimized_mesh.indices;
+
     // create vertex buffer
 
     // settings of vertex heap
@@ -188,108 +227,100 @@ fn main() {
         pOptimizedClearValue: std::ptr::null_mut(),
     };
 
-    // create indices
-    let indices = vec![
-        0, 1, 2,
-        2, 1, 3
-    ];
-
     // create vertex resources
     let vertex_buffer = lib::create_vertex_buffer_resources(d3d12_device, comitted_resource, vertices.clone());
 
-    let index_buffer = lib::create_index_buffer_resources(d3d12_device, comitted_resource, indices.clone());
-
-    // create shader object
-    let shader_error_blob = std::ptr::null_mut::<ID3DBlob>();
-    let vertex_shader_blob = lib::create_shader_resource("shaders\\VertexShader.hlsl", "BasicVS", "vs_5_0", shader_error_blob).unwrap();
-    let pixel_shader_blob = lib::create_shader_resource("shaders\\PixelShader.hlsl", "BasicPS", "ps_5_0", shader_error_blob).unwrap();
-
-    // vertex layout
-    let input_element: [D3D12_INPUT_ELEMENT_DESC; 2] = [
-        D3D12_INPUT_ELEMENT_DESC {
-            SemanticName: CString::new("POSITION").unwrap().into_raw(),
-            SemanticIndex: 0,
-            Format: DXGI_FORMAT_R32G32B32_FLOAT,
-            InputSlot: 0,
-            AlignedByteOffset: D3D12_APPEND_ALIGNED_ELEMENT,
-            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
-            InstanceDataStepRate: 0,
-        },
-        D3D12_INPUT_ELEMENT_DESC {
-            SemanticName: CString::new("TEXCOORD").unwrap().into_raw(),
-            SemanticIndex: 0,
-            Format: DXGI_FORMAT_R32G32_FLOAT,
-            InputSlot: 0,
-            AlignedByteOffset: D3D12_APPEND_ALIGNED_ELEMENT,
-            InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
-            InstanceDataStepRate: 0,
-        },
-    ];
-
-    // create root signature
-    let root_signature = lib::create_root_signature(d3d12_device, shader_error_blob);
-
-    // create graphics pipeline
-    let mut gr_pipeline: D3D12_GRAPHICS_PIPELINE_STATE_DESC = unsafe { mem::zeroed() };
-
-    // set shader
-    gr_pipeline.pRootSignature = root_signature;
-    gr_pipeline.VS.pShaderBytecode = unsafe { vertex_shader_blob.as_ref().unwrap().GetBufferPointer() };
-    gr_pipeline.VS.BytecodeLength = unsafe { vertex_shader_blob.as_ref().unwrap().GetBufferSize() };
-    gr_pipeline.PS.pShaderBytecode = unsafe { pixel_shader_blob.as_ref().unwrap().GetBufferPointer() };
-    gr_pipeline.PS.BytecodeLength = unsafe { pixel_shader_blob.as_ref().unwrap().GetBufferSize() };
-
-    // sample mask
-    gr_pipeline.SampleMask = D3D12_DEFAULT_SAMPLE_MASK;
-
-    // culling, filling
-    gr_pipeline.RasterizerState.CullMode = D3D12_CULL_MODE_NONE;
-    gr_pipeline.RasterizerState.FillMode = D3D12_FILL_MODE_SOLID;
-    gr_pipeline.RasterizerState.DepthClipEnable = BOOL::TRUE as i32;
-    gr_pipeline.RasterizerState.FrontCounterClockwise = BOOL::FALSE as i32;
-	gr_pipeline.RasterizerState.DepthBias = D3D12_DEFAULT_DEPTH_BIAS as i32;
-	gr_pipeline.RasterizerState.DepthBiasClamp = D3D12_DEFAULT_DEPTH_BIAS_CLAMP;
-	gr_pipeline.RasterizerState.SlopeScaledDepthBias = D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS;
-	gr_pipeline.RasterizerState.AntialiasedLineEnable = BOOL::FALSE as i32;
-	gr_pipeline.RasterizerState.ForcedSampleCount = 0;
-    gr_pipeline.RasterizerState.ConservativeRaster = D3D12_CONSERVATIVE_RASTERIZATION_MODE_OFF;
-
-    gr_pipeline.DepthStencilState.DepthEnable = BOOL::FALSE as i32;
-	gr_pipeline.DepthStencilState.StencilEnable = BOOL::FALSE as i32;
-
-    // blend mode
-    gr_pipeline.BlendState.AlphaToCoverageEnable = BOOL::FALSE as i32;
-    gr_pipeline.BlendState.IndependentBlendEnable = BOOL::FALSE as i32;
-
-    // render target blend settings
-    let mut render_target_blend_desc: D3D12_RENDER_TARGET_BLEND_DESC = unsafe { mem::zeroed() };
-    render_target_blend_desc.BlendEnable = BOOL::FALSE as i32;
-    render_target_blend_desc.LogicOpEnable = BOOL::FALSE as i32;
-    render_target_blend_desc.RenderTargetWriteMask = D3D12_COLOR_WRITE_ENABLE_ALL as u8;
-
-    gr_pipeline.BlendState.RenderTarget[0] = render_target_blend_desc;
+    let index_buffer = lib::create_index_buffer_resources(d3d12_device, comitted_resource, indices.clone(), vertices.len()).unwrap();
 
-    // bind input layout
-    gr_pipeline.InputLayout.pInputElementDescs = &input_element as *const _;
-    gr_pipeline.InputLayout.NumElements = input_element.len() as u32;
+    memory_tracker::set_debug_name(unsafe { vertex_buffer.buffer_object.as_ref().unwrap() }, "vertex buffer");
+    memory_tracker::set_debug_name(unsafe { index_buffer.buffer_object.as_ref().unwrap() }, "index buffer");
 
-    // way to express triangle
-    gr_pipeline.IBStripCutValue = D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_DISABLED;
+    // compile shaders through the on-disk cache, only shaders whose preprocessed source changed are compiled
+    let mut shader_cache = shader::cache::ShaderCache::new("shader_cache").unwrap();
+    shader_cache.verify().unwrap();
+    let shader_flags = D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION;
 
-    // primitive topology setting
-    gr_pipeline.PrimitiveTopologyType = D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE;
+    // shader model 6 and later compile to DXIL with dxc from PATH
+    let shader_model = shader::compiler::ShaderModel::new(5, 0);
+    let shader_compiler: Box<dyn shader::compiler::ShaderCompiler> = match shader_model.is_dxil() {
+        true => Box::new(shader::compiler::Dxc::new()),
+        false => Box::new(shader::compiler::Fxc),
+    };
+    let vertex_target = shader::compiler::profile(shader::Stage::Vertex, shader_model).unwrap();
+    let pixel_target = shader::compiler::profile(shader::Stage::Pixel, shader_model).unwrap();
+
+    let shader_preprocessor = shader::preprocessor::Preprocessor::new();
+
+    // every valid combination of the pixel shader's features, compiled in parallel
+    let pixel_features = shader::permutation::PermutationSet::new()
+        .feature("USE_TEXTURE")
+        .feature("ALPHA_TEST")
+        .requires("ALPHA_TEST", "USE_TEXTURE");
+    let shader_threads = std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    let pixel_permutations = shader::permutation::compile(
+        &pixel_features,
+        &shader::ShaderSource::new("shaders\\PixelShader.hlsl", "BasicPS", &pixel_target, shader_flags),
+        &shader_preprocessor,
+        &mut shader_cache,
+        &*shader_compiler,
+        shader_threads
+    ).unwrap_or_else(|error| panic!("{}", error));
+    let pixel_key = pixel_features.key(&["USE_TEXTURE"]).unwrap();
+
+    // and recompile the ones in use in the background when the shaders or their includes change
+    let mut shader_watch = shader::hot_reload::ShaderWatch::new(shader_cache, shader_preprocessor, shader_compiler);
+    shader_watch.watch_directory("shaders");
+    let vertex_shader = shader_watch.add(shader::ShaderSource::new("shaders\\VertexShader.hlsl", "BasicVS", &vertex_target, shader_flags))
+        .unwrap_or_else(|error| panic!("{}", error));
+    let pixel_shader = shader_watch.add(pixel_permutations.source(pixel_key).unwrap().clone())
+        .unwrap_or_else(|error| panic!("{}", error));
+    println!("shader cache: {}", shader_watch.cache_stats());
+
+    // GetMessageW blocks, so the watching thread posts a message to render a frame with the new shaders
+    let hwnd_address = hwnd as usize;
+    let mut shader_reloader = shader_watch.start(Duration::from_millis(250), move || {
+        unsafe { PostMessageW(hwnd_address as HWND, WM_NULL, 0, 0); };
+    });
+
+    // vertex layout, checked against the inputs of the vertex shader
+    let input_element = lib::Vertex::input_layout();
+    let vertex_inputs = shader::input_signature::parse_file("shaders\\VertexShader.hlsl", "BasicVS").unwrap();
+    if let Err(error) = shader::input_signature::validate(&vertex_inputs, &input_element) {
+        panic!("vertex layout doesn't match BasicVS: {}", error);
+    }
 
-    // render target settings
-    gr_pipeline.NumRenderTargets = 1;
-    gr_pipeline.RTVFormats[0] = DXGI_FORMAT_R8G8B8A8_UNORM_SRGB;
+    // create root signature, written next to the shaders as an HLSL string
+    let root_signature_desc = root_signature::hlsl::load("shaders\\ShaderHeader.hlsli", "BasicRS").unwrap();
+    let root_signature = root_signature_desc.to_builder().build(d3d12_device).unwrap();
 
-    // anti aliasing
-    gr_pipeline.RasterizerState.MultisampleEnable = BOOL::FALSE as i32;
-    gr_pipeline.SampleDesc.Count = 1;
-    gr_pipeline.SampleDesc.Quality = 0;
+    // check it against the resources the shaders declare
+    let shader_resources = [
+        shader::resources::StageResources::scan("shaders\\VertexShader.hlsl", shader::Stage::Vertex).unwrap(),
+        shader::resources::StageResources::scan("shaders\\PixelShader.hlsl", shader::Stage::Pixel).unwrap(),
+    ];
+    let binding_issues = shader::resources::check(&shader_resources).into_iter()
+        .chain(shader::resources::check_root_signature(&root_signature_desc, &shader_resources));
+    for issue in binding_issues {
+        println!("shader binding: {}", issue);
+    }
 
-    // create grahphics pipeline state object
-    let pipeline_state = lib::create_pipeline_state(d3d12_device, gr_pipeline);
+    // one copy of the constants per back buffer
+    let mut scene_constants = constant_buffer::ConstantBuffer::<constant_buffer::SceneConstants>::new(d3d12_device, swapchain_desc1.BufferCount).unwrap();
+
+    // create graphics pipeline, rebuilt when its shaders are recompiled
+    let mut pipeline_state = shader::hot_reload::ReloadablePipeline::new(&shader_reloader, &[vertex_shader, pixel_shader], move |bytecode: &[&[u8]]| {
+        pipeline::GraphicsPipelineBuilder::new()
+            .root_signature(root_signature)
+            .vertex_shader(pipeline::bytecode(bytecode[0]))
+            .pixel_shader(pipeline::bytecode(bytecode[1]))
+            .input_layout(&input_element)
+            .primitive_topology(D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE)
+            .render_target_formats(&[DXGI_FORMAT_R8G8B8A8_UNORM_SRGB])
+            .cull_mode(D3D12_CULL_MODE_NONE)
+            .disable_depth()
+            .build(d3d12_device)
+            .map_err(|error| format!("{:?}", error))
+    }).unwrap();
 
     // viewport setting
     let viewport = lib::set_viewport(WINDOW_WIDTH, WINDOW_HEIGHT);
@@ -298,7 +329,7 @@ fn main() {
     let scissor_rect = lib::set_scissor_rect(WINDOW_WIDTH, WINDOW_HEIGHT);
 
     // create intermediate texture buffer for uploade resource
-    let mut texture = lib::get_texture_data_from_file("assets\\images\\ultimate.png");
+    let texture = lib::get_texture_data_from_file("assets\\images\\ultimate.png");
 
     let texture_buffer_heap_prop = D3D12_HEAP_PROPERTIES {
         Type : D3D12_HEAP_TYPE_UPLOAD,
@@ -325,30 +356,17 @@ fn main() {
         Layout : D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
     };
 
-    let mut intermediate_buffer = std::ptr::null_mut::<ID3D12Resource>();
+    let intermediate_buffer = lib::create_committed_resource(d3d12_device, lib::CommittedResource {
+        pHeapProperties: &texture_buffer_heap_prop,
+        HeapFlags: D3D12_HEAP_FLAG_NONE,
+        pResourceDesc: &texture_buffer_resource_desc,
+        InitialResourceState: D3D12_RESOURCE_STATE_GENERIC_READ,
+        pOptimizedClearValue: std::ptr::null_mut(),
+    }).unwrap();
 
-    result = unsafe {
-        d3d12_device.as_ref().unwrap().
-        CreateCommittedResource(
-                &texture_buffer_heap_prop,
-                D3D12_HEAP_FLAG_NONE,
-                &texture_buffer_resource_desc,
-                D3D12_RESOURCE_STATE_GENERIC_READ,
-                std::ptr::null_mut(),
-                &IID_ID3D12Resource,
-                &mut intermediate_buffer as *mut *mut ID3D12Resource as *mut *mut ctypes::c_void
-        )
-    };
+    memory_tracker::set_debug_name(unsafe { intermediate_buffer.as_ref().unwrap() }, "texture upload buffer");
 
     // create buffer for copy source to destination
-    let texture_buffer_destination_prop = D3D12_HEAP_PROPERTIES {
-        Type : D3D12_HEAP_TYPE_DEFAULT,
-        CPUPageProperty : D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
-        MemoryPoolPreference : D3D12_MEMORY_POOL_UNKNOWN,
-        CreationNodeMask: 0,
-        VisibleNodeMask: 0,
-    };
-
     texture_buffer_resource_desc.Format = texture.format;
     texture_buffer_resource_desc.Width = texture.width as u64;
     texture_buffer_resource_desc.Height = texture.height;
@@ -357,48 +375,31 @@ fn main() {
     texture_buffer_resource_desc.Dimension = D3D12_RESOURCE_DIMENSION_TEXTURE2D;
     texture_buffer_resource_desc.Layout = D3D12_TEXTURE_LAYOUT_UNKNOWN;
 
-    let mut texture_buffer = std::ptr::null_mut::<ID3D12Resource>();
+    // textures are placed in heaps reserved by the allocator
+    let mut heap_allocator = gpu_allocator::HeapAllocator::new(d3d12_device, gpu_allocator::DEFAULT_HEAP_SIZE);
 
-    result = unsafe {
-        d3d12_device.as_ref().unwrap().
-        CreateCommittedResource(
-            &texture_buffer_destination_prop,
-            D3D12_HEAP_FLAG_NONE,
-            &texture_buffer_resource_desc,
-            D3D12_RESOURCE_STATE_COPY_DEST,
-            std::ptr::null_mut(),
-            &IID_ID3D12Resource,
-            &mut texture_buffer as *mut *mut ID3D12Resource as *mut *mut ctypes::c_void
-        )
-    };
+    let texture_placed = heap_allocator.create_placed_resource(
+        D3D12_HEAP_TYPE_DEFAULT,
+        &texture_buffer_resource_desc,
+        D3D12_RESOURCE_STATE_COPY_DEST,
+        std::ptr::null(),
+    ).unwrap();
 
-    // buffer map
-    let mut buffer_map = std::ptr::null_mut::<u8>();
+    let texture_buffer = texture_placed.resource;
 
-    // map buffer to GPU
-    result = unsafe {
-        intermediate_buffer.as_ref().unwrap().
-        Map(0, std::ptr::null_mut(), lib::get_pointer_of_interface(&mut buffer_map))
-    };
-
-
-    unsafe {
-        let mut tmp_pointer = texture.raw_pointer.as_mut_ptr().cast::<u8>();
-
-        for _ in 0..texture.height {
+    resource_states.register(texture_buffer, 1, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
 
-            buffer_map.copy_from_nonoverlapping(tmp_pointer, texture.alignmented_row_pitch as usize);
+    // copy each row into the 256 byte aligned rows of the upload buffer
+    {
+        let row_pitch = texture.row_pitch;
+        let alignmented_row_pitch = texture.alignmented_row_pitch as usize;
 
-            tmp_pointer = tmp_pointer.offset(texture.row_pitch as isize);
+        let mut buffer_map = lib::Mapped::<u8>::write(unsafe { intermediate_buffer.as_mut().unwrap() }, 0, texture.alignmented_slice_pitch as usize).unwrap();
 
-            buffer_map = buffer_map.offset(texture.alignmented_row_pitch as isize);
+        for (destination, source) in buffer_map.chunks_mut(alignmented_row_pitch).zip(texture.raw_pointer.chunks(row_pitch)) {
+            destination[..row_pitch].copy_from_slice(source);
         }
-    };
-
-    unsafe {
-        intermediate_buffer.as_ref().unwrap().
-        Unmap(0, std::ptr::null_mut() )
-    };
+    }
 
     // copy source description
     let mut copy_src = D3D12_TEXTURE_COPY_LOCATION {
@@ -431,45 +432,38 @@ fn main() {
     // create fence
     let fence = lib::create_fence(d3d12_device, current_frame as i32, D3D12_FENCE_FLAG_NONE).unwrap();
 
+    // GPU objects are released once the fence passes the last submission using them
+    let mut deletion_queue = deletion_queue::ResourceDeletionQueue::new();
+
     {
 
         current_frame += 1;
 
+        let mut cmd_list_states = resource_state::CommandListStates::new();
+
+        resource_states.require(&mut cmd_list_states, texture_buffer, resource_state::Subresource::All, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+        cmd_list_states.record(cmd_list);
+
         unsafe {
             cmd_list.as_ref().unwrap().CopyTextureRegion(&copy_dest, 0, 0, 0, &copy_src, std::ptr::null_mut())
         };
 
-
-        let mut texture_barrier_desc = D3D12_RESOURCE_BARRIER {
-            Type : D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-            Flags : D3D12_RESOURCE_BARRIER_FLAG_NONE,
-            u: unsafe { mem::zeroed() },
-        };
-        * unsafe { texture_barrier_desc.u.Transition_mut() } = D3D12_RESOURCE_TRANSITION_BARRIER {
-            pResource : texture_buffer,
-            Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-            StateBefore: D3D12_RESOURCE_STATE_COPY_DEST,
-            StateAfter: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
-        };
+        resource_states.require(&mut cmd_list_states, texture_buffer, resource_state::Subresource::All, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE).unwrap();
+        cmd_list_states.record(cmd_list);
 
-        unsafe {
-            cmd_list.as_ref().unwrap().ResourceBarrier(1, &texture_barrier_desc);
-        };
         unsafe {
             cmd_list.as_ref().unwrap().Close();
         };
 
-
-        let cmd_list_arrays = [ cmd_list.cast::<ID3D12CommandList>() ];
-
-        unsafe {
-            cmd_queue.as_ref().unwrap().ExecuteCommandLists(1, &cmd_list_arrays[0]);
-        };
+        fixup_cmd_list.execute(cmd_queue, &mut resource_states, cmd_list_states, cmd_list).unwrap();
 
         unsafe {
             cmd_queue.as_ref().unwrap().Signal(fence, current_frame);
         };
 
+        // the upload buffer is only needed by this copy
+        deletion_queue.retire(intermediate_buffer, current_frame);
+
         if unsafe { fence.as_ref().unwrap().GetCompletedValue() } != current_frame {
 
             let event = unsafe { CreateEventW(ptr::null_mut(), 0, 0, ptr::null_mut()) };
@@ -494,19 +488,17 @@ fn main() {
     };
 
     // cbv, srv, uav desctriptor heap
-    let texture_view_heap_desc = D3D12_DESCRIPTOR_HEAP_DESC {
-        Flags: D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
-        NodeMask: 0,
-        NumDescriptors: 1,
-        Type: D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
-    };
+    // views are created in the staging heap and copied to the shader visible heap every frame
+    let mut cbv_srv_uav_descriptors = descriptor::DescriptorAllocator::<descriptor::CbvSrvUav>::new(d3d12_device, 256).unwrap();
+
+    let mut shader_visible_descriptors = descriptor::ShaderVisibleDescriptorRing::<descriptor::CbvSrvUav>::new(d3d12_device, 64, swapchain_desc1.BufferCount).unwrap();
 
-    let mut texture_desc_heap = lib::create_descriptor_heap(d3d12_device, &texture_view_heap_desc).unwrap();
+    let texture_view = cbv_srv_uav_descriptors.allocate(1).unwrap();
 
 	// shader resource view
     let mut shader_resource_view_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
         Format: texture.format,
-        Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+        Shader4ComponentMapping: lib::D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
         ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
         u: unsafe { mem::zeroed() },
     };
@@ -517,7 +509,7 @@ fn main() {
         CreateShaderResourceView(
             texture_buffer,
             &shader_resource_view_desc,
-            texture_desc_heap.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart()
+            texture_view.cpu_handle(0)
         )
     };
 
@@ -531,6 +523,14 @@ fn main() {
     loop {
         // quit loop
         if win::quit_window(&mut msg) {
+            // wait for the GPU and release what is still queued
+            if let Some(adapter) = video_memory_adapter {
+                deletion_queue.retire(adapter, current_frame);
+            }
+            deletion_queue.flush(fence);
+
+            println!("{}", memory_tracker::memory_tracker().report());
+
             // report leak
             lib::report_live_objects(d3d12_device, DEBUG);
 
@@ -543,31 +543,33 @@ fn main() {
         // increment frame
         current_frame += 1;
 
+        // swap in pipelines whose shaders were recompiled, on failure the previous one stays
+        let shader_updates = shader_reloader.poll();
+        for update in shader_updates.iter() {
+            println!("shader reload: {}", update);
+        }
+        match pipeline_state.reload(&shader_reloader, &shader_updates) {
+            Ok(Some(previous)) => deletion_queue.retire(previous, current_frame),
+            Ok(None) => {},
+            Err(error) => println!("shader reload: keeping the previous pipeline, {}", error),
+        }
+
         // get back buffer index
         let back_buffers_index = unsafe { swapchain.cast::<IDXGISwapChain4>().as_ref().unwrap().GetCurrentBackBufferIndex() };
 
         // create resource barrier
+        let back_buffer = back_buffers[back_buffers_index as usize];
 
-        let mut barrier_desc = D3D12_RESOURCE_BARRIER {
-            Type : D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-            Flags : D3D12_RESOURCE_BARRIER_FLAG_NONE,
-            u: unsafe { mem::zeroed() },
-        };
-        * unsafe { barrier_desc.u.Transition_mut() } = D3D12_RESOURCE_TRANSITION_BARRIER {
-            pResource : back_buffers[back_buffers_index as usize],
-            Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-            StateBefore: D3D12_RESOURCE_STATE_PRESENT,
-            StateAfter: D3D12_RESOURCE_STATE_RENDER_TARGET,
-        };
+        let mut cmd_list_states = resource_state::CommandListStates::new();
 
-        unsafe { cmd_list.as_ref().unwrap().ResourceBarrier(1, &barrier_desc); };
+        resource_states.require(&mut cmd_list_states, back_buffer, resource_state::Subresource::All, D3D12_RESOURCE_STATE_RENDER_TARGET).unwrap();
+        resource_states.require(&mut cmd_list_states, texture_buffer, resource_state::Subresource::All, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE).unwrap();
+        cmd_list_states.record(cmd_list);
 
-        unsafe { cmd_list.as_ref().unwrap().SetPipelineState(pipeline_state); };
+        unsafe { cmd_list.as_ref().unwrap().SetPipelineState(pipeline_state.get()); };
 
         // set render target
-        let mut rtv_heap_start = unsafe { rtv_heaps.as_ref().unwrap().GetCPUDescriptorHandleForHeapStart() };
-
-        rtv_heap_start.ptr += (back_buffers_index * unsafe { d3d12_device.as_ref().unwrap().GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV) }) as usize;
+        let rtv_heap_start = rtv_range.cpu_handle(back_buffers_index);
 
         unsafe {
             cmd_list.as_ref().unwrap().OMSetRenderTargets(
@@ -590,22 +592,27 @@ fn main() {
         unsafe { cmd_list.as_ref().unwrap().IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST); };
         unsafe { cmd_list.as_ref().unwrap().IASetVertexBuffers(0, 1, &vertex_buffer.buffer_view); };
         unsafe { cmd_list.as_ref().unwrap().IASetIndexBuffer(&index_buffer.buffer_view); };
-        unsafe { cmd_list.as_ref().unwrap().SetDescriptorHeaps(1, &mut texture_desc_heap); };
-		unsafe { cmd_list.as_ref().unwrap().SetGraphicsRootDescriptorTable(0, texture_desc_heap.as_ref().unwrap().GetGPUDescriptorHandleForHeapStart()) };
+        // the previous frame is complete, so this back buffer's region can be reused
+        shader_visible_descriptors.begin_frame(back_buffers_index);
+
+        let texture_table = shader_visible_descriptors.copy_from(d3d12_device, &texture_view).unwrap();
+
+        unsafe { cmd_list.as_ref().unwrap().SetDescriptorHeaps(1, &mut shader_visible_descriptors.heap); };
+		unsafe { cmd_list.as_ref().unwrap().SetGraphicsRootDescriptorTable(0, texture_table.gpu_handle()) };
+
+        scene_constants.write(back_buffers_index, &constant_buffer::SceneConstants { transform: lib::XMFLOAT4X4::identity() });
+        scene_constants.bind_graphics_root(cmd_list, 1, back_buffers_index);
 
         unsafe { cmd_list.as_ref().unwrap().DrawIndexedInstanced(indices.len() as u32, 1, 0, 0, 0); };
 
         // swap barrier state
-        unsafe { barrier_desc.u.Transition_mut().StateBefore = D3D12_RESOURCE_STATE_RENDER_TARGET };
-        unsafe { barrier_desc.u.Transition_mut().StateAfter = D3D12_RESOURCE_STATE_PRESENT };
-        unsafe { cmd_list.as_ref().unwrap().ResourceBarrier(1, &barrier_desc); };
+        resource_states.require(&mut cmd_list_states, back_buffer, resource_state::Subresource::All, D3D12_RESOURCE_STATE_PRESENT).unwrap();
+        cmd_list_states.record(cmd_list);
 
         // run commands
         unsafe { cmd_list.as_ref().unwrap().Close(); };
 
-        let cmd_list_array = [ cmd_list.cast::<ID3D12CommandList>() ];
-
-        unsafe { cmd_queue.as_ref().unwrap().ExecuteCommandLists(1, &cmd_list_array[0]); };
+        fixup_cmd_list.execute(cmd_queue, &mut resource_states, cmd_list_states, cmd_list).unwrap();
 
         // handle fence
         unsafe { cmd_queue.as_ref().unwrap().Signal(fence, current_frame); };
@@ -622,7 +629,13 @@ fn main() {
 
         unsafe { cmd_allocator.as_ref().unwrap().Reset(); };
 
-        unsafe { cmd_list.as_ref().unwrap().Reset(cmd_allocator, pipeline_state); };
+        unsafe { cmd_list.as_ref().unwrap().Reset(cmd_allocator, pipeline_state.get()); };
+
+        deletion_queue.release_completed(fence);
+
+        if let Some(adapter) = video_memory_adapter {
+            memory_tracker::poll_video_memory(unsafe { adapter.as_ref().unwrap() }).unwrap();
+        }
 
 
         // swap buffer
@@ -0,0 +1,408 @@
+use winapi::{
+    um::d3d12,
+    shared::{
+        dxgi1_4,
+        winerror,
+    },
+    Interface,
+};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::{ Arc, Mutex, MutexGuard };
+
+use crate::lib;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryCategory {
+    Buffer,
+    Texture,
+    RenderTarget,
+    DepthStencil,
+    Upload,
+    Readback,
+    // heaps placed resources live in, the placed resources themselves are not counted again
+    Heap,
+}
+
+impl MemoryCategory {
+    pub fn of_resource(heap_type: d3d12::D3D12_HEAP_TYPE, desc: &d3d12::D3D12_RESOURCE_DESC) -> MemoryCategory {
+        match heap_type {
+            d3d12::D3D12_HEAP_TYPE_UPLOAD => MemoryCategory::Upload,
+            d3d12::D3D12_HEAP_TYPE_READBACK => MemoryCategory::Readback,
+            _ if desc.Dimension == d3d12::D3D12_RESOURCE_DIMENSION_BUFFER => MemoryCategory::Buffer,
+            _ if desc.Flags & d3d12::D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET != 0 => MemoryCategory::RenderTarget,
+            _ if desc.Flags & d3d12::D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL != 0 => MemoryCategory::DepthStencil,
+            _ => MemoryCategory::Texture,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedResource {
+    pub name: String,
+    pub size: u64,
+    pub heap_type: d3d12::D3D12_HEAP_TYPE,
+    pub category: MemoryCategory,
+}
+
+// budget and usage of one memory segment group, in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryBudget {
+    pub budget: u64,
+    pub usage: u64,
+}
+
+impl MemoryBudget {
+    pub fn usage_fraction(&self) -> f64 {
+        match (self.budget, self.usage) {
+            (0, 0) => 0.0,
+            (0, _) => f64::INFINITY,
+            (budget, usage) => usage as f64 / budget as f64,
+        }
+    }
+}
+
+impl From<dxgi1_4::DXGI_QUERY_VIDEO_MEMORY_INFO> for MemoryBudget {
+    fn from(info: dxgi1_4::DXGI_QUERY_VIDEO_MEMORY_INFO) -> MemoryBudget {
+        MemoryBudget {
+            budget: info.Budget,
+            usage: info.CurrentUsage,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crossing {
+    Above,
+    Below,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdEvent {
+    pub threshold: f64,
+    pub crossing: Crossing,
+    pub budget: MemoryBudget,
+}
+
+// shared so a crossing can be reported after the tracker is unlocked
+pub type ThresholdCallback = Arc<dyn Fn(&ThresholdEvent) + Send + Sync>;
+
+struct Threshold {
+    fraction: f64,
+    above: bool,
+    callback: ThresholdCallback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CategorySummary {
+    pub category: MemoryCategory,
+    pub count: usize,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryReport {
+    pub categories: Vec<CategorySummary>,
+    pub tracked_size: u64,
+    pub budget: Option<MemoryBudget>,
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for summary in self.categories.iter() {
+            writeln!(f, "{:<14}{:>6} resources {:>10.2} MiB", format!("{:?}", summary.category), summary.count, summary.size as f64 / MIB)?;
+        }
+
+        write!(f, "{:<14}{:>16} {:>10.2} MiB", "Total", "", self.tracked_size as f64 / MIB)?;
+
+        if let Some(budget) = self.budget {
+            write!(f, "\nusage {:.2} MiB of {:.2} MiB budget ({:.1}%)", budget.usage as f64 / MIB, budget.budget as f64 / MIB, budget.usage_fraction() * 100.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+// resources keyed by their address, with the last polled budget
+pub struct MemoryTracker {
+    resources: BTreeMap<usize, TrackedResource>,
+    thresholds: Vec<Threshold>,
+    budget: Option<MemoryBudget>,
+}
+
+impl Default for MemoryTracker {
+    fn default() -> MemoryTracker {
+        MemoryTracker::new()
+    }
+}
+
+impl MemoryTracker {
+    pub const fn new() -> MemoryTracker {
+        MemoryTracker {
+            resources: BTreeMap::new(),
+            thresholds: Vec::new(),
+            budget: None,
+        }
+    }
+
+    pub fn track(&mut self, key: usize, resource: TrackedResource) {
+        self.resources.insert(key, resource);
+    }
+
+    pub fn untrack(&mut self, key: usize) -> Option<TrackedResource> {
+        self.resources.remove(&key)
+    }
+
+    pub fn set_name(&mut self, key: usize, name: &str) {
+        if let Some(resource) = self.resources.get_mut(&key) {
+            resource.name = name.to_string();
+        }
+    }
+
+    pub fn get(&self, key: usize) -> Option<&TrackedResource> {
+        self.resources.get(&key)
+    }
+
+    pub fn resources(&self) -> impl Iterator<Item = &TrackedResource> {
+        self.resources.values()
+    }
+
+    // `fraction` of the budget, the callback runs each time usage goes above or back below it
+    pub fn add_threshold(&mut self, fraction: f64, callback: ThresholdCallback) {
+        let above = self.budget.is_some_and(|budget| budget.usage_fraction() >= fraction);
+
+        self.thresholds.push(Threshold {
+            fraction: fraction,
+            above: above,
+            callback: callback,
+        });
+    }
+
+    pub fn budget(&self) -> Option<MemoryBudget> {
+        self.budget
+    }
+
+    // returns the callbacks of the crossed thresholds with their events, the caller runs them once
+    // the tracker is unlocked so they can use `memory_tracker()` themselves
+    pub fn update_budget(&mut self, budget: MemoryBudget) -> Vec<(ThresholdCallback, ThresholdEvent)> {
+        self.budget = Some(budget);
+
+        let fraction = budget.usage_fraction();
+        let mut crossed = Vec::new();
+
+        for threshold in self.thresholds.iter_mut() {
+            let above = fraction >= threshold.fraction;
+
+            if above == threshold.above {
+                continue;
+            }
+
+            threshold.above = above;
+
+            crossed.push((threshold.callback.clone(), ThresholdEvent {
+                threshold: threshold.fraction,
+                crossing: if above { Crossing::Above } else { Crossing::Below },
+                budget: budget,
+            }));
+        }
+
+        crossed
+    }
+
+    pub fn report(&self) -> MemoryReport {
+        let mut categories = BTreeMap::<MemoryCategory, CategorySummary>::new();
+
+        for resource in self.resources.values() {
+            let summary = categories.entry(resource.category).or_insert(CategorySummary {
+                category: resource.category,
+                count: 0,
+                size: 0,
+            });
+
+            summary.count += 1;
+            summary.size += resource.size;
+        }
+
+        MemoryReport {
+            tracked_size: categories.values().map(|summary| summary.size).sum(),
+            categories: categories.into_values().collect(),
+            budget: self.budget,
+        }
+    }
+}
+
+static MEMORY_TRACKER: Mutex<MemoryTracker> = Mutex::new(MemoryTracker::new());
+
+// tracker shared by every helper which creates GPU memory
+pub fn memory_tracker() -> MutexGuard<'static, MemoryTracker> {
+    MEMORY_TRACKER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// record a committed resource, the size is what its device reserves for it
+pub fn track_resource(resource: &d3d12::ID3D12Resource, heap_type: d3d12::D3D12_HEAP_TYPE) {
+
+    let desc = unsafe { resource.GetDesc() };
+
+    let mut device = ptr::null_mut::<d3d12::ID3D12Device>();
+
+    let result = unsafe { resource.GetDevice(&d3d12::ID3D12Device::uuidof(), lib::get_pointer_of_interface(&mut device)) };
+
+    // left out of the report rather than failing the creation
+    if result != winerror::S_OK {
+        return;
+    }
+
+    let allocation_info = unsafe { device.as_ref().unwrap().GetResourceAllocationInfo(0, 1, &desc) };
+
+    unsafe { device.as_ref().unwrap().Release() };
+
+    memory_tracker().track(resource as *const d3d12::ID3D12Resource as usize, TrackedResource {
+        name: String::new(),
+        size: allocation_info.SizeInBytes,
+        heap_type: heap_type,
+        category: MemoryCategory::of_resource(heap_type, &desc),
+    });
+}
+
+pub fn track_heap(heap: *mut d3d12::ID3D12Heap, heap_desc: &d3d12::D3D12_HEAP_DESC) {
+    memory_tracker().track(heap as usize, TrackedResource {
+        name: String::new(),
+        size: heap_desc.SizeInBytes,
+        heap_type: heap_desc.Properties.Type,
+        category: MemoryCategory::Heap,
+    });
+}
+
+// called before the last reference of `object` is released
+pub fn untrack<I: Interface>(object: *mut I) {
+    memory_tracker().untrack(object as usize);
+}
+
+// name shown in the report and by the debug layer
+pub fn set_debug_name(object: &d3d12::ID3D12Object, name: &str) {
+
+    let wide_name = lib::utf16_to_vec(name);
+
+    unsafe { object.SetName(wide_name.as_ptr()) };
+
+    memory_tracker().set_name(object as *const d3d12::ID3D12Object as usize, name);
+}
+
+pub fn query_video_memory(adapter: &dxgi1_4::IDXGIAdapter3, segment_group: dxgi1_4::DXGI_MEMORY_SEGMENT_GROUP) -> Result<MemoryBudget, winerror::HRESULT> {
+
+    let mut info: dxgi1_4::DXGI_QUERY_VIDEO_MEMORY_INFO = unsafe { mem::zeroed() };
+
+    let result = unsafe { adapter.QueryVideoMemoryInfo(0, segment_group, &mut info) };
+
+    match result {
+        winerror::S_OK => Ok(MemoryBudget::from(info)),
+        _ => Err(result)
+    }
+}
+
+// refresh the local (video memory) budget and run the threshold callbacks, called once per frame
+pub fn poll_video_memory(adapter: &dxgi1_4::IDXGIAdapter3) -> Result<MemoryBudget, winerror::HRESULT> {
+
+    let budget = query_video_memory(adapter, dxgi1_4::DXGI_MEMORY_SEGMENT_GROUP_LOCAL)?;
+
+    update_budget(&MEMORY_TRACKER, budget);
+
+    Ok(budget)
+}
+
+// callbacks may use the tracker, so they run after it is unlocked
+fn update_budget(tracker: &Mutex<MemoryTracker>, budget: MemoryBudget) {
+
+    // the tracker is unlocked at the end of this statement
+    let crossed = tracker.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).update_budget(budget);
+
+    for (callback, event) in crossed {
+        callback(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(name: &str, size: u64, category: MemoryCategory) -> TrackedResource {
+        TrackedResource {
+            name: name.to_string(),
+            size: size,
+            heap_type: d3d12::D3D12_HEAP_TYPE_DEFAULT,
+            category: category,
+        }
+    }
+
+    #[test]
+    fn report_sums_sizes_per_category() {
+        let mut tracker = MemoryTracker::new();
+
+        tracker.track(1, resource("vertices", 64 * 1024, MemoryCategory::Buffer));
+        tracker.track(2, resource("indices", 64 * 1024, MemoryCategory::Buffer));
+        tracker.track(3, resource("albedo", 4 * 1024 * 1024, MemoryCategory::Texture));
+        tracker.track(4, resource("staging", 1024 * 1024, MemoryCategory::Upload));
+        tracker.untrack(4);
+        tracker.set_name(1, "quad vertices");
+
+        let report = tracker.report();
+
+        assert_eq!(report.categories, vec![
+            CategorySummary { category: MemoryCategory::Buffer, count: 2, size: 128 * 1024 },
+            CategorySummary { category: MemoryCategory::Texture, count: 1, size: 4 * 1024 * 1024 },
+        ]);
+        assert_eq!(report.tracked_size, 128 * 1024 + 4 * 1024 * 1024);
+        assert_eq!(tracker.get(1).unwrap().name, "quad vertices");
+    }
+
+    #[test]
+    fn thresholds_fire_once_per_crossing() {
+        let mut tracker = MemoryTracker::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = events.clone();
+        tracker.add_threshold(0.9, Arc::new(move |event| recorded.lock().unwrap().push((event.threshold, event.crossing))));
+
+        for usage in [50, 95, 97, 80, 91] {
+            for (callback, event) in tracker.update_budget(MemoryBudget { budget: 100, usage: usage }) {
+                callback(&event);
+            }
+        }
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            (0.9, Crossing::Above),
+            (0.9, Crossing::Below),
+            (0.9, Crossing::Above),
+        ]);
+    }
+
+    #[test]
+    fn callbacks_run_after_the_tracker_is_unlocked() {
+        let tracker = Arc::new(Mutex::new(MemoryTracker::new()));
+        let reports = Arc::new(Mutex::new(Vec::new()));
+
+        // weak so the callback doesn't keep its own tracker alive
+        let weak_tracker = Arc::downgrade(&tracker);
+        let recorded = reports.clone();
+        tracker.lock().unwrap().add_threshold(0.5, Arc::new(move |_| {
+            let tracker = weak_tracker.upgrade().unwrap();
+            let report = tracker.try_lock().expect("the tracker is still locked").report();
+            recorded.lock().unwrap().push(report);
+        }));
+
+        update_budget(&tracker, MemoryBudget { budget: 100, usage: 60 });
+
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn zero_budget_is_never_under_a_threshold() {
+        assert_eq!(MemoryBudget { budget: 0, usage: 0 }.usage_fraction(), 0.0);
+        assert!(MemoryBudget { budget: 0, usage: 1 }.usage_fraction() > 1.0);
+    }
+}
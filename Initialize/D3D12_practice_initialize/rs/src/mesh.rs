@@ -0,0 +1,127 @@
+use crate::lib::{ XMFLOAT2, XMFLOAT3, Vertex, NormalVertex, TangentVertex };
+
+pub mod optimizer;
+pub mod normals;
+pub mod simplify;
+pub mod meshlet;
+
+// indexed triangle list
+#[derive(Debug, Clone)]
+pub struct Mesh<T> {
+    pub vertices: Vec<T>,
+    pub indices: Vec<u32>,
+}
+
+// vertex formats the mesh algorithms can work on
+pub trait MeshVertex: Copy {
+    fn position(&self) -> XMFLOAT3;
+    fn uv(&self) -> XMFLOAT2;
+}
+
+impl MeshVertex for Vertex {
+    fn position(&self) -> XMFLOAT3 {
+        self.position
+    }
+
+    fn uv(&self) -> XMFLOAT2 {
+        self.uv
+    }
+}
+
+impl MeshVertex for NormalVertex {
+    fn position(&self) -> XMFLOAT3 {
+        self.position
+    }
+
+    fn uv(&self) -> XMFLOAT2 {
+        self.uv
+    }
+}
+
+impl MeshVertex for TangentVertex {
+    fn position(&self) -> XMFLOAT3 {
+        self.position
+    }
+
+    fn uv(&self) -> XMFLOAT2 {
+        self.uv
+    }
+}
+
+// vertex formats whose bytes can be hashed and compared as a whole,
+// sealed since the type must not have padding
+pub trait PlainVertex: MeshVertex + plain_vertex::Sealed {}
+
+impl<T: MeshVertex + plain_vertex::Sealed> PlainVertex for T {}
+
+mod plain_vertex {
+    use super::{ Vertex, NormalVertex, TangentVertex };
+
+    pub trait Sealed {}
+
+    // repr(C) and only f32 fields
+    impl Sealed for Vertex {}
+    impl Sealed for NormalVertex {}
+    impl Sealed for TangentVertex {}
+}
+
+impl<T: MeshVertex> Mesh<T> {
+    pub fn new(vertices: Vec<T>, indices: Vec<u32>) -> Mesh<T> {
+        Mesh {
+            vertices: vertices,
+            indices: indices,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn positions(&self) -> Vec<XMFLOAT3> {
+        self.vertices.iter().map(|vertex| vertex.position()).collect()
+    }
+}
+
+// vector helpers for XMFLOAT3
+pub fn add(a: XMFLOAT3, b: XMFLOAT3) -> XMFLOAT3 {
+    XMFLOAT3 { x: a.x + b.x, y: a.y + b.y, z: a.z + b.z }
+}
+
+pub fn sub(a: XMFLOAT3, b: XMFLOAT3) -> XMFLOAT3 {
+    XMFLOAT3 { x: a.x - b.x, y: a.y - b.y, z: a.z - b.z }
+}
+
+pub fn scale(a: XMFLOAT3, s: f32) -> XMFLOAT3 {
+    XMFLOAT3 { x: a.x * s, y: a.y * s, z: a.z * s }
+}
+
+pub fn dot(a: XMFLOAT3, b: XMFLOAT3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub fn cross(a: XMFLOAT3, b: XMFLOAT3) -> XMFLOAT3 {
+    XMFLOAT3 {
+        x: a.y * b.z - a.z * b.y,
+        y: a.z * b.x - a.x * b.z,
+        z: a.x * b.y - a.y * b.x,
+    }
+}
+
+pub fn length(a: XMFLOAT3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+// returns zero vector for degenerate input
+pub fn normalize(a: XMFLOAT3) -> XMFLOAT3 {
+    let len = length(a);
+    if len > 0.0 {
+        scale(a, 1.0 / len)
+    } else {
+        XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }
+    }
+}
+
+// not normalized, length is twice the triangle area
+pub fn triangle_normal(p0: XMFLOAT3, p1: XMFLOAT3, p2: XMFLOAT3) -> XMFLOAT3 {
+    cross(sub(p1, p0), sub(p2, p0))
+}
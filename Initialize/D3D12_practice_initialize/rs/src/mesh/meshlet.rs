@@ -0,0 +1,462 @@
+use crate::lib::XMFLOAT3;
+use super::{ Mesh, MeshVertex, add, sub, scale, dot, length, normalize, triangle_normal };
+
+// limits recommended for D3D12 mesh shaders
+pub const DEFAULT_MAX_VERTICES: usize = 64;
+pub const DEFAULT_MAX_PRIMITIVES: usize = 124;
+
+// hardware limit of a single mesh shader group
+const MAX_MESHLET_SIZE: usize = 256;
+
+// below this the triangles face too many directions to cull the cluster
+const MIN_CONE_DOT: f32 = 0.1;
+
+// ranges into the flat vertex and primitive index buffers
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Meshlet {
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub primitive_offset: u32,
+    pub primitive_count: u32,
+}
+
+// bounding sphere and backface culling cone of a meshlet
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MeshletBounds {
+    pub center: XMFLOAT3,
+    pub radius: f32,
+    pub cone_apex: XMFLOAT3,
+    // sine of the cone half angle, 1.0 means the cone can't be culled
+    pub cone_cutoff: f32,
+    pub cone_axis: XMFLOAT3,
+    pub padding: f32,
+}
+
+// flat buffers, each one can be uploaded as a structured buffer
+#[derive(Debug, Clone)]
+pub struct MeshletBuffers {
+    pub meshlets: Vec<Meshlet>,
+    // global vertex index of every meshlet local vertex
+    pub unique_vertex_indices: Vec<u32>,
+    // one triangle per element, three 10 bit local vertex indices
+    pub primitive_indices: Vec<u32>,
+    pub bounds: Vec<MeshletBounds>,
+}
+
+pub fn pack_primitive(i0: u32, i1: u32, i2: u32) -> u32 {
+    (i0 & 0x3ff) | ((i1 & 0x3ff) << 10) | ((i2 & 0x3ff) << 20)
+}
+
+pub fn unpack_primitive(packed: u32) -> [u32; 3] {
+    [packed & 0x3ff, (packed >> 10) & 0x3ff, (packed >> 20) & 0x3ff]
+}
+
+// greedily grow meshlets over adjacent triangles, preferring triangles which add the fewest new vertices
+pub fn build_meshlets<T: MeshVertex>(mesh: &Mesh<T>, max_vertices: usize, max_primitives: usize) -> MeshletBuffers {
+
+    assert!(max_vertices >= 3 && max_vertices <= MAX_MESHLET_SIZE, "max_vertices must be in 3..={}", MAX_MESHLET_SIZE);
+    assert!(max_primitives >= 1 && max_primitives <= MAX_MESHLET_SIZE, "max_primitives must be in 1..={}", MAX_MESHLET_SIZE);
+
+    let positions = mesh.positions();
+    let vertex_count = mesh.vertices.len();
+    let triangle_count = mesh.triangle_count();
+    let indices = &mesh.indices[..triangle_count * 3];
+
+    // vertex -> triangles adjacency
+    let mut adjacency = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners.iter() {
+            adjacency[index as usize].push(triangle as u32);
+        }
+    }
+
+    let mut live = adjacency.iter().map(|triangles| triangles.len() as u32).collect::<Vec<u32>>();
+    let mut emitted = vec![false; triangle_count];
+
+    // meshlet local index of every vertex, u32::MAX when not in the current meshlet
+    let mut local = vec![u32::max_value(); vertex_count];
+
+    let mut buffers = MeshletBuffers {
+        meshlets: Vec::new(),
+        unique_vertex_indices: Vec::new(),
+        primitive_indices: Vec::new(),
+        bounds: Vec::new(),
+    };
+
+    let mut current = Meshlet {
+        vertex_offset: 0,
+        vertex_count: 0,
+        primitive_offset: 0,
+        primitive_count: 0,
+    };
+
+    let mut cursor = 0;
+    let mut emitted_count = 0;
+
+    while emitted_count < triangle_count {
+        let next = if current.primitive_count == 0 {
+            None
+        } else {
+            best_adjacent_triangle(&buffers.unique_vertex_indices[current.vertex_offset as usize..], indices, &adjacency, &live, &emitted, &local, &positions)
+        };
+
+        let triangle = match next {
+            Some(triangle) => triangle,
+            None => {
+                // cluster is closed off, continue from the next unused triangle
+                if current.primitive_count > 0 {
+                    flush_meshlet(&mut buffers, &mut current, &mut local, &positions);
+                }
+
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+
+        let new_vertices = corners.iter().enumerate()
+            .filter(|&(i, &index)| local[index as usize] == u32::max_value() && !corners[..i].contains(&index))
+            .count();
+
+        if current.vertex_count as usize + new_vertices > max_vertices || current.primitive_count as usize >= max_primitives {
+            flush_meshlet(&mut buffers, &mut current, &mut local, &positions);
+        }
+
+        let mut packed = [0u32; 3];
+
+        for (corner, &index) in corners.iter().enumerate() {
+            if local[index as usize] == u32::max_value() {
+                local[index as usize] = current.vertex_count;
+                buffers.unique_vertex_indices.push(index);
+                current.vertex_count += 1;
+            }
+
+            packed[corner] = local[index as usize];
+            live[index as usize] -= 1;
+        }
+
+        buffers.primitive_indices.push(pack_primitive(packed[0], packed[1], packed[2]));
+        current.primitive_count += 1;
+
+        emitted[triangle] = true;
+        emitted_count += 1;
+    }
+
+    if current.primitive_count > 0 {
+        flush_meshlet(&mut buffers, &mut current, &mut local, &positions);
+    }
+
+    buffers
+}
+
+// candidates are the unused triangles touching the meshlet, scored by
+// new vertex count, then by how few unused triangles their vertices have left
+fn best_adjacent_triangle(meshlet_vertices: &[u32], indices: &[u32], adjacency: &[Vec<u32>], live: &[u32], emitted: &[bool], local: &[u32], positions: &[XMFLOAT3]) -> Option<usize> {
+
+    let mut center = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+    for &vertex in meshlet_vertices.iter() {
+        center = add(center, positions[vertex as usize]);
+    }
+    center = scale(center, 1.0 / meshlet_vertices.len() as f32);
+
+    let mut best = None;
+    let mut best_score = (u32::max_value(), u32::max_value(), std::f32::MAX);
+
+    for &vertex in meshlet_vertices.iter() {
+        for &triangle in adjacency[vertex as usize].iter() {
+            let triangle = triangle as usize;
+
+            if emitted[triangle] {
+                continue;
+            }
+
+            let corners = &indices[triangle * 3..triangle * 3 + 3];
+
+            let new_vertices = corners.iter().filter(|&&index| local[index as usize] == u32::max_value()).count() as u32;
+            let live_sum = corners.iter().map(|&index| live[index as usize]).sum::<u32>();
+
+            let triangle_center = scale(add(add(positions[corners[0] as usize], positions[corners[1] as usize]), positions[corners[2] as usize]), 1.0 / 3.0);
+            let distance = length(sub(triangle_center, center));
+
+            let score = (new_vertices, live_sum, distance);
+
+            if score.0 < best_score.0
+                || (score.0 == best_score.0 && score.1 < best_score.1)
+                || (score.0 == best_score.0 && score.1 == best_score.1 && score.2 < best_score.2) {
+                best_score = score;
+                best = Some(triangle);
+            }
+        }
+    }
+
+    best
+}
+
+fn flush_meshlet(buffers: &mut MeshletBuffers, current: &mut Meshlet, local: &mut [u32], positions: &[XMFLOAT3]) {
+
+    if current.primitive_count == 0 {
+        return;
+    }
+
+    let vertex_range = current.vertex_offset as usize..(current.vertex_offset + current.vertex_count) as usize;
+
+    for &vertex in buffers.unique_vertex_indices[vertex_range.clone()].iter() {
+        local[vertex as usize] = u32::max_value();
+    }
+
+    let primitive_range = current.primitive_offset as usize..(current.primitive_offset + current.primitive_count) as usize;
+
+    let vertices = &buffers.unique_vertex_indices[vertex_range];
+    let triangles: Vec<[XMFLOAT3; 3]> = buffers.primitive_indices[primitive_range].iter()
+        .map(|&packed| {
+            let corners = unpack_primitive(packed);
+            [
+                positions[vertices[corners[0] as usize] as usize],
+                positions[vertices[corners[1] as usize] as usize],
+                positions[vertices[corners[2] as usize] as usize],
+            ]
+        })
+        .collect();
+
+    let vertex_positions: Vec<XMFLOAT3> = vertices.iter().map(|&vertex| positions[vertex as usize]).collect();
+
+    buffers.bounds.push(compute_bounds(&vertex_positions, &triangles));
+    buffers.meshlets.push(*current);
+
+    *current = Meshlet {
+        vertex_offset: buffers.unique_vertex_indices.len() as u32,
+        vertex_count: 0,
+        primitive_offset: buffers.primitive_indices.len() as u32,
+        primitive_count: 0,
+    };
+}
+
+pub fn compute_bounds(vertex_positions: &[XMFLOAT3], triangles: &[[XMFLOAT3; 3]]) -> MeshletBounds {
+
+    let (center, radius) = bounding_sphere(vertex_positions);
+
+    // cone axis is the average triangle normal
+    let normals: Vec<Option<XMFLOAT3>> = triangles.iter()
+        .map(|triangle| {
+            let normal = triangle_normal(triangle[0], triangle[1], triangle[2]);
+            if length(normal) > 0.0 { Some(normalize(normal)) } else { None }
+        })
+        .collect();
+
+    let mut axis = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+    for normal in normals.iter().flatten() {
+        axis = add(axis, *normal);
+    }
+    let axis = normalize(axis);
+
+    let min_dot = normals.iter().flatten().map(|&normal| dot(normal, axis)).fold(1.0f32, f32::min);
+
+    if length(axis) == 0.0 || min_dot <= MIN_CONE_DOT {
+        return MeshletBounds {
+            center: center,
+            radius: radius,
+            cone_apex: center,
+            cone_cutoff: 1.0,
+            cone_axis: XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 },
+            padding: 0.0,
+        };
+    }
+
+    // move the apex back along the axis until every triangle plane is in front of it
+    let mut max_t = 0.0f32;
+    for (triangle, normal) in triangles.iter().zip(normals.iter()) {
+        if let Some(normal) = normal {
+            let t = dot(sub(center, triangle[0]), *normal) / dot(axis, *normal);
+            max_t = max_t.max(t);
+        }
+    }
+
+    MeshletBounds {
+        center: center,
+        radius: radius,
+        cone_apex: sub(center, scale(axis, max_t)),
+        cone_cutoff: (1.0 - min_dot * min_dot).sqrt(),
+        cone_axis: axis,
+        padding: 0.0,
+    }
+}
+
+// every triangle of the meshlet faces away from `camera_position`
+pub fn is_backfacing(bounds: &MeshletBounds, camera_position: XMFLOAT3) -> bool {
+    let view = sub(bounds.cone_apex, camera_position);
+    dot(view, bounds.cone_axis) >= bounds.cone_cutoff * length(view)
+}
+
+// Ritter's bounding sphere
+fn bounding_sphere(points: &[XMFLOAT3]) -> (XMFLOAT3, f32) {
+
+    if points.is_empty() {
+        return (XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }, 0.0);
+    }
+
+    let farthest_from = |from: XMFLOAT3| {
+        *points.iter().max_by(|a, b| {
+            length(sub(**a, from)).partial_cmp(&length(sub(**b, from))).unwrap_or(std::cmp::Ordering::Equal)
+        }).unwrap()
+    };
+
+    let a = farthest_from(points[0]);
+    let b = farthest_from(a);
+
+    let mut center = scale(add(a, b), 0.5);
+    let mut radius = length(sub(b, a)) * 0.5;
+
+    for &point in points.iter() {
+        let distance = length(sub(point, center));
+
+        if distance > radius {
+            let new_radius = (radius + distance) * 0.5;
+            center = add(center, scale(sub(point, center), (new_radius - radius) / distance));
+            radius = new_radius;
+        }
+    }
+
+    (center, radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{ Vertex, XMFLOAT2 };
+
+    // n x n quad grid on the xy plane, front faces look down -z
+    fn grid(n: u32) -> Mesh<Vertex> {
+        let mut vertices = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                vertices.push(Vertex {
+                    position: XMFLOAT3 { x: x as f32, y: y as f32, z: 0.0 },
+                    uv: XMFLOAT2 { x: 0.0, y: 0.0 },
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let i0 = y * (n + 1) + x;
+                indices.extend_from_slice(&[i0, i0 + n + 1, i0 + 1, i0 + 1, i0 + n + 1, i0 + n + 2]);
+            }
+        }
+
+        Mesh::new(vertices, indices)
+    }
+
+    fn sorted(triangle: [u32; 3]) -> [u32; 3] {
+        let r = (0..3).min_by_key(|&i| triangle[i]).unwrap();
+        [triangle[r], triangle[(r + 1) % 3], triangle[(r + 2) % 3]]
+    }
+
+    #[test]
+    fn meshlets_cover_every_triangle_within_limits() {
+        let mesh = grid(24);
+        let buffers = build_meshlets(&mesh, DEFAULT_MAX_VERTICES, DEFAULT_MAX_PRIMITIVES);
+
+        assert_eq!(buffers.meshlets.len(), buffers.bounds.len());
+
+        let mut triangles = Vec::new();
+
+        for meshlet in buffers.meshlets.iter() {
+            assert!(meshlet.vertex_count as usize <= DEFAULT_MAX_VERTICES);
+            assert!(meshlet.primitive_count as usize <= DEFAULT_MAX_PRIMITIVES);
+
+            let vertices = &buffers.unique_vertex_indices[meshlet.vertex_offset as usize..(meshlet.vertex_offset + meshlet.vertex_count) as usize];
+
+            for &packed in buffers.primitive_indices[meshlet.primitive_offset as usize..(meshlet.primitive_offset + meshlet.primitive_count) as usize].iter() {
+                let local = unpack_primitive(packed);
+                assert!(local.iter().all(|&i| i < meshlet.vertex_count));
+                triangles.push(sorted([vertices[local[0] as usize], vertices[local[1] as usize], vertices[local[2] as usize]]));
+            }
+        }
+
+        let mut expected: Vec<[u32; 3]> = mesh.indices.chunks(3).map(|t| sorted([t[0], t[1], t[2]])).collect();
+        triangles.sort();
+        expected.sort();
+        assert_eq!(triangles, expected);
+
+        // 1152 triangles can't fit in fewer than 10 meshlets of 124
+        assert!(buffers.meshlets.len() >= 10);
+        assert!(buffers.meshlets.len() <= 24, "{} meshlets", buffers.meshlets.len());
+    }
+
+    #[test]
+    fn meshlet_bounds_contain_vertices() {
+        let mesh = grid(16);
+        let buffers = build_meshlets(&mesh, 32, 32);
+
+        for (meshlet, bounds) in buffers.meshlets.iter().zip(buffers.bounds.iter()) {
+            for &vertex in buffers.unique_vertex_indices[meshlet.vertex_offset as usize..(meshlet.vertex_offset + meshlet.vertex_count) as usize].iter() {
+                let distance = length(sub(mesh.vertices[vertex as usize].position, bounds.center));
+                assert!(distance <= bounds.radius * 1.0001 + 1.0e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn normal_cone_culls_backfacing_cluster() {
+        let mesh = grid(4);
+        let buffers = build_meshlets(&mesh, DEFAULT_MAX_VERTICES, DEFAULT_MAX_PRIMITIVES);
+
+        assert_eq!(buffers.meshlets.len(), 1);
+
+        let bounds = &buffers.bounds[0];
+        assert!(length(sub(bounds.cone_axis, XMFLOAT3 { x: 0.0, y: 0.0, z: -1.0 })) < 1.0e-5);
+        assert!(bounds.cone_cutoff < 1.0e-3);
+
+        // front faces point to -z
+        assert!(!is_backfacing(bounds, XMFLOAT3 { x: 2.0, y: 2.0, z: -10.0 }));
+        assert!(is_backfacing(bounds, XMFLOAT3 { x: 2.0, y: 2.0, z: 10.0 }));
+    }
+
+    #[test]
+    fn folded_cluster_is_never_culled() {
+        let triangles = [
+            [XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }, XMFLOAT3 { x: 0.0, y: 1.0, z: 0.0 }, XMFLOAT3 { x: 1.0, y: 0.0, z: 0.0 }],
+            [XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }, XMFLOAT3 { x: 1.0, y: 0.0, z: 0.0 }, XMFLOAT3 { x: 0.0, y: 1.0, z: 0.0 }],
+        ];
+        let points: Vec<XMFLOAT3> = triangles.iter().flat_map(|t| t.iter().cloned()).collect();
+
+        let bounds = compute_bounds(&points, &triangles);
+
+        assert_eq!(bounds.cone_cutoff, 1.0);
+        assert!(!is_backfacing(&bounds, XMFLOAT3 { x: 0.0, y: 0.0, z: 10.0 }));
+        assert!(!is_backfacing(&bounds, XMFLOAT3 { x: 0.0, y: 0.0, z: -10.0 }));
+    }
+
+    #[test]
+    fn concave_cluster_is_visible_from_inside() {
+        // a shallow valley along y, both slopes face up and inward
+        let a = XMFLOAT3 { x: -1.0, y: 0.0, z: 0.3 };
+        let b = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+        let c = XMFLOAT3 { x: 0.0, y: 1.0, z: 0.0 };
+        let d = XMFLOAT3 { x: -1.0, y: 1.0, z: 0.3 };
+        let e = XMFLOAT3 { x: 1.0, y: 0.0, z: 0.3 };
+        let f = XMFLOAT3 { x: 1.0, y: 1.0, z: 0.3 };
+        let triangles = [[a, b, c], [a, c, d], [b, e, f], [b, f, c]];
+
+        let bounds = compute_bounds(&[a, b, c, d, e, f], &triangles);
+
+        assert!(length(sub(bounds.cone_axis, XMFLOAT3 { x: 0.0, y: 0.0, z: 1.0 })) < 1.0e-5);
+        assert!((bounds.cone_cutoff - 0.287).abs() < 1.0e-3);
+
+        // just above the valley floor every triangle is front facing
+        assert!(!is_backfacing(&bounds, XMFLOAT3 { x: 0.0, y: 0.5, z: 0.1 }));
+        assert!(!is_backfacing(&bounds, XMFLOAT3 { x: 0.0, y: 0.5, z: 10.0 }));
+        assert!(is_backfacing(&bounds, XMFLOAT3 { x: 0.0, y: 0.5, z: -10.0 }));
+    }
+
+    #[test]
+    fn primitive_packing_round_trip() {
+        assert_eq!(unpack_primitive(pack_primitive(1, 63, 255)), [1, 63, 255]);
+    }
+}
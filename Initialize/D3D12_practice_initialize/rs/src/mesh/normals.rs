@@ -0,0 +1,360 @@
+use crate::lib::{ XMFLOAT3, XMFLOAT4, NormalVertex, TangentVertex };
+use super::{ Mesh, MeshVertex, add, sub, scale, dot, cross, length, normalize, triangle_normal };
+
+use std::collections::HashMap;
+
+// below this the triangle has no usable uv mapping
+const DEGENERATE_UV_AREA: f32 = 1.0e-20;
+
+// every corner gets the normal of its triangle, vertices are split where faces meet
+pub fn generate_flat_normals<T: MeshVertex>(mesh: &Mesh<T>) -> Mesh<NormalVertex> {
+
+    let corner_normals: Vec<XMFLOAT3> = mesh.indices.chunks_exact(3)
+        .flat_map(|triangle| {
+            let normal = normalize(triangle_normal(
+                mesh.vertices[triangle[0] as usize].position(),
+                mesh.vertices[triangle[1] as usize].position(),
+                mesh.vertices[triangle[2] as usize].position()
+            ));
+            vec![normal; 3]
+        })
+        .collect();
+
+    build_normal_mesh(mesh, &corner_normals)
+}
+
+// average angle weighted face normals of all corners sharing a position,
+// faces whose normals differ more than `angle_threshold` degrees are not averaged (hard edge)
+pub fn generate_smooth_normals<T: MeshVertex>(mesh: &Mesh<T>, angle_threshold: f32) -> Mesh<NormalVertex> {
+
+    let cos_threshold = angle_threshold.to_radians().cos();
+
+    let corner_count = mesh.indices.len() / 3 * 3;
+
+    let mut face_normals = Vec::with_capacity(corner_count / 3);
+    let mut corner_weights = Vec::with_capacity(corner_count);
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let p = [
+            mesh.vertices[triangle[0] as usize].position(),
+            mesh.vertices[triangle[1] as usize].position(),
+            mesh.vertices[triangle[2] as usize].position(),
+        ];
+
+        face_normals.push(normalize(triangle_normal(p[0], p[1], p[2])));
+
+        for corner in 0..3 {
+            corner_weights.push(corner_angle(p[corner], p[(corner + 1) % 3], p[(corner + 2) % 3]));
+        }
+    }
+
+    // corners sharing the same position, uv seams don't split the normal
+    let mut position_groups = HashMap::<[u32; 3], Vec<usize>>::new();
+
+    for corner in 0..corner_count {
+        let position = mesh.vertices[mesh.indices[corner] as usize].position();
+        position_groups.entry(position_key(position)).or_insert_with(Vec::new).push(corner);
+    }
+
+    let mut corner_normals = vec![XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }; corner_count];
+
+    for group in position_groups.values() {
+        for &corner in group.iter() {
+            let face_normal = face_normals[corner / 3];
+
+            let mut normal = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+
+            for &other in group.iter() {
+                let other_normal = face_normals[other / 3];
+
+                if other == corner || dot(face_normal, other_normal) >= cos_threshold {
+                    normal = add(normal, scale(other_normal, corner_weights[other]));
+                }
+            }
+
+            corner_normals[corner] = normalize(normal);
+        }
+    }
+
+    build_normal_mesh(mesh, &corner_normals)
+}
+
+// MikkTSpace compatible tangents, corners of a vertex are averaged per uv orientation
+// so mirrored uv islands get their own vertex with the opposite handedness
+pub fn generate_tangents(mesh: &Mesh<NormalVertex>) -> Mesh<TangentVertex> {
+
+    let corner_count = mesh.indices.len() / 3 * 3;
+
+    let mut corner_tangents = Vec::with_capacity(corner_count);
+    let mut corner_orientations = Vec::with_capacity(corner_count);
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let v = [
+            mesh.vertices[triangle[0] as usize],
+            mesh.vertices[triangle[1] as usize],
+            mesh.vertices[triangle[2] as usize],
+        ];
+
+        let d1 = sub(v[1].position, v[0].position);
+        let d2 = sub(v[2].position, v[0].position);
+
+        let s1 = v[1].uv.x - v[0].uv.x;
+        let t1 = v[1].uv.y - v[0].uv.y;
+        let s2 = v[2].uv.x - v[0].uv.x;
+        let t2 = v[2].uv.y - v[0].uv.y;
+
+        let signed_area = s1 * t2 - s2 * t1;
+        let orientation_preserving = signed_area > 0.0;
+        let degenerate = signed_area.abs() <= DEGENERATE_UV_AREA;
+
+        // first order derivative of position along u, pointing in +u for both orientations
+        let sign = if orientation_preserving { 1.0 } else { -1.0 };
+        let face_tangent = normalize(scale(sub(scale(d1, t2), scale(d2, t1)), sign));
+
+        for corner in 0..3 {
+            let normal = v[corner].normal;
+
+            // project into the tangent plane of the vertex normal
+            let tangent = normalize(sub(face_tangent, scale(normal, dot(normal, face_tangent))));
+
+            let edge0 = sub(v[(corner + 1) % 3].position, v[corner].position);
+            let edge1 = sub(v[(corner + 2) % 3].position, v[corner].position);
+            let edge0 = normalize(sub(edge0, scale(normal, dot(normal, edge0))));
+            let edge1 = normalize(sub(edge1, scale(normal, dot(normal, edge1))));
+            let weight = dot(edge0, edge1).max(-1.0).min(1.0).acos();
+
+            corner_tangents.push(if degenerate { XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 } } else { scale(tangent, weight) });
+            corner_orientations.push(orientation_preserving);
+        }
+    }
+
+    // accumulate per (vertex, orientation)
+    let mut groups = HashMap::<(u32, bool), (u32, XMFLOAT3)>::new();
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut indices = Vec::with_capacity(corner_count);
+
+    for corner in 0..corner_count {
+        let index = mesh.indices[corner];
+
+        let next = vertices.len() as u32;
+        let entry = groups.entry((index, corner_orientations[corner])).or_insert((next, XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 }));
+        entry.1 = add(entry.1, corner_tangents[corner]);
+
+        if entry.0 == next {
+            let vertex = mesh.vertices[index as usize];
+            vertices.push(TangentVertex {
+                position: vertex.position,
+                normal: vertex.normal,
+                tangent: XMFLOAT4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+                uv: vertex.uv,
+            });
+        }
+
+        indices.push(entry.0);
+    }
+
+    for (&(_, orientation_preserving), &(new_index, tangent)) in groups.iter() {
+        let vertex = &mut vertices[new_index as usize];
+
+        let tangent = if length(tangent) > 0.0 { normalize(tangent) } else { any_perpendicular(vertex.normal) };
+
+        vertex.tangent = XMFLOAT4 {
+            x: tangent.x,
+            y: tangent.y,
+            z: tangent.z,
+            w: if orientation_preserving { 1.0 } else { -1.0 },
+        };
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+// bitangent reconstructed the same way shaders do
+pub fn bitangent(vertex: &TangentVertex) -> XMFLOAT3 {
+    let tangent = XMFLOAT3 { x: vertex.tangent.x, y: vertex.tangent.y, z: vertex.tangent.z };
+    scale(cross(vertex.normal, tangent), vertex.tangent.w)
+}
+
+// one output vertex per distinct (source vertex, normal)
+fn build_normal_mesh<T: MeshVertex>(mesh: &Mesh<T>, corner_normals: &[XMFLOAT3]) -> Mesh<NormalVertex> {
+
+    let mut unique = HashMap::<(u32, [u32; 3]), u32>::new();
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut indices = Vec::with_capacity(corner_normals.len());
+
+    for (corner, &normal) in corner_normals.iter().enumerate() {
+        let index = mesh.indices[corner];
+
+        let next = vertices.len() as u32;
+        let new_index = *unique.entry((index, position_key(normal))).or_insert(next);
+
+        if new_index == next {
+            let vertex = &mesh.vertices[index as usize];
+            vertices.push(NormalVertex {
+                position: vertex.position(),
+                normal: normal,
+                uv: vertex.uv(),
+            });
+        }
+
+        indices.push(new_index);
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+fn position_key(position: XMFLOAT3) -> [u32; 3] {
+    // treat -0.0 and 0.0 as the same value
+    [
+        (position.x + 0.0).to_bits(),
+        (position.y + 0.0).to_bits(),
+        (position.z + 0.0).to_bits(),
+    ]
+}
+
+// angle at `p` of the triangle p, next, previous
+fn corner_angle(p: XMFLOAT3, next: XMFLOAT3, previous: XMFLOAT3) -> f32 {
+    let edge0 = normalize(sub(next, p));
+    let edge1 = normalize(sub(previous, p));
+    dot(edge0, edge1).max(-1.0).min(1.0).acos()
+}
+
+fn any_perpendicular(normal: XMFLOAT3) -> XMFLOAT3 {
+    let axis = if normal.x.abs() < 0.9 { XMFLOAT3 { x: 1.0, y: 0.0, z: 0.0 } } else { XMFLOAT3 { x: 0.0, y: 1.0, z: 0.0 } };
+    normalize(sub(axis, scale(normal, dot(normal, axis))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{ Vertex, XMFLOAT2 };
+
+    fn vertex(x: f32, y: f32, z: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            position: XMFLOAT3 { x: x, y: y, z: z },
+            uv: XMFLOAT2 { x: u, y: v },
+        }
+    }
+
+    fn assert_near(a: XMFLOAT3, b: XMFLOAT3) {
+        assert!(length(sub(a, b)) < 1.0e-4, "{:?} != {:?}", a, b);
+    }
+
+    // unit cube with 8 shared corners, counter clockwise seen from outside
+    fn cube() -> Mesh<Vertex> {
+        let mut vertices = Vec::new();
+        for i in 0..8 {
+            vertices.push(vertex((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32, 0.0, 0.0));
+        }
+
+        let quads = [
+            [0, 2, 3, 1], [4, 5, 7, 6],
+            [0, 1, 5, 4], [2, 6, 7, 3],
+            [0, 4, 6, 2], [1, 3, 7, 5],
+        ];
+
+        let mut indices = Vec::new();
+        for quad in quads.iter() {
+            indices.extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+        }
+
+        Mesh::new(vertices, indices)
+    }
+
+    // same quad as main.rs
+    fn quad() -> Mesh<Vertex> {
+        Mesh::new(
+            vec![
+                vertex(-0.4, -0.7, 0.0, 0.0, 1.0),
+                vertex(-0.4, 0.7, 0.0, 0.0, 0.0),
+                vertex(0.4, -0.7, 0.0, 1.0, 1.0),
+                vertex(0.4, 0.7, 0.0, 1.0, 0.0),
+            ],
+            vec![0, 1, 2, 2, 1, 3]
+        )
+    }
+
+    #[test]
+    fn flat_normals_split_cube_faces() {
+        let mesh = generate_flat_normals(&cube());
+
+        assert_eq!(mesh.vertices.len(), 24);
+
+        for triangle in mesh.indices.chunks(3) {
+            let p: Vec<XMFLOAT3> = triangle.iter().map(|&i| mesh.vertices[i as usize].position).collect();
+            let expected = normalize(triangle_normal(p[0], p[1], p[2]));
+
+            for &i in triangle.iter() {
+                assert_near(mesh.vertices[i as usize].normal, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_normals_ignore_trailing_indices() {
+        let mut mesh = quad();
+        mesh.indices.push(0);
+
+        let flat = generate_flat_normals(&mesh);
+        assert_eq!(flat.indices.len(), 6);
+    }
+
+    #[test]
+    fn smooth_normals_respect_angle_threshold() {
+        // 90 degree edges are kept hard below the threshold
+        let hard = generate_smooth_normals(&cube(), 60.0);
+        assert_eq!(hard.vertices.len(), 24);
+
+        // and averaged above it
+        let smooth = generate_smooth_normals(&cube(), 100.0);
+        assert_eq!(smooth.vertices.len(), 8);
+
+        for vertex in smooth.vertices.iter() {
+            let outward = normalize(sub(vertex.position, XMFLOAT3 { x: 0.5, y: 0.5, z: 0.5 }));
+            assert_near(vertex.normal, outward);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_uv_direction() {
+        let mesh = generate_tangents(&generate_smooth_normals(&quad(), 45.0));
+
+        assert_eq!(mesh.vertices.len(), 4);
+
+        for vertex in mesh.vertices.iter() {
+            assert_near(vertex.normal, XMFLOAT3 { x: 0.0, y: 0.0, z: -1.0 });
+            assert_near(XMFLOAT3 { x: vertex.tangent.x, y: vertex.tangent.y, z: vertex.tangent.z }, XMFLOAT3 { x: 1.0, y: 0.0, z: 0.0 });
+            // v grows downwards
+            assert_near(bitangent(vertex), XMFLOAT3 { x: 0.0, y: -1.0, z: 0.0 });
+        }
+    }
+
+    #[test]
+    fn tangents_split_mirrored_uvs() {
+        // the second triangle mirrors u, so the shared vertices need both handedness
+        let mesh = Mesh::new(
+            vec![
+                vertex(0.0, 0.0, 0.0, 0.0, 1.0),
+                vertex(0.0, 1.0, 0.0, 0.0, 0.0),
+                vertex(1.0, 0.0, 0.0, 1.0, 1.0),
+                vertex(-1.0, 0.0, 0.0, 1.0, 1.0),
+            ],
+            vec![0, 1, 2, 0, 3, 1]
+        );
+
+        let tangents = generate_tangents(&generate_flat_normals(&mesh));
+
+        assert_eq!(tangents.vertices.len(), 6);
+
+        for triangle in tangents.indices.chunks(3) {
+            let w = tangents.vertices[triangle[0] as usize].tangent.w;
+            for &i in triangle.iter() {
+                assert_eq!(tangents.vertices[i as usize].tangent.w, w);
+            }
+        }
+
+        let w0 = tangents.vertices[tangents.indices[0] as usize].tangent.w;
+        let w1 = tangents.vertices[tangents.indices[3] as usize].tangent.w;
+        assert_eq!(w0, -w1);
+    }
+}
@@ -0,0 +1,653 @@
+use crate::lib::XMFLOAT3;
+use super::{ Mesh, MeshVertex, PlainVertex, add, sub, scale, dot, normalize, triangle_normal };
+
+use std::collections::HashMap;
+use std::mem;
+use std::slice;
+
+// post transform cache size used by most hardware
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+// simulated pre transform cache, 16KB with 64 byte lines
+const FETCH_CACHE_LINE: usize = 64;
+const FETCH_CACHE_LINES: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizeOptions {
+    // cache size the triangle order is optimized and measured for
+    pub cache_size: usize,
+    // allowed ACMR degradation of the overdraw pass, 1.05 means 5% worse
+    pub overdraw_threshold: f32,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> OptimizeOptions {
+        OptimizeOptions {
+            cache_size: DEFAULT_CACHE_SIZE,
+            overdraw_threshold: 1.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexCacheStatistics {
+    pub vertices_transformed: u32,
+    // average cache miss ratio, transformed vertices per triangle (0.5 ~ 3.0)
+    pub acmr: f32,
+    // average transform to vertex ratio, transformed vertices per used vertex (1.0 is optimal)
+    pub atvr: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexFetchStatistics {
+    pub bytes_fetched: u32,
+    // fetched bytes per byte of used vertex data (1.0 is optimal)
+    pub overfetch: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepStatistics {
+    pub vertex_count: usize,
+    pub cache: VertexCacheStatistics,
+    pub fetch: VertexFetchStatistics,
+}
+
+// statistics after each step of `optimize_mesh`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimizeReport {
+    pub original: StepStatistics,
+    pub weld: StepStatistics,
+    pub vertex_cache: StepStatistics,
+    pub overdraw: StepStatistics,
+    pub vertex_fetch: StepStatistics,
+}
+
+// run every pass in order: weld -> vertex cache -> overdraw -> vertex fetch
+pub fn optimize_mesh<T: PlainVertex>(mesh: &Mesh<T>, options: &OptimizeOptions) -> (Mesh<T>, OptimizeReport) {
+
+    let original = analyze_step(mesh, options.cache_size);
+
+    let welded = weld_vertices(mesh);
+    let weld = analyze_step(&welded, options.cache_size);
+
+    let cache_indices = optimize_vertex_cache(&welded.indices, welded.vertices.len(), options.cache_size);
+    let cache_optimized = Mesh::new(welded.vertices, cache_indices);
+    let vertex_cache = analyze_step(&cache_optimized, options.cache_size);
+
+    let overdraw_optimized = Mesh::new(
+        cache_optimized.vertices.clone(),
+        optimize_overdraw(&cache_optimized.indices, &cache_optimized.positions(), options.cache_size, options.overdraw_threshold)
+    );
+    let overdraw = analyze_step(&overdraw_optimized, options.cache_size);
+
+    let fetch_optimized = optimize_vertex_fetch(&overdraw_optimized);
+    let vertex_fetch = analyze_step(&fetch_optimized, options.cache_size);
+
+    let report = OptimizeReport {
+        original: original,
+        weld: weld,
+        vertex_cache: vertex_cache,
+        overdraw: overdraw,
+        vertex_fetch: vertex_fetch,
+    };
+
+    (fetch_optimized, report)
+}
+
+fn analyze_step<T: MeshVertex>(mesh: &Mesh<T>, cache_size: usize) -> StepStatistics {
+    StepStatistics {
+        vertex_count: mesh.vertices.len(),
+        cache: analyze_vertex_cache(&mesh.indices, mesh.vertices.len(), cache_size),
+        fetch: analyze_vertex_fetch(&mesh.indices, mesh.vertices.len(), mem::size_of::<T>()),
+    }
+}
+
+// simulate a FIFO post transform cache
+pub fn analyze_vertex_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> VertexCacheStatistics {
+
+    let mut cache_timestamps = vec![0usize; vertex_count];
+    let mut timestamp = cache_size + 1;
+
+    let mut used = vec![false; vertex_count];
+    let mut used_count = 0u32;
+
+    let mut misses = 0u32;
+
+    for &index in indices.iter() {
+        let index = index as usize;
+
+        if timestamp - cache_timestamps[index] > cache_size {
+            cache_timestamps[index] = timestamp;
+            timestamp += 1;
+            misses += 1;
+        }
+
+        if !used[index] {
+            used[index] = true;
+            used_count += 1;
+        }
+    }
+
+    let triangle_count = indices.len() / 3;
+
+    VertexCacheStatistics {
+        vertices_transformed: misses,
+        acmr: if triangle_count == 0 { 0.0 } else { misses as f32 / triangle_count as f32 },
+        atvr: if used_count == 0 { 0.0 } else { misses as f32 / used_count as f32 },
+    }
+}
+
+// simulate a direct mapped pre transform cache
+pub fn analyze_vertex_fetch(indices: &[u32], vertex_count: usize, vertex_size: usize) -> VertexFetchStatistics {
+
+    let mut cache_tags = vec![usize::max_value(); FETCH_CACHE_LINES];
+
+    let mut used = vec![false; vertex_count];
+    let mut used_count = 0usize;
+
+    let mut bytes_fetched = 0usize;
+
+    for &index in indices.iter() {
+        let index = index as usize;
+
+        let start = index * vertex_size;
+        let end = start + vertex_size;
+
+        for line in (start / FETCH_CACHE_LINE)..((end + FETCH_CACHE_LINE - 1) / FETCH_CACHE_LINE) {
+            let slot = line % FETCH_CACHE_LINES;
+
+            if cache_tags[slot] != line {
+                cache_tags[slot] = line;
+                bytes_fetched += FETCH_CACHE_LINE;
+            }
+        }
+
+        if !used[index] {
+            used[index] = true;
+            used_count += 1;
+        }
+    }
+
+    VertexFetchStatistics {
+        bytes_fetched: bytes_fetched as u32,
+        overfetch: if used_count == 0 { 0.0 } else { bytes_fetched as f32 / (used_count * vertex_size) as f32 },
+    }
+}
+
+// merge vertices whose bytes are identical
+pub fn weld_vertices<T: PlainVertex>(mesh: &Mesh<T>) -> Mesh<T> {
+
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut remap = vec![u32::max_value(); mesh.vertices.len()];
+    let mut unique = HashMap::<&[u8], u32>::with_capacity(mesh.vertices.len());
+
+    // only referenced vertices are kept
+    for &index in mesh.indices.iter() {
+        let index = index as usize;
+
+        if remap[index] != u32::max_value() {
+            continue;
+        }
+
+        let vertex = &mesh.vertices[index];
+        // PlainVertex guarantees there are no padding bytes
+        let bytes = unsafe { slice::from_raw_parts((vertex as *const T).cast::<u8>(), mem::size_of::<T>()) };
+
+        let next = vertices.len() as u32;
+        let new_index = *unique.entry(bytes).or_insert(next);
+
+        if new_index == next {
+            vertices.push(*vertex);
+        }
+
+        remap[index] = new_index;
+    }
+
+    let indices = mesh.indices.iter().map(|&index| remap[index as usize]).collect();
+
+    Mesh::new(vertices, indices)
+}
+
+// tipsify (Sander et al. 2007, "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw")
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<u32> {
+
+    let triangle_count = indices.len() / 3;
+
+    // vertex -> triangles adjacency
+    let mut live = vec![0u32; vertex_count];
+    for &index in indices.iter() {
+        live[index as usize] += 1;
+    }
+
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for vertex in 0..vertex_count {
+        offsets[vertex + 1] = offsets[vertex] + live[vertex] as usize;
+    }
+
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut fill = offsets.clone();
+    for (corner, &index) in indices.iter().enumerate() {
+        adjacency[fill[index as usize]] = (corner / 3) as u32;
+        fill[index as usize] += 1;
+    }
+
+    let mut cache_timestamps = vec![0usize; vertex_count];
+    let mut timestamp = cache_size + 1;
+
+    let mut emitted = vec![false; triangle_count];
+    let mut dead_end = Vec::<u32>::with_capacity(indices.len());
+    let mut candidates = Vec::<u32>::with_capacity(16);
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+
+    let mut cursor = 0usize;
+    let mut fanning = if vertex_count > 0 { Some(0u32) } else { None };
+
+    while let Some(vertex) = fanning {
+        candidates.clear();
+
+        let vertex = vertex as usize;
+
+        for &triangle in adjacency[offsets[vertex]..offsets[vertex + 1]].iter() {
+            let triangle = triangle as usize;
+
+            if emitted[triangle] {
+                continue;
+            }
+
+            for &index in indices[triangle * 3..triangle * 3 + 3].iter() {
+                output.push(index);
+                dead_end.push(index);
+                candidates.push(index);
+
+                live[index as usize] -= 1;
+
+                if timestamp - cache_timestamps[index as usize] > cache_size {
+                    cache_timestamps[index as usize] = timestamp;
+                    timestamp += 1;
+                }
+            }
+
+            emitted[triangle] = true;
+        }
+
+        fanning = next_fanning_vertex(&candidates, &live, &cache_timestamps, timestamp, cache_size)
+            .or_else(|| skip_dead_end(&mut dead_end, &live, &mut cursor));
+    }
+
+    output
+}
+
+// prefer vertices which will still be in the cache after their remaining triangles are emitted
+fn next_fanning_vertex(candidates: &[u32], live: &[u32], cache_timestamps: &[usize], timestamp: usize, cache_size: usize) -> Option<u32> {
+
+    let mut best = None;
+    let mut best_priority = -1i64;
+
+    for &vertex in candidates.iter() {
+        let vertex_live = live[vertex as usize] as usize;
+
+        if vertex_live == 0 {
+            continue;
+        }
+
+        let age = timestamp - cache_timestamps[vertex as usize];
+
+        let priority = if age + 2 * vertex_live <= cache_size { age as i64 } else { 0 };
+
+        if priority > best_priority {
+            best_priority = priority;
+            best = Some(vertex);
+        }
+    }
+
+    best
+}
+
+fn skip_dead_end(dead_end: &mut Vec<u32>, live: &[u32], cursor: &mut usize) -> Option<u32> {
+
+    while let Some(vertex) = dead_end.pop() {
+        if live[vertex as usize] > 0 {
+            return Some(vertex);
+        }
+    }
+
+    while *cursor < live.len() {
+        let vertex = *cursor;
+        *cursor += 1;
+
+        if live[vertex] > 0 {
+            return Some(vertex as u32);
+        }
+    }
+
+    None
+}
+
+// split a cache optimized triangle order into clusters and draw outward facing clusters first,
+// clusters are only split where the ACMR stays within `threshold` of the unsplit order
+pub fn optimize_overdraw(indices: &[u32], positions: &[XMFLOAT3], cache_size: usize, threshold: f32) -> Vec<u32> {
+
+    let triangle_count = indices.len() / 3;
+
+    if triangle_count == 0 {
+        return Vec::new();
+    }
+
+    let clusters = generate_soft_boundaries(indices, positions.len(), cache_size, threshold);
+
+    // mesh center, area weighted
+    let mut mesh_center = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+    let mut mesh_area = 0.0f32;
+
+    for triangle in indices.chunks(3) {
+        let (p0, p1, p2) = (positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]);
+        let area = super::length(triangle_normal(p0, p1, p2));
+
+        mesh_center = add(mesh_center, scale(add(add(p0, p1), p2), area / 3.0));
+        mesh_area += area;
+    }
+
+    if mesh_area > 0.0 {
+        mesh_center = scale(mesh_center, 1.0 / mesh_area);
+    }
+
+    let mut sort_keys = Vec::with_capacity(clusters.len());
+
+    for (cluster, range) in clusters.windows(2).enumerate() {
+        let mut center = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+        let mut normal = XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 };
+        let mut area_sum = 0.0f32;
+
+        for triangle in indices[range[0] * 3..range[1] * 3].chunks(3) {
+            let (p0, p1, p2) = (positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]);
+            let triangle_normal = triangle_normal(p0, p1, p2);
+            let area = super::length(triangle_normal);
+
+            center = add(center, scale(add(add(p0, p1), p2), area / 3.0));
+            normal = add(normal, triangle_normal);
+            area_sum += area;
+        }
+
+        if area_sum > 0.0 {
+            center = scale(center, 1.0 / area_sum);
+        }
+
+        sort_keys.push((dot(sub(center, mesh_center), normalize(normal)), cluster));
+    }
+
+    // stable, so equal keys keep the cache friendly order
+    sort_keys.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut output = Vec::with_capacity(indices.len());
+
+    for &(_, cluster) in sort_keys.iter() {
+        output.extend_from_slice(&indices[clusters[cluster] * 3..clusters[cluster + 1] * 3]);
+    }
+
+    output
+}
+
+// cache misses of a single triangle on a simulated FIFO cache
+fn update_cache(triangle: &[u32], cache_timestamps: &mut [usize], timestamp: &mut usize, cache_size: usize) -> u32 {
+
+    let mut misses = 0;
+
+    for &index in triangle.iter() {
+        if *timestamp - cache_timestamps[index as usize] > cache_size {
+            cache_timestamps[index as usize] = *timestamp;
+            *timestamp += 1;
+            misses += 1;
+        }
+    }
+
+    misses
+}
+
+// triangle offsets of cluster starts, a hard boundary is a triangle with all vertices missing the cache
+fn generate_hard_boundaries(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<usize> {
+
+    let mut cache_timestamps = vec![0usize; vertex_count];
+    let mut timestamp = cache_size + 1;
+
+    let mut boundaries = Vec::new();
+
+    for (triangle, corners) in indices.chunks(3).enumerate() {
+        let misses = update_cache(corners, &mut cache_timestamps, &mut timestamp, cache_size);
+
+        if triangle == 0 || misses == 3 {
+            boundaries.push(triangle);
+        }
+    }
+
+    boundaries.push(indices.len() / 3);
+
+    boundaries
+}
+
+fn generate_soft_boundaries(indices: &[u32], vertex_count: usize, cache_size: usize, threshold: f32) -> Vec<usize> {
+
+    let hard_boundaries = generate_hard_boundaries(indices, vertex_count, cache_size);
+
+    let mut cache_timestamps = vec![0usize; vertex_count];
+    let mut timestamp = cache_size + 1;
+
+    let mut boundaries = Vec::new();
+
+    for range in hard_boundaries.windows(2) {
+        let (start, end) = (range[0], range[1]);
+
+        // ACMR of the whole hard cluster on a fresh cache
+        timestamp += cache_size + 1;
+
+        let mut cluster_misses = 0;
+        for triangle in start..end {
+            cluster_misses += update_cache(&indices[triangle * 3..triangle * 3 + 3], &mut cache_timestamps, &mut timestamp, cache_size);
+        }
+
+        let cluster_threshold = threshold * cluster_misses as f32 / (end - start) as f32;
+
+        // split whenever the running ACMR falls below the threshold
+        timestamp += cache_size + 1;
+
+        let mut soft_start = start;
+        let mut soft_misses = 0;
+
+        boundaries.push(start);
+
+        for triangle in start..end {
+            soft_misses += update_cache(&indices[triangle * 3..triangle * 3 + 3], &mut cache_timestamps, &mut timestamp, cache_size);
+
+            if triangle + 1 < end && soft_misses as f32 / (triangle + 1 - soft_start) as f32 <= cluster_threshold {
+                boundaries.push(triangle + 1);
+
+                soft_start = triangle + 1;
+                soft_misses = 0;
+                timestamp += cache_size + 1;
+            }
+        }
+    }
+
+    boundaries.push(indices.len() / 3);
+
+    boundaries
+}
+
+// reorder vertices by first use so that vertex fetch is sequential, unused vertices are removed
+pub fn optimize_vertex_fetch<T: MeshVertex>(mesh: &Mesh<T>) -> Mesh<T> {
+
+    let mut remap = vec![u32::max_value(); mesh.vertices.len()];
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+
+    let indices = mesh.indices.iter().map(|&index| {
+        let index = index as usize;
+
+        if remap[index] == u32::max_value() {
+            remap[index] = vertices.len() as u32;
+            vertices.push(mesh.vertices[index]);
+        }
+
+        remap[index]
+    }).collect();
+
+    Mesh::new(vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{ Vertex, NormalVertex, TangentVertex, XMFLOAT2 };
+
+    // (n + 1) x (n + 1) vertex grid, without shared vertices between triangles when `split` is set
+    fn grid(n: u32, split: bool) -> Mesh<Vertex> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        let vertex = |x: u32, y: u32| Vertex {
+            position: XMFLOAT3 { x: x as f32, y: y as f32, z: 0.0 },
+            uv: XMFLOAT2 { x: x as f32 / n as f32, y: y as f32 / n as f32 },
+        };
+
+        let mut grid_vertices = Vec::new();
+
+        for y in 0..=n {
+            for x in 0..=n {
+                grid_vertices.push(vertex(x, y));
+            }
+        }
+
+        if !split {
+            vertices = grid_vertices.clone();
+        }
+
+        for y in 0..n {
+            for x in 0..n {
+                let i0 = y * (n + 1) + x;
+                let quad = [i0, i0 + n + 1, i0 + 1, i0 + 1, i0 + n + 1, i0 + n + 2];
+
+                for &index in quad.iter() {
+                    if split {
+                        indices.push(vertices.len() as u32);
+                        vertices.push(grid_vertices[index as usize]);
+                    } else {
+                        indices.push(index);
+                    }
+                }
+            }
+        }
+
+        Mesh::new(vertices, indices)
+    }
+
+    // deterministic triangle shuffle
+    fn shuffle_triangles(indices: &[u32]) -> Vec<u32> {
+        let mut triangles: Vec<&[u32]> = indices.chunks(3).collect();
+        let mut seed = 12345u32;
+
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            triangles.swap(i, (seed as usize) % (i + 1));
+        }
+
+        triangles.concat()
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = indices.chunks(3).map(|t| {
+            // rotate so the smallest index comes first, keeping the winding
+            let r = (0..3).min_by_key(|&i| t[i]).unwrap();
+            [t[r], t[(r + 1) % 3], t[(r + 2) % 3]]
+        }).collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn analyze_vertex_cache_counts_misses() {
+        let statistics = analyze_vertex_cache(&[0, 1, 2, 2, 1, 3], 4, 16);
+
+        assert_eq!(statistics.vertices_transformed, 4);
+        assert_eq!(statistics.acmr, 2.0);
+        assert_eq!(statistics.atvr, 1.0);
+    }
+
+    #[test]
+    fn weld_vertices_merges_duplicates() {
+        let mesh = grid(4, true);
+        assert_eq!(mesh.vertices.len(), 4 * 4 * 6);
+
+        let welded = weld_vertices(&mesh);
+
+        assert_eq!(welded.vertices.len(), 5 * 5);
+        assert_eq!(welded.indices.len(), mesh.indices.len());
+
+        for (&before, &after) in mesh.indices.iter().zip(welded.indices.iter()) {
+            assert_eq!(mesh.vertices[before as usize].position.x, welded.vertices[after as usize].position.x);
+            assert_eq!(mesh.vertices[before as usize].position.y, welded.vertices[after as usize].position.y);
+        }
+    }
+
+    #[test]
+    fn plain_vertices_have_no_padding() {
+        assert_eq!(mem::size_of::<Vertex>(), 5 * 4);
+        assert_eq!(mem::size_of::<NormalVertex>(), 8 * 4);
+        assert_eq!(mem::size_of::<TangentVertex>(), 12 * 4);
+    }
+
+    #[test]
+    fn optimize_vertex_cache_improves_acmr() {
+        let mesh = grid(32, false);
+        let shuffled = shuffle_triangles(&mesh.indices);
+
+        let before = analyze_vertex_cache(&shuffled, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+
+        let optimized = optimize_vertex_cache(&shuffled, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+        let after = analyze_vertex_cache(&optimized, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&shuffled));
+        assert!(after.acmr < before.acmr * 0.5, "acmr {} -> {}", before.acmr, after.acmr);
+        assert!(after.acmr < 1.0);
+    }
+
+    #[test]
+    fn optimize_overdraw_keeps_triangles_and_threshold() {
+        let mesh = grid(32, false);
+        let cache_optimized = optimize_vertex_cache(&mesh.indices, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+        let before = analyze_vertex_cache(&cache_optimized, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+
+        let optimized = optimize_overdraw(&cache_optimized, &mesh.positions(), DEFAULT_CACHE_SIZE, 1.05);
+        let after = analyze_vertex_cache(&optimized, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+
+        assert_eq!(sorted_triangles(&optimized), sorted_triangles(&cache_optimized));
+        assert!(after.acmr <= before.acmr * 1.05 + 0.05, "acmr {} -> {}", before.acmr, after.acmr);
+    }
+
+    #[test]
+    fn optimize_vertex_fetch_orders_by_first_use() {
+        let mesh = Mesh::new(grid(1, false).vertices, vec![3, 1, 2, 2, 1, 0]);
+
+        let optimized = optimize_vertex_fetch(&mesh);
+
+        assert_eq!(optimized.indices, vec![0, 1, 2, 2, 1, 3]);
+        assert_eq!(optimized.vertices[0].position.x, mesh.vertices[3].position.x);
+        assert_eq!(optimized.vertices[0].position.y, mesh.vertices[3].position.y);
+    }
+
+    #[test]
+    fn optimize_mesh_reports_every_step() {
+        let mesh = grid(16, true);
+        let shuffled = Mesh::new(mesh.vertices.clone(), shuffle_triangles(&mesh.indices));
+
+        let (optimized, report) = optimize_mesh(&shuffled, &OptimizeOptions::default());
+
+        assert_eq!(report.original.vertex_count, 16 * 16 * 6);
+        assert_eq!(report.weld.vertex_count, 17 * 17);
+        assert_eq!(optimized.vertices.len(), 17 * 17);
+        assert_eq!(optimized.indices.len(), shuffled.indices.len());
+
+        assert!(report.weld.cache.acmr < report.original.cache.acmr);
+        assert!(report.vertex_cache.cache.acmr < report.weld.cache.acmr);
+        assert!(report.overdraw.cache.acmr <= report.vertex_cache.cache.acmr * 1.05 + 0.05);
+        assert!(report.vertex_fetch.fetch.overfetch <= report.overdraw.fetch.overfetch);
+    }
+}
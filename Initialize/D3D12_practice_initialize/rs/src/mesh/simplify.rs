@@ -0,0 +1,574 @@
+use crate::lib::XMFLOAT3;
+use super::{ Mesh, MeshVertex, sub, dot, cross, length, normalize, triangle_normal };
+
+use std::collections::{ HashMap, HashSet };
+
+// border edges are kept much stiffer than interior surface
+const BORDER_WEIGHT: f64 = 10.0;
+
+// lod ratios of the original triangle count
+pub const DEFAULT_LOD_RATIOS: [f32; 4] = [1.0, 0.5, 0.25, 0.125];
+
+#[derive(Debug, Clone)]
+pub struct SimplifyResult {
+    // indices into the unchanged vertex buffer
+    pub indices: Vec<u32>,
+    // largest deviation introduced, in object space units
+    pub error: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodLevel {
+    pub index_offset: u32,
+    pub index_count: u32,
+    pub error: f32,
+}
+
+// every level shares the vertex buffer, levels are ranges of one index buffer
+#[derive(Debug, Clone)]
+pub struct LodChain {
+    pub indices: Vec<u32>,
+    pub levels: Vec<LodLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VertexKind {
+    // interior vertex, may collapse onto any neighbor
+    Manifold,
+    // open boundary, may only slide along the boundary
+    Border,
+    // uv seam, non manifold or border corner, never moves
+    Locked,
+}
+
+// symmetric 4x4 error quadric, weighted sum of squared plane distances
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric {
+    a2: f64, b2: f64, c2: f64, d2: f64,
+    ab: f64, ac: f64, ad: f64,
+    bc: f64, bd: f64,
+    cd: f64,
+    weight: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: XMFLOAT3, point: XMFLOAT3, weight: f64) -> Quadric {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+
+        Quadric {
+            a2: a * a * weight, b2: b * b * weight, c2: c * c * weight, d2: d * d * weight,
+            ab: a * b * weight, ac: a * c * weight, ad: a * d * weight,
+            bc: b * c * weight, bd: b * d * weight,
+            cd: c * d * weight,
+            weight: weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.a2 += other.a2; self.b2 += other.b2; self.c2 += other.c2; self.d2 += other.d2;
+        self.ab += other.ab; self.ac += other.ac; self.ad += other.ad;
+        self.bc += other.bc; self.bd += other.bd;
+        self.cd += other.cd;
+        self.weight += other.weight;
+    }
+
+    // average squared distance of `p` to the accumulated planes
+    fn error(&self, p: XMFLOAT3) -> f64 {
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+
+        let error = self.a2 * x * x + self.b2 * y * y + self.c2 * z * z
+            + 2.0 * (self.ab * x * y + self.ac * x * z + self.bc * y * z)
+            + 2.0 * (self.ad * x + self.bd * y + self.cd * z)
+            + self.d2;
+
+        if self.weight > 0.0 { (error / self.weight).abs() } else { 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    from: u32,
+    to: u32,
+    cost: f64,
+}
+
+// quadric error metric edge collapse (Garland & Heckbert 1997), vertices are never moved,
+// so the result indexes the original vertex buffer
+pub fn simplify<T: MeshVertex>(mesh: &Mesh<T>, target_index_count: usize, max_error: f32) -> SimplifyResult {
+    simplify_indices(&mesh.positions(), &mesh.indices, target_index_count, max_error)
+}
+
+pub fn simplify_indices(positions: &[XMFLOAT3], indices: &[u32], target_index_count: usize, max_error: f32) -> SimplifyResult {
+
+    let vertex_count = positions.len();
+
+    // vertices at the same position (uv seams) share one representative
+    let mut representatives = vec![0u32; vertex_count];
+    let mut wedge_counts = vec![0u32; vertex_count];
+    {
+        let mut first = HashMap::<[u32; 3], u32>::new();
+
+        for (vertex, position) in positions.iter().enumerate() {
+            let key = [(position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits()];
+            representatives[vertex] = *first.entry(key).or_insert(vertex as u32);
+        }
+
+        // only referenced wedges make a seam
+        let mut referenced = vec![false; vertex_count];
+        for &index in indices.iter() {
+            referenced[index as usize] = true;
+        }
+        for vertex in 0..vertex_count {
+            if referenced[vertex] {
+                wedge_counts[representatives[vertex] as usize] += 1;
+            }
+        }
+    }
+
+    let rep = |index: u32| representatives[index as usize];
+
+    let kinds = classify_vertices(indices, &representatives, &wedge_counts);
+
+    let border_edges = find_border_edges(indices, &representatives);
+
+    let mut quadrics = vec![Quadric::default(); vertex_count];
+
+    for triangle in indices.chunks_exact(3) {
+        let p = [positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]];
+
+        let normal = triangle_normal(p[0], p[1], p[2]);
+        let area = length(normal) as f64 * 0.5;
+
+        if area <= 0.0 {
+            continue;
+        }
+
+        let normal = normalize(normal);
+        let face_quadric = Quadric::from_plane(normal, p[0], area);
+
+        for corner in 0..3 {
+            quadrics[rep(triangle[corner]) as usize].add(&face_quadric);
+        }
+
+        // plane through the border edge, perpendicular to the face
+        for corner in 0..3 {
+            let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+
+            if !border_edges.contains(&(rep(a), rep(b))) {
+                continue;
+            }
+
+            let edge = sub(positions[b as usize], positions[a as usize]);
+            let edge_length = length(edge) as f64;
+            let border_quadric = Quadric::from_plane(normalize(cross(edge, normal)), positions[a as usize], edge_length * edge_length * BORDER_WEIGHT);
+
+            quadrics[rep(a) as usize].add(&border_quadric);
+            quadrics[rep(b) as usize].add(&border_quadric);
+        }
+    }
+
+    let max_cost = (max_error as f64) * (max_error as f64);
+
+    let mut indices = indices.to_vec();
+    let mut result_cost = 0.0f64;
+
+    while indices.len() > target_index_count {
+
+        let mut collapses = Vec::new();
+        {
+            let mut seen = HashSet::<(u32, u32)>::new();
+
+            for triangle in indices.chunks_exact(3) {
+                for corner in 0..3 {
+                    let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+
+                    for &(from, to) in [(a, b), (b, a)].iter() {
+                        if !seen.insert((from, to)) || !can_collapse(from, to, &kinds, &representatives, &border_edges) {
+                            continue;
+                        }
+
+                        let mut quadric = quadrics[rep(from) as usize];
+                        quadric.add(&quadrics[rep(to) as usize]);
+
+                        collapses.push(Collapse {
+                            from: from,
+                            to: to,
+                            cost: quadric.error(positions[to as usize]),
+                        });
+                    }
+                }
+            }
+        }
+
+        collapses.sort_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap_or(std::cmp::Ordering::Equal));
+
+        let adjacency = build_adjacency(&indices, vertex_count);
+
+        let mut locked = vec![false; vertex_count];
+        let mut collapse_remap: Vec<u32> = (0..vertex_count as u32).collect();
+
+        let mut triangle_estimate = indices.len() / 3;
+        let mut collapsed = 0;
+
+        for collapse in collapses.iter() {
+            if collapse.cost > max_cost || triangle_estimate * 3 <= target_index_count {
+                break;
+            }
+
+            if locked[rep(collapse.from) as usize] || locked[rep(collapse.to) as usize] {
+                continue;
+            }
+
+            if flips_triangle(collapse, &indices, &adjacency, positions) {
+                continue;
+            }
+
+            // the one ring of `from` changes, so nothing in it may collapse during this pass
+            for &triangle in adjacency[collapse.from as usize].iter() {
+                for &index in indices[triangle as usize * 3..triangle as usize * 3 + 3].iter() {
+                    locked[rep(index) as usize] = true;
+                }
+            }
+
+            collapse_remap[collapse.from as usize] = collapse.to;
+
+            let from_quadric = quadrics[rep(collapse.from) as usize];
+            quadrics[rep(collapse.to) as usize].add(&from_quadric);
+
+            triangle_estimate -= if kinds[rep(collapse.from) as usize] == VertexKind::Border { 1 } else { 2 };
+            result_cost = result_cost.max(collapse.cost);
+            collapsed += 1;
+        }
+
+        if collapsed == 0 {
+            break;
+        }
+
+        // apply collapses and drop degenerate triangles
+        let mut next_indices = Vec::with_capacity(indices.len());
+
+        for triangle in indices.chunks_exact(3) {
+            let t = [collapse_remap[triangle[0] as usize], collapse_remap[triangle[1] as usize], collapse_remap[triangle[2] as usize]];
+
+            if rep(t[0]) != rep(t[1]) && rep(t[1]) != rep(t[2]) && rep(t[2]) != rep(t[0]) {
+                next_indices.extend_from_slice(&t);
+            }
+        }
+
+        indices = next_indices;
+    }
+
+    SimplifyResult {
+        indices: indices,
+        error: result_cost.sqrt() as f32,
+    }
+}
+
+fn classify_vertices(indices: &[u32], representatives: &[u32], wedge_counts: &[u32]) -> Vec<VertexKind> {
+
+    let vertex_count = representatives.len();
+
+    let mut edge_counts = HashMap::<(u32, u32), u32>::new();
+    for triangle in indices.chunks_exact(3) {
+        for corner in 0..3 {
+            let a = representatives[triangle[corner] as usize];
+            let b = representatives[triangle[(corner + 1) % 3] as usize];
+            *edge_counts.entry((a, b)).or_insert(0) += 1;
+        }
+    }
+
+    let mut kinds = vec![VertexKind::Manifold; vertex_count];
+    let mut border_out = vec![0u32; vertex_count];
+    let mut border_in = vec![0u32; vertex_count];
+
+    for (&(a, b), &count) in edge_counts.iter() {
+        if count > 1 {
+            // the same directed edge twice is non manifold
+            kinds[a as usize] = VertexKind::Locked;
+            kinds[b as usize] = VertexKind::Locked;
+        }
+
+        if !edge_counts.contains_key(&(b, a)) {
+            border_out[a as usize] += 1;
+            border_in[b as usize] += 1;
+        }
+    }
+
+    for vertex in 0..vertex_count {
+        if kinds[vertex] == VertexKind::Locked {
+            continue;
+        }
+
+        kinds[vertex] = if wedge_counts[vertex] > 1 {
+            VertexKind::Locked
+        } else if border_out[vertex] == 0 && border_in[vertex] == 0 {
+            VertexKind::Manifold
+        } else if border_out[vertex] == 1 && border_in[vertex] == 1 {
+            VertexKind::Border
+        } else {
+            VertexKind::Locked
+        };
+    }
+
+    // wedges follow their representative
+    (0..vertex_count).map(|vertex| kinds[representatives[vertex] as usize]).collect()
+}
+
+// directed edges without an opposite edge, in representative space
+fn find_border_edges(indices: &[u32], representatives: &[u32]) -> HashSet<(u32, u32)> {
+
+    let mut edges = HashSet::new();
+    for triangle in indices.chunks_exact(3) {
+        for corner in 0..3 {
+            edges.insert((representatives[triangle[corner] as usize], representatives[triangle[(corner + 1) % 3] as usize]));
+        }
+    }
+
+    edges.iter().filter(|&&(a, b)| !edges.contains(&(b, a))).cloned().collect()
+}
+
+fn can_collapse(from: u32, to: u32, kinds: &[VertexKind], representatives: &[u32], border_edges: &HashSet<(u32, u32)>) -> bool {
+
+    let (rep_from, rep_to) = (representatives[from as usize], representatives[to as usize]);
+
+    if rep_from == rep_to {
+        return false;
+    }
+
+    match kinds[from as usize] {
+        VertexKind::Manifold => true,
+        VertexKind::Border => {
+            kinds[to as usize] != VertexKind::Manifold
+                && (border_edges.contains(&(rep_from, rep_to)) || border_edges.contains(&(rep_to, rep_from)))
+        },
+        VertexKind::Locked => false,
+    }
+}
+
+fn build_adjacency(indices: &[u32], vertex_count: usize) -> Vec<Vec<u32>> {
+
+    let mut adjacency = vec![Vec::new(); vertex_count];
+
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners.iter() {
+            adjacency[index as usize].push(triangle as u32);
+        }
+    }
+
+    adjacency
+}
+
+// moving `from` onto `to` must not turn any remaining triangle over
+fn flips_triangle(collapse: &Collapse, indices: &[u32], adjacency: &[Vec<u32>], positions: &[XMFLOAT3]) -> bool {
+
+    let target = positions[collapse.to as usize];
+
+    for &triangle in adjacency[collapse.from as usize].iter() {
+        let t = &indices[triangle as usize * 3..triangle as usize * 3 + 3];
+
+        if t.contains(&collapse.to) {
+            continue;
+        }
+
+        let p = [positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]];
+        let before = triangle_normal(p[0], p[1], p[2]);
+
+        let moved: Vec<XMFLOAT3> = t.iter().zip(p.iter()).map(|(&index, &position)| if index == collapse.from { target } else { position }).collect();
+        let after = triangle_normal(moved[0], moved[1], moved[2]);
+
+        // reject flips and collapses that squash the triangle to nothing
+        if dot(before, after) <= 1.0e-4 * dot(before, before) {
+            return true;
+        }
+    }
+
+    false
+}
+
+// one level per ratio, every level is simplified from the original mesh
+pub fn generate_lod_chain<T: MeshVertex>(mesh: &Mesh<T>, ratios: &[f32], max_error: f32) -> LodChain {
+
+    let positions = mesh.positions();
+
+    let mut chain = LodChain {
+        indices: Vec::new(),
+        levels: Vec::with_capacity(ratios.len()),
+    };
+
+    for &ratio in ratios.iter() {
+        let target_index_count = ((mesh.triangle_count() as f32 * ratio) as usize) * 3;
+
+        let result = if target_index_count >= mesh.indices.len() {
+            SimplifyResult { indices: mesh.indices.clone(), error: 0.0 }
+        } else {
+            simplify_indices(&positions, &mesh.indices, target_index_count, max_error)
+        };
+
+        // coarser levels never report less error than finer ones
+        let error = chain.levels.last().map_or(result.error, |level: &LodLevel| level.error.max(result.error));
+
+        chain.levels.push(LodLevel {
+            index_offset: chain.indices.len() as u32,
+            index_count: result.indices.len() as u32,
+            error: error,
+        });
+
+        chain.indices.extend_from_slice(&result.indices);
+    }
+
+    chain
+}
+
+// projected diameter in pixels of a bounding sphere
+pub fn screen_size(radius: f32, distance: f32, fov_y: f32, viewport_height: f32) -> f32 {
+    if distance <= radius {
+        return viewport_height;
+    }
+
+    radius / (distance * (fov_y * 0.5).tan()) * viewport_height
+}
+
+// coarsest level whose error stays below `pixel_error` on screen, the finest level if none does,
+// None for a chain without levels
+pub fn select_lod(chain: &LodChain, screen_size: f32, radius: f32, pixel_error: f32) -> Option<&LodLevel> {
+
+    let pixels_per_unit = if radius > 0.0 { screen_size / (2.0 * radius) } else { 0.0 };
+
+    chain.levels.iter().rev()
+        .find(|level| level.error * pixels_per_unit <= pixel_error)
+        .or_else(|| chain.levels.first())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{ Vertex, XMFLOAT2 };
+
+    // flat n x n quad grid, `seam` duplicates the middle column of vertices with a different uv
+    fn grid(n: u32, seam: bool) -> Mesh<Vertex> {
+        let mut vertices = Vec::new();
+
+        for y in 0..=n {
+            for x in 0..=n {
+                vertices.push(Vertex {
+                    position: XMFLOAT3 { x: x as f32, y: y as f32, z: 0.0 },
+                    uv: XMFLOAT2 { x: x as f32 / n as f32, y: y as f32 / n as f32 },
+                });
+            }
+        }
+
+        let seam_start = vertices.len() as u32;
+        if seam {
+            for y in 0..=n {
+                let mut vertex = vertices[(y * (n + 1) + n / 2) as usize];
+                vertex.uv.x += 1.0;
+                vertices.push(vertex);
+            }
+        }
+
+        let index = |x: u32, y: u32| {
+            if seam && x == n / 2 { seam_start + y } else { y * (n + 1) + x }
+        };
+
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                // the right island uses the duplicated seam vertices
+                let left = if x < n / 2 { y * (n + 1) + x } else { index(x, y) };
+                let left_up = if x < n / 2 { (y + 1) * (n + 1) + x } else { index(x, y + 1) };
+                let right = (y * (n + 1)) + x + 1;
+                let right_up = ((y + 1) * (n + 1)) + x + 1;
+
+                indices.extend_from_slice(&[left, left_up, right, right, left_up, right_up]);
+            }
+        }
+
+        Mesh::new(vertices, indices)
+    }
+
+    fn area(mesh: &Mesh<Vertex>, indices: &[u32]) -> f32 {
+        indices.chunks(3).map(|t| {
+            let normal = triangle_normal(mesh.vertices[t[0] as usize].position, mesh.vertices[t[1] as usize].position, mesh.vertices[t[2] as usize].position);
+            length(normal) * 0.5
+        }).sum()
+    }
+
+    #[test]
+    fn simplify_flat_grid_without_error() {
+        let mesh = grid(16, false);
+
+        let result = simplify(&mesh, mesh.indices.len() / 4, 1.0e-3);
+
+        assert!(result.indices.len() <= mesh.indices.len() / 4, "{} indices", result.indices.len());
+        assert!(result.error < 1.0e-3);
+        assert!((area(&mesh, &result.indices) - 256.0).abs() < 1.0e-2);
+
+        // corners of the border never move
+        for &corner in [0u32, 16, 17 * 16, 17 * 17 - 1].iter() {
+            assert!(result.indices.contains(&corner));
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_uv_seams() {
+        let mesh = grid(16, true);
+
+        let result = simplify(&mesh, 0, 1.0e-3);
+
+        assert!(result.indices.len() < mesh.indices.len() / 4);
+
+        // every seam wedge on both sides is still referenced
+        for y in 0..=16u32 {
+            assert!(result.indices.contains(&(y * 17 + 8)));
+            assert!(result.indices.contains(&(17 * 17 + y)));
+        }
+    }
+
+    #[test]
+    fn simplify_reports_error_for_curved_surface() {
+        let mut mesh = grid(16, false);
+        for vertex in mesh.vertices.iter_mut() {
+            vertex.position.z = ((vertex.position.x - 8.0) * (vertex.position.x - 8.0) + (vertex.position.y - 8.0) * (vertex.position.y - 8.0)) * 0.05;
+        }
+
+        let strict = simplify(&mesh, 0, 1.0e-4);
+        let loose = simplify(&mesh, 0, 1.0);
+
+        assert!(loose.indices.len() < strict.indices.len());
+        assert!(loose.error > 0.0 && loose.error <= 1.0);
+    }
+
+    #[test]
+    fn lod_chain_and_selection() {
+        let mut mesh = grid(32, false);
+        for vertex in mesh.vertices.iter_mut() {
+            vertex.position.z = (vertex.position.x * 0.3).sin() * (vertex.position.y * 0.3).cos();
+        }
+
+        let chain = generate_lod_chain(&mesh, &DEFAULT_LOD_RATIOS, 1.0);
+
+        assert_eq!(chain.levels.len(), 4);
+        assert_eq!(chain.levels[0].index_count as usize, mesh.indices.len());
+        assert_eq!(chain.levels[0].error, 0.0);
+
+        for pair in chain.levels.windows(2) {
+            assert_eq!(pair[1].index_offset, pair[0].index_offset + pair[0].index_count);
+            assert!(pair[1].index_count < pair[0].index_count);
+            assert!(pair[1].error >= pair[0].error);
+        }
+        assert_eq!(chain.indices.len() as u32, chain.levels[3].index_offset + chain.levels[3].index_count);
+
+        let radius = 32.0;
+        let near = screen_size(radius, 40.0, 1.0, 720.0);
+        let far = screen_size(radius, 40000.0, 1.0, 720.0);
+        assert!(near > far);
+
+        assert_eq!(select_lod(&chain, near, radius, 0.0).unwrap().index_offset, 0);
+        assert_eq!(select_lod(&chain, far, radius, 1.0).unwrap().index_offset, chain.levels[3].index_offset);
+
+        let empty = generate_lod_chain(&mesh, &[], 1.0);
+        assert!(empty.levels.is_empty());
+        assert!(select_lod(&empty, near, radius, 0.0).is_none());
+    }
+}
@@ -0,0 +1,416 @@
+use winapi::{
+    um::{
+        d3d12,
+        d3dcommon,
+    },
+    shared::{
+        dxgiformat,
+        dxgitype,
+        minwindef::{ TRUE, FALSE },
+        winerror,
+    },
+};
+
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::lib;
+
+// CD3DX12_RASTERIZER_DESC(D3D12_DEFAULT)
+pub fn default_rasterizer_desc() -> d3d12::D3D12_RASTERIZER_DESC {
+    d3d12::D3D12_RASTERIZER_DESC {
+        FillMode: d3d12::D3D12_FILL_MODE_SOLID,
+        CullMode: d3d12::D3D12_CULL_MODE_BACK,
+        FrontCounterClockwise: FALSE,
+        DepthBias: d3d12::D3D12_DEFAULT_DEPTH_BIAS as i32,
+        DepthBiasClamp: d3d12::D3D12_DEFAULT_DEPTH_BIAS_CLAMP,
+        SlopeScaledDepthBias: d3d12::D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS,
+        DepthClipEnable: TRUE,
+        MultisampleEnable: FALSE,
+        AntialiasedLineEnable: FALSE,
+        ForcedSampleCount: 0,
+        ConservativeRaster: d3d12::D3D12_CONSERVATIVE_RASTERIZATION_MODE_OFF,
+    }
+}
+
+// opaque, writes every channel
+pub fn default_render_target_blend_desc() -> d3d12::D3D12_RENDER_TARGET_BLEND_DESC {
+    d3d12::D3D12_RENDER_TARGET_BLEND_DESC {
+        BlendEnable: FALSE,
+        LogicOpEnable: FALSE,
+        SrcBlend: d3d12::D3D12_BLEND_ONE,
+        DestBlend: d3d12::D3D12_BLEND_ZERO,
+        BlendOp: d3d12::D3D12_BLEND_OP_ADD,
+        SrcBlendAlpha: d3d12::D3D12_BLEND_ONE,
+        DestBlendAlpha: d3d12::D3D12_BLEND_ZERO,
+        BlendOpAlpha: d3d12::D3D12_BLEND_OP_ADD,
+        LogicOp: d3d12::D3D12_LOGIC_OP_NOOP,
+        RenderTargetWriteMask: d3d12::D3D12_COLOR_WRITE_ENABLE_ALL as u8,
+    }
+}
+
+// CD3DX12_BLEND_DESC(D3D12_DEFAULT)
+pub fn default_blend_desc() -> d3d12::D3D12_BLEND_DESC {
+    d3d12::D3D12_BLEND_DESC {
+        AlphaToCoverageEnable: FALSE,
+        IndependentBlendEnable: FALSE,
+        RenderTarget: [default_render_target_blend_desc(); d3d12::D3D12_SIMULTANEOUS_RENDER_TARGET_COUNT as usize],
+    }
+}
+
+// CD3DX12_DEPTH_STENCIL_DESC(D3D12_DEFAULT)
+pub fn default_depth_stencil_desc() -> d3d12::D3D12_DEPTH_STENCIL_DESC {
+    let stencil_op = d3d12::D3D12_DEPTH_STENCILOP_DESC {
+        StencilFailOp: d3d12::D3D12_STENCIL_OP_KEEP,
+        StencilDepthFailOp: d3d12::D3D12_STENCIL_OP_KEEP,
+        StencilPassOp: d3d12::D3D12_STENCIL_OP_KEEP,
+        StencilFunc: d3d12::D3D12_COMPARISON_FUNC_ALWAYS,
+    };
+
+    d3d12::D3D12_DEPTH_STENCIL_DESC {
+        DepthEnable: TRUE,
+        DepthWriteMask: d3d12::D3D12_DEPTH_WRITE_MASK_ALL,
+        DepthFunc: d3d12::D3D12_COMPARISON_FUNC_LESS,
+        StencilEnable: FALSE,
+        StencilReadMask: d3d12::D3D12_DEFAULT_STENCIL_READ_MASK as u8,
+        StencilWriteMask: d3d12::D3D12_DEFAULT_STENCIL_WRITE_MASK as u8,
+        FrontFace: stencil_op,
+        BackFace: stencil_op,
+    }
+}
+
+// bytecode of a compiled shader, the blob has to outlive the pipeline creation
+pub fn shader_bytecode(blob: *mut d3dcommon::ID3DBlob) -> d3d12::D3D12_SHADER_BYTECODE {
+    unsafe {
+        d3d12::D3D12_SHADER_BYTECODE {
+            pShaderBytecode: blob.as_ref().unwrap().GetBufferPointer(),
+            BytecodeLength: blob.as_ref().unwrap().GetBufferSize(),
+        }
+    }
+}
+
+// bytecode kept in memory, e.g. from the shader cache
+pub fn bytecode(code: &[u8]) -> d3d12::D3D12_SHADER_BYTECODE {
+    d3d12::D3D12_SHADER_BYTECODE {
+        pShaderBytecode: code.as_ptr() as *const _,
+        BytecodeLength: code.len(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PipelineError {
+    MissingRootSignature,
+    MissingVertexShader,
+    TooManyRenderTargets(u32),
+    // render_target_blend() was given an index past the last render target slot
+    BlendIndexOutOfRange(usize),
+    // a format is missing below NumRenderTargets or set above it
+    RenderTargetFormat { index: usize, format: dxgiformat::DXGI_FORMAT },
+    DepthStencilWithoutFormat,
+    BlendAndLogicOp { index: usize },
+    InvalidSampleDesc { count: u32, quality: u32 },
+    Device(winerror::HRESULT),
+}
+
+fn is_enabled(value: i32) -> bool {
+    value != FALSE
+}
+
+// checks the debug layer would otherwise only report at creation
+pub fn validate(desc: &d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC) -> Result<(), PipelineError> {
+
+    if desc.pRootSignature.is_null() {
+        return Err(PipelineError::MissingRootSignature);
+    }
+
+    if desc.VS.pShaderBytecode.is_null() || desc.VS.BytecodeLength == 0 {
+        return Err(PipelineError::MissingVertexShader);
+    }
+
+    if desc.NumRenderTargets > d3d12::D3D12_SIMULTANEOUS_RENDER_TARGET_COUNT {
+        return Err(PipelineError::TooManyRenderTargets(desc.NumRenderTargets));
+    }
+
+    for (index, &format) in desc.RTVFormats.iter().enumerate() {
+        let bound = index < desc.NumRenderTargets as usize;
+
+        if bound == (format == dxgiformat::DXGI_FORMAT_UNKNOWN) {
+            return Err(PipelineError::RenderTargetFormat { index: index, format: format });
+        }
+    }
+
+    let depth_stencil = &desc.DepthStencilState;
+    let depth_stencil_enabled = is_enabled(depth_stencil.DepthEnable) || is_enabled(depth_stencil.StencilEnable);
+
+    // a depth format with depth and stencil disabled is fine, the pass may still bind a depth buffer
+    if desc.DSVFormat == dxgiformat::DXGI_FORMAT_UNKNOWN && depth_stencil_enabled {
+        return Err(PipelineError::DepthStencilWithoutFormat);
+    }
+
+    // without independent blend only the first render target's blend state is used
+    let blend_count = match is_enabled(desc.BlendState.IndependentBlendEnable) {
+        true => desc.NumRenderTargets as usize,
+        false => 1,
+    };
+
+    for (index, blend) in desc.BlendState.RenderTarget.iter().take(blend_count).enumerate() {
+        if is_enabled(blend.BlendEnable) && is_enabled(blend.LogicOpEnable) {
+            return Err(PipelineError::BlendAndLogicOp { index: index });
+        }
+    }
+
+    let sample_desc = desc.SampleDesc;
+
+    if sample_desc.Count == 0 || !sample_desc.Count.is_power_of_two() || (sample_desc.Count == 1 && sample_desc.Quality != 0) {
+        return Err(PipelineError::InvalidSampleDesc { count: sample_desc.Count, quality: sample_desc.Quality });
+    }
+
+    Ok(())
+}
+
+// graphics pipeline description with CD3DX12 defaults, the input layout is borrowed until build()
+pub struct GraphicsPipelineBuilder<'a> {
+    desc: d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC,
+    input_layout: PhantomData<&'a [d3d12::D3D12_INPUT_ELEMENT_DESC]>,
+    // first out of range index passed to render_target_blend(), reported by validate()
+    invalid_blend_index: Option<usize>,
+}
+
+impl<'a> Default for GraphicsPipelineBuilder<'a> {
+    fn default() -> GraphicsPipelineBuilder<'a> {
+        GraphicsPipelineBuilder::new()
+    }
+}
+
+impl<'a> GraphicsPipelineBuilder<'a> {
+    // one triangle list render target of R8G8B8A8_UNORM, no shaders, no depth format
+    pub fn new() -> GraphicsPipelineBuilder<'a> {
+
+        let mut desc: d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC = unsafe { mem::zeroed() };
+
+        desc.BlendState = default_blend_desc();
+        desc.SampleMask = d3d12::D3D12_DEFAULT_SAMPLE_MASK;
+        desc.RasterizerState = default_rasterizer_desc();
+        desc.DepthStencilState = default_depth_stencil_desc();
+        desc.IBStripCutValue = d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_DISABLED;
+        desc.PrimitiveTopologyType = d3d12::D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE;
+        desc.NumRenderTargets = 1;
+        desc.RTVFormats[0] = dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM;
+        desc.SampleDesc = dxgitype::DXGI_SAMPLE_DESC { Count: 1, Quality: 0 };
+
+        GraphicsPipelineBuilder {
+            desc: desc,
+            input_layout: PhantomData,
+            invalid_blend_index: None,
+        }
+    }
+
+    pub fn root_signature(mut self, root_signature: *mut d3d12::ID3D12RootSignature) -> Self {
+        self.desc.pRootSignature = root_signature;
+        self
+    }
+
+    pub fn vertex_shader(mut self, bytecode: d3d12::D3D12_SHADER_BYTECODE) -> Self {
+        self.desc.VS = bytecode;
+        self
+    }
+
+    pub fn pixel_shader(mut self, bytecode: d3d12::D3D12_SHADER_BYTECODE) -> Self {
+        self.desc.PS = bytecode;
+        self
+    }
+
+    pub fn geometry_shader(mut self, bytecode: d3d12::D3D12_SHADER_BYTECODE) -> Self {
+        self.desc.GS = bytecode;
+        self
+    }
+
+    pub fn hull_shader(mut self, bytecode: d3d12::D3D12_SHADER_BYTECODE) -> Self {
+        self.desc.HS = bytecode;
+        self
+    }
+
+    pub fn domain_shader(mut self, bytecode: d3d12::D3D12_SHADER_BYTECODE) -> Self {
+        self.desc.DS = bytecode;
+        self
+    }
+
+    pub fn input_layout(mut self, input_elements: &'a [d3d12::D3D12_INPUT_ELEMENT_DESC]) -> Self {
+        self.desc.InputLayout = d3d12::D3D12_INPUT_LAYOUT_DESC {
+            pInputElementDescs: input_elements.as_ptr(),
+            NumElements: input_elements.len() as u32,
+        };
+        self
+    }
+
+    pub fn primitive_topology(mut self, topology_type: d3d12::D3D12_PRIMITIVE_TOPOLOGY_TYPE) -> Self {
+        self.desc.PrimitiveTopologyType = topology_type;
+        self
+    }
+
+    pub fn strip_cut_value(mut self, strip_cut_value: d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE) -> Self {
+        self.desc.IBStripCutValue = strip_cut_value;
+        self
+    }
+
+    // sets NumRenderTargets as well, formats after the last one are cleared
+    pub fn render_target_formats(mut self, formats: &[dxgiformat::DXGI_FORMAT]) -> Self {
+        self.desc.NumRenderTargets = formats.len() as u32;
+
+        for (index, slot) in self.desc.RTVFormats.iter_mut().enumerate() {
+            *slot = formats.get(index).copied().unwrap_or(dxgiformat::DXGI_FORMAT_UNKNOWN);
+        }
+        self
+    }
+
+    pub fn depth_format(mut self, format: dxgiformat::DXGI_FORMAT) -> Self {
+        self.desc.DSVFormat = format;
+        self
+    }
+
+    pub fn blend_state(mut self, blend_state: d3d12::D3D12_BLEND_DESC) -> Self {
+        self.desc.BlendState = blend_state;
+        self
+    }
+
+    // blend state of one render target, enables independent blend for any but the first
+    pub fn render_target_blend(mut self, index: usize, blend: d3d12::D3D12_RENDER_TARGET_BLEND_DESC) -> Self {
+        if index >= self.desc.BlendState.RenderTarget.len() {
+            self.invalid_blend_index = self.invalid_blend_index.or(Some(index));
+            return self;
+        }
+
+        if index > 0 {
+            self.desc.BlendState.IndependentBlendEnable = TRUE;
+        }
+
+        self.desc.BlendState.RenderTarget[index] = blend;
+        self
+    }
+
+    pub fn rasterizer_state(mut self, rasterizer_state: d3d12::D3D12_RASTERIZER_DESC) -> Self {
+        self.desc.RasterizerState = rasterizer_state;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: d3d12::D3D12_CULL_MODE) -> Self {
+        self.desc.RasterizerState.CullMode = cull_mode;
+        self
+    }
+
+    pub fn fill_mode(mut self, fill_mode: d3d12::D3D12_FILL_MODE) -> Self {
+        self.desc.RasterizerState.FillMode = fill_mode;
+        self
+    }
+
+    pub fn depth_stencil_state(mut self, depth_stencil_state: d3d12::D3D12_DEPTH_STENCIL_DESC) -> Self {
+        self.desc.DepthStencilState = depth_stencil_state;
+        self
+    }
+
+    // for pipelines without a depth buffer
+    pub fn disable_depth(mut self) -> Self {
+        self.desc.DepthStencilState.DepthEnable = FALSE;
+        self.desc.DepthStencilState.StencilEnable = FALSE;
+        self.desc.DSVFormat = dxgiformat::DXGI_FORMAT_UNKNOWN;
+        self
+    }
+
+    pub fn sample_desc(mut self, count: u32, quality: u32) -> Self {
+        self.desc.SampleDesc = dxgitype::DXGI_SAMPLE_DESC { Count: count, Quality: quality };
+        self.desc.RasterizerState.MultisampleEnable = if count > 1 { TRUE } else { FALSE };
+        self
+    }
+
+    pub fn sample_mask(mut self, sample_mask: u32) -> Self {
+        self.desc.SampleMask = sample_mask;
+        self
+    }
+
+    pub fn desc(&self) -> &d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        &self.desc
+    }
+
+    pub fn validate(&self) -> Result<(), PipelineError> {
+        if let Some(index) = self.invalid_blend_index {
+            return Err(PipelineError::BlendIndexOutOfRange(index));
+        }
+
+        validate(&self.desc)
+    }
+
+    pub fn build(self, device: *mut d3d12::ID3D12Device) -> Result<*mut d3d12::ID3D12PipelineState, PipelineError> {
+        self.validate()?;
+
+        lib::create_pipeline_state(device, self.desc).map_err(PipelineError::Device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    static BYTECODE: [u8; 4] = [0x44, 0x58, 0x42, 0x43];
+
+    fn with_shaders<'a>() -> GraphicsPipelineBuilder<'a> {
+        GraphicsPipelineBuilder::new()
+            .root_signature(ptr::NonNull::dangling().as_ptr())
+            .vertex_shader(d3d12::D3D12_SHADER_BYTECODE {
+                pShaderBytecode: BYTECODE.as_ptr() as *const _,
+                BytecodeLength: BYTECODE.len(),
+            })
+    }
+
+    #[test]
+    fn defaults_match_cd3dx12() {
+        let builder = with_shaders();
+        let desc = builder.desc();
+
+        assert_eq!(desc.RasterizerState.CullMode, d3d12::D3D12_CULL_MODE_BACK);
+        assert_eq!(desc.RasterizerState.DepthClipEnable, TRUE);
+        assert_eq!(desc.BlendState.RenderTarget[7].RenderTargetWriteMask, d3d12::D3D12_COLOR_WRITE_ENABLE_ALL as u8);
+        assert_eq!(desc.DepthStencilState.DepthFunc, d3d12::D3D12_COMPARISON_FUNC_LESS);
+        assert_eq!(desc.SampleMask, u32::MAX);
+
+        // the default depth test needs a depth format
+        assert_eq!(builder.validate(), Err(PipelineError::DepthStencilWithoutFormat));
+        assert_eq!(with_shaders().depth_format(dxgiformat::DXGI_FORMAT_D32_FLOAT).validate(), Ok(()));
+        assert_eq!(with_shaders().disable_depth().validate(), Ok(()));
+    }
+
+    #[test]
+    fn render_target_formats_must_match_the_count() {
+        let formats = [dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM, dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT];
+
+        let builder = with_shaders().disable_depth().render_target_formats(&formats);
+        assert_eq!(builder.desc().NumRenderTargets, 2);
+        assert_eq!(builder.validate(), Ok(()));
+
+        let builder = builder.render_target_formats(&[dxgiformat::DXGI_FORMAT_UNKNOWN]);
+        assert_eq!(builder.validate(), Err(PipelineError::RenderTargetFormat { index: 0, format: dxgiformat::DXGI_FORMAT_UNKNOWN }));
+
+        let mut desc = *builder.desc();
+        desc.NumRenderTargets = 1;
+        desc.RTVFormats = [dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM; 8];
+        assert_eq!(validate(&desc), Err(PipelineError::RenderTargetFormat { index: 1, format: dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM }));
+    }
+
+    #[test]
+    fn invalid_states_are_rejected() {
+        assert_eq!(GraphicsPipelineBuilder::new().validate(), Err(PipelineError::MissingRootSignature));
+
+        // valid D3D12, a depth buffer bound with depth and stencil disabled
+        let depth_format = with_shaders().disable_depth().depth_format(dxgiformat::DXGI_FORMAT_D24_UNORM_S8_UINT);
+        assert_eq!(depth_format.validate(), Ok(()));
+
+        let mut blend = default_render_target_blend_desc();
+        blend.BlendEnable = TRUE;
+        blend.LogicOpEnable = TRUE;
+        assert_eq!(with_shaders().disable_depth().render_target_blend(0, blend).validate(), Err(PipelineError::BlendAndLogicOp { index: 0 }));
+        assert_eq!(with_shaders().disable_depth().render_target_blend(8, blend).validate(), Err(PipelineError::BlendIndexOutOfRange(8)));
+
+        assert_eq!(with_shaders().disable_depth().sample_desc(3, 0).validate(), Err(PipelineError::InvalidSampleDesc { count: 3, quality: 0 }));
+        assert_eq!(with_shaders().disable_depth().sample_desc(4, 0).desc().RasterizerState.MultisampleEnable, TRUE);
+    }
+}
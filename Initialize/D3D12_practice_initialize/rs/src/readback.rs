@@ -0,0 +1,243 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+};
+
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+
+use crate::lib;
+use crate::memory_tracker;
+
+pub fn create_readback_buffer(device: *mut d3d12::ID3D12Device, size: u64) -> Result<*mut d3d12::ID3D12Resource, winerror::HRESULT> {
+
+    let heap_prop = d3d12::D3D12_HEAP_PROPERTIES {
+        Type : d3d12::D3D12_HEAP_TYPE_READBACK,
+        CPUPageProperty : d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+        MemoryPoolPreference : d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+        CreationNodeMask: 0,
+        VisibleNodeMask: 0,
+    };
+
+    let resource_desc = lib::create_buffer_resource_desc(size);
+
+    // readback heaps only allow the copy destination state
+    let buffer = lib::create_committed_resource(device, lib::CommittedResource {
+        pHeapProperties: &heap_prop,
+        HeapFlags: d3d12::D3D12_HEAP_FLAG_NONE,
+        pResourceDesc: &resource_desc,
+        InitialResourceState: d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+        pOptimizedClearValue: ptr::null_mut(),
+    })?;
+
+    Ok(buffer)
+}
+
+// drop the padding at the end of each row, rows in `data` are `row_pitch` bytes apart
+pub fn remove_row_padding(data: &[u8], row_pitch: usize, row_size: usize, rows: usize) -> Vec<u8> {
+    assert!(row_size <= row_pitch, "row size {} is larger than the row pitch {}", row_size, row_pitch);
+
+    let mut pixels = Vec::with_capacity(row_size * rows);
+
+    for row in data.chunks(row_pitch).take(rows) {
+        pixels.extend_from_slice(&row[..row_size]);
+    }
+
+    pixels
+}
+
+// pending copy of a texture subresource, resolves once the fence passes `fence_value`
+pub struct TextureReadback {
+    buffer: *mut d3d12::ID3D12Resource,
+    footprint: d3d12::D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+    num_rows: u32,
+    row_size: u64,
+    total_size: u64,
+    fence_value: u64,
+}
+
+// record a copy of `subresource` of `texture` into a new readback buffer
+//
+// `texture` has to be in D3D12_RESOURCE_STATE_COPY_SOURCE, `fence_value` is signaled after the command list
+pub fn read_texture(device: *mut d3d12::ID3D12Device, cmd_list: *mut d3d12::ID3D12GraphicsCommandList, texture: *mut d3d12::ID3D12Resource, subresource: u32, fence_value: u64) -> Result<TextureReadback, winerror::HRESULT> {
+
+    let texture_desc = unsafe { texture.as_ref().unwrap().GetDesc() };
+
+    let mut footprint: d3d12::D3D12_PLACED_SUBRESOURCE_FOOTPRINT = unsafe { mem::zeroed() };
+    let mut num_rows = 0;
+    let mut row_size = 0;
+    let mut total_size = 0;
+
+    // rows of the copy are padded to D3D12_TEXTURE_DATA_PITCH_ALIGNMENT
+    unsafe {
+        device.as_ref().unwrap().
+        GetCopyableFootprints(&texture_desc, subresource, 1, 0, &mut footprint, &mut num_rows, &mut row_size, &mut total_size)
+    };
+
+    let buffer = create_readback_buffer(device, total_size)?;
+
+    let mut copy_dest = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+        pResource: buffer,
+        Type: d3d12::D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+        u: unsafe { mem::zeroed() },
+    };
+    * unsafe { copy_dest.u.PlacedFootprint_mut() } = footprint;
+
+    let mut copy_src = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+        pResource: texture,
+        Type: d3d12::D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+        u: unsafe { mem::zeroed() },
+    };
+    * unsafe { copy_src.u.SubresourceIndex_mut() } = subresource;
+
+    unsafe {
+        cmd_list.as_ref().unwrap().CopyTextureRegion(&copy_dest, 0, 0, 0, &copy_src, ptr::null())
+    };
+
+    Ok(TextureReadback {
+        buffer: buffer,
+        footprint: footprint,
+        num_rows: num_rows,
+        row_size: row_size,
+        total_size: total_size,
+        fence_value: fence_value,
+    })
+}
+
+impl TextureReadback {
+    pub fn is_complete(&self, fence: *mut d3d12::ID3D12Fence) -> bool {
+        unsafe { fence.as_ref().unwrap().GetCompletedValue() >= self.fence_value }
+    }
+
+    // the copied pixels without row padding, None while the GPU is still copying
+    pub fn try_resolve(&self, fence: *mut d3d12::ID3D12Fence) -> Option<Result<lib::Image, lib::MapError>> {
+
+        if !self.is_complete(fence) {
+            return None;
+        }
+
+        let mapped = match lib::Mapped::<u8>::read(unsafe { self.buffer.as_mut().unwrap() }, 0, self.total_size as usize) {
+            Ok(mapped) => mapped,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let footprint = self.footprint.Footprint;
+        let rows = (self.num_rows * footprint.Depth) as usize;
+
+        let pixels = remove_row_padding(&mapped, footprint.RowPitch as usize, self.row_size as usize, rows);
+
+        Some(Ok(lib::Image {
+            width: footprint.Width as u64,
+            height: footprint.Height,
+            format: footprint.Format,
+            row_pitch: self.row_size as usize,
+            slice_pitch: pixels.len(),
+            alignmented_row_pitch: footprint.RowPitch,
+            alignmented_slice_pitch: self.total_size,
+            raw_pointer: pixels,
+        }))
+    }
+
+    // block until the copy is done
+    pub fn wait(self, fence: *mut d3d12::ID3D12Fence) -> Result<lib::Image, lib::MapError> {
+        lib::wait_for_fence(fence, self.fence_value);
+
+        self.try_resolve(fence).unwrap()
+    }
+}
+
+impl Drop for TextureReadback {
+    fn drop(&mut self) {
+        memory_tracker::untrack(self.buffer);
+        unsafe { self.buffer.as_ref().unwrap().Release() };
+    }
+}
+
+// pending copy of buffer contents
+pub struct BufferReadback<T> {
+    buffer: *mut d3d12::ID3D12Resource,
+    count: usize,
+    fence_value: u64,
+    kind: PhantomData<T>,
+}
+
+// record a copy of `count` elements from element `first` of `source`
+//
+// `source` has to be in D3D12_RESOURCE_STATE_COPY_SOURCE, `fence_value` is signaled after the command list
+pub fn read_buffer<T: lib::PlainData>(device: *mut d3d12::ID3D12Device, cmd_list: *mut d3d12::ID3D12GraphicsCommandList, source: *mut d3d12::ID3D12Resource, first: usize, count: usize, fence_value: u64) -> Result<BufferReadback<T>, winerror::HRESULT> {
+
+    let size = (count * mem::size_of::<T>()) as u64;
+
+    let buffer = create_readback_buffer(device, size)?;
+
+    unsafe {
+        cmd_list.as_ref().unwrap().CopyBufferRegion(buffer, 0, source, (first * mem::size_of::<T>()) as u64, size)
+    };
+
+    Ok(BufferReadback {
+        buffer: buffer,
+        count: count,
+        fence_value: fence_value,
+        kind: PhantomData,
+    })
+}
+
+impl<T: lib::PlainData> BufferReadback<T> {
+    pub fn is_complete(&self, fence: *mut d3d12::ID3D12Fence) -> bool {
+        unsafe { fence.as_ref().unwrap().GetCompletedValue() >= self.fence_value }
+    }
+
+    pub fn try_resolve(&self, fence: *mut d3d12::ID3D12Fence) -> Option<Result<Vec<T>, lib::MapError>> {
+
+        if !self.is_complete(fence) {
+            return None;
+        }
+
+        Some(lib::Mapped::<T>::read(unsafe { self.buffer.as_mut().unwrap() }, 0, self.count).map(|mapped| mapped.to_vec()))
+    }
+
+    pub fn wait(self, fence: *mut d3d12::ID3D12Fence) -> Result<Vec<T>, lib::MapError> {
+        lib::wait_for_fence(fence, self.fence_value);
+
+        self.try_resolve(fence).unwrap()
+    }
+}
+
+impl<T> Drop for BufferReadback<T> {
+    fn drop(&mut self) {
+        memory_tracker::untrack(self.buffer);
+        unsafe { self.buffer.as_ref().unwrap().Release() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_padding_is_removed() {
+        // 2 rows of 3 RGBA pixels with 256 byte pitch
+        let mut data = vec![0xffu8; 256 * 2];
+        for (i, byte) in data[..12].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        for (i, byte) in data[256..268].iter_mut().enumerate() {
+            *byte = 100 + i as u8;
+        }
+
+        let pixels = remove_row_padding(&data, 256, 12, 2);
+
+        assert_eq!(pixels.len(), 24);
+        assert_eq!(&pixels[..12], &(0..12).collect::<Vec<u8>>()[..]);
+        assert_eq!(&pixels[12..], &(100..112).collect::<Vec<u8>>()[..]);
+    }
+
+    #[test]
+    fn last_row_may_be_shorter_than_the_pitch() {
+        // the last row of a placed footprint is not padded
+        let data = vec![1u8; 256 + 8];
+
+        assert_eq!(remove_row_padding(&data, 256, 8, 2), vec![1u8; 16]);
+    }
+}
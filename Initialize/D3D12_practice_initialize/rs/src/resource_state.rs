@@ -0,0 +1,558 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+};
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::ptr;
+
+use crate::lib;
+
+pub type ResourceKey = *mut d3d12::ID3D12Resource;
+
+// states which can not be combined with any other state
+const WRITE_STATES: d3d12::D3D12_RESOURCE_STATES =
+    d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET |
+    d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS |
+    d3d12::D3D12_RESOURCE_STATE_DEPTH_WRITE |
+    d3d12::D3D12_RESOURCE_STATE_STREAM_OUT |
+    d3d12::D3D12_RESOURCE_STATE_COPY_DEST |
+    d3d12::D3D12_RESOURCE_STATE_RESOLVE_DEST;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subresource {
+    All,
+    Index(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Barrier {
+    Transition {
+        resource: ResourceKey,
+        // D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES or a subresource index
+        subresource: u32,
+        before: d3d12::D3D12_RESOURCE_STATES,
+        after: d3d12::D3D12_RESOURCE_STATES,
+    },
+    Uav {
+        resource: ResourceKey,
+    },
+}
+
+impl Barrier {
+    pub fn to_d3d12(&self) -> d3d12::D3D12_RESOURCE_BARRIER {
+        match *self {
+            Barrier::Transition { resource, subresource, before, after } => {
+                let mut barrier_desc = d3d12::D3D12_RESOURCE_BARRIER {
+                    Type : d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+                    Flags : d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                    u: unsafe { mem::zeroed() },
+                };
+                * unsafe { barrier_desc.u.Transition_mut() } = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
+                    pResource : resource,
+                    Subresource: subresource,
+                    StateBefore: before,
+                    StateAfter: after,
+                };
+
+                barrier_desc
+            },
+            Barrier::Uav { resource } => {
+                let mut barrier_desc = d3d12::D3D12_RESOURCE_BARRIER {
+                    Type : d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV,
+                    Flags : d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                    u: unsafe { mem::zeroed() },
+                };
+                * unsafe { barrier_desc.u.UAV_mut() } = d3d12::D3D12_RESOURCE_UAV_BARRIER {
+                    pResource : resource,
+                };
+
+                barrier_desc
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateError {
+    UnknownResource(ResourceKey),
+    AlreadyRegistered(ResourceKey),
+    SubresourceOutOfRange { resource: ResourceKey, subresource: u32, subresource_count: u32 },
+    // a write state combined with other states
+    InvalidState(d3d12::D3D12_RESOURCE_STATES),
+    // UAV barrier on a resource which is not in D3D12_RESOURCE_STATE_UNORDERED_ACCESS
+    UavBarrierOutsideUnorderedAccess { resource: ResourceKey, state: d3d12::D3D12_RESOURCE_STATES },
+}
+
+fn validate_state(state: d3d12::D3D12_RESOURCE_STATES) -> Result<(), StateError> {
+    if state & WRITE_STATES != 0 && state.count_ones() > 1 {
+        return Err(StateError::InvalidState(state));
+    }
+
+    Ok(())
+}
+
+fn is_read_only(state: d3d12::D3D12_RESOURCE_STATES) -> bool {
+    state != d3d12::D3D12_RESOURCE_STATE_COMMON && state & WRITE_STATES == 0
+}
+
+// states of every subresource after the last submitted command list
+#[derive(Debug, Default)]
+pub struct ResourceStateTracker {
+    resources: BTreeMap<ResourceKey, Vec<d3d12::D3D12_RESOURCE_STATES>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LocalState {
+    // first state the command list expects, resolved at submission
+    initial: d3d12::D3D12_RESOURCE_STATES,
+    current: d3d12::D3D12_RESOURCE_STATES,
+    // a barrier inside the command list already depends on `initial`
+    transitioned: bool,
+}
+
+// states seen while recording a single command list
+#[derive(Debug, Default)]
+pub struct CommandListStates {
+    resources: BTreeMap<ResourceKey, Vec<Option<LocalState>>>,
+    pending: Vec<Barrier>,
+}
+
+impl ResourceStateTracker {
+    pub fn new() -> ResourceStateTracker {
+        ResourceStateTracker::default()
+    }
+
+    pub fn register(&mut self, resource: ResourceKey, subresource_count: u32, initial_state: d3d12::D3D12_RESOURCE_STATES) -> Result<(), StateError> {
+
+        validate_state(initial_state)?;
+
+        if self.resources.contains_key(&resource) {
+            return Err(StateError::AlreadyRegistered(resource));
+        }
+
+        self.resources.insert(resource, vec![initial_state; subresource_count.max(1) as usize]);
+
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, resource: ResourceKey) {
+        self.resources.remove(&resource);
+    }
+
+    pub fn state(&self, resource: ResourceKey, subresource: u32) -> Option<d3d12::D3D12_RESOURCE_STATES> {
+        self.resources.get(&resource).and_then(|states| states.get(subresource as usize).copied())
+    }
+
+    fn subresources(&self, resource: ResourceKey, subresource: Subresource) -> Result<std::ops::Range<u32>, StateError> {
+
+        let count = self.resources.get(&resource).ok_or(StateError::UnknownResource(resource))?.len() as u32;
+
+        match subresource {
+            Subresource::All => Ok(0..count),
+            Subresource::Index(index) if index < count => Ok(index..index + 1),
+            Subresource::Index(index) => Err(StateError::SubresourceOutOfRange { resource: resource, subresource: index, subresource_count: count }),
+        }
+    }
+
+    // declare the state the next commands of `list` need
+    pub fn require(&self, list: &mut CommandListStates, resource: ResourceKey, subresource: Subresource, state: d3d12::D3D12_RESOURCE_STATES) -> Result<(), StateError> {
+
+        validate_state(state)?;
+
+        let range = self.subresources(resource, subresource)?;
+        let count = self.resources[&resource].len();
+
+        let local = list.resources.entry(resource).or_insert_with(|| vec![None; count]);
+
+        let mut transitions = Vec::new();
+
+        for index in range {
+            let slot = &mut local[index as usize];
+
+            match slot {
+                None => {
+                    *slot = Some(LocalState { initial: state, current: state, transitioned: false });
+                },
+                Some(local_state) => {
+                    let current = local_state.current;
+
+                    if current == state || (is_read_only(current) && current & state == state) {
+                        continue;
+                    }
+
+                    // several read states can be active at the same time
+                    let after = if is_read_only(current) && is_read_only(state) { current | state } else { state };
+
+                    // nothing depends on the initial state yet, so ask for the combined read state at submission
+                    if !local_state.transitioned && is_read_only(local_state.initial) && is_read_only(after) {
+                        local_state.initial = after;
+                        local_state.current = after;
+                        continue;
+                    }
+
+                    local_state.current = after;
+                    local_state.transitioned = true;
+
+                    transitions.push((index, current, after));
+                }
+            }
+        }
+
+        let whole_resource = transitions.len() == count && count > 1 &&
+            transitions.iter().all(|&(_, before, after)| before == transitions[0].1 && after == transitions[0].2);
+
+        if whole_resource {
+            list.push_transition(resource, d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, transitions[0].1, transitions[0].2);
+        } else {
+            for (index, before, after) in transitions {
+                let index = if count == 1 { d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES } else { index };
+
+                list.push_transition(resource, index, before, after);
+            }
+        }
+
+        Ok(())
+    }
+
+    // order UAV writes of consecutive draws or dispatches
+    pub fn uav_barrier(&self, list: &mut CommandListStates, resource: ResourceKey) -> Result<(), StateError> {
+
+        self.subresources(resource, Subresource::All)?;
+
+        let known_state = list.resources.get(&resource)
+            .and_then(|states| states.iter().flatten().map(|local_state| local_state.current).find(|&state| state != d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS));
+
+        if let Some(state) = known_state {
+            return Err(StateError::UavBarrierOutsideUnorderedAccess { resource: resource, state: state });
+        }
+
+        let duplicated = list.pending.iter().any(|barrier| *barrier == Barrier::Uav { resource: resource });
+
+        if !duplicated {
+            list.pending.push(Barrier::Uav { resource: resource });
+        }
+
+        Ok(())
+    }
+
+    // barriers which have to run before `list`, the final states of `list` become the tracked states
+    pub fn submit(&mut self, list: CommandListStates) -> Result<Vec<Barrier>, StateError> {
+
+        let mut fixups = Vec::new();
+
+        for (resource, local) in list.resources.iter() {
+
+            let states = self.resources.get_mut(resource).ok_or(StateError::UnknownResource(*resource))?;
+
+            let mut transitions = Vec::new();
+
+            for (index, local_state) in local.iter().enumerate() {
+                if let Some(local_state) = local_state {
+                    if states[index] != local_state.initial {
+                        transitions.push((index as u32, states[index], local_state.initial));
+                    }
+
+                    states[index] = local_state.current;
+                }
+            }
+
+            let whole_resource = transitions.len() == states.len() &&
+                transitions.iter().all(|&(_, before, after)| before == transitions[0].1 && after == transitions[0].2);
+
+            if whole_resource {
+                fixups.push(Barrier::Transition { resource: *resource, subresource: d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, before: transitions[0].1, after: transitions[0].2 });
+            } else {
+                for (index, before, after) in transitions {
+                    fixups.push(Barrier::Transition { resource: *resource, subresource: index, before: before, after: after });
+                }
+            }
+        }
+
+        Ok(fixups)
+    }
+}
+
+impl CommandListStates {
+    pub fn new() -> CommandListStates {
+        CommandListStates::default()
+    }
+
+    fn push_transition(&mut self, resource: ResourceKey, subresource: u32, before: d3d12::D3D12_RESOURCE_STATES, after: d3d12::D3D12_RESOURCE_STATES) {
+
+        // A -> B directly followed by B -> C becomes A -> C,
+        // a barrier in between (e.g. a UAV barrier) has to see state B
+        let chained = match self.pending.last() {
+            Some(&Barrier::Transition { resource: r, subresource: s, before: first_before, after: a }) if r == resource && s == subresource && a == before => Some(first_before),
+            _ => None,
+        };
+
+        match chained {
+            Some(first_before) => {
+                self.pending.pop();
+
+                if first_before != after {
+                    self.pending.push(Barrier::Transition { resource: resource, subresource: subresource, before: first_before, after: after });
+                }
+            },
+            None => self.pending.push(Barrier::Transition { resource: resource, subresource: subresource, before: before, after: after }),
+        }
+    }
+
+    // take the batched barriers
+    pub fn flush(&mut self) -> Vec<Barrier> {
+        mem::replace(&mut self.pending, Vec::new())
+    }
+
+    // record the batched barriers with a single ResourceBarrier call
+    pub fn record(&mut self, cmd_list: *mut d3d12::ID3D12GraphicsCommandList) {
+        record_barriers(cmd_list, &self.flush());
+    }
+}
+
+pub fn record_barriers(cmd_list: *mut d3d12::ID3D12GraphicsCommandList, barriers: &[Barrier]) {
+
+    if barriers.is_empty() {
+        return;
+    }
+
+    let barrier_descs = barriers.iter().map(|barrier| barrier.to_d3d12()).collect::<Vec<_>>();
+
+    unsafe { cmd_list.as_ref().unwrap().ResourceBarrier(barrier_descs.len() as u32, barrier_descs.as_ptr()) };
+}
+
+// small command list which carries the barriers resolved at submission
+pub struct FixupCommandList {
+    cmd_allocator: *mut d3d12::ID3D12CommandAllocator,
+    cmd_list: *mut d3d12::ID3D12GraphicsCommandList,
+}
+
+impl FixupCommandList {
+    pub fn new(device: *mut d3d12::ID3D12Device) -> Result<FixupCommandList, winerror::HRESULT> {
+
+        let cmd_allocator = lib::create_command_allocator(device, d3d12::D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+        let cmd_list = lib::create_command_list(device, 0, d3d12::D3D12_COMMAND_LIST_TYPE_DIRECT, cmd_allocator, ptr::null_mut())?;
+
+        unsafe { cmd_list.as_ref().unwrap().Close() };
+
+        Ok(FixupCommandList {
+            cmd_allocator: cmd_allocator,
+            cmd_list: cmd_list,
+        })
+    }
+
+    // execute `cmd_list` after the barriers its recorded states depend on
+    // the previous submission has to be completed on the GPU
+    pub fn execute(&self, cmd_queue: *mut d3d12::ID3D12CommandQueue, tracker: &mut ResourceStateTracker, states: CommandListStates, cmd_list: *mut d3d12::ID3D12GraphicsCommandList) -> Result<(), StateError> {
+
+        let fixups = tracker.submit(states)?;
+
+        if fixups.is_empty() {
+            let cmd_list_array = [ cmd_list.cast::<d3d12::ID3D12CommandList>() ];
+
+            unsafe { cmd_queue.as_ref().unwrap().ExecuteCommandLists(1, &cmd_list_array[0]) };
+
+            return Ok(());
+        }
+
+        unsafe { self.cmd_allocator.as_ref().unwrap().Reset() };
+        unsafe { self.cmd_list.as_ref().unwrap().Reset(self.cmd_allocator, ptr::null_mut()) };
+
+        record_barriers(self.cmd_list, &fixups);
+
+        unsafe { self.cmd_list.as_ref().unwrap().Close() };
+
+        let cmd_list_array = [ self.cmd_list.cast::<d3d12::ID3D12CommandList>(), cmd_list.cast::<d3d12::ID3D12CommandList>() ];
+
+        unsafe { cmd_queue.as_ref().unwrap().ExecuteCommandLists(2, &cmd_list_array[0]) };
+
+        Ok(())
+    }
+}
+
+impl Drop for FixupCommandList {
+    fn drop(&mut self) {
+        unsafe {
+            self.cmd_list.as_ref().unwrap().Release();
+            self.cmd_allocator.as_ref().unwrap().Release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use winapi::um::d3d12::*;
+
+    fn resource(id: usize) -> ResourceKey {
+        id as ResourceKey
+    }
+
+    #[test]
+    fn only_needed_transitions_are_recorded() {
+        let mut tracker = ResourceStateTracker::new();
+        let back_buffer = resource(1);
+
+        tracker.register(back_buffer, 1, D3D12_RESOURCE_STATE_PRESENT).unwrap();
+
+        let mut list = CommandListStates::new();
+
+        tracker.require(&mut list, back_buffer, Subresource::All, D3D12_RESOURCE_STATE_RENDER_TARGET).unwrap();
+        tracker.require(&mut list, back_buffer, Subresource::All, D3D12_RESOURCE_STATE_RENDER_TARGET).unwrap();
+
+        // the first state is resolved at submission
+        assert!(list.flush().is_empty());
+
+        tracker.require(&mut list, back_buffer, Subresource::All, D3D12_RESOURCE_STATE_PRESENT).unwrap();
+
+        assert_eq!(list.flush(), vec![Barrier::Transition {
+            resource: back_buffer,
+            subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            before: D3D12_RESOURCE_STATE_RENDER_TARGET,
+            after: D3D12_RESOURCE_STATE_PRESENT,
+        }]);
+
+        let fixups = tracker.submit(list).unwrap();
+
+        assert_eq!(fixups, vec![Barrier::Transition {
+            resource: back_buffer,
+            subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            before: D3D12_RESOURCE_STATE_PRESENT,
+            after: D3D12_RESOURCE_STATE_RENDER_TARGET,
+        }]);
+        assert_eq!(tracker.state(back_buffer, 0), Some(D3D12_RESOURCE_STATE_PRESENT));
+    }
+
+    #[test]
+    fn batched_transitions_are_chained_and_read_states_merged() {
+        let mut tracker = ResourceStateTracker::new();
+        let texture = resource(1);
+
+        tracker.register(texture, 1, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+
+        let mut list = CommandListStates::new();
+
+        tracker.require(&mut list, texture, Subresource::All, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+        tracker.require(&mut list, texture, Subresource::All, D3D12_RESOURCE_STATE_COPY_SOURCE).unwrap();
+        tracker.require(&mut list, texture, Subresource::All, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE).unwrap();
+
+        let expected_state = D3D12_RESOURCE_STATE_COPY_SOURCE | D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE;
+
+        assert_eq!(list.flush(), vec![Barrier::Transition {
+            resource: texture,
+            subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            before: D3D12_RESOURCE_STATE_COPY_DEST,
+            after: expected_state,
+        }]);
+
+        assert!(tracker.submit(list).unwrap().is_empty());
+        assert_eq!(tracker.state(texture, 0), Some(expected_state));
+    }
+
+    #[test]
+    fn transitions_are_not_chained_across_a_uav_barrier() {
+        let mut tracker = ResourceStateTracker::new();
+        let buffer = resource(1);
+
+        tracker.register(buffer, 1, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+
+        let mut list = CommandListStates::new();
+
+        tracker.require(&mut list, buffer, Subresource::All, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+        tracker.require(&mut list, buffer, Subresource::All, D3D12_RESOURCE_STATE_UNORDERED_ACCESS).unwrap();
+        tracker.uav_barrier(&mut list, buffer).unwrap();
+        tracker.require(&mut list, buffer, Subresource::All, D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE).unwrap();
+
+        assert_eq!(list.flush(), vec![
+            Barrier::Transition {
+                resource: buffer,
+                subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                before: D3D12_RESOURCE_STATE_COPY_DEST,
+                after: D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            },
+            Barrier::Uav { resource: buffer },
+            Barrier::Transition {
+                resource: buffer,
+                subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                before: D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                after: D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+            },
+        ]);
+    }
+
+    #[test]
+    fn subresources_are_tracked_separately() {
+        let mut tracker = ResourceStateTracker::new();
+        let texture = resource(1);
+
+        tracker.register(texture, 3, D3D12_RESOURCE_STATE_COMMON).unwrap();
+
+        let mut list = CommandListStates::new();
+
+        tracker.require(&mut list, texture, Subresource::Index(1), D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+        tracker.require(&mut list, texture, Subresource::All, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE).unwrap();
+
+        // only mip 1 had a known state inside the command list
+        assert_eq!(list.flush(), vec![Barrier::Transition {
+            resource: texture,
+            subresource: 1,
+            before: D3D12_RESOURCE_STATE_COPY_DEST,
+            after: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        }]);
+
+        let fixups = tracker.submit(list).unwrap();
+
+        assert_eq!(fixups, vec![
+            Barrier::Transition { resource: texture, subresource: 0, before: D3D12_RESOURCE_STATE_COMMON, after: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE },
+            Barrier::Transition { resource: texture, subresource: 1, before: D3D12_RESOURCE_STATE_COMMON, after: D3D12_RESOURCE_STATE_COPY_DEST },
+            Barrier::Transition { resource: texture, subresource: 2, before: D3D12_RESOURCE_STATE_COMMON, after: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE },
+        ]);
+
+        // all subresources are in the same state again
+        let mut list = CommandListStates::new();
+        tracker.require(&mut list, texture, Subresource::All, D3D12_RESOURCE_STATE_COPY_SOURCE).unwrap();
+
+        assert_eq!(tracker.submit(list).unwrap(), vec![Barrier::Transition {
+            resource: texture,
+            subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            before: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            after: D3D12_RESOURCE_STATE_COPY_SOURCE,
+        }]);
+    }
+
+    #[test]
+    fn invalid_requests_are_errors() {
+        let mut tracker = ResourceStateTracker::new();
+        let buffer = resource(1);
+
+        tracker.register(buffer, 1, D3D12_RESOURCE_STATE_COMMON).unwrap();
+
+        assert_eq!(tracker.register(buffer, 1, D3D12_RESOURCE_STATE_COMMON), Err(StateError::AlreadyRegistered(buffer)));
+
+        let mut list = CommandListStates::new();
+
+        let write_and_read = D3D12_RESOURCE_STATE_COPY_DEST | D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE;
+        assert_eq!(tracker.require(&mut list, buffer, Subresource::All, write_and_read), Err(StateError::InvalidState(write_and_read)));
+
+        assert_eq!(tracker.require(&mut list, resource(2), Subresource::All, D3D12_RESOURCE_STATE_COPY_DEST), Err(StateError::UnknownResource(resource(2))));
+        assert_eq!(
+            tracker.require(&mut list, buffer, Subresource::Index(1), D3D12_RESOURCE_STATE_COPY_DEST),
+            Err(StateError::SubresourceOutOfRange { resource: buffer, subresource: 1, subresource_count: 1 })
+        );
+
+        tracker.require(&mut list, buffer, Subresource::All, D3D12_RESOURCE_STATE_COPY_DEST).unwrap();
+        assert_eq!(
+            tracker.uav_barrier(&mut list, buffer),
+            Err(StateError::UavBarrierOutsideUnorderedAccess { resource: buffer, state: D3D12_RESOURCE_STATE_COPY_DEST })
+        );
+
+        tracker.require(&mut list, buffer, Subresource::All, D3D12_RESOURCE_STATE_UNORDERED_ACCESS).unwrap();
+        list.flush();
+
+        tracker.uav_barrier(&mut list, buffer).unwrap();
+        tracker.uav_barrier(&mut list, buffer).unwrap();
+        assert_eq!(list.flush(), vec![Barrier::Uav { resource: buffer }]);
+    }
+}
@@ -0,0 +1,576 @@
+use winapi::{
+    um::{
+        d3d12,
+        d3dcommon,
+    },
+    shared::winerror,
+    Interface,
+};
+
+use std::mem;
+use std::ptr;
+
+use crate::lib;
+
+pub mod hlsl;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootDescriptorType {
+    Cbv,
+    Srv,
+    Uav,
+}
+
+impl RootDescriptorType {
+    fn parameter_type(self) -> d3d12::D3D12_ROOT_PARAMETER_TYPE {
+        match self {
+            RootDescriptorType::Cbv => d3d12::D3D12_ROOT_PARAMETER_TYPE_CBV,
+            RootDescriptorType::Srv => d3d12::D3D12_ROOT_PARAMETER_TYPE_SRV,
+            RootDescriptorType::Uav => d3d12::D3D12_ROOT_PARAMETER_TYPE_UAV,
+        }
+    }
+}
+
+// one range of a descriptor table, appended after the previous range unless an offset is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorRange {
+    pub range_type: d3d12::D3D12_DESCRIPTOR_RANGE_TYPE,
+    pub count: u32,
+    pub base_register: u32,
+    pub space: u32,
+    pub flags: d3d12::D3D12_DESCRIPTOR_RANGE_FLAGS,
+    pub offset: u32,
+}
+
+impl DescriptorRange {
+    pub fn new(range_type: d3d12::D3D12_DESCRIPTOR_RANGE_TYPE, count: u32, base_register: u32) -> DescriptorRange {
+        DescriptorRange {
+            range_type: range_type,
+            count: count,
+            base_register: base_register,
+            space: 0,
+            flags: d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_NONE,
+            offset: d3d12::D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+        }
+    }
+
+    pub fn cbv(count: u32, base_register: u32) -> DescriptorRange {
+        DescriptorRange::new(d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_CBV, count, base_register)
+    }
+
+    pub fn srv(count: u32, base_register: u32) -> DescriptorRange {
+        DescriptorRange::new(d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SRV, count, base_register)
+    }
+
+    pub fn uav(count: u32, base_register: u32) -> DescriptorRange {
+        DescriptorRange::new(d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_UAV, count, base_register)
+    }
+
+    pub fn sampler(count: u32, base_register: u32) -> DescriptorRange {
+        DescriptorRange::new(d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER, count, base_register)
+    }
+
+    pub fn space(mut self, space: u32) -> DescriptorRange {
+        self.space = space;
+        self
+    }
+
+    // version 1.1 only, validate_1_0() rejects them
+    pub fn flags(mut self, flags: d3d12::D3D12_DESCRIPTOR_RANGE_FLAGS) -> DescriptorRange {
+        self.flags = flags;
+        self
+    }
+
+    pub fn offset(mut self, offset: u32) -> DescriptorRange {
+        self.offset = offset;
+        self
+    }
+
+    fn to_d3d12_1_1(self) -> d3d12::D3D12_DESCRIPTOR_RANGE1 {
+        d3d12::D3D12_DESCRIPTOR_RANGE1 {
+            RangeType: self.range_type,
+            NumDescriptors: self.count,
+            BaseShaderRegister: self.base_register,
+            RegisterSpace: self.space,
+            Flags: self.flags,
+            OffsetInDescriptorsFromTableStart: self.offset,
+        }
+    }
+
+    fn to_d3d12_1_0(self) -> d3d12::D3D12_DESCRIPTOR_RANGE {
+        d3d12::D3D12_DESCRIPTOR_RANGE {
+            RangeType: self.range_type,
+            NumDescriptors: self.count,
+            BaseShaderRegister: self.base_register,
+            RegisterSpace: self.space,
+            OffsetInDescriptorsFromTableStart: self.offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootParameter {
+    Constants {
+        register: u32,
+        space: u32,
+        num_values: u32,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    },
+    Descriptor {
+        descriptor_type: RootDescriptorType,
+        register: u32,
+        space: u32,
+        // version 1.1 only, validate_1_0() rejects them
+        flags: d3d12::D3D12_ROOT_DESCRIPTOR_FLAGS,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    },
+    Table {
+        ranges: Vec<DescriptorRange>,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    },
+}
+
+impl RootParameter {
+    // size in DWORDs, a root signature holds at most 64
+    pub fn cost(&self) -> u32 {
+        match self {
+            RootParameter::Constants { num_values, .. } => *num_values,
+            RootParameter::Descriptor { .. } => 2,
+            RootParameter::Table { .. } => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootSignatureError {
+    EmptyTable { parameter: usize },
+    // sampler ranges can't share a table with CBV/SRV/UAV ranges
+    MixedSamplerTable { parameter: usize },
+    TooLarge { cost: u32 },
+    // a range or root descriptor has flags, serializing as 1.0 would drop them
+    FlagsNeedVersion1_1 { parameter: usize },
+    // text of the serializer's error blob
+    Serialize { result: winerror::HRESULT, message: String },
+    Device(winerror::HRESULT),
+}
+
+pub const MAX_ROOT_SIGNATURE_COST: u32 = 64;
+
+// a static sampler with the same address mode on every axis
+pub fn static_sampler_desc(filter: d3d12::D3D12_FILTER, address_mode: d3d12::D3D12_TEXTURE_ADDRESS_MODE, register: u32, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> d3d12::D3D12_STATIC_SAMPLER_DESC {
+    d3d12::D3D12_STATIC_SAMPLER_DESC {
+        Filter: filter,
+        AddressU: address_mode,
+        AddressV: address_mode,
+        AddressW: address_mode,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 16,
+        ComparisonFunc: d3d12::D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: d3d12::D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: d3d12::D3D12_FLOAT32_MAX,
+        ShaderRegister: register,
+        RegisterSpace: 0,
+        ShaderVisibility: visibility,
+    }
+}
+
+// highest version the runtime can serialize, 1.0 when the feature query fails
+pub fn highest_root_signature_version(device: *mut d3d12::ID3D12Device) -> d3d12::D3D_ROOT_SIGNATURE_VERSION {
+
+    let mut feature_data = d3d12::D3D12_FEATURE_DATA_ROOT_SIGNATURE {
+        HighestVersion: d3d12::D3D_ROOT_SIGNATURE_VERSION_1_1,
+    };
+
+    let result = unsafe {
+        device.as_ref().unwrap().
+        CheckFeatureSupport(
+            d3d12::D3D12_FEATURE_ROOT_SIGNATURE,
+            &mut feature_data as *mut _ as *mut _,
+            mem::size_of::<d3d12::D3D12_FEATURE_DATA_ROOT_SIGNATURE>() as u32
+        )
+    };
+
+    match result {
+        winerror::S_OK => feature_data.HighestVersion,
+        _ => d3d12::D3D_ROOT_SIGNATURE_VERSION_1_0,
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RootSignatureBuilder {
+    parameters: Vec<RootParameter>,
+    static_samplers: Vec<d3d12::D3D12_STATIC_SAMPLER_DESC>,
+    flags: d3d12::D3D12_ROOT_SIGNATURE_FLAGS,
+}
+
+impl RootSignatureBuilder {
+    pub fn new() -> RootSignatureBuilder {
+        RootSignatureBuilder::default()
+    }
+
+    // root parameter indices follow the order parameters are added in
+    pub fn parameter(mut self, parameter: RootParameter) -> RootSignatureBuilder {
+        self.parameters.push(parameter);
+        self
+    }
+
+    pub fn constants(self, register: u32, space: u32, num_values: u32, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.parameter(RootParameter::Constants {
+            register: register,
+            space: space,
+            num_values: num_values,
+            visibility: visibility,
+        })
+    }
+
+    pub fn descriptor(self, descriptor_type: RootDescriptorType, register: u32, space: u32, flags: d3d12::D3D12_ROOT_DESCRIPTOR_FLAGS, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.parameter(RootParameter::Descriptor {
+            descriptor_type: descriptor_type,
+            register: register,
+            space: space,
+            flags: flags,
+            visibility: visibility,
+        })
+    }
+
+    pub fn cbv(self, register: u32, space: u32, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.descriptor(RootDescriptorType::Cbv, register, space, d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE, visibility)
+    }
+
+    pub fn srv(self, register: u32, space: u32, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.descriptor(RootDescriptorType::Srv, register, space, d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE, visibility)
+    }
+
+    pub fn uav(self, register: u32, space: u32, visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.descriptor(RootDescriptorType::Uav, register, space, d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE, visibility)
+    }
+
+    pub fn table(self, ranges: &[DescriptorRange], visibility: d3d12::D3D12_SHADER_VISIBILITY) -> RootSignatureBuilder {
+        self.parameter(RootParameter::Table {
+            ranges: ranges.to_vec(),
+            visibility: visibility,
+        })
+    }
+
+    pub fn static_sampler(mut self, sampler: d3d12::D3D12_STATIC_SAMPLER_DESC) -> RootSignatureBuilder {
+        self.static_samplers.push(sampler);
+        self
+    }
+
+    pub fn flags(mut self, flags: d3d12::D3D12_ROOT_SIGNATURE_FLAGS) -> RootSignatureBuilder {
+        self.flags = flags;
+        self
+    }
+
+    pub fn parameters(&self) -> &[RootParameter] {
+        &self.parameters
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.parameters.iter().map(|parameter| parameter.cost()).sum()
+    }
+
+    pub fn validate(&self) -> Result<(), RootSignatureError> {
+
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            if let RootParameter::Table { ranges, .. } = parameter {
+                if ranges.is_empty() {
+                    return Err(RootSignatureError::EmptyTable { parameter: index });
+                }
+
+                let samplers = ranges.iter().filter(|range| range.range_type == d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER).count();
+
+                if samplers != 0 && samplers != ranges.len() {
+                    return Err(RootSignatureError::MixedSamplerTable { parameter: index });
+                }
+            }
+        }
+
+        let cost = self.cost();
+
+        if cost > MAX_ROOT_SIGNATURE_COST {
+            return Err(RootSignatureError::TooLarge { cost: cost });
+        }
+
+        Ok(())
+    }
+
+    // range and root descriptor flags only exist in version 1.1
+    pub fn validate_1_0(&self) -> Result<(), RootSignatureError> {
+        self.validate()?;
+
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            let has_flags = match parameter {
+                RootParameter::Constants { .. } => false,
+                RootParameter::Descriptor { flags, .. } => *flags != d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE,
+                RootParameter::Table { ranges, .. } => ranges.iter().any(|range| range.flags != d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_NONE),
+            };
+
+            if has_flags {
+                return Err(RootSignatureError::FlagsNeedVersion1_1 { parameter: index });
+            }
+        }
+
+        Ok(())
+    }
+
+    // serialize with `version`, version 1.0 goes through D3D12SerializeRootSignature
+    pub fn serialize(&self, version: d3d12::D3D_ROOT_SIGNATURE_VERSION) -> Result<*mut d3dcommon::ID3DBlob, RootSignatureError> {
+
+        let mut root_signature_blob = ptr::null_mut::<d3dcommon::ID3DBlob>();
+        let mut error_blob = ptr::null_mut::<d3dcommon::ID3DBlob>();
+
+        let result = match version {
+            d3d12::D3D_ROOT_SIGNATURE_VERSION_1_0 => {
+                self.validate_1_0()?;
+
+                // the parameters point into the range vectors, both live until the call returns
+                let (_ranges, parameters) = self.parameters_1_0();
+
+                let desc = d3d12::D3D12_ROOT_SIGNATURE_DESC {
+                    NumParameters: parameters.len() as u32,
+                    pParameters: parameters.as_ptr(),
+                    NumStaticSamplers: self.static_samplers.len() as u32,
+                    pStaticSamplers: self.static_samplers.as_ptr(),
+                    Flags: self.flags,
+                };
+
+                unsafe { d3d12::D3D12SerializeRootSignature(&desc, version, &mut root_signature_blob, &mut error_blob) }
+            },
+            _ => {
+                self.validate()?;
+
+                let (_ranges, parameters) = self.parameters_1_1();
+
+                let mut versioned_desc = d3d12::D3D12_VERSIONED_ROOT_SIGNATURE_DESC {
+                    Version: version,
+                    u: unsafe { mem::zeroed() },
+                };
+
+                * unsafe { versioned_desc.u.Desc_1_1_mut() } = d3d12::D3D12_ROOT_SIGNATURE_DESC1 {
+                    NumParameters: parameters.len() as u32,
+                    pParameters: parameters.as_ptr(),
+                    NumStaticSamplers: self.static_samplers.len() as u32,
+                    pStaticSamplers: self.static_samplers.as_ptr(),
+                    Flags: self.flags,
+                };
+
+                unsafe { d3d12::D3D12SerializeVersionedRootSignature(&versioned_desc, &mut root_signature_blob, &mut error_blob) }
+            },
+        };
+
+        match result {
+            winerror::S_OK => Ok(root_signature_blob),
+            _ => {
+                let message = match error_blob.is_null() {
+                    true => String::new(),
+                    false => {
+                        let message = lib::blob_to_string(error_blob);
+                        unsafe { error_blob.as_ref().unwrap().Release() };
+                        message
+                    },
+                };
+
+                Err(RootSignatureError::Serialize { result: result, message: message })
+            },
+        }
+    }
+
+    // serialize with the highest version the device supports and create the root signature
+    pub fn build(&self, device: *mut d3d12::ID3D12Device) -> Result<*mut d3d12::ID3D12RootSignature, RootSignatureError> {
+
+        let root_signature_blob = self.serialize(highest_root_signature_version(device))?;
+
+        let mut root_signature = ptr::null_mut::<d3d12::ID3D12RootSignature>();
+
+        let result = unsafe {
+            device.as_ref().unwrap().
+            CreateRootSignature(
+                0,
+                root_signature_blob.as_ref().unwrap().GetBufferPointer(),
+                root_signature_blob.as_ref().unwrap().GetBufferSize(),
+                &d3d12::ID3D12RootSignature::uuidof(),
+                lib::get_pointer_of_interface(&mut root_signature)
+            )
+        };
+
+        unsafe { root_signature_blob.as_ref().unwrap().Release() };
+
+        match result {
+            winerror::S_OK => Ok(root_signature),
+            _ => Err(RootSignatureError::Device(result))
+        }
+    }
+
+    fn parameters_1_1(&self) -> (Vec<Vec<d3d12::D3D12_DESCRIPTOR_RANGE1>>, Vec<d3d12::D3D12_ROOT_PARAMETER1>) {
+
+        let mut table_ranges = Vec::new();
+        let mut parameters = Vec::with_capacity(self.parameters.len());
+
+        for parameter in self.parameters.iter() {
+            let mut root_parameter = d3d12::D3D12_ROOT_PARAMETER1 {
+                ParameterType: d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                u: unsafe { mem::zeroed() },
+                ShaderVisibility: d3d12::D3D12_SHADER_VISIBILITY_ALL,
+            };
+
+            match parameter {
+                RootParameter::Constants { register, space, num_values, visibility } => {
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.Constants_mut() } = d3d12::D3D12_ROOT_CONSTANTS {
+                        ShaderRegister: *register,
+                        RegisterSpace: *space,
+                        Num32BitValues: *num_values,
+                    };
+                },
+                RootParameter::Descriptor { descriptor_type, register, space, flags, visibility } => {
+                    root_parameter.ParameterType = descriptor_type.parameter_type();
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.Descriptor_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR1 {
+                        ShaderRegister: *register,
+                        RegisterSpace: *space,
+                        Flags: *flags,
+                    };
+                },
+                RootParameter::Table { ranges, visibility } => {
+                    let ranges: Vec<_> = ranges.iter().map(|range| range.to_d3d12_1_1()).collect();
+
+                    root_parameter.ParameterType = d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE;
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.DescriptorTable_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR_TABLE1 {
+                        NumDescriptorRanges: ranges.len() as u32,
+                        pDescriptorRanges: ranges.as_ptr(),
+                    };
+
+                    table_ranges.push(ranges);
+                },
+            }
+
+            parameters.push(root_parameter);
+        }
+
+        (table_ranges, parameters)
+    }
+
+    fn parameters_1_0(&self) -> (Vec<Vec<d3d12::D3D12_DESCRIPTOR_RANGE>>, Vec<d3d12::D3D12_ROOT_PARAMETER>) {
+
+        let mut table_ranges = Vec::new();
+        let mut parameters = Vec::with_capacity(self.parameters.len());
+
+        for parameter in self.parameters.iter() {
+            let mut root_parameter = d3d12::D3D12_ROOT_PARAMETER {
+                ParameterType: d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                u: unsafe { mem::zeroed() },
+                ShaderVisibility: d3d12::D3D12_SHADER_VISIBILITY_ALL,
+            };
+
+            match parameter {
+                RootParameter::Constants { register, space, num_values, visibility } => {
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.Constants_mut() } = d3d12::D3D12_ROOT_CONSTANTS {
+                        ShaderRegister: *register,
+                        RegisterSpace: *space,
+                        Num32BitValues: *num_values,
+                    };
+                },
+                RootParameter::Descriptor { descriptor_type, register, space, visibility, .. } => {
+                    root_parameter.ParameterType = descriptor_type.parameter_type();
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.Descriptor_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR {
+                        ShaderRegister: *register,
+                        RegisterSpace: *space,
+                    };
+                },
+                RootParameter::Table { ranges, visibility } => {
+                    let ranges: Vec<_> = ranges.iter().map(|range| range.to_d3d12_1_0()).collect();
+
+                    root_parameter.ParameterType = d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE;
+                    root_parameter.ShaderVisibility = *visibility;
+                    * unsafe { root_parameter.u.DescriptorTable_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR_TABLE {
+                        NumDescriptorRanges: ranges.len() as u32,
+                        pDescriptorRanges: ranges.as_ptr(),
+                    };
+
+                    table_ranges.push(ranges);
+                },
+            }
+
+            parameters.push(root_parameter);
+        }
+
+        (table_ranges, parameters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> RootSignatureBuilder {
+        RootSignatureBuilder::new()
+            .constants(0, 0, 4, d3d12::D3D12_SHADER_VISIBILITY_ALL)
+            .cbv(1, 0, d3d12::D3D12_SHADER_VISIBILITY_VERTEX)
+            .table(&[
+                DescriptorRange::srv(4, 0).flags(d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC),
+                DescriptorRange::uav(1, 0).space(1),
+            ], d3d12::D3D12_SHADER_VISIBILITY_PIXEL)
+            .static_sampler(static_sampler_desc(d3d12::D3D12_FILTER_MIN_MAG_MIP_POINT, d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP, 0, d3d12::D3D12_SHADER_VISIBILITY_PIXEL))
+    }
+
+    #[test]
+    fn tables_are_validated() {
+        assert_eq!(builder().validate(), Ok(()));
+        assert_eq!(builder().cost(), 4 + 2 + 1);
+
+        let mixed = builder().table(&[DescriptorRange::srv(1, 4), DescriptorRange::sampler(1, 1)], d3d12::D3D12_SHADER_VISIBILITY_PIXEL);
+        assert_eq!(mixed.validate(), Err(RootSignatureError::MixedSamplerTable { parameter: 3 }));
+
+        let empty = builder().table(&[], d3d12::D3D12_SHADER_VISIBILITY_ALL);
+        assert_eq!(empty.validate(), Err(RootSignatureError::EmptyTable { parameter: 3 }));
+
+        let too_large = builder().constants(2, 0, 60, d3d12::D3D12_SHADER_VISIBILITY_ALL);
+        assert_eq!(too_large.validate(), Err(RootSignatureError::TooLarge { cost: 67 }));
+    }
+
+    #[test]
+    fn version_1_0_rejects_flags() {
+        // the srv range is DATA_STATIC
+        assert_eq!(builder().validate_1_0(), Err(RootSignatureError::FlagsNeedVersion1_1 { parameter: 2 }));
+
+        let volatile = RootSignatureBuilder::new()
+            .cbv(0, 0, d3d12::D3D12_SHADER_VISIBILITY_ALL)
+            .descriptor(RootDescriptorType::Srv, 0, 0, d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_VOLATILE, d3d12::D3D12_SHADER_VISIBILITY_ALL);
+        assert_eq!(volatile.validate_1_0(), Err(RootSignatureError::FlagsNeedVersion1_1 { parameter: 1 }));
+
+        let plain = RootSignatureBuilder::new()
+            .cbv(0, 0, d3d12::D3D12_SHADER_VISIBILITY_ALL)
+            .table(&[DescriptorRange::srv(2, 0)], d3d12::D3D12_SHADER_VISIBILITY_PIXEL);
+        assert_eq!(plain.validate_1_0(), Ok(()));
+    }
+
+    #[test]
+    fn both_versions_describe_the_same_parameters() {
+        let builder = builder();
+
+        let (ranges_1_1, parameters_1_1) = builder.parameters_1_1();
+        let (ranges_1_0, parameters_1_0) = builder.parameters_1_0();
+
+        assert_eq!(parameters_1_1.len(), 3);
+        assert_eq!(parameters_1_0.len(), 3);
+
+        for (parameter_1_1, parameter_1_0) in parameters_1_1.iter().zip(parameters_1_0.iter()) {
+            assert_eq!(parameter_1_1.ParameterType, parameter_1_0.ParameterType);
+            assert_eq!(parameter_1_1.ShaderVisibility, parameter_1_0.ShaderVisibility);
+        }
+
+        assert_eq!(unsafe { parameters_1_1[0].u.Constants().Num32BitValues }, 4);
+        assert_eq!(unsafe { parameters_1_0[1].u.Descriptor().ShaderRegister }, 1);
+
+        let table = unsafe { parameters_1_1[2].u.DescriptorTable() };
+        assert_eq!(table.NumDescriptorRanges, 2);
+        assert_eq!(table.pDescriptorRanges, ranges_1_1[0].as_ptr());
+        assert_eq!(ranges_1_1[0][0].Flags, d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC);
+        assert_eq!(ranges_1_0[0][1].RegisterSpace, 1);
+    }
+}
@@ -0,0 +1,1142 @@
+// root signatures written in the HLSL grammar, e.g.
+// "RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), DescriptorTable(SRV(t0)), StaticSampler(s0)"
+use winapi::um::d3d12;
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+use super::{ DescriptorRange, RootDescriptorType, RootParameter, RootSignatureBuilder };
+
+const ROOT_FLAGS: &[(&str, u32)] = &[
+    ("ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT", d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT),
+    ("DENY_VERTEX_SHADER_ROOT_ACCESS", d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_VERTEX_SHADER_ROOT_ACCESS),
+    ("DENY_HULL_SHADER_ROOT_ACCESS", d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS),
+    ("DENY_DOMAIN_SHADER_ROOT_ACCESS", d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_DOMAIN_SHADER_ROOT_ACCESS),
+    ("DENY_GEOMETRY_SHADER_ROOT_ACCESS", d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_GEOMETRY_SHADER_ROOT_ACCESS),
+    ("DENY_PIXEL_SHADER_ROOT_ACCESS", d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_PIXEL_SHADER_ROOT_ACCESS),
+    ("ALLOW_STREAM_OUTPUT", d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_STREAM_OUTPUT),
+];
+
+const ROOT_DESCRIPTOR_FLAGS: &[(&str, u32)] = &[
+    ("DATA_VOLATILE", d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_VOLATILE),
+    ("DATA_STATIC_WHILE_SET_AT_EXECUTE", d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE),
+    ("DATA_STATIC", d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_STATIC),
+];
+
+// missing from winapi's D3D12_DESCRIPTOR_RANGE_FLAGS
+pub const D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_STATIC_KEEPING_BUFFER_BOUNDS_CHECKS: u32 = 0x10000;
+
+const DESCRIPTOR_RANGE_FLAGS: &[(&str, u32)] = &[
+    ("DESCRIPTORS_VOLATILE", d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_VOLATILE),
+    ("DATA_VOLATILE", d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_VOLATILE),
+    ("DATA_STATIC_WHILE_SET_AT_EXECUTE", d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE),
+    ("DATA_STATIC", d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC),
+    ("DESCRIPTORS_STATIC_KEEPING_BUFFER_BOUNDS_CHECKS", D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_STATIC_KEEPING_BUFFER_BOUNDS_CHECKS),
+];
+
+const SHADER_VISIBILITIES: &[(&str, u32)] = &[
+    ("SHADER_VISIBILITY_ALL", d3d12::D3D12_SHADER_VISIBILITY_ALL),
+    ("SHADER_VISIBILITY_VERTEX", d3d12::D3D12_SHADER_VISIBILITY_VERTEX),
+    ("SHADER_VISIBILITY_HULL", d3d12::D3D12_SHADER_VISIBILITY_HULL),
+    ("SHADER_VISIBILITY_DOMAIN", d3d12::D3D12_SHADER_VISIBILITY_DOMAIN),
+    ("SHADER_VISIBILITY_GEOMETRY", d3d12::D3D12_SHADER_VISIBILITY_GEOMETRY),
+    ("SHADER_VISIBILITY_PIXEL", d3d12::D3D12_SHADER_VISIBILITY_PIXEL),
+];
+
+const FILTERS: &[(&str, u32)] = &[
+    ("FILTER_MIN_MAG_MIP_POINT", d3d12::D3D12_FILTER_MIN_MAG_MIP_POINT),
+    ("FILTER_MIN_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MIN_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MIN_POINT_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MIN_POINT_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MIN_POINT_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MIN_POINT_MAG_MIP_LINEAR),
+    ("FILTER_MIN_LINEAR_MAG_MIP_POINT", d3d12::D3D12_FILTER_MIN_LINEAR_MAG_MIP_POINT),
+    ("FILTER_MIN_LINEAR_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MIN_LINEAR_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MIN_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MIN_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MIN_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MIN_MAG_MIP_LINEAR),
+    ("FILTER_ANISOTROPIC", d3d12::D3D12_FILTER_ANISOTROPIC),
+    ("FILTER_COMPARISON_MIN_MAG_MIP_POINT", d3d12::D3D12_FILTER_COMPARISON_MIN_MAG_MIP_POINT),
+    ("FILTER_COMPARISON_MIN_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_COMPARISON_MIN_MAG_POINT_MIP_LINEAR),
+    ("FILTER_COMPARISON_MIN_POINT_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_COMPARISON_MIN_POINT_MAG_LINEAR_MIP_POINT),
+    ("FILTER_COMPARISON_MIN_POINT_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_COMPARISON_MIN_POINT_MAG_MIP_LINEAR),
+    ("FILTER_COMPARISON_MIN_LINEAR_MAG_MIP_POINT", d3d12::D3D12_FILTER_COMPARISON_MIN_LINEAR_MAG_MIP_POINT),
+    ("FILTER_COMPARISON_MIN_LINEAR_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_COMPARISON_MIN_LINEAR_MAG_POINT_MIP_LINEAR),
+    ("FILTER_COMPARISON_MIN_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_COMPARISON_MIN_MAG_LINEAR_MIP_POINT),
+    ("FILTER_COMPARISON_MIN_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_COMPARISON_MIN_MAG_MIP_LINEAR),
+    ("FILTER_COMPARISON_ANISOTROPIC", d3d12::D3D12_FILTER_COMPARISON_ANISOTROPIC),
+    ("FILTER_MINIMUM_MIN_MAG_MIP_POINT", d3d12::D3D12_FILTER_MINIMUM_MIN_MAG_MIP_POINT),
+    ("FILTER_MINIMUM_MIN_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MINIMUM_MIN_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MINIMUM_MIN_POINT_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MINIMUM_MIN_POINT_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MINIMUM_MIN_POINT_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MINIMUM_MIN_POINT_MAG_MIP_LINEAR),
+    ("FILTER_MINIMUM_MIN_LINEAR_MAG_MIP_POINT", d3d12::D3D12_FILTER_MINIMUM_MIN_LINEAR_MAG_MIP_POINT),
+    ("FILTER_MINIMUM_MIN_LINEAR_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MINIMUM_MIN_LINEAR_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MINIMUM_MIN_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MINIMUM_MIN_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MINIMUM_MIN_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MINIMUM_MIN_MAG_MIP_LINEAR),
+    ("FILTER_MINIMUM_ANISOTROPIC", d3d12::D3D12_FILTER_MINIMUM_ANISOTROPIC),
+    ("FILTER_MAXIMUM_MIN_MAG_MIP_POINT", d3d12::D3D12_FILTER_MAXIMUM_MIN_MAG_MIP_POINT),
+    ("FILTER_MAXIMUM_MIN_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MAXIMUM_MIN_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MAXIMUM_MIN_POINT_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MAXIMUM_MIN_POINT_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MAXIMUM_MIN_POINT_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MAXIMUM_MIN_POINT_MAG_MIP_LINEAR),
+    ("FILTER_MAXIMUM_MIN_LINEAR_MAG_MIP_POINT", d3d12::D3D12_FILTER_MAXIMUM_MIN_LINEAR_MAG_MIP_POINT),
+    ("FILTER_MAXIMUM_MIN_LINEAR_MAG_POINT_MIP_LINEAR", d3d12::D3D12_FILTER_MAXIMUM_MIN_LINEAR_MAG_POINT_MIP_LINEAR),
+    ("FILTER_MAXIMUM_MIN_MAG_LINEAR_MIP_POINT", d3d12::D3D12_FILTER_MAXIMUM_MIN_MAG_LINEAR_MIP_POINT),
+    ("FILTER_MAXIMUM_MIN_MAG_MIP_LINEAR", d3d12::D3D12_FILTER_MAXIMUM_MIN_MAG_MIP_LINEAR),
+    ("FILTER_MAXIMUM_ANISOTROPIC", d3d12::D3D12_FILTER_MAXIMUM_ANISOTROPIC),
+];
+
+const ADDRESS_MODES: &[(&str, u32)] = &[
+    ("TEXTURE_ADDRESS_WRAP", d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP),
+    ("TEXTURE_ADDRESS_MIRROR", d3d12::D3D12_TEXTURE_ADDRESS_MODE_MIRROR),
+    ("TEXTURE_ADDRESS_CLAMP", d3d12::D3D12_TEXTURE_ADDRESS_MODE_CLAMP),
+    ("TEXTURE_ADDRESS_BORDER", d3d12::D3D12_TEXTURE_ADDRESS_MODE_BORDER),
+    ("TEXTURE_ADDRESS_MIRROR_ONCE", d3d12::D3D12_TEXTURE_ADDRESS_MODE_MIRROR_ONCE),
+];
+
+const COMPARISON_FUNCS: &[(&str, u32)] = &[
+    ("COMPARISON_NEVER", d3d12::D3D12_COMPARISON_FUNC_NEVER),
+    ("COMPARISON_LESS", d3d12::D3D12_COMPARISON_FUNC_LESS),
+    ("COMPARISON_EQUAL", d3d12::D3D12_COMPARISON_FUNC_EQUAL),
+    ("COMPARISON_LESS_EQUAL", d3d12::D3D12_COMPARISON_FUNC_LESS_EQUAL),
+    ("COMPARISON_GREATER", d3d12::D3D12_COMPARISON_FUNC_GREATER),
+    ("COMPARISON_NOT_EQUAL", d3d12::D3D12_COMPARISON_FUNC_NOT_EQUAL),
+    ("COMPARISON_GREATER_EQUAL", d3d12::D3D12_COMPARISON_FUNC_GREATER_EQUAL),
+    ("COMPARISON_ALWAYS", d3d12::D3D12_COMPARISON_FUNC_ALWAYS),
+];
+
+const BORDER_COLORS: &[(&str, u32)] = &[
+    ("STATIC_BORDER_COLOR_TRANSPARENT_BLACK", d3d12::D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK),
+    ("STATIC_BORDER_COLOR_OPAQUE_BLACK", d3d12::D3D12_STATIC_BORDER_COLOR_OPAQUE_BLACK),
+    ("STATIC_BORDER_COLOR_OPAQUE_WHITE", d3d12::D3D12_STATIC_BORDER_COLOR_OPAQUE_WHITE),
+];
+
+// numDescriptors = unbounded
+pub const UNBOUNDED: u32 = u32::MAX;
+
+fn name_of(table: &[(&'static str, u32)], value: u32) -> &'static str {
+    table.iter().find(|&&(_, entry)| entry == value).map(|&(name, _)| name).unwrap_or("?")
+}
+
+// static sampler with the HLSL defaults for omitted arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StaticSampler {
+    pub register: u32,
+    pub space: u32,
+    pub filter: d3d12::D3D12_FILTER,
+    pub address_u: d3d12::D3D12_TEXTURE_ADDRESS_MODE,
+    pub address_v: d3d12::D3D12_TEXTURE_ADDRESS_MODE,
+    pub address_w: d3d12::D3D12_TEXTURE_ADDRESS_MODE,
+    pub mip_lod_bias: f32,
+    pub max_anisotropy: u32,
+    pub comparison_func: d3d12::D3D12_COMPARISON_FUNC,
+    pub border_color: d3d12::D3D12_STATIC_BORDER_COLOR,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub visibility: d3d12::D3D12_SHADER_VISIBILITY,
+}
+
+impl StaticSampler {
+    pub fn new(register: u32) -> StaticSampler {
+        StaticSampler {
+            register: register,
+            space: 0,
+            filter: d3d12::D3D12_FILTER_ANISOTROPIC,
+            address_u: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            address_v: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            address_w: d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            mip_lod_bias: 0.0,
+            max_anisotropy: 16,
+            comparison_func: d3d12::D3D12_COMPARISON_FUNC_LESS_EQUAL,
+            border_color: d3d12::D3D12_STATIC_BORDER_COLOR_OPAQUE_WHITE,
+            min_lod: 0.0,
+            max_lod: d3d12::D3D12_FLOAT32_MAX,
+            visibility: d3d12::D3D12_SHADER_VISIBILITY_ALL,
+        }
+    }
+
+    pub fn from_d3d12(desc: &d3d12::D3D12_STATIC_SAMPLER_DESC) -> StaticSampler {
+        StaticSampler {
+            register: desc.ShaderRegister,
+            space: desc.RegisterSpace,
+            filter: desc.Filter,
+            address_u: desc.AddressU,
+            address_v: desc.AddressV,
+            address_w: desc.AddressW,
+            mip_lod_bias: desc.MipLODBias,
+            max_anisotropy: desc.MaxAnisotropy,
+            comparison_func: desc.ComparisonFunc,
+            border_color: desc.BorderColor,
+            min_lod: desc.MinLOD,
+            max_lod: desc.MaxLOD,
+            visibility: desc.ShaderVisibility,
+        }
+    }
+
+    pub fn to_d3d12(&self) -> d3d12::D3D12_STATIC_SAMPLER_DESC {
+        d3d12::D3D12_STATIC_SAMPLER_DESC {
+            Filter: self.filter,
+            AddressU: self.address_u,
+            AddressV: self.address_v,
+            AddressW: self.address_w,
+            MipLODBias: self.mip_lod_bias,
+            MaxAnisotropy: self.max_anisotropy,
+            ComparisonFunc: self.comparison_func,
+            BorderColor: self.border_color,
+            MinLOD: self.min_lod,
+            MaxLOD: self.max_lod,
+            ShaderRegister: self.register,
+            RegisterSpace: self.space,
+            ShaderVisibility: self.visibility,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RootSignatureDesc {
+    pub flags: d3d12::D3D12_ROOT_SIGNATURE_FLAGS,
+    pub parameters: Vec<RootParameter>,
+    pub static_samplers: Vec<StaticSampler>,
+}
+
+impl RootSignatureDesc {
+    pub fn from_builder(builder: &RootSignatureBuilder) -> RootSignatureDesc {
+        RootSignatureDesc {
+            flags: builder.flags,
+            parameters: builder.parameters.clone(),
+            static_samplers: builder.static_samplers.iter().map(StaticSampler::from_d3d12).collect(),
+        }
+    }
+
+    pub fn to_builder(&self) -> RootSignatureBuilder {
+        let mut builder = RootSignatureBuilder::new().flags(self.flags);
+
+        for parameter in self.parameters.iter() {
+            builder = builder.parameter(parameter.clone());
+        }
+
+        for sampler in self.static_samplers.iter() {
+            builder = builder.static_sampler(sampler.to_d3d12());
+        }
+
+        builder
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Equals,
+    Pipe,
+    End,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Ident(name) => write!(f, "`{}`", name),
+            TokenKind::Number(number) => write!(f, "`{}`", number),
+            TokenKind::LeftParen => write!(f, "`(`"),
+            TokenKind::RightParen => write!(f, "`)`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Equals => write!(f, "`=`"),
+            TokenKind::Pipe => write!(f, "`|`"),
+            TokenKind::End => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    // byte offset into the source
+    offset: usize,
+}
+
+// 1 based line and column (in characters) of a byte offset
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+    (line, source[line_start..offset].chars().count() + 1)
+}
+
+fn error_at(source: &str, offset: usize, message: String) -> ParseError {
+    let (line, column) = position(source, offset);
+
+    ParseError { line: line, column: column, message: message }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        let kind = match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                i += 1;
+                continue;
+            },
+            b'(' => TokenKind::LeftParen,
+            b')' => TokenKind::RightParen,
+            b',' => TokenKind::Comma,
+            b'=' => TokenKind::Equals,
+            b'|' => TokenKind::Pipe,
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+
+                tokens.push(Token { kind: TokenKind::Ident(source[start..i].to_string()), offset: start });
+                continue;
+            },
+            b'0'..=b'9' | b'.' | b'-' | b'+' => {
+                // [+-]digits[.digits][e[+-]digits][f]
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                    i += 1;
+                    if i < bytes.len() && (bytes[i] == b'-' || bytes[i] == b'+') {
+                        i += 1;
+                    }
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                if i < bytes.len() && (bytes[i] == b'f' || bytes[i] == b'F') {
+                    i += 1;
+                }
+
+                tokens.push(Token { kind: TokenKind::Number(source[start..i].to_string()), offset: start });
+                continue;
+            },
+            _ => {
+                let character = source[start..].chars().next().unwrap();
+                return Err(error_at(source, start, format!("unexpected character `{}`", character)));
+            },
+        };
+
+        tokens.push(Token { kind: kind, offset: start });
+        i += 1;
+    }
+
+    tokens.push(Token { kind: TokenKind::End, offset: source.len() });
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+// keyword arguments already given to the current element
+struct SeenArguments<'a>(Vec<&'a str>);
+
+impl<'a> SeenArguments<'a> {
+    fn insert(&mut self, parser: &Parser, key: &'a str, offset: usize) -> Result<(), ParseError> {
+        if self.0.contains(&key) {
+            return Err(parser.error(offset, format!("`{}` is given more than once", key)));
+        }
+
+        self.0.push(key);
+        Ok(())
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        error_at(self.source, offset, message)
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn peek_second(&self) -> &TokenKind {
+        &self.tokens[(self.position + 1).min(self.tokens.len() - 1)].kind
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.peek().kind == kind {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), ParseError> {
+        let token = self.next();
+
+        match token.kind == kind {
+            true => Ok(()),
+            false => Err(self.error(token.offset, format!("expected {}, found {}", kind, token.kind))),
+        }
+    }
+
+    fn ident(&mut self, what: &str) -> Result<(String, usize), ParseError> {
+        let token = self.next();
+
+        match token.kind {
+            TokenKind::Ident(name) => Ok((name, token.offset)),
+            kind => Err(self.error(token.offset, format!("expected {}, found {}", what, kind))),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, ParseError> {
+        let token = self.next();
+
+        if let TokenKind::Number(ref number) = token.kind {
+            let digits = number.trim_end_matches(['f', 'F']);
+
+            if let Ok(value) = digits.parse::<T>() {
+                return Ok(value);
+            }
+        }
+
+        Err(self.error(token.offset, format!("expected {}, found {}", what, token.kind)))
+    }
+
+    fn enumerant(&mut self, table: &[(&str, u32)], what: &str) -> Result<u32, ParseError> {
+        let (name, offset) = self.ident(what)?;
+
+        table.iter().find(|&&(entry, _)| entry.eq_ignore_ascii_case(&name)).map(|&(_, value)| value)
+            .ok_or_else(|| self.error(offset, format!("unknown {} `{}`", what, name)))
+    }
+
+    // NAME | NAME | ... or 0
+    fn flag_set(&mut self, table: &[(&str, u32)], what: &str) -> Result<u32, ParseError> {
+        if let TokenKind::Number(_) = self.peek().kind {
+            let offset = self.peek().offset;
+
+            return match self.number::<u32>(what)? {
+                0 => Ok(0),
+                _ => Err(self.error(offset, format!("{} must be named, only 0 is allowed as a number", what))),
+            };
+        }
+
+        let mut flags = self.enumerant(table, what)?;
+
+        while self.eat(TokenKind::Pipe) {
+            flags |= self.enumerant(table, what)?;
+        }
+
+        Ok(flags)
+    }
+
+    // t0, b3, s1 or u2 with the expected register class
+    fn register(&mut self, class: char) -> Result<u32, ParseError> {
+        let (name, offset) = self.ident("a register")?;
+
+        let mut characters = name.chars();
+        let prefix = characters.next().unwrap();
+        let index = characters.as_str();
+
+        if !prefix.eq_ignore_ascii_case(&class) || index.is_empty() {
+            return Err(self.error(offset, format!("expected a `{}` register, found `{}`", class, name)));
+        }
+
+        index.parse().map_err(|_| self.error(offset, format!("invalid register `{}`", name)))
+    }
+
+    // `key =` when the next argument is a keyword argument
+    fn keyword(&mut self) -> Option<(String, usize)> {
+        if let TokenKind::Ident(name) = &self.peek().kind {
+            if *self.peek_second() == TokenKind::Equals {
+                let key = (name.clone(), self.peek().offset);
+                self.position += 2;
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    fn missing_register(&self, element: &str, class: char) -> ParseError {
+        self.error(self.peek().offset, format!("{} needs a `{}` register", element, class))
+    }
+
+    fn unknown_argument(&self, element: &str, key: &str, offset: usize) -> ParseError {
+        self.error(offset, format!("unknown argument `{}` for {}", key, element))
+    }
+
+    fn root_signature(&mut self) -> Result<RootSignatureDesc, ParseError> {
+
+        let mut desc = RootSignatureDesc::default();
+        let mut flags_offset = None;
+
+        if self.peek().kind == TokenKind::End {
+            return Ok(desc);
+        }
+
+        loop {
+            let (name, offset) = self.ident("a root signature element")?;
+
+            match name.to_ascii_lowercase().as_str() {
+                "rootflags" => {
+                    if flags_offset.is_some() {
+                        return Err(self.error(offset, "RootFlags is given more than once".to_string()));
+                    }
+                    flags_offset = Some(offset);
+
+                    self.expect(TokenKind::LeftParen)?;
+                    desc.flags = self.flag_set(ROOT_FLAGS, "root flag")?;
+                    self.expect(TokenKind::RightParen)?;
+                },
+                "rootconstants" => desc.parameters.push(self.root_constants()?),
+                "cbv" => desc.parameters.push(self.root_descriptor(RootDescriptorType::Cbv)?),
+                "srv" => desc.parameters.push(self.root_descriptor(RootDescriptorType::Srv)?),
+                "uav" => desc.parameters.push(self.root_descriptor(RootDescriptorType::Uav)?),
+                "descriptortable" => desc.parameters.push(self.descriptor_table()?),
+                "staticsampler" => desc.static_samplers.push(self.static_sampler()?),
+                _ => return Err(self.error(offset, format!("unknown root signature element `{}`", name))),
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        let token = self.next();
+
+        match token.kind {
+            TokenKind::End => Ok(desc),
+            kind => Err(self.error(token.offset, format!("expected `,` or end of input, found {}", kind))),
+        }
+    }
+
+    fn root_constants(&mut self) -> Result<RootParameter, ParseError> {
+
+        let mut register = None;
+        let mut num_values = None;
+        let mut space = 0;
+        let mut visibility = d3d12::D3D12_SHADER_VISIBILITY_ALL;
+        let mut seen = SeenArguments(Vec::new());
+
+        self.expect(TokenKind::LeftParen)?;
+
+        loop {
+            match self.keyword() {
+                Some((key, offset)) => match key.to_ascii_lowercase().as_str() {
+                    "num32bitconstants" => { seen.insert(self, "num32BitConstants", offset)?; num_values = Some(self.number("a constant count")?); },
+                    "space" => { seen.insert(self, "space", offset)?; space = self.number("a register space")?; },
+                    "visibility" => { seen.insert(self, "visibility", offset)?; visibility = self.enumerant(SHADER_VISIBILITIES, "shader visibility")?; },
+                    _ => return Err(self.unknown_argument("RootConstants", &key, offset)),
+                },
+                None => {
+                    let offset = self.peek().offset;
+                    seen.insert(self, "register", offset)?;
+                    register = Some(self.register('b')?);
+                },
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        let register = register.ok_or_else(|| self.missing_register("RootConstants", 'b'))?;
+        let num_values = num_values.ok_or_else(|| self.error(self.peek().offset, "RootConstants needs num32BitConstants".to_string()))?;
+
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(RootParameter::Constants {
+            register: register,
+            space: space,
+            num_values: num_values,
+            visibility: visibility,
+        })
+    }
+
+    fn root_descriptor(&mut self, descriptor_type: RootDescriptorType) -> Result<RootParameter, ParseError> {
+
+        let (element, class) = match descriptor_type {
+            RootDescriptorType::Cbv => ("CBV", 'b'),
+            RootDescriptorType::Srv => ("SRV", 't'),
+            RootDescriptorType::Uav => ("UAV", 'u'),
+        };
+
+        let mut register = None;
+        let mut space = 0;
+        let mut visibility = d3d12::D3D12_SHADER_VISIBILITY_ALL;
+        let mut flags = d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE;
+        let mut seen = SeenArguments(Vec::new());
+
+        self.expect(TokenKind::LeftParen)?;
+
+        loop {
+            match self.keyword() {
+                Some((key, offset)) => match key.to_ascii_lowercase().as_str() {
+                    "space" => { seen.insert(self, "space", offset)?; space = self.number("a register space")?; },
+                    "visibility" => { seen.insert(self, "visibility", offset)?; visibility = self.enumerant(SHADER_VISIBILITIES, "shader visibility")?; },
+                    "flags" => { seen.insert(self, "flags", offset)?; flags = self.flag_set(ROOT_DESCRIPTOR_FLAGS, "root descriptor flag")?; },
+                    _ => return Err(self.unknown_argument(element, &key, offset)),
+                },
+                None => {
+                    let offset = self.peek().offset;
+                    seen.insert(self, "register", offset)?;
+                    register = Some(self.register(class)?);
+                },
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        let register = register.ok_or_else(|| self.missing_register(element, class))?;
+
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(RootParameter::Descriptor {
+            descriptor_type: descriptor_type,
+            register: register,
+            space: space,
+            flags: flags,
+            visibility: visibility,
+        })
+    }
+
+    fn descriptor_table(&mut self) -> Result<RootParameter, ParseError> {
+
+        let mut ranges = Vec::new();
+        let mut visibility = d3d12::D3D12_SHADER_VISIBILITY_ALL;
+        let mut seen = SeenArguments(Vec::new());
+
+        self.expect(TokenKind::LeftParen)?;
+
+        if self.eat(TokenKind::RightParen) {
+            return Ok(RootParameter::Table { ranges: ranges, visibility: visibility });
+        }
+
+        loop {
+            match self.keyword() {
+                Some((key, offset)) => match key.to_ascii_lowercase().as_str() {
+                    "visibility" => { seen.insert(self, "visibility", offset)?; visibility = self.enumerant(SHADER_VISIBILITIES, "shader visibility")?; },
+                    _ => return Err(self.unknown_argument("DescriptorTable", &key, offset)),
+                },
+                None => ranges.push(self.descriptor_range()?),
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(RootParameter::Table { ranges: ranges, visibility: visibility })
+    }
+
+    fn descriptor_range(&mut self) -> Result<DescriptorRange, ParseError> {
+
+        let (name, offset) = self.ident("a descriptor range")?;
+
+        let (element, range_type, class) = match name.to_ascii_lowercase().as_str() {
+            "cbv" => ("CBV", d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_CBV, 'b'),
+            "srv" => ("SRV", d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SRV, 't'),
+            "uav" => ("UAV", d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_UAV, 'u'),
+            "sampler" => ("Sampler", d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER, 's'),
+            _ => return Err(self.error(offset, format!("unknown descriptor range `{}`", name))),
+        };
+
+        let mut register = None;
+        let mut range = DescriptorRange::new(range_type, 1, 0);
+        let mut seen = SeenArguments(Vec::new());
+
+        self.expect(TokenKind::LeftParen)?;
+
+        loop {
+            match self.keyword() {
+                Some((key, offset)) => match key.to_ascii_lowercase().as_str() {
+                    "numdescriptors" => {
+                        seen.insert(self, "numDescriptors", offset)?;
+                        range.count = match &self.peek().kind {
+                            TokenKind::Ident(value) if value.eq_ignore_ascii_case("unbounded") => { self.next(); UNBOUNDED },
+                            _ => self.number("a descriptor count or `unbounded`")?,
+                        };
+                    },
+                    "space" => { seen.insert(self, "space", offset)?; range.space = self.number("a register space")?; },
+                    "offset" => {
+                        seen.insert(self, "offset", offset)?;
+                        range.offset = match &self.peek().kind {
+                            TokenKind::Ident(value) if value.eq_ignore_ascii_case("DESCRIPTOR_RANGE_OFFSET_APPEND") => { self.next(); d3d12::D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND },
+                            _ => self.number("an offset or `DESCRIPTOR_RANGE_OFFSET_APPEND`")?,
+                        };
+                    },
+                    "flags" => { seen.insert(self, "flags", offset)?; range.flags = self.flag_set(DESCRIPTOR_RANGE_FLAGS, "descriptor range flag")?; },
+                    _ => return Err(self.unknown_argument(element, &key, offset)),
+                },
+                None => {
+                    let offset = self.peek().offset;
+                    seen.insert(self, "register", offset)?;
+                    register = Some(self.register(class)?);
+                },
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        range.base_register = register.ok_or_else(|| self.missing_register(element, class))?;
+
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(range)
+    }
+
+    fn static_sampler(&mut self) -> Result<StaticSampler, ParseError> {
+
+        let mut register = None;
+        let mut sampler = StaticSampler::new(0);
+        let mut seen = SeenArguments(Vec::new());
+
+        self.expect(TokenKind::LeftParen)?;
+
+        loop {
+            match self.keyword() {
+                Some((key, offset)) => match key.to_ascii_lowercase().as_str() {
+                    "filter" => { seen.insert(self, "filter", offset)?; sampler.filter = self.enumerant(FILTERS, "filter")?; },
+                    "addressu" => { seen.insert(self, "addressU", offset)?; sampler.address_u = self.enumerant(ADDRESS_MODES, "address mode")?; },
+                    "addressv" => { seen.insert(self, "addressV", offset)?; sampler.address_v = self.enumerant(ADDRESS_MODES, "address mode")?; },
+                    "addressw" => { seen.insert(self, "addressW", offset)?; sampler.address_w = self.enumerant(ADDRESS_MODES, "address mode")?; },
+                    "miplodbias" => { seen.insert(self, "mipLODBias", offset)?; sampler.mip_lod_bias = self.number("a number")?; },
+                    "maxanisotropy" => { seen.insert(self, "maxAnisotropy", offset)?; sampler.max_anisotropy = self.number("an anisotropy level")?; },
+                    "comparisonfunc" => { seen.insert(self, "comparisonFunc", offset)?; sampler.comparison_func = self.enumerant(COMPARISON_FUNCS, "comparison function")?; },
+                    "bordercolor" => { seen.insert(self, "borderColor", offset)?; sampler.border_color = self.enumerant(BORDER_COLORS, "border color")?; },
+                    "minlod" => { seen.insert(self, "minLOD", offset)?; sampler.min_lod = self.number("a number")?; },
+                    "maxlod" => { seen.insert(self, "maxLOD", offset)?; sampler.max_lod = self.number("a number")?; },
+                    "space" => { seen.insert(self, "space", offset)?; sampler.space = self.number("a register space")?; },
+                    "visibility" => { seen.insert(self, "visibility", offset)?; sampler.visibility = self.enumerant(SHADER_VISIBILITIES, "shader visibility")?; },
+                    _ => return Err(self.unknown_argument("StaticSampler", &key, offset)),
+                },
+                None => {
+                    let offset = self.peek().offset;
+                    seen.insert(self, "register", offset)?;
+                    register = Some(self.register('s')?);
+                },
+            }
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
+        }
+
+        sampler.register = register.ok_or_else(|| self.missing_register("StaticSampler", 's'))?;
+
+        self.expect(TokenKind::RightParen)?;
+
+        Ok(sampler)
+    }
+}
+
+pub fn parse(source: &str) -> Result<RootSignatureDesc, ParseError> {
+    let mut parser = Parser {
+        source: source,
+        tokens: tokenize(source)?,
+        position: 0,
+    };
+
+    parser.root_signature()
+}
+
+fn write_flags(f: &mut fmt::Formatter, table: &[(&'static str, u32)], flags: u32) -> fmt::Result {
+    if flags == 0 {
+        return write!(f, "0");
+    }
+
+    let mut names: Vec<_> = table.iter().filter(|&&(_, value)| flags & value == value).map(|&(name, _)| name.to_string()).collect();
+
+    // bits without a name are printed as a number so they are not lost silently, the parser rejects them
+    let unknown = table.iter().fold(flags, |rest, &(_, value)| rest & !value);
+
+    if unknown != 0 {
+        names.push(format!("{:#x}", unknown));
+    }
+
+    write!(f, "{}", names.join(" | "))
+}
+
+fn write_visibility(f: &mut fmt::Formatter, visibility: u32) -> fmt::Result {
+    match visibility {
+        d3d12::D3D12_SHADER_VISIBILITY_ALL => Ok(()),
+        _ => write!(f, ", visibility = {}", name_of(SHADER_VISIBILITIES, visibility)),
+    }
+}
+
+fn write_space(f: &mut fmt::Formatter, space: u32) -> fmt::Result {
+    match space {
+        0 => Ok(()),
+        _ => write!(f, ", space = {}", space),
+    }
+}
+
+fn write_range(f: &mut fmt::Formatter, range: &DescriptorRange) -> fmt::Result {
+    let (name, class) = match range.range_type {
+        d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_CBV => ("CBV", 'b'),
+        d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_UAV => ("UAV", 'u'),
+        d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER => ("Sampler", 's'),
+        _ => ("SRV", 't'),
+    };
+
+    write!(f, "{}({}{}", name, class, range.base_register)?;
+
+    match range.count {
+        1 => {},
+        UNBOUNDED => write!(f, ", numDescriptors = unbounded")?,
+        count => write!(f, ", numDescriptors = {}", count)?,
+    }
+
+    write_space(f, range.space)?;
+
+    if range.offset != d3d12::D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND {
+        write!(f, ", offset = {}", range.offset)?;
+    }
+
+    if range.flags != d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_NONE {
+        write!(f, ", flags = ")?;
+        write_flags(f, DESCRIPTOR_RANGE_FLAGS, range.flags)?;
+    }
+
+    write!(f, ")")
+}
+
+fn write_sampler(f: &mut fmt::Formatter, sampler: &StaticSampler) -> fmt::Result {
+    let defaults = StaticSampler::new(sampler.register);
+
+    write!(f, "StaticSampler(s{}", sampler.register)?;
+
+    if sampler.filter != defaults.filter {
+        write!(f, ", filter = {}", name_of(FILTERS, sampler.filter))?;
+    }
+
+    for &(name, mode, default) in [("addressU", sampler.address_u, defaults.address_u), ("addressV", sampler.address_v, defaults.address_v), ("addressW", sampler.address_w, defaults.address_w)].iter() {
+        if mode != default {
+            write!(f, ", {} = {}", name, name_of(ADDRESS_MODES, mode))?;
+        }
+    }
+
+    // {:?} prints the shortest text which parses back to the same f32
+    if sampler.mip_lod_bias != defaults.mip_lod_bias {
+        write!(f, ", mipLODBias = {:?}", sampler.mip_lod_bias)?;
+    }
+    if sampler.max_anisotropy != defaults.max_anisotropy {
+        write!(f, ", maxAnisotropy = {}", sampler.max_anisotropy)?;
+    }
+    if sampler.comparison_func != defaults.comparison_func {
+        write!(f, ", comparisonFunc = {}", name_of(COMPARISON_FUNCS, sampler.comparison_func))?;
+    }
+    if sampler.border_color != defaults.border_color {
+        write!(f, ", borderColor = {}", name_of(BORDER_COLORS, sampler.border_color))?;
+    }
+    if sampler.min_lod != defaults.min_lod {
+        write!(f, ", minLOD = {:?}", sampler.min_lod)?;
+    }
+    if sampler.max_lod != defaults.max_lod {
+        write!(f, ", maxLOD = {:?}", sampler.max_lod)?;
+    }
+
+    write_space(f, sampler.space)?;
+    write_visibility(f, sampler.visibility)?;
+
+    write!(f, ")")
+}
+
+// prints the HLSL form, arguments with default values are left out
+impl fmt::Display for RootSignatureDesc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        let mut separator = "";
+
+        if self.flags != d3d12::D3D12_ROOT_SIGNATURE_FLAG_NONE {
+            write!(f, "RootFlags(")?;
+            write_flags(f, ROOT_FLAGS, self.flags)?;
+            write!(f, ")")?;
+            separator = ", ";
+        }
+
+        for parameter in self.parameters.iter() {
+            write!(f, "{}", separator)?;
+            separator = ", ";
+
+            match parameter {
+                RootParameter::Constants { register, space, num_values, visibility } => {
+                    write!(f, "RootConstants(num32BitConstants = {}, b{}", num_values, register)?;
+                    write_space(f, *space)?;
+                    write_visibility(f, *visibility)?;
+                    write!(f, ")")?;
+                },
+                RootParameter::Descriptor { descriptor_type, register, space, flags, visibility } => {
+                    let (name, class) = match descriptor_type {
+                        RootDescriptorType::Cbv => ("CBV", 'b'),
+                        RootDescriptorType::Srv => ("SRV", 't'),
+                        RootDescriptorType::Uav => ("UAV", 'u'),
+                    };
+
+                    write!(f, "{}({}{}", name, class, register)?;
+                    write_space(f, *space)?;
+                    write_visibility(f, *visibility)?;
+
+                    if *flags != d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE {
+                        write!(f, ", flags = ")?;
+                        write_flags(f, ROOT_DESCRIPTOR_FLAGS, *flags)?;
+                    }
+
+                    write!(f, ")")?;
+                },
+                RootParameter::Table { ranges, visibility } => {
+                    write!(f, "DescriptorTable(")?;
+
+                    for (index, range) in ranges.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write_range(f, range)?;
+                    }
+
+                    match (ranges.is_empty(), *visibility) {
+                        (_, d3d12::D3D12_SHADER_VISIBILITY_ALL) => {},
+                        (true, visibility) => write!(f, "visibility = {}", name_of(SHADER_VISIBILITIES, visibility))?,
+                        (false, visibility) => write_visibility(f, visibility)?,
+                    }
+
+                    write!(f, ")")?;
+                },
+            }
+        }
+
+        for sampler in self.static_samplers.iter() {
+            write!(f, "{}", separator)?;
+            separator = ", ";
+
+            write_sampler(f, sampler)?;
+        }
+
+        Ok(())
+    }
+}
+
+// concatenated string literals of `#define name "..." \` in HLSL source
+pub fn find_define(source: &str, name: &str) -> Option<String> {
+
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        let rest = match line.trim_start().strip_prefix('#') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+
+        let rest = match rest.strip_prefix("define") {
+            Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim_start(),
+            _ => continue,
+        };
+
+        let value = match rest.strip_prefix(name) {
+            Some(value) if value.is_empty() || value.starts_with(char::is_whitespace) => value,
+            _ => continue,
+        };
+
+        // continuation lines end with a backslash
+        let mut body = String::new();
+        let mut current = value;
+
+        loop {
+            let trimmed = current.trim_end();
+
+            match trimmed.strip_suffix('\\') {
+                Some(continued) => {
+                    body.push_str(continued);
+                    current = lines.next().unwrap_or("");
+                },
+                None => {
+                    body.push_str(trimmed);
+                    break;
+                },
+            }
+        }
+
+        return Some(string_literals(&body));
+    }
+
+    None
+}
+
+// "a" "b" -> ab
+fn string_literals(text: &str) -> String {
+    let mut value = String::new();
+    let mut inside = false;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        match (inside, escaped, c) {
+            (true, false, '\\') => escaped = true,
+            (true, false, '"') => inside = false,
+            (true, _, c) => {
+                value.push(c);
+                escaped = false;
+            },
+            (false, _, '"') => inside = true,
+            (false, _, _) => {},
+        }
+    }
+
+    value
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    MissingDefine(String),
+    Parse(ParseError),
+}
+
+// parse the root signature `#define`d as `name` in an HLSL file
+pub fn load(path: &str, name: &str) -> Result<RootSignatureDesc, LoadError> {
+    let source = fs::read_to_string(path).map_err(LoadError::Io)?;
+
+    let root_signature = find_define(&source, name).ok_or_else(|| LoadError::MissingDefine(name.to_string()))?;
+
+    parse(&root_signature).map_err(LoadError::Parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT | DENY_HULL_SHADER_ROOT_ACCESS), \
+        RootConstants(num32BitConstants = 4, b1, visibility = SHADER_VISIBILITY_VERTEX), \
+        CBV(b0, flags = DATA_STATIC), \
+        DescriptorTable(SRV(t0, numDescriptors = unbounded, space = 1, flags = DESCRIPTORS_VOLATILE | DATA_VOLATILE), UAV(u2, offset = 8), visibility = SHADER_VISIBILITY_PIXEL), \
+        DescriptorTable(Sampler(s1, numDescriptors = 2)), \
+        StaticSampler(s0, filter = FILTER_MIN_MAG_MIP_POINT, addressU = TEXTURE_ADDRESS_CLAMP, mipLODBias = -0.5f, maxLOD = 8.0, visibility = SHADER_VISIBILITY_PIXEL)";
+
+    #[test]
+    fn elements_are_parsed_in_order() {
+        let desc = parse(EXAMPLE).unwrap();
+
+        assert_eq!(desc.flags, d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT | d3d12::D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS);
+        assert_eq!(desc.parameters.len(), 4);
+        assert_eq!(desc.parameters[0], RootParameter::Constants {
+            register: 1,
+            space: 0,
+            num_values: 4,
+            visibility: d3d12::D3D12_SHADER_VISIBILITY_VERTEX,
+        });
+        assert_eq!(desc.parameters[2], RootParameter::Table {
+            ranges: vec![
+                DescriptorRange::srv(UNBOUNDED, 0).space(1).flags(d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_VOLATILE | d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_VOLATILE),
+                DescriptorRange::uav(1, 2).offset(8),
+            ],
+            visibility: d3d12::D3D12_SHADER_VISIBILITY_PIXEL,
+        });
+
+        let sampler = desc.static_samplers[0];
+        assert_eq!(sampler.filter, d3d12::D3D12_FILTER_MIN_MAG_MIP_POINT);
+        assert_eq!(sampler.address_u, d3d12::D3D12_TEXTURE_ADDRESS_MODE_CLAMP);
+        assert_eq!(sampler.address_v, d3d12::D3D12_TEXTURE_ADDRESS_MODE_WRAP);
+        assert_eq!(sampler.mip_lod_bias, -0.5);
+        assert_eq!(sampler.max_lod, 8.0);
+
+        assert_eq!(desc.to_builder().validate(), Ok(()));
+    }
+
+    #[test]
+    fn printing_round_trips() {
+        let desc = parse(EXAMPLE).unwrap();
+        let printed = desc.to_string();
+
+        assert_eq!(parse(&printed).unwrap(), desc);
+        assert_eq!(parse(&printed).unwrap().to_string(), printed);
+        assert_eq!(parse("").unwrap().to_string(), "");
+    }
+
+    #[test]
+    fn builder_descriptions_round_trip() {
+        let builder = RootSignatureBuilder::new()
+            .flags(d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT)
+            .constants(0, 0, 2, d3d12::D3D12_SHADER_VISIBILITY_VERTEX)
+            .descriptor(RootDescriptorType::Srv, 1, 2, d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_STATIC, d3d12::D3D12_SHADER_VISIBILITY_ALL)
+            .table(&[
+                DescriptorRange::srv(UNBOUNDED, 0).flags(D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_STATIC_KEEPING_BUFFER_BOUNDS_CHECKS),
+                DescriptorRange::cbv(2, 1).space(3),
+            ], d3d12::D3D12_SHADER_VISIBILITY_PIXEL)
+            .static_sampler(StaticSampler::new(0).to_d3d12());
+
+        let desc = RootSignatureDesc::from_builder(&builder);
+        let printed = desc.to_string();
+
+        assert!(printed.contains("flags = DESCRIPTORS_STATIC_KEEPING_BUFFER_BOUNDS_CHECKS"), "{}", printed);
+        assert_eq!(parse(&printed).unwrap(), desc);
+
+        // unknown bits show up in the output and fail to parse
+        let unknown = RootSignatureDesc::from_builder(&RootSignatureBuilder::new().flags(0x40 | 0x100));
+        assert_eq!(unknown.to_string(), "RootFlags(ALLOW_STREAM_OUTPUT | 0x100)");
+        assert!(parse(&unknown.to_string()).is_err());
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let error = parse("CBV(b0), SRV(b1)").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 14));
+        assert_eq!(error.message, "expected a `t` register, found `b1`");
+
+        let error = parse("RootFlags(0),\n  DescriptorTable(CBV(b0, spce = 1))").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 27));
+        assert_eq!(error.message, "unknown argument `spce` for CBV");
+
+        let error = parse("StaticSampler(s0, filter = FILTER_POINT)").unwrap_err();
+        assert_eq!(error.column, 28);
+
+        let error = parse("CBV(b0, space = 1, space = 2)").unwrap_err();
+        assert_eq!(error.column, 20);
+
+        let error = parse("CBV(b0) SRV(t0)").unwrap_err();
+        assert_eq!(error.to_string(), "1:9: expected `,` or end of input, found `SRV`");
+
+        let error = parse("RootConstants(b0)").unwrap_err();
+        assert_eq!(error.message, "RootConstants needs num32BitConstants");
+    }
+
+    #[test]
+    fn defines_are_read_from_hlsl() {
+        let source = "#include \"Other.hlsli\"\n\
+            #define OtherRS \"CBV(b1)\"\n\
+            #define BasicRS \"RootFlags(0), \" \\\n    \"CBV(b0)\"\n\
+            float4 main(): SV_TARGET { return 0; }\n";
+
+        assert_eq!(find_define(source, "BasicRS").unwrap(), "RootFlags(0), CBV(b0)");
+        assert_eq!(find_define(source, "Basic"), None);
+    }
+}
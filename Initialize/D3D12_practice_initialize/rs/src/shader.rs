@@ -0,0 +1,322 @@
+// shader stages and source helpers shared by the HLSL scanners
+use winapi::um::d3d12;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+pub mod resources;
+pub mod input_signature;
+pub mod preprocessor;
+pub mod cache;
+pub mod hot_reload;
+pub mod compiler;
+pub mod permutation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Vertex,
+    Hull,
+    Domain,
+    Geometry,
+    Pixel,
+    Compute,
+}
+
+impl Stage {
+    pub fn visibility(self) -> d3d12::D3D12_SHADER_VISIBILITY {
+        match self {
+            Stage::Vertex => d3d12::D3D12_SHADER_VISIBILITY_VERTEX,
+            Stage::Hull => d3d12::D3D12_SHADER_VISIBILITY_HULL,
+            Stage::Domain => d3d12::D3D12_SHADER_VISIBILITY_DOMAIN,
+            Stage::Geometry => d3d12::D3D12_SHADER_VISIBILITY_GEOMETRY,
+            Stage::Pixel => d3d12::D3D12_SHADER_VISIBILITY_PIXEL,
+            Stage::Compute => d3d12::D3D12_SHADER_VISIBILITY_ALL,
+        }
+    }
+
+    // "vs" for vs_5_0
+    pub fn profile_prefix(self) -> &'static str {
+        match self {
+            Stage::Vertex => "vs",
+            Stage::Hull => "hs",
+            Stage::Domain => "ds",
+            Stage::Geometry => "gs",
+            Stage::Pixel => "ps",
+            Stage::Compute => "cs",
+        }
+    }
+
+    pub fn from_profile(profile: &str) -> Option<Stage> {
+        let prefix = profile.split('_').next().unwrap_or("");
+
+        [Stage::Vertex, Stage::Hull, Stage::Domain, Stage::Geometry, Stage::Pixel, Stage::Compute].iter()
+            .find(|stage| stage.profile_prefix() == prefix)
+            .copied()
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.profile_prefix().to_ascii_uppercase())
+    }
+}
+
+// an entry point of a shader file and how it is compiled
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderSource {
+    pub path: String,
+    pub entry_point: String,
+    pub target: String,
+    pub flags: u32,
+    // like /D NAME=value, on top of the preprocessor's defines
+    pub defines: Vec<(String, String)>,
+}
+
+impl ShaderSource {
+    pub fn new(path: &str, entry_point: &str, target: &str, flags: u32) -> ShaderSource {
+        ShaderSource {
+            path: path.to_string(),
+            entry_point: entry_point.to_string(),
+            target: target.to_string(),
+            flags: flags,
+            defines: Vec::new(),
+        }
+    }
+
+    pub fn define(mut self, name: &str, value: &str) -> ShaderSource {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+// replaces comments with spaces, keeping newlines so line numbers stay put
+pub fn strip_comments(source: &str) -> String {
+
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                stripped.push(c);
+            },
+            '\\' if in_string => {
+                stripped.push(c);
+                if let Some(escaped) = chars.next() {
+                    stripped.push(escaped);
+                }
+            },
+            '/' if !in_string && chars.peek() == Some(&'/') => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    stripped.push(' ');
+                }
+                stripped.push(' ');
+            },
+            '/' if !in_string && chars.peek() == Some(&'*') => {
+                chars.next();
+                stripped.push_str("  ");
+
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    stripped.push(if next == '\n' { '\n' } else { ' ' });
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            },
+            '\n' => {
+                // strings don't continue over lines
+                in_string = false;
+                stripped.push(c);
+            },
+            _ => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+// names in #include "name" and #include <name> lines
+pub fn includes(source: &str) -> Vec<String> {
+    strip_comments(source).lines()
+        .filter_map(|line| line.trim_start().strip_prefix('#'))
+        .filter_map(|directive| directive.trim_start().strip_prefix("include"))
+        .filter_map(|rest| {
+            let rest = rest.trim();
+            let close = match rest.chars().next() {
+                Some('"') => '"',
+                Some('<') => '>',
+                _ => return None,
+            };
+            rest[1..].find(close).map(|end| rest[1..end + 1].to_string())
+        })
+        .collect()
+}
+
+// a file and the files it includes, included files first and each file once.
+// includes are looked up next to the including file like D3D_COMPILE_STANDARD_FILE_INCLUDE does
+pub fn read_with_includes(path: &str) -> io::Result<Vec<SourceFile>> {
+    let mut files = Vec::new();
+    collect_includes(Path::new(path), &mut files, &mut Vec::new())?;
+    Ok(files)
+}
+
+fn collect_includes(path: &Path, files: &mut Vec<SourceFile>, visited: &mut Vec<PathBuf>) -> io::Result<()> {
+    if visited.iter().any(|visited| visited == path) {
+        return Ok(());
+    }
+    visited.push(path.to_path_buf());
+
+    let text = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for include in includes(&text) {
+        collect_includes(&directory.join(include.replace('\\', "/")), files, visited)?;
+    }
+
+    files.push(SourceFile {
+        path: path.to_path_buf(),
+        text: text,
+    });
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+pub struct Lexeme {
+    pub token: Token,
+    pub line: usize,
+}
+
+// rough HLSL tokens for the declaration scanners, preprocessor lines and string literals are dropped
+pub fn tokenize(source: &str) -> Vec<Lexeme> {
+
+    let mut lexemes = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line += 1;
+                line_start = true;
+                continue;
+            },
+            _ if c.is_whitespace() => continue,
+            '#' if line_start => {
+                // up to the end of the line, following backslash continuations
+                let mut previous = c;
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' && previous != '\\' {
+                        break;
+                    }
+                    if next == '\n' {
+                        line += 1;
+                    }
+                    if !next.is_whitespace() {
+                        previous = next;
+                    }
+                    chars.next();
+                }
+            },
+            '"' => {
+                while let Some(next) = chars.next() {
+                    match next {
+                        '\\' => { chars.next(); },
+                        '"' | '\n' => break,
+                        _ => {},
+                    }
+                }
+            },
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    ident.push(next);
+                    chars.next();
+                }
+                lexemes.push(Lexeme { token: Token::Ident(ident), line: line });
+            },
+            _ if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '.') {
+                        break;
+                    }
+                    number.push(next);
+                    chars.next();
+                }
+                lexemes.push(Lexeme { token: Token::Number(number), line: line });
+            },
+            _ => lexemes.push(Lexeme { token: Token::Punct(c), line: line }),
+        }
+
+        line_start = false;
+    }
+
+    lexemes
+}
+
+pub fn is_punct(lexeme: Option<&Lexeme>, c: char) -> bool {
+    matches!(lexeme, Some(Lexeme { token: Token::Punct(p), .. }) if *p == c)
+}
+
+pub fn ident(lexeme: Option<&Lexeme>) -> Option<&str> {
+    match lexeme {
+        Some(Lexeme { token: Token::Ident(name), .. }) => Some(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_are_blanked_in_place() {
+        let source = "float a; // b\n/* c\n d */ float e; \"// f\"";
+        let stripped = strip_comments(source);
+
+        assert_eq!(stripped.len(), source.len());
+        assert_eq!(stripped.lines().count(), 3);
+        assert!(!stripped.contains('b') && !stripped.contains('c') && !stripped.contains('d'));
+        assert!(stripped.contains("float e;") && stripped.contains("\"// f\""));
+
+        assert_eq!(includes("#include \"A.hlsli\"\n  # include <B.hlsli>\n// #include \"C.hlsli\""), vec!["A.hlsli", "B.hlsli"]);
+        assert_eq!(Stage::from_profile("ps_5_1"), Some(Stage::Pixel));
+    }
+
+    #[test]
+    fn includes_come_first() {
+        let files = read_with_includes("shaders/VertexShader.hlsl").unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert!(files[0].path.ends_with("ShaderHeader.hlsli"));
+        assert!(files[1].path.ends_with("VertexShader.hlsl"));
+    }
+}
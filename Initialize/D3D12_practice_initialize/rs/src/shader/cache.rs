@@ -0,0 +1,465 @@
+// compiled shaders on disk, keyed by a hash of everything that affects the bytecode:
+// the preprocessed source, entry point, target profile, defines and compiler flags
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::time::SystemTime;
+
+use super::ShaderSource;
+use super::compiler::ShaderCompiler;
+use super::preprocessor::{ Preprocessed, Preprocessor, PreprocessError };
+
+const MAGIC: &[u8; 4] = b"RSSC";
+// bump when the entry layout or the key changes, older entries are then evicted as stale
+pub const CACHE_VERSION: u32 = 1;
+// magic, version, key, bytecode length, bytecode checksum
+const HEADER_SIZE: usize = 4 + 4 + 8 + 8 + 8;
+const EXTENSION: &str = "cso";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// 64 bit FNV-1a, unlike DefaultHasher it is the same on every run and toolchain
+pub fn fnv1a(bytes: &[u8], hash: u64) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderKey(pub u64);
+
+impl ShaderKey {
+    // `compiler` is ShaderCompiler::identity
+    pub fn new(source: &str, entry_point: &str, target: &str, defines: &[(String, String)], flags: u32, compiler: &str) -> ShaderKey {
+        // every part is length prefixed so "ab" + "c" and "a" + "bc" differ
+        let part = |hash: u64, bytes: &[u8]| fnv1a(bytes, fnv1a(&(bytes.len() as u64).to_le_bytes(), hash));
+
+        let mut hash = part(FNV_OFFSET_BASIS, &CACHE_VERSION.to_le_bytes());
+        hash = part(hash, source.as_bytes());
+        hash = part(hash, entry_point.as_bytes());
+        hash = part(hash, target.as_bytes());
+
+        for (name, value) in defines.iter() {
+            hash = part(hash, name.as_bytes());
+            hash = part(hash, value.as_bytes());
+        }
+
+        hash = part(hash, &flags.to_le_bytes());
+
+        ShaderKey(part(hash, compiler.as_bytes()))
+    }
+
+    // the key of `source` compiled from its preprocessed text, `defines` are the ones it was preprocessed with
+    pub fn of_source(preprocessed: &Preprocessed, defines: &[(String, String)], source: &ShaderSource, compiler: &dyn ShaderCompiler) -> ShaderKey {
+        ShaderKey::new(&preprocessed.source, &source.entry_point, &source.target, defines, source.flags, &compiler.identity())
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}.{}", self, EXTENSION)
+    }
+}
+
+impl fmt::Display for ShaderKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // corrupt or stale entries that were removed, and entries trimmed for space
+    pub evicted: u64,
+    pub written: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses, {} evicted, {} written", self.hits, self.misses, self.evicted, self.written)
+    }
+}
+
+fn encode(key: ShaderKey, bytecode: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + bytecode.len());
+
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+    data.extend_from_slice(&key.0.to_le_bytes());
+    data.extend_from_slice(&(bytecode.len() as u64).to_le_bytes());
+    data.extend_from_slice(&fnv1a(bytecode, FNV_OFFSET_BASIS).to_le_bytes());
+    data.extend_from_slice(bytecode);
+
+    data
+}
+
+// the bytecode of an entry, None when it is corrupt or written by another cache version
+fn decode(key: ShaderKey, data: &[u8]) -> Option<&[u8]> {
+    if data.len() < HEADER_SIZE || &data[0..4] != MAGIC {
+        return None;
+    }
+
+    let u64_at = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    };
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[4..8]);
+
+    let bytecode = &data[HEADER_SIZE..];
+    let valid = u32::from_le_bytes(version) == CACHE_VERSION
+        && u64_at(8) == key.0
+        && u64_at(16) == bytecode.len() as u64
+        && u64_at(24) == fnv1a(bytecode, FNV_OFFSET_BASIS);
+
+    match valid {
+        true => Some(bytecode),
+        false => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Preprocess(PreprocessError),
+    // the compiler's messages
+    Compile(String),
+    // the shader compiled but its cache entry couldn't be written
+    Cache(io::Error),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Preprocess(error) => write!(f, "{}", error),
+            CompileError::Compile(messages) => write!(f, "{}", messages),
+            CompileError::Cache(error) => write!(f, "can't write the shader cache: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ShaderCache {
+    directory: PathBuf,
+    stats: CacheStats,
+}
+
+impl ShaderCache {
+    pub fn new(directory: &str) -> io::Result<ShaderCache> {
+        fs::create_dir_all(directory)?;
+
+        Ok(ShaderCache {
+            directory: PathBuf::from(directory),
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn path(&self, key: ShaderKey) -> PathBuf {
+        self.directory.join(key.file_name())
+    }
+
+    fn evict(&mut self, path: &Path) {
+        if fs::remove_file(path).is_ok() {
+            self.stats.evicted += 1;
+        }
+    }
+
+    pub fn get(&mut self, key: ShaderKey) -> Option<Vec<u8>> {
+        let path = self.path(key);
+
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => {
+                self.stats.misses += 1;
+                return None;
+            },
+        };
+
+        match decode(key, &data) {
+            Some(bytecode) => {
+                self.stats.hits += 1;
+
+                // the modification time orders entries for trim()
+                let _ = fs::File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now()));
+
+                Some(bytecode.to_vec())
+            },
+            None => {
+                self.evict(&path);
+                self.stats.misses += 1;
+                None
+            },
+        }
+    }
+
+    // written next to the entry first, so a crash never leaves a half written entry
+    pub fn put(&mut self, key: ShaderKey, bytecode: &[u8]) -> io::Result<()> {
+        let path = self.path(key);
+        let temporary = path.with_extension("tmp");
+
+        let result = fs::write(&temporary, encode(key, bytecode)).and_then(|_| fs::rename(&temporary, &path));
+
+        if let Err(error) = result {
+            let _ = fs::remove_file(&temporary);
+            return Err(error);
+        }
+
+        self.stats.written += 1;
+        Ok(())
+    }
+
+    pub fn get_or_compile<F>(&mut self, key: ShaderKey, compile: F) -> Result<Vec<u8>, CompileError>
+    where
+        F: FnOnce() -> Result<Vec<u8>, CompileError>,
+    {
+        if let Some(bytecode) = self.get(key) {
+            return Ok(bytecode);
+        }
+
+        let bytecode = compile()?;
+        self.put(key, &bytecode).map_err(CompileError::Cache)?;
+
+        Ok(bytecode)
+    }
+
+    // preprocesses the file, then returns its cached bytecode or compiles and stores it
+    pub fn compile_file(&mut self, preprocessor: &Preprocessor, path: &str, entry_point: &str, target: &str, flags: u32, compiler: &dyn ShaderCompiler) -> Result<Vec<u8>, CompileError> {
+        self.compile_source(preprocessor, &ShaderSource::new(path, entry_point, target, flags), compiler)
+    }
+
+    // like compile_file, the source's defines are added to the preprocessor's
+    pub fn compile_source(&mut self, preprocessor: &Preprocessor, source: &ShaderSource, compiler: &dyn ShaderCompiler) -> Result<Vec<u8>, CompileError> {
+        let preprocessor = preprocessor.with_defines(&source.defines);
+        let preprocessed = preprocessor.preprocess_file(&source.path).map_err(CompileError::Preprocess)?;
+
+        self.compile_preprocessed(&preprocessed, preprocessor.defines(), source, compiler)
+    }
+
+    // like compile_file for `source` already preprocessed with `defines`
+    pub fn compile_preprocessed(&mut self, preprocessed: &Preprocessed, defines: &[(String, String)], source: &ShaderSource, compiler: &dyn ShaderCompiler) -> Result<Vec<u8>, CompileError> {
+        let key = ShaderKey::of_source(preprocessed, defines, source, compiler);
+
+        self.get_or_compile(key, || {
+            compiler.compile(&preprocessed.source, &source.path, &source.entry_point, &source.target, source.flags).map_err(CompileError::Compile)
+        })
+    }
+
+    // cache entries with their metadata, leftovers of interrupted writes are removed
+    fn entries(&mut self) -> io::Result<Vec<(PathBuf, fs::Metadata)>> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some(EXTENSION) => {
+                    let metadata = fs::metadata(&path)?;
+                    entries.push((path, metadata));
+                },
+                Some("tmp") => self.evict(&path),
+                _ => {},
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // checks every entry and evicts corrupt or stale ones, returns how many were evicted
+    pub fn verify(&mut self) -> io::Result<usize> {
+        let evicted = self.stats.evicted;
+
+        for (path, _) in self.entries()? {
+            let key = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| u64::from_str_radix(stem, 16).ok());
+
+            let valid = match key {
+                Some(key) => fs::read(&path).map(|data| decode(ShaderKey(key), &data).is_some()).unwrap_or(false),
+                None => false,
+            };
+
+            if !valid {
+                self.evict(&path);
+            }
+        }
+
+        Ok((self.stats.evicted - evicted) as usize)
+    }
+
+    // evicts the least recently used entries until the cache takes at most `max_bytes`
+    pub fn trim(&mut self, max_bytes: u64) -> io::Result<usize> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|(_, metadata)| metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+
+        let mut size: u64 = entries.iter().map(|(_, metadata)| metadata.len()).sum();
+        let mut evicted = 0;
+
+        for (path, metadata) in entries {
+            if size <= max_bytes {
+                break;
+            }
+
+            self.evict(&path);
+            size -= metadata.len();
+            evicted += 1;
+        }
+
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::compiler::FakeCompiler;
+    use std::time::Duration;
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rs_shader_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn keys_cover_every_input() {
+        assert_eq!(fnv1a(b"", FNV_OFFSET_BASIS), FNV_OFFSET_BASIS);
+        assert_eq!(fnv1a(b"a", FNV_OFFSET_BASIS), 0xaf63_dc4c_8601_ec8c);
+
+        let defines = vec![("USE_TEXTURE".to_string(), "1".to_string())];
+        let key = ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_0", &defines, 1, "fxc");
+
+        assert_eq!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_0", &defines, 1, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 1; }", "main", "ps_5_0", &defines, 1, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "BasicPS", "ps_5_0", &defines, 1, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_1", &defines, 1, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_0", &[], 1, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_0", &defines, 0, "fxc"));
+        assert_ne!(key, ShaderKey::new("float4 main() : SV_TARGET { return 0; }", "main", "ps_5_0", &defines, 1, "dxc"));
+        assert_ne!(ShaderKey::new("ab", "c", "", &[], 0, "fxc"), ShaderKey::new("a", "bc", "", &[], 0, "fxc"));
+    }
+
+    #[test]
+    fn bad_entries_are_evicted() {
+        let directory = temporary_directory("evict");
+        let mut cache = ShaderCache::new(&directory.to_string_lossy()).unwrap();
+        let key = ShaderKey(0x1234);
+
+        assert_eq!(cache.get(key), None);
+        cache.put(key, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(cache.get(key), Some(vec![1, 2, 3, 4]));
+
+        // a flipped bit fails the checksum
+        let path = directory.join(key.file_name());
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, &data).unwrap();
+
+        assert_eq!(cache.get(key), None);
+        assert!(!path.exists());
+
+        // entries from another cache version and stray files are stale
+        cache.put(key, &[5]).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        data[4] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        fs::write(directory.join("not_a_key.cso"), b"junk").unwrap();
+        fs::write(directory.join("0000000000000001.tmp"), b"half").unwrap();
+        cache.put(ShaderKey(7), &[6]).unwrap();
+
+        assert_eq!(cache.verify().unwrap(), 3);
+        assert_eq!(cache.get(ShaderKey(7)), Some(vec![6]));
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 2, evicted: 4, written: 3 });
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_are_reported_and_cleaned_up() {
+        let directory = temporary_directory("write");
+        let mut cache = ShaderCache::new(&directory.to_string_lossy()).unwrap();
+        let key = ShaderKey(0x42);
+
+        // a directory in the way of the entry fails the rename
+        fs::create_dir(directory.join(key.file_name())).unwrap();
+
+        assert!(cache.put(key, &[1]).is_err());
+        assert!(!directory.join(key.file_name()).with_extension("tmp").exists());
+
+        match cache.get_or_compile(key, || Ok(vec![1])) {
+            Err(CompileError::Cache(_)) => {},
+            result => panic!("expected a cache error, got {:?}", result),
+        }
+        assert_eq!(cache.stats().written, 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn trim_evicts_least_recently_used() {
+        let directory = temporary_directory("trim");
+        let mut cache = ShaderCache::new(&directory.to_string_lossy()).unwrap();
+
+        let entry_size = (HEADER_SIZE + 100) as u64;
+        let now = SystemTime::now();
+
+        for index in 0..4u64 {
+            cache.put(ShaderKey(index), &[0; 100]).unwrap();
+
+            let file = fs::File::options().write(true).open(directory.join(ShaderKey(index).file_name())).unwrap();
+            file.set_modified(now - Duration::from_secs(100 - index)).unwrap();
+        }
+
+        // reading the oldest entry makes it the most recently used
+        cache.get(ShaderKey(0)).unwrap();
+
+        assert_eq!(cache.trim(2 * entry_size).unwrap(), 2);
+        assert!(cache.get(ShaderKey(0)).is_some());
+        assert!(cache.get(ShaderKey(1)).is_none());
+        assert!(cache.get(ShaderKey(2)).is_none());
+        assert!(cache.get(ShaderKey(3)).is_some());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn only_changed_shaders_are_recompiled() {
+        let directory = temporary_directory("compile");
+        fs::create_dir_all(&directory).unwrap();
+
+        let header = directory.join("Header.hlsli");
+        let shader = directory.join("Shader.hlsl");
+        fs::write(&header, "#define COLOR 1\n").unwrap();
+        fs::write(&shader, "#include \"Header.hlsli\"\nfloat4 main() : SV_TARGET { return COLOR; }\n").unwrap();
+
+        let mut cache = ShaderCache::new(&directory.join("cache").to_string_lossy()).unwrap();
+        let shader_path = shader.to_string_lossy().into_owned();
+        let compiler = FakeCompiler::new().failing_on("error");
+
+        let compile = |cache: &mut ShaderCache, preprocessor: &Preprocessor| {
+            cache.compile_file(preprocessor, &shader_path, "main", "ps_5_0", 0, &compiler)
+        };
+
+        let first = compile(&mut cache, &Preprocessor::new()).unwrap();
+        let second = compile(&mut cache, &Preprocessor::new()).unwrap();
+        assert_eq!(first, second);
+
+        // an include changed
+        fs::write(&header, "#define COLOR 0.5\n").unwrap();
+        compile(&mut cache, &Preprocessor::new()).unwrap();
+        compile(&mut cache, &Preprocessor::new().define("EXTRA", "1")).unwrap();
+
+        fs::write(&header, "#define COLOR error\n").unwrap();
+        let failed = compile(&mut cache, &Preprocessor::new());
+        assert!(failed.unwrap_err().to_string().contains("error X3000"));
+
+        let compiled = compiler.compiled();
+        assert_eq!(compiled.len(), 4);
+        assert!(compiled[2].contains("return 0.5;"));
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 4, evicted: 0, written: 3 });
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
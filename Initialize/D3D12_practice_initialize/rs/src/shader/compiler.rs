@@ -0,0 +1,352 @@
+// shader compilers: FXC through d3dcompiler_47 for shader model 5.x, DXC as a separate process for DXIL
+use winapi::um::d3dcompiler;
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use super::Stage;
+use crate::lib;
+
+pub trait ShaderCompiler: Send + Sync {
+    // what the bytecode depends on besides the compile inputs, part of the shader cache key
+    fn identity(&self) -> String;
+
+    // `source` is preprocessed text, `source_name` is used in messages.
+    // `flags` are D3DCOMPILE_* flags. returns the bytecode, or the compiler's messages
+    fn compile(&self, source: &str, source_name: &str, entry_point: &str, target: &str, flags: u32) -> Result<Vec<u8>, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderModel {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ShaderModel {
+    pub fn new(major: u32, minor: u32) -> ShaderModel {
+        ShaderModel { major: major, minor: minor }
+    }
+
+    // the model of a target such as "ps_6_6"
+    pub fn of_target(target: &str) -> Option<ShaderModel> {
+        let mut parts = target.split('_').skip(1);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+
+        match parts.next() {
+            None => Some(ShaderModel::new(major, minor)),
+            Some(_) => None,
+        }
+    }
+
+    // 6.0 and later compile to DXIL, which only DXC produces
+    pub fn is_dxil(self) -> bool {
+        self.major >= 6
+    }
+}
+
+impl fmt::Display for ShaderModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileError {
+    // hull and domain shaders came with 5.0
+    StageNotInModel { stage: Stage, model: ShaderModel },
+    UnknownModel(ShaderModel),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::StageNotInModel { stage, model } => write!(f, "shader model {} has no {} stage", model, stage),
+            ProfileError::UnknownModel(model) => write!(f, "unknown shader model {}", model),
+        }
+    }
+}
+
+// the target string for a stage and model, vs_5_0 or ps_6_6
+pub fn profile(stage: Stage, model: ShaderModel) -> Result<String, ProfileError> {
+    let known = match model.major {
+        4 => model.minor <= 1,
+        5 => model.minor <= 1,
+        6 => model.minor <= 8,
+        _ => false,
+    };
+    if !known {
+        return Err(ProfileError::UnknownModel(model));
+    }
+
+    if model.major < 5 && (stage == Stage::Hull || stage == Stage::Domain) {
+        return Err(ProfileError::StageNotInModel { stage: stage, model: model });
+    }
+
+    Ok(format!("{}_{}_{}", stage.profile_prefix(), model.major, model.minor))
+}
+
+const FXC_DLL: &str = "d3dcompiler_47.dll";
+
+// the DLL is the same for the whole process, it's looked up once
+static FXC_VERSION: OnceLock<String> = OnceLock::new();
+
+// the legacy compiler, D3DCompile from d3dcompiler_47
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fxc;
+
+impl ShaderCompiler for Fxc {
+    // windows updates replace the DLL, its file version tells the builds apart
+    fn identity(&self) -> String {
+        let version = FXC_VERSION.get_or_init(|| match lib::get_file_version(FXC_DLL) {
+            Some(version) => version.iter().map(|part| part.to_string()).collect::<Vec<_>>().join("."),
+            None => "unknown".to_string(),
+        });
+
+        format!("fxc {}", version)
+    }
+
+    fn compile(&self, source: &str, source_name: &str, entry_point: &str, target: &str, flags: u32) -> Result<Vec<u8>, String> {
+        if let Some(model) = ShaderModel::of_target(target).filter(|model| model.is_dxil()) {
+            return Err(format!("{}: fxc can't compile shader model {} ({}), use dxc", source_name, model, target));
+        }
+
+        lib::compile_shader(source, source_name, entry_point, target, flags)
+    }
+}
+
+// names of the temporary files handed to dxc, unique between threads
+static DXC_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// dxc.exe from the DirectX Shader Compiler, run once per shader
+#[derive(Debug, Clone)]
+pub struct Dxc {
+    executable: PathBuf,
+    arguments: Vec<String>,
+    // `dxc --version`, run the first time the identity is needed
+    version: OnceLock<String>,
+}
+
+impl Default for Dxc {
+    fn default() -> Dxc {
+        Dxc {
+            executable: PathBuf::from("dxc"),
+            arguments: Vec::new(),
+            version: OnceLock::new(),
+        }
+    }
+}
+
+impl Dxc {
+    // dxc found through PATH
+    pub fn new() -> Dxc {
+        Dxc::default()
+    }
+
+    pub fn executable(mut self, path: &str) -> Self {
+        self.executable = PathBuf::from(path);
+        self.version = OnceLock::new();
+        self
+    }
+
+    // e.g. "dxcompiler.dll: 1.7 - 1.7.2308.7 (69e54e290); dxil.dll: 1.7(101.7.2308.12)",
+    // "unknown" if the executable can't be run
+    pub fn version(&self) -> &str {
+        self.version.get_or_init(|| {
+            match Command::new(&self.executable).arg("--version").output() {
+                Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+                _ => "unknown".to_string(),
+            }
+        })
+    }
+
+    // passed after the arguments mapped from the flags, e.g. "-HV" "2021" or "-enable-16bit-types"
+    pub fn argument(mut self, argument: &str) -> Self {
+        self.arguments.push(argument.to_string());
+        self
+    }
+
+    // dxc arguments equivalent to D3DCOMPILE_* flags, flags without an equivalent are dropped
+    pub fn flag_arguments(flags: u32) -> Vec<&'static str> {
+        let mut arguments = Vec::new();
+
+        if flags & d3dcompiler::D3DCOMPILE_DEBUG != 0 {
+            arguments.extend_from_slice(&["-Zi", "-Qembed_debug"]);
+        }
+
+        let optimization = flags & d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL2;
+        if flags & d3dcompiler::D3DCOMPILE_SKIP_OPTIMIZATION != 0 {
+            arguments.push("-Od");
+        } else if optimization == d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL0 {
+            arguments.push("-O0");
+        } else if optimization == d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL2 {
+            arguments.push("-O2");
+        } else if optimization == d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL3 {
+            arguments.push("-O3");
+        }
+
+        let mapped = [
+            (d3dcompiler::D3DCOMPILE_SKIP_VALIDATION, "-Vd"),
+            (d3dcompiler::D3DCOMPILE_PACK_MATRIX_ROW_MAJOR, "-Zpr"),
+            (d3dcompiler::D3DCOMPILE_PACK_MATRIX_COLUMN_MAJOR, "-Zpc"),
+            (d3dcompiler::D3DCOMPILE_AVOID_FLOW_CONTROL, "-Gfa"),
+            (d3dcompiler::D3DCOMPILE_PREFER_FLOW_CONTROL, "-Gfp"),
+            (d3dcompiler::D3DCOMPILE_ENABLE_STRICTNESS, "-Ges"),
+            (d3dcompiler::D3DCOMPILE_ENABLE_BACKWARDS_COMPATIBILITY, "-Gec"),
+            (d3dcompiler::D3DCOMPILE_IEEE_STRICTNESS, "-Gis"),
+            (d3dcompiler::D3DCOMPILE_WARNINGS_ARE_ERRORS, "-WX"),
+            (d3dcompiler::D3DCOMPILE_RESOURCES_MAY_ALIAS, "-res_may_alias"),
+            (d3dcompiler::D3DCOMPILE_ALL_RESOURCES_BOUND, "-all_resources_bound"),
+        ];
+        arguments.extend(mapped.iter().filter(|(flag, _)| flags & flag != 0).map(|&(_, argument)| argument));
+
+        arguments
+    }
+
+    // the full command line after the executable
+    pub fn command_arguments(&self, input: &str, output: &str, entry_point: &str, target: &str, flags: u32) -> Vec<String> {
+        let mut arguments: Vec<String> = vec!["-nologo".to_string(), "-T".to_string(), target.to_string(), "-E".to_string(), entry_point.to_string(), "-Fo".to_string(), output.to_string()];
+
+        arguments.extend(Dxc::flag_arguments(flags).iter().map(|argument| argument.to_string()));
+        arguments.extend(self.arguments.iter().cloned());
+        arguments.push(input.to_string());
+
+        arguments
+    }
+}
+
+impl ShaderCompiler for Dxc {
+    fn identity(&self) -> String {
+        format!("dxc {} ({}) {}", self.executable.display(), self.version(), self.arguments.join(" "))
+    }
+
+    fn compile(&self, source: &str, source_name: &str, entry_point: &str, target: &str, flags: u32) -> Result<Vec<u8>, String> {
+        let stem = format!("rs_dxc_{}_{}", std::process::id(), DXC_FILE_COUNTER.fetch_add(1, Ordering::SeqCst));
+        let input = std::env::temp_dir().join(format!("{}.hlsl", stem));
+        let output = std::env::temp_dir().join(format!("{}.dxil", stem));
+
+        // messages point at the original files through the #line directives of the preprocessed source
+        fs::write(&input, source).map_err(|error| format!("{}: can't write {}: {}", source_name, input.display(), error))?;
+
+        let arguments = self.command_arguments(&input.to_string_lossy(), &output.to_string_lossy(), entry_point, target, flags);
+        let result = Command::new(&self.executable).args(&arguments).output();
+
+        let _ = fs::remove_file(&input);
+
+        let result = match result {
+            Ok(result) => result,
+            Err(error) => return Err(format!("{}: can't run {}: {}", source_name, self.executable.display(), error)),
+        };
+
+        let bytecode = match result.status.success() {
+            true => fs::read(&output).map_err(|error| format!("{}: dxc wrote no output: {}", source_name, error)),
+            false => {
+                let messages = String::from_utf8_lossy(&result.stderr).trim().to_string();
+                match messages.is_empty() {
+                    true => Err(format!("{}: dxc failed with {}", source_name, result.status)),
+                    false => Err(messages),
+                }
+            },
+        };
+
+        let _ = fs::remove_file(&output);
+        bytecode
+    }
+}
+
+// for tests: the bytecode is the target and the source text,
+// and sources containing the failure marker don't compile
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FakeCompiler {
+    fail_on: Option<String>,
+    compiled: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl FakeCompiler {
+    pub fn new() -> FakeCompiler {
+        FakeCompiler::default()
+    }
+
+    pub fn failing_on(mut self, marker: &str) -> Self {
+        self.fail_on = Some(marker.to_string());
+        self
+    }
+
+    // the sources compiled so far, failed ones included
+    pub fn compiled(&self) -> Vec<String> {
+        self.compiled.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl ShaderCompiler for FakeCompiler {
+    fn identity(&self) -> String {
+        "fake".to_string()
+    }
+
+    fn compile(&self, source: &str, source_name: &str, _entry_point: &str, target: &str, _flags: u32) -> Result<Vec<u8>, String> {
+        self.compiled.lock().unwrap().push(source.to_string());
+
+        match &self.fail_on {
+            Some(marker) if source.contains(marker.as_str()) => Err(format!("{}: error X3000: `{}`", source_name, marker)),
+            _ => Ok(format!("{}\n{}", target, source).into_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_map_stage_and_model() {
+        assert_eq!(profile(Stage::Vertex, ShaderModel::new(5, 0)), Ok("vs_5_0".to_string()));
+        assert_eq!(profile(Stage::Pixel, ShaderModel::new(6, 6)), Ok("ps_6_6".to_string()));
+        assert_eq!(profile(Stage::Hull, ShaderModel::new(4, 1)), Err(ProfileError::StageNotInModel { stage: Stage::Hull, model: ShaderModel::new(4, 1) }));
+        assert_eq!(profile(Stage::Compute, ShaderModel::new(7, 0)), Err(ProfileError::UnknownModel(ShaderModel::new(7, 0))));
+
+        assert_eq!(ShaderModel::of_target("cs_6_2"), Some(ShaderModel::new(6, 2)));
+        assert_eq!(ShaderModel::of_target("ps_5_0_level_9_3"), None);
+        assert!(ShaderModel::new(6, 0).is_dxil() && !ShaderModel::new(5, 1).is_dxil());
+    }
+
+    #[test]
+    fn dxc_arguments_follow_the_flags() {
+        let dxc = Dxc::new().argument("-HV").argument("2021");
+        let flags = d3dcompiler::D3DCOMPILE_DEBUG | d3dcompiler::D3DCOMPILE_SKIP_OPTIMIZATION | d3dcompiler::D3DCOMPILE_PACK_MATRIX_ROW_MAJOR;
+
+        assert_eq!(
+            dxc.command_arguments("in.hlsl", "out.dxil", "BasicPS", "ps_6_0", flags),
+            vec!["-nologo", "-T", "ps_6_0", "-E", "BasicPS", "-Fo", "out.dxil", "-Zi", "-Qembed_debug", "-Od", "-Zpr", "-HV", "2021", "in.hlsl"]
+        );
+        assert_eq!(Dxc::flag_arguments(d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL3 | d3dcompiler::D3DCOMPILE_WARNINGS_ARE_ERRORS), vec!["-O3", "-WX"]);
+        assert!(Dxc::flag_arguments(d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL1).is_empty());
+
+        // the arguments and the compiler version change the bytecode, so they are part of the cache key
+        let missing = Dxc::new().executable("rs-missing-dxc");
+        assert_eq!(missing.version(), "unknown");
+        assert_eq!(missing.identity(), "dxc rs-missing-dxc (unknown) ");
+        assert_ne!(missing.clone().argument("-HV").identity(), missing.identity());
+        assert!(Fxc.identity().starts_with("fxc "));
+    }
+
+    #[test]
+    fn compilers_report_what_they_cannot_do() {
+        let missing = Dxc::new().executable("rs-missing-dxc").compile("float4 main() : SV_TARGET { return 0; }", "Pixel.hlsl", "main", "ps_6_0", 0);
+        assert!(missing.unwrap_err().contains("rs-missing-dxc"));
+
+        let dxil = Fxc.compile("", "Pixel.hlsl", "main", "ps_6_0", 0);
+        assert_eq!(dxil, Err("Pixel.hlsl: fxc can't compile shader model 6.0 (ps_6_0), use dxc".to_string()));
+
+        let fake = FakeCompiler::new().failing_on("broken");
+        assert_eq!(fake.compile("float4 a;", "A.hlsl", "main", "ps_5_0", 0), Ok(b"ps_5_0\nfloat4 a;".to_vec()));
+        assert!(fake.compile("broken", "B.hlsl", "main", "ps_5_0", 0).unwrap_err().starts_with("B.hlsl: error X3000"));
+        assert_eq!(fake.compiled(), vec!["float4 a;", "broken"]);
+    }
+}
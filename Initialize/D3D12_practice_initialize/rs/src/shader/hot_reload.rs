@@ -0,0 +1,512 @@
+// shader hot reload: a thread watches the shader files and their includes, recompiles what changed
+// and hands the bytecode to the render loop, which rebuilds the pipelines using it between frames
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc;
+use std::thread;
+use std::time::{ Duration, SystemTime };
+
+use super::ShaderSource;
+use super::cache::{ CompileError, ShaderCache, CacheStats };
+use super::compiler::ShaderCompiler;
+use super::preprocessor::{ canonical, Preprocessor };
+
+// modification times of the files in watched directories and of single watched files
+#[derive(Debug, Default)]
+pub struct FileWatcher {
+    directories: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    times: HashMap<PathBuf, SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher::default()
+    }
+
+    // the files directly in `path`, including ones created later
+    pub fn watch_directory(&mut self, path: &Path) {
+        let path = canonical(path);
+        if !self.directories.contains(&path) {
+            self.directories.push(path);
+            self.record_new();
+        }
+    }
+
+    // e.g. an include outside the watched directories
+    pub fn watch_file(&mut self, path: &Path) {
+        let path = canonical(path);
+        if !self.files.contains(&path) {
+            self.files.push(path);
+            self.record_new();
+        }
+    }
+
+    // newly watched files don't count as changed
+    fn record_new(&mut self) {
+        for (path, time) in self.scan() {
+            self.times.entry(path).or_insert(time);
+        }
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut times = HashMap::new();
+
+        let directory_files = self.directories.iter()
+            .filter_map(|directory| fs::read_dir(directory).ok())
+            .flat_map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()));
+
+        for path in directory_files.chain(self.files.iter().cloned()) {
+            let time = fs::metadata(&path).ok()
+                .filter(|metadata| metadata.is_file())
+                .and_then(|metadata| metadata.modified().ok());
+
+            if let Some(time) = time {
+                times.insert(canonical(&path), time);
+            }
+        }
+
+        times
+    }
+
+    // files created, modified or removed since the last poll
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let times = self.scan();
+
+        let mut changed: Vec<PathBuf> = times.iter()
+            .filter(|(path, time)| self.times.get(*path) != Some(*time))
+            .map(|(path, _)| path.clone())
+            .chain(self.times.keys().filter(|path| !times.contains_key(*path)).cloned())
+            .collect();
+        changed.sort();
+
+        self.times = times;
+        changed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderId(usize);
+
+// a recompiled shader, or why it failed to compile
+#[derive(Debug)]
+pub struct ShaderUpdate {
+    pub id: ShaderId,
+    pub source: ShaderSource,
+    pub result: Result<Vec<u8>, CompileError>,
+}
+
+impl fmt::Display for ShaderUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(_) => write!(f, "{} ({}) recompiled", self.source.path, self.source.entry_point),
+            Err(error) => write!(f, "{} ({}) failed to compile:\n{}", self.source.path, self.source.entry_point, error),
+        }
+    }
+}
+
+// current bytecode of the shaders by id
+pub trait BytecodeSource {
+    fn bytecode(&self, id: ShaderId) -> &[u8];
+}
+
+#[derive(Debug)]
+struct WatchedShader {
+    source: ShaderSource,
+    // canonical paths of the file and its includes
+    dependencies: Vec<PathBuf>,
+    bytecode: Vec<u8>,
+    // the last compile failed, so the next success is reported even if the bytecode is the same
+    failed: bool,
+}
+
+// shaders are added and compiled up front, then `start` moves them to the watching thread
+pub struct ShaderWatch {
+    cache: ShaderCache,
+    preprocessor: Preprocessor,
+    compiler: Box<dyn ShaderCompiler>,
+    watcher: FileWatcher,
+    shaders: Vec<WatchedShader>,
+}
+
+impl ShaderWatch {
+    pub fn new(cache: ShaderCache, preprocessor: Preprocessor, compiler: Box<dyn ShaderCompiler>) -> ShaderWatch {
+        ShaderWatch {
+            cache: cache,
+            preprocessor: preprocessor,
+            compiler: compiler,
+            watcher: FileWatcher::new(),
+            shaders: Vec::new(),
+        }
+    }
+
+    // the includes of added shaders are watched anyway, this also picks up files they start including
+    pub fn watch_directory(&mut self, path: &str) {
+        self.watcher.watch_directory(Path::new(path));
+    }
+
+    pub fn add(&mut self, source: ShaderSource) -> Result<ShaderId, CompileError> {
+        let (bytecode, dependencies) = self.compile_shader(&source)?;
+
+        for dependency in dependencies.iter() {
+            self.watcher.watch_file(dependency);
+        }
+
+        self.shaders.push(WatchedShader {
+            source: source,
+            dependencies: dependencies,
+            bytecode: bytecode,
+            failed: false,
+        });
+
+        Ok(ShaderId(self.shaders.len() - 1))
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn compile_shader(&mut self, source: &ShaderSource) -> Result<(Vec<u8>, Vec<PathBuf>), CompileError> {
+        let preprocessor = self.preprocessor.with_defines(&source.defines);
+        let preprocessed = preprocessor.preprocess_file(&source.path).map_err(CompileError::Preprocess)?;
+        let bytecode = self.cache.compile_preprocessed(&preprocessed, preprocessor.defines(), source, &*self.compiler)?;
+
+        Ok((bytecode, preprocessed.dependencies.iter().map(|dependency| canonical(dependency)).collect()))
+    }
+
+    // recompiles the shaders depending on files changed since the last poll.
+    // a failed shader keeps its previous bytecode, saving a file without changes reports nothing
+    pub fn poll(&mut self) -> Vec<ShaderUpdate> {
+        let changed = self.watcher.poll();
+        let mut updates = Vec::new();
+
+        for index in 0..self.shaders.len() {
+            if !self.shaders[index].dependencies.iter().any(|dependency| changed.contains(dependency)) {
+                continue;
+            }
+
+            let source = self.shaders[index].source.clone();
+
+            let result = match self.compile_shader(&source) {
+                Ok((bytecode, dependencies)) => {
+                    for dependency in dependencies.iter() {
+                        self.watcher.watch_file(dependency);
+                    }
+
+                    let shader = &mut self.shaders[index];
+                    shader.dependencies = dependencies;
+
+                    if bytecode == shader.bytecode && !shader.failed {
+                        continue;
+                    }
+
+                    shader.bytecode = bytecode.clone();
+                    shader.failed = false;
+                    Ok(bytecode)
+                },
+                Err(error) => {
+                    self.shaders[index].failed = true;
+                    Err(error)
+                },
+            };
+
+            updates.push(ShaderUpdate {
+                id: ShaderId(index),
+                source: source,
+                result: result,
+            });
+        }
+
+        updates
+    }
+
+    // polls on a thread every `interval`. `wake` is called after updates were queued,
+    // e.g. to post a message to a loop that blocks on GetMessageW
+    pub fn start<W>(mut self, interval: Duration, wake: W) -> HotReloader
+    where
+        W: Fn() + Send + 'static,
+    {
+        let bytecode = self.shaders.iter().map(|shader| shader.bytecode.clone()).collect();
+        let (update_sender, updates) = mpsc::channel();
+        let (stop, stop_receiver) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            // runs until the HotReloader drops its sender
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                let shader_updates = self.poll();
+                if shader_updates.is_empty() {
+                    continue;
+                }
+
+                for update in shader_updates {
+                    if update_sender.send(update).is_err() {
+                        return;
+                    }
+                }
+
+                wake();
+            }
+        });
+
+        HotReloader {
+            bytecode: bytecode,
+            updates: updates,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl BytecodeSource for ShaderWatch {
+    fn bytecode(&self, id: ShaderId) -> &[u8] {
+        &self.shaders[id.0].bytecode
+    }
+}
+
+// the render loop's side of the watching thread
+pub struct HotReloader {
+    bytecode: Vec<Vec<u8>>,
+    updates: mpsc::Receiver<ShaderUpdate>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl HotReloader {
+    // updates that arrived since the last call, called between frames.
+    // recompiled bytecode replaces what bytecode() returns
+    pub fn poll(&mut self) -> Vec<ShaderUpdate> {
+        let updates: Vec<ShaderUpdate> = self.updates.try_iter().collect();
+
+        for update in updates.iter() {
+            if let Ok(bytecode) = &update.result {
+                self.bytecode[update.id.0] = bytecode.clone();
+            }
+        }
+
+        updates
+    }
+}
+
+impl BytecodeSource for HotReloader {
+    fn bytecode(&self, id: ShaderId) -> &[u8] {
+        &self.bytecode[id.0]
+    }
+}
+
+impl Drop for HotReloader {
+    fn drop(&mut self) {
+        self.stop.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+type Build<T> = Box<dyn Fn(&[&[u8]]) -> Result<T, String>>;
+
+// a pipeline object rebuilt when one of its shaders is recompiled
+pub struct ReloadablePipeline<T> {
+    current: T,
+    shaders: Vec<ShaderId>,
+    build: Build<T>,
+}
+
+impl<T: Copy> ReloadablePipeline<T> {
+    // `build` gets the bytecode of `shaders` in the same order
+    pub fn new<S, F>(source: &S, shaders: &[ShaderId], build: F) -> Result<ReloadablePipeline<T>, String>
+    where
+        S: BytecodeSource,
+        F: Fn(&[&[u8]]) -> Result<T, String> + 'static,
+    {
+        let bytecode: Vec<&[u8]> = shaders.iter().map(|&id| source.bytecode(id)).collect();
+
+        Ok(ReloadablePipeline {
+            current: build(&bytecode)?,
+            shaders: shaders.to_vec(),
+            build: Box::new(build),
+        })
+    }
+
+    pub fn get(&self) -> T {
+        self.current
+    }
+
+    // rebuilds if a shader it uses was recompiled and returns the replaced object, which the GPU may still use.
+    // when the rebuild fails the current object is kept
+    pub fn reload<S: BytecodeSource>(&mut self, source: &S, updates: &[ShaderUpdate]) -> Result<Option<T>, String> {
+        let recompiled = updates.iter().any(|update| update.result.is_ok() && self.shaders.contains(&update.id));
+        if !recompiled {
+            return Ok(None);
+        }
+
+        let bytecode: Vec<&[u8]> = self.shaders.iter().map(|&id| source.bytecode(id)).collect();
+        let pipeline = (self.build)(&bytecode)?;
+
+        Ok(Some(mem::replace(&mut self.current, pipeline)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::compiler::FakeCompiler;
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::time::Instant;
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rs_hot_reload_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // mtimes can be coarser than the time between two writes in a test
+    fn write_later(path: &Path, text: &str, seconds: u64) {
+        fs::write(path, text).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(seconds)).unwrap();
+    }
+
+    fn shader_watch(directory: &Path) -> (ShaderWatch, ShaderId, ShaderId) {
+        fs::write(directory.join("Common.hlsli"), "#define COLOR 1\n").unwrap();
+        fs::write(directory.join("Pixel.hlsl"), "#include \"Common.hlsli\"\nfloat4 main() : SV_TARGET { return COLOR; }\n").unwrap();
+        fs::write(directory.join("Vertex.hlsl"), "float4 main() : SV_POSITION { return 0; }\n").unwrap();
+
+        let cache = ShaderCache::new(&directory.join("cache").to_string_lossy()).unwrap();
+        let mut watch = ShaderWatch::new(cache, Preprocessor::new(), Box::new(FakeCompiler::new().failing_on("error")));
+        watch.watch_directory(&directory.to_string_lossy());
+
+        let pixel = watch.add(ShaderSource::new(&directory.join("Pixel.hlsl").to_string_lossy(), "main", "ps_5_0", 0)).unwrap();
+        let vertex = watch.add(ShaderSource::new(&directory.join("Vertex.hlsl").to_string_lossy(), "main", "vs_5_0", 0)).unwrap();
+
+        (watch, pixel, vertex)
+    }
+
+    #[test]
+    fn watcher_reports_changed_files() {
+        let directory = temporary_directory("watcher");
+        let outside = temporary_directory("watcher_outside");
+        fs::write(directory.join("A.hlsl"), "a").unwrap();
+        fs::write(directory.join("B.hlsl"), "b").unwrap();
+        fs::write(outside.join("C.hlsli"), "c").unwrap();
+
+        let mut watcher = FileWatcher::new();
+        watcher.watch_directory(&directory);
+        watcher.watch_file(&outside.join("C.hlsli"));
+        assert!(watcher.poll().is_empty());
+
+        write_later(&directory.join("A.hlsl"), "a2", 10);
+        write_later(&outside.join("C.hlsli"), "c2", 10);
+        fs::remove_file(directory.join("B.hlsl")).unwrap();
+        fs::write(directory.join("D.hlsl"), "d").unwrap();
+
+        let mut expected = vec![
+            canonical(&directory.join("A.hlsl")),
+            canonical(&directory).join("B.hlsl"),
+            canonical(&outside.join("C.hlsli")),
+            canonical(&directory.join("D.hlsl")),
+        ];
+        expected.sort();
+
+        assert_eq!(watcher.poll(), expected);
+        assert!(watcher.poll().is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn changed_includes_recompile_their_shaders() {
+        let directory = temporary_directory("recompile");
+        let (mut watch, pixel, vertex) = shader_watch(&directory);
+        let vertex_bytecode = watch.bytecode(vertex).to_vec();
+
+        // only the pixel shader includes the header
+        write_later(&directory.join("Common.hlsli"), "#define COLOR 0.5\n", 10);
+        let updates = watch.poll();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].id, pixel);
+        assert!(String::from_utf8_lossy(watch.bytecode(pixel)).contains("return 0.5;"));
+        assert_eq!(watch.bytecode(vertex), &vertex_bytecode[..]);
+
+        // a broken shader keeps its last bytecode
+        write_later(&directory.join("Vertex.hlsl"), "float4 main() : SV_POSITION { error }\n", 20);
+        let updates = watch.poll();
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].to_string().contains("error X3000"));
+        assert_eq!(watch.bytecode(vertex), &vertex_bytecode[..]);
+
+        // fixing it is reported even though the bytecode is the same as before
+        write_later(&directory.join("Vertex.hlsl"), "float4 main() : SV_POSITION { return 0; }\n", 30);
+        let updates = watch.poll();
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].result.is_ok());
+
+        // saving without changes
+        write_later(&directory.join("Vertex.hlsl"), "float4 main() : SV_POSITION { return 0; }\n", 40);
+        assert!(watch.poll().is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn pipelines_keep_the_last_good_build() {
+        let directory = temporary_directory("pipeline");
+        let (mut watch, pixel, vertex) = shader_watch(&directory);
+
+        // the fake pipeline object is the total bytecode length, a pixel shader without main fails to build
+        let mut pipeline = ReloadablePipeline::new(&watch, &[vertex, pixel], |bytecode: &[&[u8]]| {
+            match String::from_utf8_lossy(bytecode[1]).contains("main") {
+                true => Ok(bytecode.iter().map(|code| code.len()).sum::<usize>()),
+                false => Err("no entry point main".to_string()),
+            }
+        }).unwrap();
+        let first = pipeline.get();
+
+        assert_eq!(pipeline.reload(&watch, &[]), Ok(None));
+
+        write_later(&directory.join("Common.hlsli"), "#define COLOR 0.25\n", 10);
+        let updates = watch.poll();
+        assert_eq!(pipeline.reload(&watch, &updates), Ok(Some(first)));
+        assert_eq!(pipeline.get(), first + 3);
+
+        write_later(&directory.join("Pixel.hlsl"), "float4 other() : SV_TARGET { return 0; }\n", 20);
+        let updates = watch.poll();
+        assert_eq!(pipeline.reload(&watch, &updates), Err("no entry point main".to_string()));
+        assert_eq!(pipeline.get(), first + 3);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn updates_arrive_from_the_watching_thread() {
+        let directory = temporary_directory("thread");
+        let (watch, pixel, _) = shader_watch(&directory);
+
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let thread_wakes = wakes.clone();
+        let mut reloader = watch.start(Duration::from_millis(10), move || { thread_wakes.fetch_add(1, Ordering::SeqCst); });
+
+        write_later(&directory.join("Common.hlsli"), "#define COLOR 0.75\n", 10);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut updates = Vec::new();
+        while updates.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            updates = reloader.poll();
+        }
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].id, pixel);
+        assert!(String::from_utf8_lossy(reloader.bytecode(pixel)).contains("return 0.75;"));
+        assert!(wakes.load(Ordering::SeqCst) >= 1);
+
+        drop(reloader);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
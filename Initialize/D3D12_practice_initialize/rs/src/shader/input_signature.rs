@@ -0,0 +1,585 @@
+// vertex shader inputs read from the entry point, e.g.
+// Output BasicVS(float4 position : POSITION, float2 uv : TEXCOORD)
+use winapi::{
+    um::d3d12,
+    shared::{
+        dxgiformat::*,
+        ntdef::LPCSTR,
+    },
+};
+
+use std::collections::HashMap;
+use std::ffi::{ CStr, CString };
+use std::fmt;
+use std::io;
+
+use super::{ strip_comments, read_with_includes, tokenize, is_punct, ident, Token, Lexeme };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentType {
+    Float,
+    Int,
+    Uint,
+}
+
+impl fmt::Display for ComponentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComponentType::Float => write!(f, "float"),
+            ComponentType::Int => write!(f, "int"),
+            ComponentType::Uint => write!(f, "uint"),
+        }
+    }
+}
+
+// one input register, matrices and arrays take one per row or element
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexInput {
+    // parameter name, or the path to a struct member like input.uv
+    pub name: String,
+    // without the trailing index, as written
+    pub semantic: String,
+    pub semantic_index: u32,
+    pub component_type: ComponentType,
+    pub components: u32,
+}
+
+impl VertexInput {
+    fn label(&self) -> String {
+        format!("`{}` ({}{})", self.name, self.semantic, self.semantic_index)
+    }
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Io(io::Error),
+    MissingEntryPoint(String),
+    Syntax { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Io(error) => write!(f, "{}", error),
+            SignatureError::MissingEntryPoint(name) => write!(f, "entry point `{}` not found", name),
+            SignatureError::Syntax { file, line, message } => write!(f, "{}({}): {}", file, line, message),
+        }
+    }
+}
+
+// system generated values don't come from the input assembler
+const SYSTEM_VALUES: &[&str] = &["SV_VERTEXID", "SV_INSTANCEID", "SV_PRIMITIVEID", "SV_VIEWID"];
+
+const MODIFIERS: &[&str] = &[
+    "in", "const", "uniform", "precise", "row_major", "column_major",
+    "linear", "centroid", "nointerpolation", "noperspective", "sample",
+];
+
+// float3 -> (Float, 1, 3), float4x4 -> (Float, 4, 4)
+fn numeric_type(name: &str) -> Option<(ComponentType, u32, u32)> {
+    const SCALARS: &[(&str, ComponentType)] = &[
+        ("min16float", ComponentType::Float),
+        ("min10float", ComponentType::Float),
+        ("float", ComponentType::Float),
+        ("half", ComponentType::Float),
+        ("double", ComponentType::Float),
+        ("min16uint", ComponentType::Uint),
+        ("min16int", ComponentType::Int),
+        ("min12int", ComponentType::Int),
+        ("uint", ComponentType::Uint),
+        ("dword", ComponentType::Uint),
+        ("bool", ComponentType::Uint),
+        ("int", ComponentType::Int),
+    ];
+
+    let &(scalar, component_type) = SCALARS.iter().find(|(scalar, _)| name.starts_with(scalar))?;
+    let dimensions = &name[scalar.len()..];
+
+    let valid = |n: u32| (1..=4).contains(&n);
+
+    match dimensions.split_once('x') {
+        None if dimensions.is_empty() => Some((component_type, 1, 1)),
+        None => dimensions.parse().ok().filter(|&n| valid(n)).map(|n| (component_type, 1, n)),
+        Some((rows, columns)) => match (rows.parse(), columns.parse()) {
+            (Ok(rows), Ok(columns)) if valid(rows) && valid(columns) => Some((component_type, rows, columns)),
+            _ => None,
+        },
+    }
+}
+
+// TEXCOORD1 -> ("TEXCOORD", 1)
+fn split_semantic(semantic: &str) -> (String, u32) {
+    let name = semantic.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    (name.to_string(), semantic[name.len()..].parse().unwrap_or(0))
+}
+
+// tokens of one file with the file name for messages
+struct Unit {
+    file: String,
+    lexemes: Vec<Lexeme>,
+}
+
+// `type name[N] : SEMANTIC` of a parameter or struct member
+struct Declaration {
+    type_name: String,
+    name: String,
+    array: u32,
+    semantic: Option<String>,
+    line: usize,
+}
+
+struct Parser<'a> {
+    units: &'a [Unit],
+    // struct name -> (unit, index of the opening brace)
+    structs: HashMap<String, (usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(units: &'a [Unit]) -> Parser<'a> {
+        let mut structs = HashMap::new();
+
+        for (unit_index, unit) in units.iter().enumerate() {
+            for index in 0..unit.lexemes.len() {
+                if ident(unit.lexemes.get(index)) == Some("struct") && is_punct(unit.lexemes.get(index + 2), '{') {
+                    if let Some(name) = ident(unit.lexemes.get(index + 1)) {
+                        structs.insert(name.to_string(), (unit_index, index + 2));
+                    }
+                }
+            }
+        }
+
+        Parser {
+            units: units,
+            structs: structs,
+        }
+    }
+
+    fn error(&self, unit: usize, line: usize, message: String) -> SignatureError {
+        SignatureError::Syntax {
+            file: self.units[unit].file.clone(),
+            line: line,
+            message: message,
+        }
+    }
+
+    // (unit, index of `(`) of a function definition at global scope
+    fn find_function(&self, name: &str) -> Option<(usize, usize)> {
+        for (unit_index, unit) in self.units.iter().enumerate() {
+            let mut depth = 0;
+
+            for (index, lexeme) in unit.lexemes.iter().enumerate() {
+                match &lexeme.token {
+                    Token::Punct('{') => depth += 1,
+                    Token::Punct('}') => depth -= 1,
+                    Token::Ident(ident_name) if depth == 0 && ident_name == name && index > 0 => {
+                        let returns = matches!(unit.lexemes[index - 1].token, Token::Ident(_) | Token::Punct('>'));
+                        if returns && is_punct(unit.lexemes.get(index + 1), '(') {
+                            return Some((unit_index, index + 1));
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        None
+    }
+
+    // token ranges between `open` and its closing bracket, split at `separator`
+    fn split(&self, unit: usize, open: usize, separator: char) -> Vec<(usize, usize)> {
+        let lexemes = &self.units[unit].lexemes;
+        let mut pieces = Vec::new();
+        let mut depth = 0;
+        let mut start = open + 1;
+
+        for (index, lexeme) in lexemes.iter().enumerate().skip(open) {
+            match lexeme.token {
+                Token::Punct('(') | Token::Punct('{') | Token::Punct('[') | Token::Punct('<') => depth += 1,
+                Token::Punct(')') | Token::Punct('}') | Token::Punct(']') | Token::Punct('>') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if start < index {
+                            pieces.push((start, index));
+                        }
+                        return pieces;
+                    }
+                },
+                Token::Punct(c) if c == separator && depth == 1 => {
+                    if start < index {
+                        pieces.push((start, index));
+                    }
+                    start = index + 1;
+                },
+                _ => {},
+            }
+        }
+
+        pieces
+    }
+
+    // returns None for out parameters
+    fn declaration(&self, unit: usize, start: usize, end: usize) -> Result<Option<Declaration>, SignatureError> {
+        let lexemes = &self.units[unit].lexemes[start..end];
+        let line = lexemes[0].line;
+        let mut index = 0;
+
+        while let Some(word) = ident(lexemes.get(index)) {
+            match word {
+                "out" => return Ok(None),
+                "inout" => index += 1,
+                _ if MODIFIERS.contains(&word) => index += 1,
+                _ => break,
+            }
+        }
+
+        let type_name = ident(lexemes.get(index)).ok_or_else(|| self.error(unit, line, "expected a type".to_string()))?.to_string();
+        let name = ident(lexemes.get(index + 1)).ok_or_else(|| self.error(unit, line, format!("expected a name after `{}`", type_name)))?.to_string();
+        index += 2;
+
+        let mut array = 1;
+        if is_punct(lexemes.get(index), '[') {
+            array = match lexemes.get(index + 1).map(|lexeme| &lexeme.token) {
+                Some(Token::Number(size)) => size.parse().map_err(|_| self.error(unit, line, format!("`{}` needs a literal array size", name)))?,
+                _ => return Err(self.error(unit, line, format!("`{}` needs a literal array size", name))),
+            };
+            index += 3;
+        }
+
+        let semantic = match is_punct(lexemes.get(index), ':') {
+            true => Some(ident(lexemes.get(index + 1)).ok_or_else(|| self.error(unit, line, format!("expected a semantic after `{}:`", name)))?.to_string()),
+            false => None,
+        };
+
+        Ok(Some(Declaration {
+            type_name: type_name,
+            name: name,
+            array: array,
+            semantic: semantic,
+            line: line,
+        }))
+    }
+
+    fn expand(&self, unit: usize, declaration: Declaration, path: &str, inputs: &mut Vec<VertexInput>, depth: usize) -> Result<(), SignatureError> {
+        let path = match path {
+            "" => declaration.name.clone(),
+            _ => format!("{}.{}", path, declaration.name),
+        };
+
+        if let Some((component_type, rows, columns)) = numeric_type(&declaration.type_name) {
+            let line = declaration.line;
+            let semantic = declaration.semantic.ok_or_else(|| self.error(unit, line, format!("`{}` has no semantic", path)))?;
+            let (semantic, first_index) = split_semantic(&semantic);
+
+            if SYSTEM_VALUES.iter().any(|system_value| system_value.eq_ignore_ascii_case(&semantic)) {
+                return Ok(());
+            }
+
+            // rows of a matrix and elements of an array take consecutive semantic indices
+            for register in 0..declaration.array * rows {
+                inputs.push(VertexInput {
+                    name: path.clone(),
+                    semantic: semantic.clone(),
+                    semantic_index: first_index + register,
+                    component_type: component_type,
+                    components: columns,
+                });
+            }
+
+            return Ok(());
+        }
+
+        let &(struct_unit, open) = self.structs.get(&declaration.type_name)
+            .ok_or_else(|| self.error(unit, declaration.line, format!("`{}` has unsupported type `{}`", path, declaration.type_name)))?;
+
+        if depth > 16 || declaration.array != 1 {
+            return Err(self.error(unit, declaration.line, format!("`{}` can't be used as a vertex input", path)));
+        }
+
+        let members = self.split(struct_unit, open, ';');
+
+        for (start, end) in members {
+            if let Some(member) = self.declaration(struct_unit, start, end)? {
+                self.expand(struct_unit, member, &path, inputs, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry_point(&self, name: &str) -> Result<Vec<VertexInput>, SignatureError> {
+        let (unit, open) = self.find_function(name).ok_or_else(|| SignatureError::MissingEntryPoint(name.to_string()))?;
+        let parameters = self.split(unit, open, ',');
+        let mut inputs = Vec::new();
+
+        for (start, end) in parameters {
+            // `= default` values are left out
+            let end = (start..end).find(|&index| is_punct(self.units[unit].lexemes.get(index), '=')).unwrap_or(end);
+
+            if let Some(declaration) = self.declaration(unit, start, end)? {
+                if ident(self.units[unit].lexemes.get(start)) == Some("uniform") {
+                    continue;
+                }
+                self.expand(unit, declaration, "", &mut inputs, 0)?;
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+// inputs of the entry point `name` in one source text
+pub fn parse_source(source: &str, name: &str) -> Result<Vec<VertexInput>, SignatureError> {
+    let units = [Unit { file: "<source>".to_string(), lexemes: tokenize(&strip_comments(source)) }];
+
+    Parser::new(&units).entry_point(name)
+}
+
+// inputs of the entry point `name` in a shader file, struct types may come from includes
+pub fn parse_file(path: &str, name: &str) -> Result<Vec<VertexInput>, SignatureError> {
+    let units: Vec<_> = read_with_includes(path).map_err(SignatureError::Io)?.into_iter()
+        .map(|file| Unit {
+            file: file.path.to_string_lossy().into_owned(),
+            lexemes: tokenize(&strip_comments(&file.text)),
+        })
+        .collect();
+
+    Parser::new(&units).entry_point(name)
+}
+
+const FORMATS: &[(DXGI_FORMAT, &str, ComponentType, u32)] = &[
+    (DXGI_FORMAT_R32G32B32A32_FLOAT, "R32G32B32A32_FLOAT", ComponentType::Float, 4),
+    (DXGI_FORMAT_R32G32B32A32_UINT, "R32G32B32A32_UINT", ComponentType::Uint, 4),
+    (DXGI_FORMAT_R32G32B32A32_SINT, "R32G32B32A32_SINT", ComponentType::Int, 4),
+    (DXGI_FORMAT_R32G32B32_FLOAT, "R32G32B32_FLOAT", ComponentType::Float, 3),
+    (DXGI_FORMAT_R32G32B32_UINT, "R32G32B32_UINT", ComponentType::Uint, 3),
+    (DXGI_FORMAT_R32G32B32_SINT, "R32G32B32_SINT", ComponentType::Int, 3),
+    (DXGI_FORMAT_R32G32_FLOAT, "R32G32_FLOAT", ComponentType::Float, 2),
+    (DXGI_FORMAT_R32G32_UINT, "R32G32_UINT", ComponentType::Uint, 2),
+    (DXGI_FORMAT_R32G32_SINT, "R32G32_SINT", ComponentType::Int, 2),
+    (DXGI_FORMAT_R32_FLOAT, "R32_FLOAT", ComponentType::Float, 1),
+    (DXGI_FORMAT_R32_UINT, "R32_UINT", ComponentType::Uint, 1),
+    (DXGI_FORMAT_R32_SINT, "R32_SINT", ComponentType::Int, 1),
+    (DXGI_FORMAT_R16G16B16A16_FLOAT, "R16G16B16A16_FLOAT", ComponentType::Float, 4),
+    (DXGI_FORMAT_R16G16B16A16_UNORM, "R16G16B16A16_UNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R16G16B16A16_SNORM, "R16G16B16A16_SNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R16G16B16A16_UINT, "R16G16B16A16_UINT", ComponentType::Uint, 4),
+    (DXGI_FORMAT_R16G16B16A16_SINT, "R16G16B16A16_SINT", ComponentType::Int, 4),
+    (DXGI_FORMAT_R16G16_FLOAT, "R16G16_FLOAT", ComponentType::Float, 2),
+    (DXGI_FORMAT_R16G16_UNORM, "R16G16_UNORM", ComponentType::Float, 2),
+    (DXGI_FORMAT_R16G16_SNORM, "R16G16_SNORM", ComponentType::Float, 2),
+    (DXGI_FORMAT_R16G16_UINT, "R16G16_UINT", ComponentType::Uint, 2),
+    (DXGI_FORMAT_R16G16_SINT, "R16G16_SINT", ComponentType::Int, 2),
+    (DXGI_FORMAT_R16_FLOAT, "R16_FLOAT", ComponentType::Float, 1),
+    (DXGI_FORMAT_R16_UNORM, "R16_UNORM", ComponentType::Float, 1),
+    (DXGI_FORMAT_R16_UINT, "R16_UINT", ComponentType::Uint, 1),
+    (DXGI_FORMAT_R16_SINT, "R16_SINT", ComponentType::Int, 1),
+    (DXGI_FORMAT_R10G10B10A2_UNORM, "R10G10B10A2_UNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R10G10B10A2_UINT, "R10G10B10A2_UINT", ComponentType::Uint, 4),
+    (DXGI_FORMAT_R11G11B10_FLOAT, "R11G11B10_FLOAT", ComponentType::Float, 3),
+    (DXGI_FORMAT_R8G8B8A8_UNORM, "R8G8B8A8_UNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R8G8B8A8_SNORM, "R8G8B8A8_SNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R8G8B8A8_UINT, "R8G8B8A8_UINT", ComponentType::Uint, 4),
+    (DXGI_FORMAT_R8G8B8A8_SINT, "R8G8B8A8_SINT", ComponentType::Int, 4),
+    (DXGI_FORMAT_B8G8R8A8_UNORM, "B8G8R8A8_UNORM", ComponentType::Float, 4),
+    (DXGI_FORMAT_R8G8_UNORM, "R8G8_UNORM", ComponentType::Float, 2),
+    (DXGI_FORMAT_R8G8_UINT, "R8G8_UINT", ComponentType::Uint, 2),
+    (DXGI_FORMAT_R8_UNORM, "R8_UNORM", ComponentType::Float, 1),
+    (DXGI_FORMAT_R8_UINT, "R8_UINT", ComponentType::Uint, 1),
+];
+
+fn format_name(format: DXGI_FORMAT) -> String {
+    match FORMATS.iter().find(|entry| entry.0 == format) {
+        Some(entry) => format!("DXGI_FORMAT_{}", entry.1),
+        None => format!("DXGI_FORMAT {}", format),
+    }
+}
+
+// 32 bit per component format for an input
+pub fn input_format(input: &VertexInput) -> DXGI_FORMAT {
+    FORMATS.iter()
+        .find(|&&(_, name, component_type, components)| name.starts_with("R32") && component_type == input.component_type && components == input.components)
+        .map(|entry| entry.0)
+        .unwrap_or(DXGI_FORMAT_UNKNOWN)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputLayoutError {
+    // no element with the input's semantic
+    Missing { input: VertexInput },
+    // the element's format converts to a different component type
+    TypeMismatch { input: VertexInput, format: DXGI_FORMAT },
+    UnknownFormat { semantic: String, semantic_index: u32, format: DXGI_FORMAT },
+    Duplicate { semantic: String, semantic_index: u32 },
+}
+
+impl fmt::Display for InputLayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputLayoutError::Missing { input } => write!(f, "{} has no input element", input.label()),
+            InputLayoutError::TypeMismatch { input, format } => write!(
+                f, "{} is {} in the shader but its element is {}",
+                input.label(), input.component_type, format_name(*format)
+            ),
+            InputLayoutError::UnknownFormat { semantic, semantic_index, format } => write!(f, "element {}{} has an unsupported vertex format {}", semantic, semantic_index, format_name(*format)),
+            InputLayoutError::Duplicate { semantic, semantic_index } => write!(f, "element {}{} is given more than once", semantic, semantic_index),
+        }
+    }
+}
+
+fn semantic_name(element: &d3d12::D3D12_INPUT_ELEMENT_DESC) -> String {
+    match element.SemanticName.is_null() {
+        true => String::new(),
+        false => unsafe { CStr::from_ptr(element.SemanticName) }.to_string_lossy().into_owned(),
+    }
+}
+
+// checks that every shader input is fed by an element of a compatible format.
+// elements the shader doesn't read and formats with fewer components than the input are fine for D3D12
+pub fn validate(inputs: &[VertexInput], elements: &[d3d12::D3D12_INPUT_ELEMENT_DESC]) -> Result<(), InputLayoutError> {
+
+    for (index, element) in elements.iter().enumerate() {
+        let semantic = semantic_name(element);
+
+        let duplicate = elements[..index].iter()
+            .any(|other| other.SemanticIndex == element.SemanticIndex && semantic_name(other).eq_ignore_ascii_case(&semantic));
+        if duplicate {
+            return Err(InputLayoutError::Duplicate { semantic: semantic, semantic_index: element.SemanticIndex });
+        }
+
+        if !FORMATS.iter().any(|entry| entry.0 == element.Format) {
+            return Err(InputLayoutError::UnknownFormat { semantic: semantic, semantic_index: element.SemanticIndex, format: element.Format });
+        }
+    }
+
+    for input in inputs.iter() {
+        let element = elements.iter()
+            .find(|element| element.SemanticIndex == input.semantic_index && semantic_name(element).eq_ignore_ascii_case(&input.semantic))
+            .ok_or_else(|| InputLayoutError::Missing { input: input.clone() })?;
+
+        let &(_, _, component_type, _) = FORMATS.iter().find(|entry| entry.0 == element.Format).unwrap();
+        if component_type != input.component_type {
+            return Err(InputLayoutError::TypeMismatch { input: input.clone(), format: element.Format });
+        }
+    }
+
+    Ok(())
+}
+
+// input layout generated from a signature, packed into one vertex buffer slot
+pub struct InputLayout {
+    // the elements point into these
+    semantic_names: Vec<CString>,
+    elements: Vec<d3d12::D3D12_INPUT_ELEMENT_DESC>,
+    stride: u32,
+}
+
+impl InputLayout {
+    pub fn new(inputs: &[VertexInput], slot: u32) -> InputLayout {
+        let semantic_names: Vec<_> = inputs.iter().map(|input| CString::new(input.semantic.clone()).unwrap()).collect();
+        let mut elements = Vec::with_capacity(inputs.len());
+        let mut offset = 0;
+
+        for (input, semantic_name) in inputs.iter().zip(semantic_names.iter()) {
+            elements.push(d3d12::D3D12_INPUT_ELEMENT_DESC {
+                SemanticName: semantic_name.as_ptr() as LPCSTR,
+                SemanticIndex: input.semantic_index,
+                Format: input_format(input),
+                InputSlot: slot,
+                AlignedByteOffset: offset,
+                InputSlotClass: d3d12::D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+                InstanceDataStepRate: 0,
+            });
+            offset += 4 * input.components;
+        }
+
+        InputLayout {
+            semantic_names: semantic_names,
+            elements: elements,
+            stride: offset,
+        }
+    }
+
+    pub fn elements(&self) -> &[d3d12::D3D12_INPUT_ELEMENT_DESC] {
+        &self.elements
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    pub fn semantic_names(&self) -> &[CString] {
+        &self.semantic_names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib;
+
+    const SOURCE: &str = "struct Skinning { uint4 joints : BLENDINDICES; float4 weights : BLENDWEIGHT; };\n\
+        struct VSInput {\n\
+            float3 position : POSITION;\n\
+            float2 uv[2] : TEXCOORD1;\n\
+            Skinning skinning;\n\
+            row_major float4x4 world : WORLD;\n\
+            uint instance : SV_InstanceID;\n\
+        };\n\
+        float4 helper(float4 v : POSITION) { return v; }\n\
+        float4 main(VSInput input, uint vertex_id : SV_VertexID, out float fog : FOG, uniform float scale = 1.0) : SV_POSITION {\n\
+            return helper(float4(input.position, 1));\n\
+        }\n";
+
+    #[test]
+    fn struct_parameters_are_flattened() {
+        let inputs = parse_source(SOURCE, "main").unwrap();
+        let summary: Vec<_> = inputs.iter().map(|input| (input.name.as_str(), input.semantic.as_str(), input.semantic_index, input.component_type, input.components)).collect();
+
+        assert_eq!(summary, vec![
+            ("input.position", "POSITION", 0, ComponentType::Float, 3),
+            ("input.uv", "TEXCOORD", 1, ComponentType::Float, 2),
+            ("input.uv", "TEXCOORD", 2, ComponentType::Float, 2),
+            ("input.skinning.joints", "BLENDINDICES", 0, ComponentType::Uint, 4),
+            ("input.skinning.weights", "BLENDWEIGHT", 0, ComponentType::Float, 4),
+            ("input.world", "WORLD", 0, ComponentType::Float, 4),
+            ("input.world", "WORLD", 1, ComponentType::Float, 4),
+            ("input.world", "WORLD", 2, ComponentType::Float, 4),
+            ("input.world", "WORLD", 3, ComponentType::Float, 4),
+        ]);
+
+        let error = parse_source("float4 main(float3 position) : SV_POSITION { return 0; }", "main").unwrap_err();
+        assert_eq!(error.to_string(), "<source>(1): `position` has no semantic");
+        assert_eq!(parse_source(SOURCE, "BasicVS").unwrap_err().to_string(), "entry point `BasicVS` not found");
+    }
+
+    #[test]
+    fn generated_layout_validates() {
+        let inputs = parse_source(SOURCE, "main").unwrap();
+        let layout = InputLayout::new(&inputs, 0);
+
+        assert_eq!(layout.elements().len(), inputs.len());
+        assert_eq!(layout.elements()[3].Format, DXGI_FORMAT_R32G32B32A32_UINT);
+        assert_eq!(layout.elements()[3].AlignedByteOffset, 12 + 8 + 8);
+        assert_eq!(layout.stride(), 12 + 16 + 16 + 16 + 64);
+        assert_eq!(validate(&inputs, layout.elements()), Ok(()));
+    }
+
+    #[test]
+    fn shipped_vertex_layout_matches_the_shader() {
+        let inputs = parse_file("shaders/VertexShader.hlsl", "BasicVS").unwrap();
+        let elements = lib::Vertex::input_layout();
+
+        // float4 position is fed by R32G32B32_FLOAT, w defaults to 1
+        assert_eq!(validate(&inputs, &elements), Ok(()));
+
+        let mut wrong_type = elements.clone();
+        wrong_type[1].Format = DXGI_FORMAT_R32G32_UINT;
+        assert_eq!(
+            validate(&inputs, &wrong_type).unwrap_err().to_string(),
+            "`uv` (TEXCOORD0) is float in the shader but its element is DXGI_FORMAT_R32G32_UINT"
+        );
+
+        assert_eq!(validate(&inputs, &elements[..1]).unwrap_err().to_string(), "`uv` (TEXCOORD0) has no input element");
+    }
+}
@@ -0,0 +1,424 @@
+// shader permutations: feature keywords such as USE_TEXTURE become defines, every combination
+// the constraints allow is compiled once, and pipelines look the bytecode up by a bitmask of features
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+
+use super::ShaderSource;
+use super::cache::{ CompileError, ShaderCache, ShaderKey };
+use super::compiler::ShaderCompiler;
+use super::preprocessor::{ Preprocessed, Preprocessor };
+
+pub const MAX_FEATURES: usize = 32;
+
+// bit i is the i-th declared feature
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PermutationKey(pub u32);
+
+impl PermutationKey {
+    pub fn contains(self, other: PermutationKey) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Constraint {
+    // the first feature needs the second
+    Requires(String, String),
+    // at most one of them
+    Exclusive(Vec<String>),
+}
+
+// constraints as masks of feature bits
+#[derive(Debug, Clone, Copy)]
+enum Mask {
+    Requires { feature: u32, required: u32 },
+    Exclusive(u32),
+}
+
+impl Mask {
+    // whether the features decided so far already break the constraint
+    fn violated(self, decided: u32, enabled: u32) -> bool {
+        match self {
+            Mask::Requires { feature, required } => enabled & feature != 0 && decided & required != 0 && enabled & required == 0,
+            Mask::Exclusive(features) => (enabled & features).count_ones() > 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PermutationError {
+    UnknownFeature(String),
+    DuplicateFeature(String),
+    TooManyFeatures(usize),
+    // the permutations that failed, by their features
+    Compile(Vec<(String, CompileError)>),
+}
+
+impl fmt::Display for PermutationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PermutationError::UnknownFeature(name) => write!(f, "unknown feature `{}`", name),
+            PermutationError::DuplicateFeature(name) => write!(f, "feature `{}` is declared twice", name),
+            PermutationError::TooManyFeatures(count) => write!(f, "{} features, keys hold at most {}", count, MAX_FEATURES),
+            PermutationError::Compile(failures) => {
+                for (index, (features, error)) in failures.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "permutation {}: {}", features, error)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// the feature keywords of a shader and which combinations of them are valid
+#[derive(Debug, Clone, Default)]
+pub struct PermutationSet {
+    features: Vec<String>,
+    constraints: Vec<Constraint>,
+}
+
+impl PermutationSet {
+    pub fn new() -> PermutationSet {
+        PermutationSet::default()
+    }
+
+    pub fn feature(mut self, name: &str) -> Self {
+        self.features.push(name.to_string());
+        self
+    }
+
+    // `feature` is only enabled together with `required`, e.g. ALPHA_TEST needs USE_TEXTURE
+    pub fn requires(mut self, feature: &str, required: &str) -> Self {
+        self.constraints.push(Constraint::Requires(feature.to_string(), required.to_string()));
+        self
+    }
+
+    // at most one of `features` is enabled
+    pub fn exclusive(mut self, features: &[&str]) -> Self {
+        self.constraints.push(Constraint::Exclusive(features.iter().map(|name| name.to_string()).collect()));
+        self
+    }
+
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    // keys are u32 bit sets, checked before any bit is shifted in
+    fn check_count(&self) -> Result<(), PermutationError> {
+        match self.features.len() {
+            count if count > MAX_FEATURES => Err(PermutationError::TooManyFeatures(count)),
+            _ => Ok(()),
+        }
+    }
+
+    // callers run check_count first so the shift can't overflow
+    fn bit(&self, name: &str) -> Result<u32, PermutationError> {
+        match self.features.iter().position(|feature| feature == name) {
+            Some(index) => Ok(1 << index),
+            None => Err(PermutationError::UnknownFeature(name.to_string())),
+        }
+    }
+
+    pub fn key(&self, features: &[&str]) -> Result<PermutationKey, PermutationError> {
+        self.check_count()?;
+
+        features.iter().try_fold(PermutationKey(0), |key, name| Ok(PermutationKey(key.0 | self.bit(name)?)))
+    }
+
+    // checks the declarations and resolves the constraints
+    fn masks(&self) -> Result<Vec<Mask>, PermutationError> {
+        self.check_count()?;
+
+        let duplicate = self.features.iter().enumerate().find(|(index, name)| self.features[..*index].contains(name));
+        if let Some((_, name)) = duplicate {
+            return Err(PermutationError::DuplicateFeature(name.clone()));
+        }
+
+        self.constraints.iter().map(|constraint| match constraint {
+            Constraint::Requires(feature, required) => Ok(Mask::Requires { feature: self.bit(feature)?, required: self.bit(required)? }),
+            Constraint::Exclusive(features) => Ok(Mask::Exclusive(features.iter().try_fold(0, |mask, name| Ok(mask | self.bit(name)?))?)),
+        }).collect()
+    }
+
+    pub fn is_valid(&self, key: PermutationKey) -> Result<bool, PermutationError> {
+        let masks = self.masks()?;
+
+        let all = match self.features.len() {
+            MAX_FEATURES => u32::MAX,
+            count => (1u32 << count) - 1,
+        };
+
+        Ok(key.0 & !all == 0 && !masks.iter().any(|mask| mask.violated(all, key.0)))
+    }
+
+    // every key the constraints allow, ascending. features are decided one at a time
+    // and a combination is dropped as soon as the decided features break a constraint
+    pub fn permutations(&self) -> Result<Vec<PermutationKey>, PermutationError> {
+        let masks = self.masks()?;
+        let mut keys = Vec::new();
+
+        enumerate(&masks, self.features.len(), 0, 0, &mut keys);
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn enabled(&self, key: PermutationKey) -> impl Iterator<Item = &String> {
+        self.features.iter().enumerate().take(MAX_FEATURES)
+            .filter(move |(index, _)| key.0 & (1 << index) != 0)
+            .map(|(_, name)| name)
+    }
+
+    // enabled features are defined to 1 and disabled ones left undefined, so #if and #ifdef both work
+    pub fn defines(&self, key: PermutationKey) -> Vec<(String, String)> {
+        self.enabled(key).map(|name| (name.clone(), "1".to_string())).collect()
+    }
+
+    // "USE_TEXTURE|ALPHA_TEST", or "none"
+    pub fn describe(&self, key: PermutationKey) -> String {
+        let names: Vec<&str> = self.enabled(key).map(|name| name.as_str()).collect();
+
+        match names.is_empty() {
+            true => "none".to_string(),
+            false => names.join("|"),
+        }
+    }
+}
+
+fn enumerate(masks: &[Mask], count: usize, index: usize, enabled: u32, keys: &mut Vec<PermutationKey>) {
+    let decided = match index {
+        MAX_FEATURES => u32::MAX,
+        index => (1u32 << index) - 1,
+    };
+
+    if masks.iter().any(|mask| mask.violated(decided, enabled)) {
+        return;
+    }
+
+    if index == count {
+        keys.push(PermutationKey(enabled));
+        return;
+    }
+
+    enumerate(masks, count, index + 1, enabled, keys);
+    enumerate(masks, count, index + 1, enabled | (1 << index), keys);
+}
+
+// the compiled permutations of one shader
+#[derive(Debug, Default)]
+pub struct ShaderPermutations {
+    permutations: HashMap<PermutationKey, (ShaderSource, Vec<u8>)>,
+}
+
+impl ShaderPermutations {
+    pub fn get(&self, key: PermutationKey) -> Option<&[u8]> {
+        self.permutations.get(&key).map(|(_, bytecode)| &bytecode[..])
+    }
+
+    // the source with the permutation's defines, e.g. to hot reload it
+    pub fn source(&self, key: PermutationKey) -> Option<&ShaderSource> {
+        self.permutations.get(&key).map(|(source, _)| source)
+    }
+
+    pub fn keys(&self) -> Vec<PermutationKey> {
+        let mut keys: Vec<PermutationKey> = self.permutations.keys().copied().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.permutations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.permutations.is_empty()
+    }
+}
+
+// a permutation missing from the cache
+struct Pending {
+    key: PermutationKey,
+    source: ShaderSource,
+    preprocessed: Preprocessed,
+    cache_key: ShaderKey,
+}
+
+// compiles every valid permutation of `source`. cached ones are read first,
+// the others are compiled on up to `threads` threads and written to the cache
+pub fn compile(set: &PermutationSet, source: &ShaderSource, preprocessor: &Preprocessor, cache: &mut ShaderCache, compiler: &dyn ShaderCompiler, threads: usize) -> Result<ShaderPermutations, PermutationError> {
+    let mut permutations = ShaderPermutations::default();
+    let mut pending = Vec::new();
+    let mut failures = Vec::new();
+
+    for key in set.permutations()? {
+        let permutation_source = set.defines(key).iter().fold(source.clone(), |source, (name, value)| source.define(name, value));
+        let permutation_preprocessor = preprocessor.with_defines(&permutation_source.defines);
+
+        let preprocessed = match permutation_preprocessor.preprocess_file(&source.path) {
+            Ok(preprocessed) => preprocessed,
+            Err(error) => {
+                failures.push((set.describe(key), CompileError::Preprocess(error)));
+                continue;
+            },
+        };
+
+        let cache_key = ShaderKey::of_source(&preprocessed, permutation_preprocessor.defines(), &permutation_source, compiler);
+
+        match cache.get(cache_key) {
+            Some(bytecode) => { permutations.permutations.insert(key, (permutation_source, bytecode)); },
+            None => pending.push(Pending { key: key, source: permutation_source, preprocessed: preprocessed, cache_key: cache_key }),
+        }
+    }
+
+    // workers take the next pending permutation until none are left
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, pending.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let permutation = match pending.get(index) {
+                    Some(permutation) => permutation,
+                    None => break,
+                };
+
+                let source = &permutation.source;
+                let result = compiler.compile(&permutation.preprocessed.source, &source.path, &source.entry_point, &source.target, source.flags);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+
+    for ((_, result), permutation) in results.into_iter().zip(pending) {
+        match result {
+            Ok(bytecode) => match cache.put(permutation.cache_key, &bytecode) {
+                Ok(()) => { permutations.permutations.insert(permutation.key, (permutation.source, bytecode)); },
+                Err(error) => failures.push((set.describe(permutation.key), CompileError::Cache(error))),
+            },
+            Err(messages) => failures.push((set.describe(permutation.key), CompileError::Compile(messages))),
+        }
+    }
+
+    match failures.is_empty() {
+        true => Ok(permutations),
+        false => Err(PermutationError::Compile(failures)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::compiler::FakeCompiler;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rs_permutation_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn features() -> PermutationSet {
+        PermutationSet::new()
+            .feature("USE_TEXTURE")
+            .feature("ALPHA_TEST")
+            .feature("SKINNED")
+            .requires("ALPHA_TEST", "USE_TEXTURE")
+    }
+
+    #[test]
+    fn keys_are_feature_bits() {
+        let set = features();
+        let key = set.key(&["USE_TEXTURE", "SKINNED"]).unwrap();
+
+        assert_eq!(key, PermutationKey(0b101));
+        assert!(key.contains(set.key(&["SKINNED"]).unwrap()));
+        assert_eq!(set.describe(key), "USE_TEXTURE|SKINNED");
+        assert_eq!(set.describe(PermutationKey(0)), "none");
+        assert_eq!(set.defines(key), vec![("USE_TEXTURE".to_string(), "1".to_string()), ("SKINNED".to_string(), "1".to_string())]);
+
+        assert!(matches!(set.key(&["LIGHTING"]), Err(PermutationError::UnknownFeature(name)) if name == "LIGHTING"));
+        assert!(matches!(features().feature("SKINNED").permutations(), Err(PermutationError::DuplicateFeature(_))));
+        assert!(matches!(features().requires("SKINNED", "MORPH").permutations(), Err(PermutationError::UnknownFeature(_))));
+
+        let too_many = (0..33).fold(PermutationSet::new(), |set, index| set.feature(&format!("F{}", index)));
+        assert!(matches!(too_many.permutations(), Err(PermutationError::TooManyFeatures(33))));
+        assert!(matches!(too_many.is_valid(PermutationKey(0)), Err(PermutationError::TooManyFeatures(33))));
+        assert!(matches!(too_many.key(&["F32"]), Err(PermutationError::TooManyFeatures(33))));
+        assert_eq!(too_many.describe(PermutationKey(u32::MAX)).split('|').count(), MAX_FEATURES);
+    }
+
+    #[test]
+    fn constraints_prune_permutations() {
+        let set = features();
+
+        // alpha test without a texture is pruned
+        let keys: Vec<String> = set.permutations().unwrap().into_iter().map(|key| set.describe(key)).collect();
+        assert_eq!(keys, vec!["none", "USE_TEXTURE", "USE_TEXTURE|ALPHA_TEST", "SKINNED", "USE_TEXTURE|SKINNED", "USE_TEXTURE|ALPHA_TEST|SKINNED"]);
+        assert!(!set.is_valid(set.key(&["ALPHA_TEST"]).unwrap()).unwrap());
+        assert!(set.is_valid(set.key(&["ALPHA_TEST", "USE_TEXTURE"]).unwrap()).unwrap());
+        assert!(!set.is_valid(PermutationKey(0b1000)).unwrap());
+
+        let lighting = PermutationSet::new().feature("UNLIT").feature("LAMBERT").feature("PHONG").exclusive(&["UNLIT", "LAMBERT", "PHONG"]);
+        assert_eq!(lighting.permutations().unwrap(), vec![PermutationKey(0), PermutationKey(1), PermutationKey(2), PermutationKey(4)]);
+    }
+
+    #[test]
+    fn permutations_compile_in_parallel_through_the_cache() {
+        let directory = temporary_directory("compile");
+        let shader = directory.join("Pixel.hlsl");
+        fs::write(&shader, concat!(
+            "float4 main() : SV_TARGET {\n",
+            "#if USE_TEXTURE\n    return sample_texture;\n#else\n    return 1;\n#endif\n",
+            "}\n",
+            "#ifdef SKINNED\nskinned\n#endif\n",
+        )).unwrap();
+
+        let set = features();
+        let source = ShaderSource::new(&shader.to_string_lossy(), "main", "ps_5_0", 0);
+        let mut cache = ShaderCache::new(&directory.join("cache").to_string_lossy()).unwrap();
+        let compiler = FakeCompiler::new();
+
+        let permutations = compile(&set, &source, &Preprocessor::new(), &mut cache, &compiler, 4).unwrap();
+        assert_eq!(permutations.keys(), set.permutations().unwrap());
+        assert_eq!(compiler.compiled().len(), 6);
+
+        let textured = String::from_utf8(permutations.get(set.key(&["USE_TEXTURE"]).unwrap()).unwrap().to_vec()).unwrap();
+        assert!(textured.contains("sample_texture") && !textured.contains("skinned"));
+        let skinned = String::from_utf8(permutations.get(set.key(&["SKINNED"]).unwrap()).unwrap().to_vec()).unwrap();
+        assert!(skinned.contains("return 1;") && skinned.contains("skinned"));
+        assert!(permutations.get(set.key(&["ALPHA_TEST"]).unwrap()).is_none());
+        assert_eq!(permutations.source(PermutationKey(0b011)).unwrap().defines.len(), 2);
+
+        // a second run only reads the cache
+        let cached = compile(&set, &source, &Preprocessor::new(), &mut cache, &compiler, 4).unwrap();
+        assert_eq!(cached.len(), 6);
+        assert_eq!(compiler.compiled().len(), 6);
+
+        // every failed permutation is reported
+        let mut failing_cache = ShaderCache::new(&directory.join("failing_cache").to_string_lossy()).unwrap();
+        let failing = FakeCompiler::new().failing_on("skinned");
+        let error = compile(&set, &source, &Preprocessor::new(), &mut failing_cache, &failing, 4).unwrap_err();
+        assert_eq!(failing.compiled().len(), 6);
+        match error {
+            PermutationError::Compile(failures) => {
+                let features: Vec<&str> = failures.iter().map(|(features, _)| features.as_str()).collect();
+                assert_eq!(features, vec!["SKINNED", "USE_TEXTURE|SKINNED", "USE_TEXTURE|ALPHA_TEST|SKINNED"]);
+            },
+            _ => panic!("expected compile failures"),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,1025 @@
+// HLSL preprocessor, flattens a shader and its includes into one source with #line directives
+// so it can be hashed and compiled without D3D_COMPILE_STANDARD_FILE_INCLUDE
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+
+use super::strip_comments;
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Io { path: PathBuf, error: io::Error },
+    IncludeNotFound { file: String, line: usize, name: String },
+    Syntax { file: String, line: usize, message: String },
+    // #error in the source
+    User { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreprocessError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            PreprocessError::IncludeNotFound { file, line, name } => write!(f, "{}({}): cannot open include file `{}`", file, line, name),
+            PreprocessError::Syntax { file, line, message } => write!(f, "{}({}): {}", file, line, message),
+            PreprocessError::User { file, line, message } => write!(f, "{}({}): #error {}", file, line, message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Preprocessed {
+    pub source: String,
+    // every file that was read, the main file first
+    pub dependencies: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PpToken {
+    Ident(String),
+    Number(String),
+    // string or character literal with its quotes
+    Literal(String),
+    Punct(String),
+    Space(String),
+}
+
+impl PpToken {
+    fn text(&self) -> &str {
+        match self {
+            PpToken::Ident(text) | PpToken::Number(text) | PpToken::Literal(text) | PpToken::Punct(text) | PpToken::Space(text) => text,
+        }
+    }
+
+    fn is_space(&self) -> bool {
+        matches!(self, PpToken::Space(_))
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self, PpToken::Punct(text) if text == punct)
+    }
+}
+
+const PUNCTUATORS: &[&str] = &[
+    "<<=", ">>=", "...",
+    "##", "&&", "||", "<<", ">>", "<=", ">=", "==", "!=", "++", "--", "->", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "::",
+];
+
+fn pp_tokens(text: &str) -> Vec<PpToken> {
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let take_while = |start: usize, predicate: &dyn Fn(char) -> bool| {
+        let mut end = start;
+        while end < chars.len() && predicate(chars[end]) {
+            end += 1;
+        }
+        end
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        let token = if c.is_whitespace() {
+            i = take_while(i, &|c| c.is_whitespace());
+            PpToken::Space(chars[start..i].iter().collect())
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            i = take_while(i, &|c| c.is_ascii_alphanumeric() || c == '_');
+            PpToken::Ident(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            // pp-number, 1.5e-3f
+            i += 1;
+            while i < chars.len() {
+                let exponent_sign = (chars[i] == '+' || chars[i] == '-') && (chars[i - 1] == 'e' || chars[i - 1] == 'E');
+                if !(exponent_sign || chars[i].is_ascii_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
+                    break;
+                }
+                i += 1;
+            }
+            PpToken::Number(chars[start..i].iter().collect())
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            PpToken::Literal(chars[start..i].iter().collect())
+        } else {
+            let rest: String = chars[i..(i + 3).min(chars.len())].iter().collect();
+            let punct = PUNCTUATORS.iter().find(|punct| rest.starts_with(*punct)).map_or(c.to_string(), |punct| punct.to_string());
+            i += punct.chars().count();
+            PpToken::Punct(punct)
+        };
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn join(tokens: &[PpToken]) -> String {
+    tokens.iter().map(|token| token.text()).collect()
+}
+
+fn trim(tokens: &[PpToken]) -> &[PpToken] {
+    let start = tokens.iter().position(|token| !token.is_space()).unwrap_or(tokens.len());
+    let end = tokens.iter().rposition(|token| !token.is_space()).map_or(start, |end| end + 1);
+    &tokens[start..end]
+}
+
+fn open_parenthesis(tokens: &[PpToken]) -> bool {
+    let depth = tokens.iter().fold(0i32, |depth, token| match token {
+        PpToken::Punct(punct) if punct == "(" => depth + 1,
+        PpToken::Punct(punct) if punct == ")" => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+fn next_non_space(tokens: &[PpToken], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|&index| !tokens[index].is_space())
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    // None for object-like macros
+    params: Option<Vec<String>>,
+    variadic: bool,
+    body: Vec<PpToken>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Condition {
+    parent_active: bool,
+    active: bool,
+    // some branch of this #if has been taken
+    taken: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+// where an error happened
+#[derive(Clone, Copy)]
+struct Location<'a> {
+    file: &'a str,
+    line: usize,
+}
+
+impl<'a> Location<'a> {
+    fn error(&self, message: String) -> PreprocessError {
+        PreprocessError::Syntax {
+            file: self.file.to_string(),
+            line: self.line,
+            message: message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    include_paths: Vec<PathBuf>,
+    defines: Vec<(String, String)>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Preprocessor {
+        Preprocessor::default()
+    }
+
+    // searched for <name> includes first and for "name" includes after the including file's directory
+    pub fn include_path(mut self, path: &str) -> Preprocessor {
+        self.include_paths.push(PathBuf::from(path));
+        self
+    }
+
+    // object-like macro, like /D NAME=value
+    pub fn define(mut self, name: &str, value: &str) -> Preprocessor {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn defines(&self) -> &[(String, String)] {
+        &self.defines
+    }
+
+    // a copy with `defines` added, e.g. a shader's own defines
+    pub fn with_defines(&self, defines: &[(String, String)]) -> Preprocessor {
+        let mut preprocessor = self.clone();
+        preprocessor.defines.extend(defines.iter().cloned());
+        preprocessor
+    }
+
+    pub fn preprocess_file(&self, path: &str) -> Result<Preprocessed, PreprocessError> {
+        let path = PathBuf::from(path);
+        let text = fs::read_to_string(&path).map_err(|error| PreprocessError::Io { path: path.clone(), error: error })?;
+
+        self.preprocess_source(&text, &path.to_string_lossy())
+    }
+
+    // `path` names the source in #line directives and is where its quoted includes are looked up from
+    pub fn preprocess_source(&self, source: &str, path: &str) -> Result<Preprocessed, PreprocessError> {
+        let mut state = State {
+            include_paths: &self.include_paths,
+            macros: HashMap::new(),
+            once: HashSet::new(),
+            output: String::new(),
+            dependencies: Vec::new(),
+        };
+
+        for (name, value) in self.defines.iter() {
+            state.macros.insert(name.clone(), Macro { params: None, variadic: false, body: pp_tokens(value) });
+        }
+
+        let path = PathBuf::from(path);
+        state.dependencies.push(path.clone());
+        state.process(&path, source, 0)?;
+
+        Ok(Preprocessed {
+            source: state.output,
+            dependencies: state.dependencies,
+        })
+    }
+}
+
+struct State<'a> {
+    include_paths: &'a [PathBuf],
+    macros: HashMap<String, Macro>,
+    // canonical paths of #pragma once files
+    once: HashSet<PathBuf>,
+    output: String,
+    dependencies: Vec<PathBuf>,
+}
+
+pub fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn line_directive(line: usize, path: &Path) -> String {
+    format!("#line {} \"{}\"\n", line, path.to_string_lossy().replace('\\', "\\\\"))
+}
+
+impl<'a> State<'a> {
+    fn process(&mut self, path: &Path, text: &str, depth: usize) -> Result<(), PreprocessError> {
+
+        let file = path.to_string_lossy().into_owned();
+        let stripped = strip_comments(text);
+        let mut lines: Vec<&str> = stripped.split('\n').map(|line| line.trim_end_matches('\r')).collect();
+        let mut conditions: Vec<Condition> = Vec::new();
+        let mut index = 0;
+
+        // a trailing newline doesn't start another line
+        if lines.len() > 1 && lines.last() == Some(&"") {
+            lines.pop();
+        }
+
+        self.output.push_str(&line_directive(1, path));
+
+        while index < lines.len() {
+            let location = Location { file: &file, line: index + 1 };
+
+            // backslash continuations make one logical line
+            let mut logical = lines[index].to_string();
+            let mut consumed = 1;
+            while logical.ends_with('\\') && index + consumed < lines.len() {
+                logical.pop();
+                logical.push_str(lines[index + consumed]);
+                consumed += 1;
+            }
+            index += consumed;
+
+            let active = conditions.iter().all(|condition| condition.active);
+            let mut newlines = consumed;
+
+            match logical.trim_start().strip_prefix('#') {
+                Some(directive) => {
+                    let included = self.directive(path, location, directive, &mut conditions, active, depth)?;
+
+                    if included {
+                        self.output.push_str(&line_directive(index + 1, path));
+                        continue;
+                    }
+                },
+                None if active => {
+                    // the arguments of a macro call can go on over the next lines, keep joining them
+                    // while a parenthesis is open
+                    let mut tokens = pp_tokens(&logical);
+                    while open_parenthesis(&tokens) && index < lines.len() && !lines[index].trim_start().starts_with('#') {
+                        tokens.push(PpToken::Space("\n".to_string()));
+                        tokens.extend(pp_tokens(lines[index]));
+                        index += 1;
+                        newlines += 1;
+                    }
+
+                    let expanded = join(&self.expand(&tokens, &[], location)?);
+                    self.output.push_str(&expanded);
+                    newlines = newlines.saturating_sub(expanded.matches('\n').count());
+                },
+                None => {},
+            }
+
+            // blank lines keep the line numbers of the flattened source in step
+            for _ in 0..newlines {
+                self.output.push('\n');
+            }
+        }
+
+        match conditions.last() {
+            Some(condition) => Err(PreprocessError::Syntax {
+                file: file,
+                line: condition.line,
+                message: "#if without #endif".to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // returns true if the directive pulled in an include
+    fn directive(&mut self, path: &Path, location: Location, directive: &str, conditions: &mut Vec<Condition>, active: bool, depth: usize) -> Result<bool, PreprocessError> {
+
+        let directive = directive.trim();
+        let name_end = directive.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(directive.len());
+        let (name, rest) = (&directive[..name_end], directive[name_end..].trim());
+
+        match name {
+            "if" | "ifdef" | "ifndef" => {
+                let value = match (active, name) {
+                    (false, _) => false,
+                    (true, "ifdef") => self.macros.contains_key(self.macro_name(rest, location)?),
+                    (true, "ifndef") => !self.macros.contains_key(self.macro_name(rest, location)?),
+                    (true, _) => self.evaluate(rest, location)?,
+                };
+
+                conditions.push(Condition {
+                    parent_active: active,
+                    active: value,
+                    taken: value,
+                    seen_else: false,
+                    line: location.line,
+                });
+            },
+            "elif" | "else" => {
+                let condition = *conditions.last().ok_or_else(|| location.error(format!("#{} without #if", name)))?;
+                if condition.seen_else {
+                    return Err(location.error(format!("#{} after #else", name)));
+                }
+
+                let value = match name {
+                    "elif" => condition.parent_active && !condition.taken && self.evaluate(rest, location)?,
+                    _ => condition.parent_active && !condition.taken,
+                };
+
+                let last = conditions.last_mut().unwrap();
+                last.active = value;
+                last.taken |= value;
+                last.seen_else = name == "else";
+            },
+            "endif" => {
+                conditions.pop().ok_or_else(|| location.error("#endif without #if".to_string()))?;
+            },
+            _ if !active => {},
+            "" => {},
+            "define" => self.define(rest, location)?,
+            "undef" => {
+                let name = self.macro_name(rest, location)?.to_string();
+                self.macros.remove(&name);
+            },
+            "include" => return self.include(path, location, rest, depth),
+            "pragma" if rest == "once" => {
+                self.once.insert(canonical(path));
+            },
+            // other pragmas and #line go to the compiler
+            "pragma" | "line" => self.output.push_str(&format!("#{}", directive)),
+            "error" => {
+                return Err(PreprocessError::User {
+                    file: location.file.to_string(),
+                    line: location.line,
+                    message: rest.to_string(),
+                });
+            },
+            _ => return Err(location.error(format!("unknown directive #{}", name))),
+        }
+
+        Ok(false)
+    }
+
+    fn macro_name<'b>(&self, rest: &'b str, location: Location) -> Result<&'b str, PreprocessError> {
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+
+        match &rest[..end] {
+            name if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) => Ok(name),
+            _ => Err(location.error("expected a macro name".to_string())),
+        }
+    }
+
+    // NAME body or NAME(a, b, ...) body
+    fn define(&mut self, rest: &str, location: Location) -> Result<(), PreprocessError> {
+        let name = self.macro_name(rest, location)?.to_string();
+        let after = &rest[name.len()..];
+
+        // a parameter list has to follow the name without a space
+        let (params, variadic, body) = match after.strip_prefix('(') {
+            Some(list) => {
+                let close = list.find(')').ok_or_else(|| location.error(format!("missing `)` in the parameters of `{}`", name)))?;
+                let mut params = Vec::new();
+                let mut variadic = false;
+
+                for param in list[..close].split(',').map(str::trim).filter(|param| !param.is_empty()) {
+                    match param {
+                        "..." => {
+                            params.push("__VA_ARGS__".to_string());
+                            variadic = true;
+                        },
+                        _ if !variadic && self.macro_name(param, location)? == param => params.push(param.to_string()),
+                        _ => return Err(location.error(format!("invalid parameter `{}` of `{}`", param, name))),
+                    }
+                }
+
+                (Some(params), variadic, &list[close + 1..])
+            },
+            None => (None, false, after),
+        };
+
+        self.macros.insert(name, Macro {
+            params: params,
+            variadic: variadic,
+            body: trim(&pp_tokens(body)).to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, location: Location, rest: &str, depth: usize) -> Result<bool, PreprocessError> {
+        // #include MACRO expands to one of the other forms
+        let rest = match rest.starts_with('"') || rest.starts_with('<') {
+            true => rest.to_string(),
+            false => join(&self.expand(&pp_tokens(rest), &[], location)?).trim().to_string(),
+        };
+
+        let (name, quoted) = match rest.chars().next() {
+            Some('"') => (rest[1..].split('"').next().unwrap_or(""), true),
+            Some('<') => (rest[1..].split('>').next().unwrap_or(""), false),
+            _ => return Err(location.error(format!("expected \"file\" or <file> after #include, found `{}`", rest))),
+        };
+
+        let directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let relative = name.replace('\\', "/");
+
+        let mut candidates: Vec<PathBuf> = self.include_paths.iter().map(|include_path| include_path.join(&relative)).collect();
+        match quoted {
+            true => candidates.insert(0, directory.join(&relative)),
+            false => candidates.push(directory.join(&relative)),
+        }
+
+        let include_path = candidates.into_iter().find(|candidate| candidate.is_file())
+            .ok_or_else(|| PreprocessError::IncludeNotFound {
+                file: location.file.to_string(),
+                line: location.line,
+                name: name.to_string(),
+            })?;
+
+        let canonical_path = canonical(&include_path);
+        if self.once.contains(&canonical_path) {
+            return Ok(false);
+        }
+
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error(format!("includes nested deeper than {}", MAX_INCLUDE_DEPTH)));
+        }
+
+        if !self.dependencies.iter().any(|dependency| canonical(dependency) == canonical_path) {
+            self.dependencies.push(include_path.clone());
+        }
+
+        let text = fs::read_to_string(&include_path).map_err(|error| PreprocessError::Io { path: include_path.clone(), error: error })?;
+        self.process(&include_path, &text, depth + 1)?;
+
+        Ok(true)
+    }
+
+    // macros in `hidden` are being expanded and aren't expanded again
+    fn expand(&self, tokens: &[PpToken], hidden: &[String], location: Location) -> Result<Vec<PpToken>, PreprocessError> {
+
+        // tokens left to scan in reverse order, each with the macros it came out of. an expansion goes
+        // back on top so it's rescanned together with the tokens after it, `F(2)` with `#define F G`
+        let hidden = Rc::new(hidden.to_vec());
+        let mut pending: Vec<(PpToken, Rc<Vec<String>>)> = tokens.iter().rev().map(|token| (token.clone(), hidden.clone())).collect();
+        let mut expanded = Vec::with_capacity(tokens.len());
+
+        while let Some((token, hidden)) = pending.pop() {
+
+            let name = match &token {
+                PpToken::Ident(name) if !hidden.contains(name) => name.clone(),
+                _ => {
+                    expanded.push(token);
+                    continue;
+                },
+            };
+
+            match name.as_str() {
+                "__LINE__" => {
+                    expanded.push(PpToken::Number(location.line.to_string()));
+                    continue;
+                },
+                "__FILE__" => {
+                    expanded.push(PpToken::Literal(format!("\"{}\"", location.file.replace('\\', "\\\\"))));
+                    continue;
+                },
+                _ => {},
+            }
+
+            let definition = match self.macros.get(&name) {
+                Some(definition) => definition,
+                None => {
+                    expanded.push(token);
+                    continue;
+                },
+            };
+
+            let body = match &definition.params {
+                Some(params) => {
+                    // a function-like macro without arguments is a plain identifier
+                    let open = match pending.iter().rev().position(|(token, _)| !token.is_space()) {
+                        Some(open) if pending[pending.len() - 1 - open].0.is_punct("(") => open,
+                        _ => {
+                            expanded.push(token);
+                            continue;
+                        },
+                    };
+
+                    let (mut args, close) = self.arguments(pending.iter().rev().map(|(token, _)| token).skip(open), &name, location)?;
+                    pending.truncate(pending.len() - (open + close + 1));
+
+                    if definition.variadic && args.len() > params.len() {
+                        let rest = args.split_off(params.len() - 1);
+                        let mut joined = Vec::new();
+                        for (arg_index, arg) in rest.into_iter().enumerate() {
+                            if arg_index > 0 {
+                                joined.push(PpToken::Punct(",".to_string()));
+                            }
+                            joined.extend(arg);
+                        }
+                        args.push(joined);
+                    }
+
+                    // NAME() passes one empty argument
+                    if params.is_empty() && args.len() == 1 && trim(&args[0]).is_empty() {
+                        args.clear();
+                    }
+                    if definition.variadic && args.len() + 1 == params.len() {
+                        args.push(Vec::new());
+                    }
+
+                    if args.len() != params.len() {
+                        return Err(location.error(format!("`{}` takes {} arguments but {} were given", name, params.len(), args.len())));
+                    }
+
+                    self.substitute(definition, params, &args, &hidden, location)?
+                },
+                None => definition.body.clone(),
+            };
+
+            let mut inner = hidden.to_vec();
+            inner.push(name);
+            let inner = Rc::new(inner);
+            pending.extend(body.into_iter().rev().map(|token| (token, inner.clone())));
+        }
+
+        Ok(expanded)
+    }
+
+    // arguments from the `(` that `tokens` starts with to the matching `)` split at top level commas,
+    // and the index of the `)`
+    fn arguments<'t>(&self, tokens: impl Iterator<Item = &'t PpToken>, name: &str, location: Location) -> Result<(Vec<Vec<PpToken>>, usize), PreprocessError> {
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+
+        for (index, token) in tokens.enumerate() {
+            match token {
+                PpToken::Punct(punct) if punct == "(" => {
+                    depth += 1;
+                    if depth == 1 {
+                        continue;
+                    }
+                },
+                PpToken::Punct(punct) if punct == ")" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok((args, index));
+                    }
+                },
+                PpToken::Punct(punct) if punct == "," && depth == 1 => {
+                    args.push(Vec::new());
+                    continue;
+                },
+                _ => {},
+            }
+            args.last_mut().unwrap().push(token.clone());
+        }
+
+        Err(location.error(format!("unterminated argument list for `{}`", name)))
+    }
+
+    // replaces parameters in the body, handling # and ##
+    fn substitute(&self, definition: &Macro, params: &[String], args: &[Vec<PpToken>], hidden: &[String], location: Location) -> Result<Vec<PpToken>, PreprocessError> {
+
+        let body = &definition.body;
+        let param_index = |token: &PpToken| match token {
+            PpToken::Ident(name) => params.iter().position(|param| param == name),
+            _ => None,
+        };
+
+        let mut substituted: Vec<PpToken> = Vec::new();
+        let mut index = 0;
+
+        while index < body.len() {
+            let token = &body[index];
+
+            // #param
+            if token.is_punct("#") {
+                if let Some(operand) = next_non_space(body, index + 1).filter(|&operand| param_index(&body[operand]).is_some()) {
+                    let text = join(trim(&args[param_index(&body[operand]).unwrap()]));
+                    substituted.push(PpToken::Literal(format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))));
+                    index = operand + 1;
+                    continue;
+                }
+            }
+
+            // left ## right
+            if token.is_punct("##") {
+                while substituted.last().is_some_and(PpToken::is_space) {
+                    substituted.pop();
+                }
+
+                let operand = next_non_space(body, index + 1).ok_or_else(|| location.error("`##` at the end of a macro".to_string()))?;
+                let right = match param_index(&body[operand]) {
+                    Some(param) => trim(&args[param]).to_vec(),
+                    None => vec![body[operand].clone()],
+                };
+
+                let left = substituted.pop().map_or(String::new(), |left| left.text().to_string());
+                let first = right.first().map_or("", |first| first.text());
+
+                substituted.extend(pp_tokens(&format!("{}{}", left, first)));
+                substituted.extend(right.into_iter().skip(1));
+                index = operand + 1;
+                continue;
+            }
+
+            match param_index(token) {
+                Some(param) => {
+                    // operands of ## aren't expanded
+                    let pasted = next_non_space(body, index + 1).is_some_and(|next| body[next].is_punct("##"));
+                    match pasted {
+                        true => substituted.extend(trim(&args[param]).iter().cloned()),
+                        false => substituted.extend(self.expand(trim(&args[param]), hidden, location)?),
+                    }
+                },
+                None => substituted.push(token.clone()),
+            }
+            index += 1;
+        }
+
+        Ok(substituted)
+    }
+
+    fn evaluate(&self, expression: &str, location: Location) -> Result<bool, PreprocessError> {
+
+        let tokens = pp_tokens(expression);
+        let mut replaced = Vec::with_capacity(tokens.len());
+        let mut index = 0;
+
+        // defined NAME and defined(NAME) before expansion
+        while index < tokens.len() {
+            if tokens[index] != PpToken::Ident("defined".to_string()) {
+                replaced.push(tokens[index].clone());
+                index += 1;
+                continue;
+            }
+
+            let mut operand = next_non_space(&tokens, index + 1);
+            let parenthesized = operand.is_some_and(|operand| tokens[operand].is_punct("("));
+            if parenthesized {
+                operand = next_non_space(&tokens, operand.unwrap() + 1);
+            }
+
+            let name = match operand.map(|operand| &tokens[operand]) {
+                Some(PpToken::Ident(name)) => name,
+                _ => return Err(location.error("expected a macro name after `defined`".to_string())),
+            };
+
+            let mut end = operand.unwrap() + 1;
+            if parenthesized {
+                end = match next_non_space(&tokens, end) {
+                    Some(close) if tokens[close].is_punct(")") => close + 1,
+                    _ => return Err(location.error("missing `)` after `defined(`".to_string())),
+                };
+            }
+
+            let value = if self.macros.contains_key(name) { "1" } else { "0" };
+            replaced.push(PpToken::Number(value.to_string()));
+            index = end;
+        }
+
+        let expanded: Vec<PpToken> = self.expand(&replaced, &[], location)?.into_iter().filter(|token| !token.is_space()).collect();
+
+        let mut evaluator = Evaluator {
+            tokens: &expanded,
+            position: 0,
+            location: location,
+        };
+
+        let value = evaluator.conditional()?;
+        match evaluator.tokens.get(evaluator.position) {
+            None => Ok(value != 0),
+            Some(token) => Err(location.error(format!("unexpected `{}` in #if", token.text()))),
+        }
+    }
+}
+
+// integer #if expressions, identifiers left after expansion are 0
+struct Evaluator<'a> {
+    tokens: &'a [PpToken],
+    position: usize,
+    location: Location<'a>,
+}
+
+// binary operators from the loosest to the tightest binding
+const BINARY_OPERATORS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl<'a> Evaluator<'a> {
+    fn peek_punct(&self) -> Option<&str> {
+        match self.tokens.get(self.position) {
+            Some(PpToken::Punct(punct)) => Some(punct),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), PreprocessError> {
+        match self.peek_punct() {
+            Some(found) if found == punct => {
+                self.position += 1;
+                Ok(())
+            },
+            _ => Err(self.location.error(format!("expected `{}` in #if", punct))),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<i64, PreprocessError> {
+        let condition = self.binary(0)?;
+
+        if self.peek_punct() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.position += 1;
+        let when_true = self.conditional()?;
+        self.expect(":")?;
+        let when_false = self.conditional()?;
+
+        Ok(if condition != 0 { when_true } else { when_false })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, PreprocessError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some(operator) = self.peek_punct().filter(|punct| BINARY_OPERATORS[level].contains(punct)).map(str::to_string) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+
+            left = match operator.as_str() {
+                "||" => ((left != 0) || (right != 0)) as i64,
+                "&&" => ((left != 0) && (right != 0)) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                ">" => (left > right) as i64,
+                "<=" => (left <= right) as i64,
+                ">=" => (left >= right) as i64,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if right == 0 => return Err(self.location.error("division by zero in #if".to_string())),
+                "/" => left.wrapping_div(right),
+                _ => left.wrapping_rem(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, PreprocessError> {
+        let token = self.tokens.get(self.position).cloned()
+            .ok_or_else(|| self.location.error("expected a value in #if".to_string()))?;
+        self.position += 1;
+
+        match &token {
+            PpToken::Punct(punct) => match punct.as_str() {
+                "!" => Ok((self.unary()? == 0) as i64),
+                "~" => Ok(!self.unary()?),
+                "-" => Ok(self.unary()?.wrapping_neg()),
+                "+" => self.unary(),
+                "(" => {
+                    let value = self.conditional()?;
+                    self.expect(")")?;
+                    Ok(value)
+                },
+                _ => Err(self.location.error(format!("unexpected `{}` in #if", punct))),
+            },
+            PpToken::Number(number) => self.number(number),
+            PpToken::Ident(name) => Ok((name == "true") as i64),
+            _ => Err(self.location.error(format!("unexpected `{}` in #if", token.text()))),
+        }
+    }
+
+    // 42, 0x2A, 052 with optional u/l suffixes
+    fn number(&self, number: &str) -> Result<i64, PreprocessError> {
+        let digits = number.trim_end_matches(['u', 'U', 'l', 'L']);
+
+        let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+
+        parsed.map_err(|_| self.location.error(format!("`{}` is not an integer", number)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> String {
+        Preprocessor::new().define("QUALITY", "2").preprocess_source(source, "test.hlsl").unwrap().source
+    }
+
+    // the flattened source without #line directives and blank lines
+    fn code(source: &str) -> Vec<String> {
+        run(source).lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with("#line"))
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn macros_are_expanded() {
+        let source = "#define SCALE 2.0\n\
+            #define MUL(a, b) ((a) * (b))\n\
+            #define NAME(prefix, index) prefix ## index\n\
+            #define STR(x) #x\n\
+            #define LOG(format, ...) print(format, __VA_ARGS__)\n\
+            #define SELF SELF + 1\n\
+            float a = MUL(SCALE, MUL(1, 2));\n\
+            float NAME(tex, 0) = 0; // NAME(a, b)\n\
+            string s = STR(a \"b\");\n\
+            LOG(\"%d %d\", 1, 2); int line = __LINE__;\n\
+            #undef SCALE\n\
+            float b = SCALE + SELF + MUL;\n";
+
+        assert_eq!(code(source), vec![
+            "float a = ((2.0) * (((1) * (2))));",
+            "float tex0 = 0;",
+            "string s = \"a \\\"b\\\"\";",
+            "print(\"%d %d\", 1, 2); int line = 10;",
+            "float b = SCALE + SELF + 1 + MUL;",
+        ]);
+
+        // the expansion of F is rescanned with the argument list after it
+        let source = "#define G(x) (x+1)\n\
+            #define F G\n\
+            float c = F(2) + F;\n";
+        assert_eq!(code(source), vec!["float c = (2+1) + G;"]);
+
+        let error = Preprocessor::new().preprocess_source("#define F(a) a\nF(1, 2)", "test.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "test.hlsl(2): `F` takes 1 arguments but 2 were given");
+    }
+
+    #[test]
+    fn macro_arguments_span_lines() {
+        let source = "#define MUL(a, b) ((a) * (b))\n\
+            float a = MUL(1,\n\
+                2);\n\
+            int line = __LINE__;\n";
+
+        let output = run(source);
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines.len(), 1 + source.lines().count());
+        assert_eq!(lines[2].trim(), "float a = ((1) * (2));");
+        assert_eq!(lines[4].trim(), "int line = 4;");
+
+        let error = Preprocessor::new().preprocess_source("#define F(a) a\nF(1,\n#endif", "test.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "test.hlsl(2): unterminated argument list for `F`");
+    }
+
+    #[test]
+    fn conditionals_keep_line_numbers() {
+        let source = "#if QUALITY > 1 && defined(QUALITY)\n\
+            high\n\
+            #elif QUALITY\n\
+            medium\n\
+            #else\n\
+            #bogus inside a skipped branch\n\
+            #endif\n\
+            #ifndef MISSING\n\
+            #if (1 << 3) % 5 == 3 ? !0 : 0\n\
+            shifted\n\
+            #endif\n\
+            #endif\n\
+            last \\\n\
+            line\n";
+
+        let output = run(source);
+        let lines: Vec<_> = output.lines().collect();
+
+        // #line 1 and then one line per source line
+        assert_eq!(lines.len(), 1 + source.lines().count());
+        assert_eq!(lines[2], "high");
+        assert_eq!(lines[10], "shifted");
+        assert_eq!(lines[13], "last line");
+        assert_eq!(code(source), vec!["high", "shifted", "last line"]);
+
+        let error = Preprocessor::new().preprocess_source("#ifdef A\n#error unsupported", "test.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "test.hlsl(1): #if without #endif");
+        let error = Preprocessor::new().preprocess_source("#if 1\n#error unsupported\n#endif", "test.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "test.hlsl(2): #error unsupported");
+    }
+
+    #[test]
+    fn includes_are_flattened() {
+        let directory = std::env::temp_dir().join(format!("rs_preprocessor_{}", std::process::id()));
+        let common = directory.join("common");
+        fs::create_dir_all(&common).unwrap();
+
+        fs::write(directory.join("Once.hlsli"), "#pragma once\nfloat once;\n").unwrap();
+        fs::write(common.join("Common.hlsli"), "#include \"../Once.hlsli\"\nfloat common;\n").unwrap();
+        fs::write(directory.join("Main.hlsl"), "#include \"Once.hlsli\"\n#include <Common.hlsli>\n#include \"Once.hlsli\"\nfloat main;\n").unwrap();
+
+        let main = directory.join("Main.hlsl");
+        let preprocessed = Preprocessor::new()
+            .include_path(&common.to_string_lossy())
+            .preprocess_file(&main.to_string_lossy())
+            .unwrap();
+
+        let code: Vec<_> = preprocessed.source.lines().filter(|line| !line.is_empty()).collect();
+        let main_line = |line: usize| line_directive(line, &main).trim_end().to_string();
+
+        assert_eq!(code, vec![
+            main_line(1).as_str(),
+            line_directive(1, &directory.join("Once.hlsli")).trim_end(),
+            "float once;",
+            main_line(2).as_str(),
+            line_directive(1, &common.join("Common.hlsli")).trim_end(),
+            "float common;",
+            main_line(3).as_str(),
+            "float main;",
+        ]);
+        assert_eq!(preprocessed.dependencies, vec![main.clone(), directory.join("Once.hlsli"), common.join("Common.hlsli")]);
+
+        let error = Preprocessor::new().preprocess_source("\n#include <Common.hlsli>", &main.to_string_lossy()).unwrap_err();
+        assert!(error.to_string().ends_with("(2): cannot open include file `Common.hlsli`"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn shipped_shaders_preprocess() {
+        let preprocessed = Preprocessor::new().preprocess_file("shaders/VertexShader.hlsl").unwrap();
+
+        assert_eq!(preprocessed.dependencies, vec![PathBuf::from("shaders/VertexShader.hlsl"), PathBuf::from("shaders/ShaderHeader.hlsli")]);
+        assert!(preprocessed.source.contains("row_major float4x4 transform;"));
+        assert!(preprocessed.source.contains("[RootSignature(\"RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), \""));
+    }
+}
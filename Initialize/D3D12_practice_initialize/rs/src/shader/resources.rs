@@ -0,0 +1,752 @@
+// resources a shader declares at global scope, e.g.
+// Texture2D<float4> tex: register(t0); cbuffer SceneConstants: register(b0) { ... };
+use winapi::um::d3d12;
+
+use std::collections::{ BTreeMap, HashSet };
+use std::fmt;
+use std::io;
+
+use crate::root_signature::{ DescriptorRange, RootDescriptorType, RootParameter };
+use crate::root_signature::hlsl::{ RootSignatureDesc, UNBOUNDED };
+
+use super::{ strip_comments, read_with_includes, tokenize, is_punct, ident, Token, Lexeme, Stage };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    ConstantBuffer,
+    ShaderResource,
+    UnorderedAccess,
+    Sampler,
+}
+
+impl ResourceKind {
+    // kind of a declared HLSL type, None for anything that isn't a resource
+    pub fn of_type(type_name: &str) -> Option<ResourceKind> {
+        let base = type_name.split('<').next().unwrap_or("").trim();
+
+        match base {
+            "cbuffer" | "ConstantBuffer" => Some(ResourceKind::ConstantBuffer),
+            "tbuffer" | "texture" | "Buffer" | "ByteAddressBuffer" | "StructuredBuffer" | "RaytracingAccelerationStructure" => Some(ResourceKind::ShaderResource),
+            "sampler" | "SamplerState" | "SamplerComparisonState" => Some(ResourceKind::Sampler),
+            "AppendStructuredBuffer" | "ConsumeStructuredBuffer" => Some(ResourceKind::UnorderedAccess),
+            _ if base.starts_with("RW") || base.starts_with("RasterizerOrdered") => Some(ResourceKind::UnorderedAccess),
+            _ if base.starts_with("Texture") => Some(ResourceKind::ShaderResource),
+            _ => None,
+        }
+    }
+
+    pub fn register_class(self) -> char {
+        match self {
+            ResourceKind::ConstantBuffer => 'b',
+            ResourceKind::ShaderResource => 't',
+            ResourceKind::UnorderedAccess => 'u',
+            ResourceKind::Sampler => 's',
+        }
+    }
+
+    pub fn range_type(self) -> d3d12::D3D12_DESCRIPTOR_RANGE_TYPE {
+        match self {
+            ResourceKind::ConstantBuffer => d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_CBV,
+            ResourceKind::ShaderResource => d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            ResourceKind::UnorderedAccess => d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            ResourceKind::Sampler => d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER,
+        }
+    }
+
+    fn of_root_descriptor(descriptor_type: RootDescriptorType) -> ResourceKind {
+        match descriptor_type {
+            RootDescriptorType::Cbv => ResourceKind::ConstantBuffer,
+            RootDescriptorType::Srv => ResourceKind::ShaderResource,
+            RootDescriptorType::Uav => ResourceKind::UnorderedAccess,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceBinding {
+    pub name: String,
+    // as declared, "Texture2D<float4>" or "cbuffer"
+    pub type_name: String,
+    pub kind: ResourceKind,
+    // None without a register(...) annotation
+    pub register: Option<u32>,
+    pub space: u32,
+    // array size, UNBOUNDED for name[]
+    pub count: u32,
+    // member names of a cbuffer
+    pub members: Vec<String>,
+    // referenced from a function body of the scanned files
+    pub used: bool,
+    pub file: String,
+    pub line: usize,
+}
+
+impl ResourceBinding {
+    // "t0" or "t0, space1"
+    fn slot(&self) -> String {
+        let register = self.register.map_or("?".to_string(), |register| register.to_string());
+
+        match self.space {
+            0 => format!("{}{}", self.kind.register_class(), register),
+            space => format!("{}{}, space{}", self.kind.register_class(), register, space),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    Io(io::Error),
+    Syntax { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Io(error) => write!(f, "{}", error),
+            ScanError::Syntax { file, line, message } => write!(f, "{}({}): {}", file, line, message),
+        }
+    }
+}
+
+struct Scanner<'a> {
+    lexemes: Vec<Lexeme>,
+    file: &'a str,
+    bindings: Vec<ResourceBinding>,
+    // identifiers used in function bodies
+    referenced: HashSet<String>,
+}
+
+impl<'a> Scanner<'a> {
+    fn error(&self, line: usize, message: String) -> ScanError {
+        ScanError::Syntax {
+            file: self.file.to_string(),
+            line: line,
+            message: message,
+        }
+    }
+
+    // index just past the `}` closing the block opened at `open`
+    fn skip_block(&self, open: usize) -> usize {
+        let mut depth = 0;
+
+        for (index, lexeme) in self.lexemes.iter().enumerate().skip(open) {
+            match lexeme.token {
+                Token::Punct('{') => depth += 1,
+                Token::Punct('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return index + 1;
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        self.lexemes.len()
+    }
+
+    fn scan(mut self) -> Result<(Vec<ResourceBinding>, HashSet<String>), ScanError> {
+
+        let mut start = 0;
+
+        while start < self.lexemes.len() {
+            // a statement ends at `;` or at the `{` of a block, ignoring ones inside () and []
+            let mut depth = 0;
+            let mut end = start;
+
+            while end < self.lexemes.len() {
+                match self.lexemes[end].token {
+                    Token::Punct('(') | Token::Punct('[') => depth += 1,
+                    Token::Punct(')') | Token::Punct(']') => depth -= 1,
+                    Token::Punct(';') | Token::Punct('{') if depth == 0 => break,
+                    _ => {},
+                }
+                end += 1;
+            }
+
+            if is_punct(self.lexemes.get(end), '{') {
+                let block_end = self.skip_block(end);
+
+                // cbuffers are the only blocks declaring resources, anything but a struct is a function body
+                match ident(self.lexemes.get(start)) {
+                    Some("cbuffer") | Some("tbuffer") => self.buffer_block(start, end, block_end)?,
+                    Some("struct") | Some("class") | Some("interface") => {},
+                    _ => {
+                        for lexeme in self.lexemes[end..block_end].iter() {
+                            if let Token::Ident(name) = &lexeme.token {
+                                self.referenced.insert(name.clone());
+                            }
+                        }
+                    },
+                }
+
+                start = block_end;
+                if is_punct(self.lexemes.get(start), ';') {
+                    start += 1;
+                }
+            } else {
+                self.declaration(start, end)?;
+                start = end + 1;
+            }
+        }
+
+        Ok((self.bindings, self.referenced))
+    }
+
+    // cbuffer Name : register(b0) { members };
+    fn buffer_block(&mut self, start: usize, end: usize, block_end: usize) -> Result<(), ScanError> {
+        let keyword = ident(self.lexemes.get(start)).unwrap().to_string();
+        let line = self.lexemes[start].line;
+
+        let name = match ident(self.lexemes.get(start + 1)) {
+            Some(name) if start + 1 < end => name.to_string(),
+            _ => return Err(self.error(line, format!("{} without a name", keyword))),
+        };
+
+        let kind = ResourceKind::of_type(&keyword).unwrap();
+        let mut binding = ResourceBinding {
+            name: name,
+            type_name: keyword,
+            kind: kind,
+            register: None,
+            space: 0,
+            count: 1,
+            members: Vec::new(),
+            used: false,
+            file: self.file.to_string(),
+            line: line,
+        };
+
+        // a member name is the identifier right before `;`, `,`, `[`, `:` or `=`
+        for (index, lexeme) in self.lexemes.iter().enumerate().take(block_end).skip(end + 1) {
+            if let Token::Ident(member) = &lexeme.token {
+                let next = self.lexemes.get(index + 1);
+                if [';', ',', '[', ':', '='].iter().any(|&c| is_punct(next, c)) {
+                    binding.members.push(member.clone());
+                }
+            }
+        }
+
+        self.annotations(start + 2, end, &mut binding)?;
+        self.bindings.push(binding);
+
+        Ok(())
+    }
+
+    // [modifiers] Type<...> name[N] : register(t0, space1), name2 ... ;
+    fn declaration(&mut self, start: usize, end: usize) -> Result<(), ScanError> {
+
+        let mut index = start;
+
+        while let Some(word) = ident(self.lexemes.get(index)).filter(|_| index < end) {
+            match word {
+                "uniform" | "extern" | "const" | "globallycoherent" | "precise" | "row_major" | "column_major" => index += 1,
+                // static and groupshared variables aren't bound from outside
+                "static" | "groupshared" | "typedef" => return Ok(()),
+                _ => break,
+            }
+        }
+
+        let line = match self.lexemes.get(index) {
+            Some(lexeme) if index < end => lexeme.line,
+            _ => return Ok(()),
+        };
+
+        let mut type_name = match ident(self.lexemes.get(index)) {
+            Some(name) => name.to_string(),
+            None => return Ok(()),
+        };
+        index += 1;
+
+        // template arguments
+        if is_punct(self.lexemes.get(index), '<') {
+            let mut depth = 0;
+
+            while index < end {
+                match &self.lexemes[index].token {
+                    Token::Punct('<') => depth += 1,
+                    Token::Punct('>') => depth -= 1,
+                    _ => {},
+                }
+
+                match &self.lexemes[index].token {
+                    Token::Ident(text) | Token::Number(text) => type_name.push_str(text),
+                    Token::Punct(',') => type_name.push_str(", "),
+                    Token::Punct(c) => type_name.push(*c),
+                }
+
+                index += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+
+        let kind = match ResourceKind::of_type(&type_name) {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+
+        // one binding per declarator
+        loop {
+            let name = match ident(self.lexemes.get(index)) {
+                Some(name) if index < end => name.to_string(),
+                _ => return Err(self.error(line, format!("expected a name after `{}`", type_name))),
+            };
+            index += 1;
+
+            let mut count = 1u32;
+            while is_punct(self.lexemes.get(index), '[') {
+                count = match self.lexemes.get(index + 1).filter(|_| index + 1 < end).map(|lexeme| &lexeme.token) {
+                    Some(Token::Punct(']')) => UNBOUNDED,
+                    Some(Token::Number(number)) => {
+                        let size = number.parse::<u32>().map_err(|_| self.error(line, format!("`{}` has an array size that isn't a literal", name)))?;
+                        count.saturating_mul(size)
+                    },
+                    Some(_) => return Err(self.error(line, format!("`{}` has an array size that isn't a literal", name))),
+                    None => return Err(self.error(line, format!("`{}` has an unterminated array size", name))),
+                };
+
+                while index < end && !is_punct(self.lexemes.get(index), ']') {
+                    index += 1;
+                }
+                index += 1;
+            }
+
+            // the declarator ends at the next `,` outside of parentheses
+            let mut declarator_end = index;
+            let mut depth = 0;
+            while declarator_end < end {
+                match self.lexemes[declarator_end].token {
+                    Token::Punct('(') => depth += 1,
+                    Token::Punct(')') => depth -= 1,
+                    Token::Punct(',') if depth == 0 => break,
+                    _ => {},
+                }
+                declarator_end += 1;
+            }
+
+            let mut binding = ResourceBinding {
+                name: name,
+                type_name: type_name.clone(),
+                kind: kind,
+                register: None,
+                space: 0,
+                count: count,
+                members: Vec::new(),
+                used: false,
+                file: self.file.to_string(),
+                line: line,
+            };
+
+            self.annotations(index, declarator_end, &mut binding)?;
+            self.bindings.push(binding);
+
+            if declarator_end >= end {
+                return Ok(());
+            }
+            index = declarator_end + 1;
+        }
+    }
+
+    // `: register(t0, space1)` among other annotations like semantics and packoffset
+    fn annotations(&self, start: usize, end: usize, binding: &mut ResourceBinding) -> Result<(), ScanError> {
+
+        let mut index = start;
+
+        while index < end {
+            let is_register = is_punct(self.lexemes.get(index), ':') && ident(self.lexemes.get(index + 1)) == Some("register") && is_punct(self.lexemes.get(index + 2), '(');
+            if !is_register {
+                index += 1;
+                continue;
+            }
+
+            index += 3;
+            while index < end && !is_punct(self.lexemes.get(index), ')') {
+                let lexeme = &self.lexemes[index];
+                index += 1;
+
+                let argument = match &lexeme.token {
+                    Token::Ident(argument) => argument,
+                    Token::Punct(',') => continue,
+                    _ => return Err(self.error(lexeme.line, format!("unexpected register argument for `{}`", binding.name))),
+                };
+
+                // register(ps_5_0, t0) binds for one profile only
+                if argument.contains('_') {
+                    continue;
+                }
+
+                if let Some(space) = argument.strip_prefix("space") {
+                    binding.space = space.parse().map_err(|_| self.error(lexeme.line, format!("invalid register space `{}`", argument)))?;
+                    continue;
+                }
+
+                let mut characters = argument.chars();
+                let class = characters.next().unwrap().to_ascii_lowercase();
+                let register = characters.as_str().parse().map_err(|_| self.error(lexeme.line, format!("invalid register `{}`", argument)))?;
+
+                if class != binding.kind.register_class() {
+                    return Err(self.error(lexeme.line, format!("`{}` is a {} and needs a `{}` register, not `{}`", binding.name, binding.type_name, binding.kind.register_class(), argument)));
+                }
+
+                binding.register = Some(register);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn scan_text(source: &str, file: &str) -> Result<(Vec<ResourceBinding>, HashSet<String>), ScanError> {
+    let scanner = Scanner {
+        lexemes: tokenize(&strip_comments(source)),
+        file: file,
+        bindings: Vec::new(),
+        referenced: HashSet::new(),
+    };
+
+    scanner.scan()
+}
+
+fn mark_used(bindings: &mut [ResourceBinding], referenced: &HashSet<String>) {
+    for binding in bindings.iter_mut() {
+        binding.used = referenced.contains(&binding.name) || binding.members.iter().any(|member| referenced.contains(member));
+    }
+}
+
+// resources declared in one source text, `file` is only used for messages
+pub fn scan_source(source: &str, file: &str) -> Result<Vec<ResourceBinding>, ScanError> {
+    let (mut bindings, referenced) = scan_text(source, file)?;
+    mark_used(&mut bindings, &referenced);
+
+    Ok(bindings)
+}
+
+// resources declared in a shader file and the files it includes.
+// a header declares its resources for every stage, `used` tells which ones this shader touches
+pub fn scan_file(path: &str) -> Result<Vec<ResourceBinding>, ScanError> {
+    let mut bindings = Vec::new();
+    let mut referenced = HashSet::new();
+
+    for file in read_with_includes(path).map_err(ScanError::Io)? {
+        let (file_bindings, file_referenced) = scan_text(&file.text, &file.path.to_string_lossy())?;
+        bindings.extend(file_bindings);
+        referenced.extend(file_referenced);
+    }
+
+    mark_used(&mut bindings, &referenced);
+
+    Ok(bindings)
+}
+
+#[derive(Debug, Clone)]
+pub struct StageResources {
+    pub stage: Stage,
+    pub bindings: Vec<ResourceBinding>,
+}
+
+impl StageResources {
+    pub fn scan(path: &str, stage: Stage) -> Result<StageResources, ScanError> {
+        Ok(StageResources {
+            stage: stage,
+            bindings: scan_file(path)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindingIssue {
+    // declared without register(...)
+    Unbound { stage: Stage, name: String },
+    // two different resources on overlapping registers
+    Conflict { first: (Stage, ResourceBinding), second: (Stage, ResourceBinding) },
+    // bound in the shader but not reachable through the root signature
+    NotInRootSignature { stage: Stage, binding: ResourceBinding },
+}
+
+impl fmt::Display for BindingIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingIssue::Unbound { stage, name } => write!(f, "{}: `{}` has no register binding", stage, name),
+            BindingIssue::Conflict { first, second } => write!(
+                f, "{} `{}` ({}) and {} `{}` ({}) overlap",
+                first.0, first.1.name, first.1.slot(), second.0, second.1.name, second.1.slot()
+            ),
+            BindingIssue::NotInRootSignature { stage, binding } => write!(f, "{}: `{}` ({}) is not in the root signature", stage, binding.name, binding.slot()),
+        }
+    }
+}
+
+fn last_register(base: u32, count: u32) -> u32 {
+    match count {
+        UNBOUNDED => u32::MAX,
+        _ => base.saturating_add(count.max(1) - 1),
+    }
+}
+
+fn overlaps(first: u32, first_count: u32, second: u32, second_count: u32) -> bool {
+    first <= last_register(second, second_count) && second <= last_register(first, first_count)
+}
+
+// unbound resources and register clashes within and between stages.
+// a declaration shared through a header is the same resource in every stage
+pub fn check(stages: &[StageResources]) -> Vec<BindingIssue> {
+
+    let mut issues = Vec::new();
+    let mut bound = Vec::new();
+
+    for stage in stages.iter() {
+        for binding in stage.bindings.iter() {
+            match binding.register {
+                Some(register) => bound.push((stage.stage, binding, register)),
+                None => issues.push(BindingIssue::Unbound { stage: stage.stage, name: binding.name.clone() }),
+            }
+        }
+    }
+
+    for (index, &(first_stage, first, first_register)) in bound.iter().enumerate() {
+        for &(second_stage, second, second_register) in bound[index + 1..].iter() {
+            if first.kind != second.kind || first.space != second.space || !overlaps(first_register, first.count, second_register, second.count) {
+                continue;
+            }
+
+            let shared = first_stage != second_stage && first.name == second.name && first.type_name == second.type_name
+                && first_register == second_register && first.count == second.count;
+
+            if !shared {
+                issues.push(BindingIssue::Conflict {
+                    first: (first_stage, first.clone()),
+                    second: (second_stage, second.clone()),
+                });
+            }
+        }
+    }
+
+    issues
+}
+
+// root signature for the used, bound resources of the stages: constant buffers as root CBVs,
+// other resources in one descriptor table per visibility, samplers in their own tables
+pub fn derive(stages: &[StageResources]) -> RootSignatureDesc {
+
+    // (kind, space, register) -> (count, visibility), the first declaration wins on conflicts
+    let mut resources = BTreeMap::new();
+
+    for stage in stages.iter() {
+        for binding in stage.bindings.iter() {
+            let register = match binding.register {
+                Some(register) if binding.used => register,
+                _ => continue,
+            };
+
+            let entry = resources.entry((binding.kind, binding.space, register)).or_insert((binding.count, stage.stage.visibility()));
+            if entry.1 != stage.stage.visibility() {
+                entry.1 = d3d12::D3D12_SHADER_VISIBILITY_ALL;
+            }
+        }
+    }
+
+    let mut desc = RootSignatureDesc::default();
+    let mut tables: BTreeMap<(bool, d3d12::D3D12_SHADER_VISIBILITY), Vec<DescriptorRange>> = BTreeMap::new();
+
+    if stages.iter().any(|stage| stage.stage == Stage::Vertex) {
+        desc.flags = d3d12::D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT;
+    }
+
+    for (&(kind, space, register), &(count, visibility)) in resources.iter() {
+        match kind {
+            ResourceKind::ConstantBuffer if count == 1 => desc.parameters.push(RootParameter::Descriptor {
+                descriptor_type: RootDescriptorType::Cbv,
+                register: register,
+                space: space,
+                flags: d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_NONE,
+                visibility: visibility,
+            }),
+            _ => tables.entry((kind == ResourceKind::Sampler, visibility)).or_default()
+                .push(DescriptorRange::new(kind.range_type(), count, register).space(space)),
+        }
+    }
+
+    for ((_, visibility), ranges) in tables {
+        desc.parameters.push(RootParameter::Table {
+            ranges: merge_ranges(ranges),
+            visibility: visibility,
+        });
+    }
+
+    desc
+}
+
+// t0 and t1 become one range of two
+fn merge_ranges(ranges: Vec<DescriptorRange>) -> Vec<DescriptorRange> {
+    let mut merged: Vec<DescriptorRange> = Vec::new();
+
+    for range in ranges {
+        if let Some(last) = merged.last_mut() {
+            let contiguous = last.range_type == range.range_type && last.space == range.space && last.flags == range.flags
+                && last.count != UNBOUNDED && last.base_register.checked_add(last.count) == Some(range.base_register);
+
+            if contiguous {
+                last.count = if range.count == UNBOUNDED { UNBOUNDED } else { last.count + range.count };
+                continue;
+            }
+        }
+        merged.push(range);
+    }
+
+    merged
+}
+
+fn visible_to(visibility: d3d12::D3D12_SHADER_VISIBILITY, stage: Stage) -> bool {
+    visibility == d3d12::D3D12_SHADER_VISIBILITY_ALL || visibility == stage.visibility()
+}
+
+fn covers(desc: &RootSignatureDesc, stage: Stage, binding: &ResourceBinding, register: u32) -> bool {
+    let single = |kind: ResourceKind, parameter_register: u32, space: u32, visibility| {
+        kind == binding.kind && binding.count == 1 && parameter_register == register && space == binding.space && visible_to(visibility, stage)
+    };
+
+    let in_parameters = desc.parameters.iter().any(|parameter| match parameter {
+        RootParameter::Constants { register: parameter_register, space, visibility, .. } => single(ResourceKind::ConstantBuffer, *parameter_register, *space, *visibility),
+        RootParameter::Descriptor { descriptor_type, register: parameter_register, space, visibility, .. } => single(ResourceKind::of_root_descriptor(*descriptor_type), *parameter_register, *space, *visibility),
+        RootParameter::Table { ranges, visibility } => visible_to(*visibility, stage) && ranges.iter().any(|range| {
+            range.range_type == binding.kind.range_type() && range.space == binding.space
+                && register >= range.base_register && last_register(register, binding.count) <= last_register(range.base_register, range.count)
+        }),
+    });
+
+    in_parameters || desc.static_samplers.iter().any(|sampler| single(ResourceKind::Sampler, sampler.register, sampler.space, sampler.visibility))
+}
+
+// used resources the root signature doesn't give the stage access to
+pub fn check_root_signature(desc: &RootSignatureDesc, stages: &[StageResources]) -> Vec<BindingIssue> {
+    let mut issues = Vec::new();
+
+    for stage in stages.iter() {
+        for binding in stage.bindings.iter() {
+            if let (Some(register), true) = (binding.register, binding.used) {
+                if !covers(desc, stage.stage, binding, register) {
+                    issues.push(BindingIssue::NotInRootSignature { stage: stage.stage, binding: binding.clone() });
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::root_signature::hlsl;
+
+    const SOURCE: &str = "#include \"Common.hlsli\"\n\
+        #define ROOT \"CBV(b0)\" \\\n    \"SRV(t9)\"\n\
+        Texture2D<float4> albedo : register(t0);\n\
+        Texture2D normals[2] : register(t1, space0);\n\
+        // Texture2D commented : register(t7);\n\
+        RWStructuredBuffer<uint> counters : register(u1, space2);\n\
+        SamplerComparisonState shadow : register(ps_5_0, s1), linear_sampler : register(s0);\n\
+        static const float scale = 2.0;\n\
+        struct Light { float3 direction; };\n\
+        cbuffer Frame : register(b1)\n{\n    float4x4 view : packoffset(c0);\n    float4 tint, fog;\n};\n\
+        ConstantBuffer<Light> light;\n\
+        float4 main(float2 uv : TEXCOORD) : SV_TARGET {\n    return albedo.Sample(linear_sampler, uv) * fog;\n}\n";
+
+    // every declaration is used by a function body
+    fn stage(stage: Stage, declarations: &str) -> StageResources {
+        let names: Vec<_> = scan_source(declarations, "test.hlsl").unwrap().into_iter().map(|binding| binding.name).collect();
+        let source = format!("{}\nvoid main() {{ {}; }}", declarations, names.join("; "));
+
+        StageResources {
+            stage: stage,
+            bindings: scan_source(&source, "test.hlsl").unwrap(),
+        }
+    }
+
+    #[test]
+    fn declarations_are_found() {
+        let bindings = scan_source(SOURCE, "test.hlsl").unwrap();
+        let summary: Vec<_> = bindings.iter().map(|binding| (binding.name.as_str(), binding.kind, binding.register, binding.space, binding.count, binding.used)).collect();
+
+        assert_eq!(summary, vec![
+            ("albedo", ResourceKind::ShaderResource, Some(0), 0, 1, true),
+            ("normals", ResourceKind::ShaderResource, Some(1), 0, 2, false),
+            ("counters", ResourceKind::UnorderedAccess, Some(1), 2, 1, false),
+            ("shadow", ResourceKind::Sampler, Some(1), 0, 1, false),
+            ("linear_sampler", ResourceKind::Sampler, Some(0), 0, 1, true),
+            ("Frame", ResourceKind::ConstantBuffer, Some(1), 0, 1, true),
+            ("light", ResourceKind::ConstantBuffer, None, 0, 1, false),
+        ]);
+        assert_eq!(bindings[0].type_name, "Texture2D<float4>");
+        assert_eq!(bindings[0].line, 4);
+        assert_eq!(bindings[5].members, vec!["view", "tint", "fog"]);
+
+        let error = scan_source("\nTexture2D tex : register(s0);", "bad.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "bad.hlsl(2): `tex` is a Texture2D and needs a `t` register, not `s0`");
+
+        let error = scan_source("Texture2D tex[", "bad.hlsl").unwrap_err();
+        assert_eq!(error.to_string(), "bad.hlsl(1): `tex` has an unterminated array size");
+    }
+
+    #[test]
+    fn stages_are_checked_against_each_other() {
+        let header = "Texture2D tex : register(t0); SamplerState smp : register(s0);";
+        let vertex = stage(Stage::Vertex, &format!("{} cbuffer Scene : register(b0) {{ float4 a; }};", header));
+        let pixel = stage(Stage::Pixel, &format!("{} Texture2D masks[4] : register(t2); Texture2D detail : register(t3); Texture2D lost;", header));
+
+        let issues = check(&[vertex, pixel]);
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0], BindingIssue::Unbound { stage: Stage::Pixel, name: "lost".to_string() });
+        assert_eq!(issues[1].to_string(), "PS `masks` (t2) and PS `detail` (t3) overlap");
+    }
+
+    #[test]
+    fn derived_layout_covers_the_shaders() {
+        let header = "Texture2D tex : register(t0); Texture2D extra : register(t1); SamplerState smp : register(s0);";
+        let stages = [
+            stage(Stage::Vertex, &format!("{} cbuffer Scene : register(b0) {{ float4 a; }}; Texture2D height : register(t4);", header)),
+            stage(Stage::Pixel, header),
+        ];
+
+        let desc = derive(&stages);
+
+        assert_eq!(desc.to_string(), "RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), \
+            CBV(b0, visibility = SHADER_VISIBILITY_VERTEX), \
+            DescriptorTable(SRV(t0, numDescriptors = 2)), \
+            DescriptorTable(SRV(t4), visibility = SHADER_VISIBILITY_VERTEX), \
+            DescriptorTable(Sampler(s0))");
+        assert!(check_root_signature(&desc, &stages).is_empty());
+        assert!(desc.to_builder().validate().is_ok());
+
+        let narrow = hlsl::parse("CBV(b0), DescriptorTable(SRV(t0), visibility = SHADER_VISIBILITY_PIXEL), StaticSampler(s0)").unwrap();
+        let missing: Vec<_> = check_root_signature(&narrow, &stages).iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(missing, vec![
+            "VS: `tex` (t0) is not in the root signature",
+            "VS: `extra` (t1) is not in the root signature",
+            "VS: `height` (t4) is not in the root signature",
+            "PS: `extra` (t1) is not in the root signature",
+        ]);
+    }
+
+    #[test]
+    fn shipped_root_signature_matches_the_shaders() {
+        let stages = [
+            StageResources::scan("shaders/VertexShader.hlsl", Stage::Vertex).unwrap(),
+            StageResources::scan("shaders/PixelShader.hlsl", Stage::Pixel).unwrap(),
+        ];
+        let desc = hlsl::load("shaders/ShaderHeader.hlsli", "BasicRS").unwrap();
+
+        assert!(check(&stages).is_empty());
+        assert!(check_root_signature(&desc, &stages).is_empty());
+
+        // the header is shared, but only the vertex shader reads the constants
+        let derived = derive(&stages);
+        assert_eq!(derived.to_string(), "RootFlags(ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT), \
+            CBV(b0, visibility = SHADER_VISIBILITY_VERTEX), \
+            DescriptorTable(SRV(t0), visibility = SHADER_VISIBILITY_PIXEL), \
+            DescriptorTable(Sampler(s0), visibility = SHADER_VISIBILITY_PIXEL)");
+    }
+}
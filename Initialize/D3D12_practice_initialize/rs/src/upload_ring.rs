@@ -0,0 +1,307 @@
+use winapi::{
+    um::d3d12,
+    shared::winerror,
+};
+
+use std::collections::VecDeque;
+use std::mem;
+use std::ptr;
+
+use crate::lib;
+use crate::memory_tracker;
+
+// constant buffer views must start on 256 bytes
+pub const UPLOAD_ALIGNMENT: u64 = d3d12::D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RingAllocation {
+    // byte offset from the start of the ring buffer
+    pub offset: u64,
+    pub size: u64,
+    // the space is reused once the GPU has passed this fence value
+    pub fence_value: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RingError {
+    // larger than the whole ring
+    TooLarge { size: u64, capacity: u64 },
+    // frames in flight still use the space
+    OutOfSpace { size: u64, available: u64 },
+    InvalidAlignment(u64),
+    // fence values have to be passed in increasing order
+    FenceWentBackwards { fence_value: u64, last_fence_value: u64 },
+}
+
+// allocation and wrap logic of the upload ring, no GPU objects involved
+//
+// head and tail are positions which only grow, the byte offset is `position % capacity`
+#[derive(Debug, Clone)]
+pub struct RingAllocator {
+    capacity: u64,
+    head: u64,
+    tail: u64,
+    // (fence value, head after the last allocation tagged with it)
+    in_flight: VecDeque<(u64, u64)>,
+}
+
+impl RingAllocator {
+    pub fn new(capacity: u64) -> RingAllocator {
+        assert!(capacity > 0 && capacity % UPLOAD_ALIGNMENT == 0, "ring capacity must be a multiple of {}", UPLOAD_ALIGNMENT);
+
+        RingAllocator {
+            capacity: capacity,
+            head: 0,
+            tail: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    // bytes held by frames which have not completed yet, including padding
+    pub fn used(&self) -> u64 {
+        self.head - self.tail
+    }
+
+    pub fn allocate(&mut self, size: u64, alignment: u64, fence_value: u64) -> Result<RingAllocation, RingError> {
+
+        if alignment == 0 || !alignment.is_power_of_two() || self.capacity % alignment != 0 {
+            return Err(RingError::InvalidAlignment(alignment));
+        }
+
+        if let Some(&(last_fence_value, _)) = self.in_flight.back() {
+            if fence_value < last_fence_value {
+                return Err(RingError::FenceWentBackwards { fence_value: fence_value, last_fence_value: last_fence_value });
+            }
+        }
+
+        let size = align_up(size.max(1), alignment);
+
+        if size > self.capacity {
+            return Err(RingError::TooLarge { size: size, capacity: self.capacity });
+        }
+
+        let mut position = align_up(self.head, alignment);
+
+        // an allocation never straddles the end, skip the rest of the buffer instead
+        let offset = position % self.capacity;
+        if offset + size > self.capacity {
+            position += self.capacity - offset;
+        }
+
+        if position + size - self.tail > self.capacity {
+            return Err(RingError::OutOfSpace { size: size, available: self.capacity - self.used() });
+        }
+
+        self.head = position + size;
+
+        match self.in_flight.back_mut() {
+            Some(frame) if frame.0 == fence_value => frame.1 = self.head,
+            _ => self.in_flight.push_back((fence_value, self.head)),
+        }
+
+        Ok(RingAllocation {
+            offset: position % self.capacity,
+            size: size,
+            fence_value: fence_value,
+        })
+    }
+
+    // release everything tagged with a fence value the GPU has reached
+    pub fn reclaim(&mut self, completed_fence_value: u64) {
+        while let Some(&(fence_value, head)) = self.in_flight.front() {
+            if fence_value > completed_fence_value {
+                break;
+            }
+
+            self.tail = head;
+            self.in_flight.pop_front();
+        }
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+// suballocation of the mapped upload buffer
+#[derive(Debug, Clone, Copy)]
+pub struct UploadAllocation {
+    pub cpu_address: *mut u8,
+    pub gpu_address: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    pub offset: u64,
+    pub size: u64,
+    pub fence_value: u64,
+}
+
+// persistently mapped D3D12_HEAP_TYPE_UPLOAD buffer for per frame data
+pub struct UploadRing {
+    pub buffer_object: *mut d3d12::ID3D12Resource,
+    mapped: *mut u8,
+    gpu_address: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    allocator: RingAllocator,
+}
+
+impl UploadRing {
+    pub fn new(device: *mut d3d12::ID3D12Device, capacity: u64) -> Result<UploadRing, winerror::HRESULT> {
+
+        let allocator = RingAllocator::new(capacity);
+
+        let heap_prop = d3d12::D3D12_HEAP_PROPERTIES {
+            Type : d3d12::D3D12_HEAP_TYPE_UPLOAD,
+            CPUPageProperty : d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference : d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 0,
+            VisibleNodeMask: 0,
+        };
+
+        let resource_desc = lib::create_buffer_resource_desc(capacity);
+
+        let buffer = lib::create_committed_resource(device, lib::CommittedResource {
+            pHeapProperties: &heap_prop,
+            HeapFlags: d3d12::D3D12_HEAP_FLAG_NONE,
+            pResourceDesc: &resource_desc,
+            InitialResourceState: d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+            pOptimizedClearValue: ptr::null_mut(),
+        })?;
+
+        // upload heaps may stay mapped for the lifetime of the resource
+        let mut mapped = ptr::null_mut::<u8>();
+
+        let result = unsafe {
+            buffer.as_ref().unwrap().
+            Map(0, &d3d12::D3D12_RANGE { Begin: 0, End: 0 }, lib::get_pointer_of_interface(&mut mapped))
+        };
+
+        if result != winerror::S_OK {
+            memory_tracker::untrack(buffer);
+            unsafe { buffer.as_ref().unwrap().Release() };
+            return Err(result);
+        }
+
+        let gpu_address = unsafe { buffer.as_ref().unwrap().GetGPUVirtualAddress() };
+
+        Ok(UploadRing {
+            buffer_object: buffer,
+            mapped: mapped,
+            gpu_address: gpu_address,
+            allocator: allocator,
+        })
+    }
+
+    pub fn allocate(&mut self, size: u64, alignment: u64, fence_value: u64) -> Result<UploadAllocation, RingError> {
+
+        let allocation = self.allocator.allocate(size, alignment, fence_value)?;
+
+        Ok(UploadAllocation {
+            cpu_address: unsafe { self.mapped.offset(allocation.offset as isize) },
+            gpu_address: self.gpu_address + allocation.offset,
+            offset: allocation.offset,
+            size: allocation.size,
+            fence_value: allocation.fence_value,
+        })
+    }
+
+    // copy `data` into a fresh 256 byte aligned suballocation
+    pub fn push<T: Copy>(&mut self, data: &[T], fence_value: u64) -> Result<UploadAllocation, RingError> {
+
+        let byte_size = mem::size_of_val(data) as u64;
+
+        let allocation = self.allocate(byte_size, UPLOAD_ALIGNMENT, fence_value)?;
+
+        unsafe {
+            allocation.cpu_address.copy_from_nonoverlapping(data.as_ptr().cast::<u8>(), byte_size as usize)
+        };
+
+        Ok(allocation)
+    }
+
+    pub fn reclaim(&mut self, fence: *mut d3d12::ID3D12Fence) {
+        let completed_fence_value = unsafe { fence.as_ref().unwrap().GetCompletedValue() };
+
+        self.allocator.reclaim(completed_fence_value);
+    }
+
+    pub fn allocator(&self) -> &RingAllocator {
+        &self.allocator
+    }
+}
+
+impl Drop for UploadRing {
+    fn drop(&mut self) {
+        unsafe {
+            self.buffer_object.as_ref().unwrap().Unmap(0, ptr::null());
+            memory_tracker::untrack(self.buffer_object);
+            self.buffer_object.as_ref().unwrap().Release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_tagged() {
+        let mut ring = RingAllocator::new(4096);
+
+        let a = ring.allocate(100, UPLOAD_ALIGNMENT, 1).unwrap();
+        let b = ring.allocate(300, UPLOAD_ALIGNMENT, 1).unwrap();
+        let c = ring.allocate(16, UPLOAD_ALIGNMENT, 2).unwrap();
+
+        assert_eq!(a, RingAllocation { offset: 0, size: 256, fence_value: 1 });
+        assert_eq!(b, RingAllocation { offset: 256, size: 512, fence_value: 1 });
+        assert_eq!(c, RingAllocation { offset: 768, size: 256, fence_value: 2 });
+        assert_eq!(ring.used(), 1024);
+    }
+
+    #[test]
+    fn space_is_reclaimed_per_fence() {
+        let mut ring = RingAllocator::new(1024);
+
+        ring.allocate(512, UPLOAD_ALIGNMENT, 1).unwrap();
+        ring.allocate(512, UPLOAD_ALIGNMENT, 2).unwrap();
+
+        assert_eq!(ring.allocate(256, UPLOAD_ALIGNMENT, 3), Err(RingError::OutOfSpace { size: 256, available: 0 }));
+
+        // frame 1 is done on the GPU
+        ring.reclaim(1);
+        assert_eq!(ring.used(), 512);
+
+        let wrapped = ring.allocate(256, UPLOAD_ALIGNMENT, 3).unwrap();
+        assert_eq!(wrapped.offset, 0);
+
+        ring.reclaim(3);
+        assert_eq!(ring.used(), 0);
+    }
+
+    #[test]
+    fn allocation_skips_the_end_instead_of_straddling() {
+        let mut ring = RingAllocator::new(1024);
+
+        ring.allocate(768, UPLOAD_ALIGNMENT, 1).unwrap();
+        ring.reclaim(1);
+
+        // 256 bytes left at the end are wasted until the frame completes
+        let allocation = ring.allocate(512, UPLOAD_ALIGNMENT, 2).unwrap();
+        assert_eq!(allocation.offset, 0);
+        assert_eq!(ring.used(), 768);
+
+        assert_eq!(ring.allocate(512, UPLOAD_ALIGNMENT, 2), Err(RingError::OutOfSpace { size: 512, available: 256 }));
+        assert!(ring.allocate(256, UPLOAD_ALIGNMENT, 2).is_ok());
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let mut ring = RingAllocator::new(1024);
+
+        assert_eq!(ring.allocate(2048, UPLOAD_ALIGNMENT, 1), Err(RingError::TooLarge { size: 2048, capacity: 1024 }));
+        assert_eq!(ring.allocate(16, 3, 1), Err(RingError::InvalidAlignment(3)));
+
+        ring.allocate(16, UPLOAD_ALIGNMENT, 5).unwrap();
+        assert_eq!(ring.allocate(16, UPLOAD_ALIGNMENT, 4), Err(RingError::FenceWentBackwards { fence_value: 4, last_fence_value: 5 }));
+    }
+}
@@ -0,0 +1,280 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{ Span, TokenStream as TokenStream2 };
+use quote::quote;
+use syn::{
+    parse_macro_input,
+    spanned::Spanned,
+    Data,
+    DeriveInput,
+    Error,
+    Fields,
+    Lit,
+    LitByteStr,
+    Meta,
+    NestedMeta,
+    Type,
+};
+
+// #[derive(VertexLayout)]
+//
+// generates `input_layout()` which returns D3D12_INPUT_ELEMENT_DESC for each field.
+//
+// #[derive(VertexLayout)]
+// #[repr(C)]
+// #[vertex(slot = 0)]                      // optional, input slot of the whole struct
+// #[vertex(instance_step_rate = 1)]        // optional, makes the struct per instance data
+// pub struct Vertex {
+//     #[vertex(semantic = "POSITION")]
+//     pub position: XMFLOAT3,
+//     #[vertex(semantic = "TEXCOORD", index = 1, format = "DXGI_FORMAT_R16G16_FLOAT")]
+//     pub uv: [u16; 2],
+//     #[vertex(skip)]
+//     pub padding: u32,
+// }
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct StructAttributes {
+    slot: u32,
+    instance_step_rate: Option<u32>,
+}
+
+struct FieldAttributes {
+    semantic: Option<String>,
+    index: u32,
+    format: Option<syn::Ident>,
+    skip: bool,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+
+    let name = &input.ident;
+
+    if !has_repr_c(input) {
+        return Err(Error::new(input.ident.span(), "VertexLayout requires #[repr(C)] so that field offsets match the GPU layout"));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new(input.span(), "VertexLayout only supports structs with named fields")),
+        },
+        _ => return Err(Error::new(input.span(), "VertexLayout only supports structs")),
+    };
+
+    let struct_attributes = parse_struct_attributes(input)?;
+
+    let slot = struct_attributes.slot;
+
+    let (input_slot_class, step_rate) = match struct_attributes.instance_step_rate {
+        Some(rate) => (quote!(::winapi::um::d3d12::D3D12_INPUT_CLASSIFICATION_PER_INSTANCE_DATA), rate),
+        None => (quote!(::winapi::um::d3d12::D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA), 0),
+    };
+
+    let mut elements = Vec::new();
+
+    for field in fields.iter() {
+        let field_attributes = parse_field_attributes(field)?;
+
+        if field_attributes.skip {
+            continue;
+        }
+
+        let field_name = field.ident.as_ref().unwrap();
+
+        let semantic = match field_attributes.semantic {
+            Some(semantic) => semantic,
+            None => return Err(Error::new(field.span(), "missing #[vertex(semantic = \"...\")] or #[vertex(skip)]")),
+        };
+
+        let format = match field_attributes.format {
+            Some(format) => format,
+            None => match infer_format(&field.ty) {
+                Some(format) => syn::Ident::new(format, Span::call_site()),
+                None => return Err(Error::new(field.ty.span(), "cannot infer DXGI_FORMAT of this type, add #[vertex(format = \"DXGI_FORMAT_...\")]")),
+            },
+        };
+
+        // semantic names point into static byte strings, so nothing has to be freed
+        let semantic_name = LitByteStr::new(format!("{}\0", semantic).as_bytes(), field.span());
+
+        let semantic_index = field_attributes.index;
+
+        elements.push(quote! {
+            ::winapi::um::d3d12::D3D12_INPUT_ELEMENT_DESC {
+                SemanticName: #semantic_name.as_ptr() as ::winapi::shared::ntdef::LPCSTR,
+                SemanticIndex: #semantic_index,
+                Format: ::winapi::shared::dxgiformat::#format,
+                InputSlot: #slot,
+                AlignedByteOffset: ::std::mem::offset_of!(Self, #field_name) as u32,
+                InputSlotClass: #input_slot_class,
+                InstanceDataStepRate: #step_rate,
+            }
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            pub fn input_layout() -> Vec<::winapi::um::d3d12::D3D12_INPUT_ELEMENT_DESC> {
+                vec![ #(#elements),* ]
+            }
+        }
+    })
+}
+
+fn has_repr_c(input: &DeriveInput) -> bool {
+    input.attrs.iter()
+        .filter(|attr| attr.path.is_ident("repr"))
+        .filter_map(|attr| attr.parse_meta().ok())
+        .any(|meta| match meta {
+            Meta::List(list) => list.nested.iter().any(|nested| match nested {
+                NestedMeta::Meta(Meta::Path(path)) => path.is_ident("C"),
+                _ => false,
+            }),
+            _ => false,
+        })
+}
+
+fn vertex_attributes(attrs: &[syn::Attribute]) -> Result<Vec<NestedMeta>, Error> {
+
+    let mut nested_metas = Vec::new();
+
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("vertex")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => nested_metas.extend(list.nested.into_iter()),
+            meta => return Err(Error::new(meta.span(), "expected #[vertex(...)]")),
+        }
+    }
+
+    Ok(nested_metas)
+}
+
+fn parse_struct_attributes(input: &DeriveInput) -> Result<StructAttributes, Error> {
+
+    let mut attributes = StructAttributes {
+        slot: 0,
+        instance_step_rate: None,
+    };
+
+    for nested in vertex_attributes(&input.attrs)? {
+        match &nested {
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("slot") => {
+                attributes.slot = lit_to_u32(&pair.lit)?;
+            },
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("instance_step_rate") => {
+                attributes.instance_step_rate = Some(lit_to_u32(&pair.lit)?);
+            },
+            _ => return Err(Error::new(nested.span(), "unknown vertex attribute, expected `slot` or `instance_step_rate`")),
+        }
+    }
+
+    Ok(attributes)
+}
+
+fn parse_field_attributes(field: &syn::Field) -> Result<FieldAttributes, Error> {
+
+    let mut attributes = FieldAttributes {
+        semantic: None,
+        index: 0,
+        format: None,
+        skip: false,
+    };
+
+    for nested in vertex_attributes(&field.attrs)? {
+        match &nested {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                attributes.skip = true;
+            },
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("semantic") => {
+                let semantic = lit_to_string(&pair.lit)?;
+                if semantic.is_empty() || semantic.contains('\0') {
+                    return Err(Error::new(pair.lit.span(), "invalid semantic name"));
+                }
+                attributes.semantic = Some(semantic);
+            },
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("index") => {
+                attributes.index = lit_to_u32(&pair.lit)?;
+            },
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("format") => {
+                let format = lit_to_string(&pair.lit)?;
+                if !format.starts_with("DXGI_FORMAT_") {
+                    return Err(Error::new(pair.lit.span(), "format must be a DXGI_FORMAT_* constant name"));
+                }
+                attributes.format = Some(syn::Ident::new(&format, pair.lit.span()));
+            },
+            _ => return Err(Error::new(nested.span(), "unknown vertex attribute, expected `semantic`, `index`, `format` or `skip`")),
+        }
+    }
+
+    Ok(attributes)
+}
+
+fn lit_to_u32(lit: &Lit) -> Result<u32, Error> {
+    match lit {
+        Lit::Int(int) => int.base10_parse::<u32>(),
+        _ => Err(Error::new(lit.span(), "expected integer literal")),
+    }
+}
+
+fn lit_to_string(lit: &Lit) -> Result<String, Error> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        _ => Err(Error::new(lit.span(), "expected string literal")),
+    }
+}
+
+// map well known field types to DXGI_FORMAT
+fn infer_format(ty: &Type) -> Option<&'static str> {
+    match ty {
+        Type::Path(type_path) => {
+            let ident = type_path.path.segments.last()?.ident.to_string();
+            match ident.as_str() {
+                "f32" => Some("DXGI_FORMAT_R32_FLOAT"),
+                "u32" => Some("DXGI_FORMAT_R32_UINT"),
+                "i32" => Some("DXGI_FORMAT_R32_SINT"),
+                "XMFLOAT2" => Some("DXGI_FORMAT_R32G32_FLOAT"),
+                "XMFLOAT3" => Some("DXGI_FORMAT_R32G32B32_FLOAT"),
+                "XMFLOAT4" => Some("DXGI_FORMAT_R32G32B32A32_FLOAT"),
+                _ => None,
+            }
+        },
+        Type::Array(array) => {
+            let len = match &array.len {
+                syn::Expr::Lit(syn::ExprLit { lit: Lit::Int(int), .. }) => int.base10_parse::<usize>().ok()?,
+                _ => return None,
+            };
+            let elem = match &*array.elem {
+                Type::Path(type_path) => type_path.path.segments.last()?.ident.to_string(),
+                _ => return None,
+            };
+            match (elem.as_str(), len) {
+                ("f32", 1) => Some("DXGI_FORMAT_R32_FLOAT"),
+                ("f32", 2) => Some("DXGI_FORMAT_R32G32_FLOAT"),
+                ("f32", 3) => Some("DXGI_FORMAT_R32G32B32_FLOAT"),
+                ("f32", 4) => Some("DXGI_FORMAT_R32G32B32A32_FLOAT"),
+                ("u32", 1) => Some("DXGI_FORMAT_R32_UINT"),
+                ("u32", 2) => Some("DXGI_FORMAT_R32G32_UINT"),
+                ("u32", 3) => Some("DXGI_FORMAT_R32G32B32_UINT"),
+                ("u32", 4) => Some("DXGI_FORMAT_R32G32B32A32_UINT"),
+                ("i32", 1) => Some("DXGI_FORMAT_R32_SINT"),
+                ("i32", 2) => Some("DXGI_FORMAT_R32G32_SINT"),
+                ("i32", 3) => Some("DXGI_FORMAT_R32G32B32_SINT"),
+                ("i32", 4) => Some("DXGI_FORMAT_R32G32B32A32_SINT"),
+                ("u8", 4) => Some("DXGI_FORMAT_R8G8B8A8_UNORM"),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}